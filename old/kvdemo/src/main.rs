@@ -1,12 +1,12 @@
 extern crate getopts;
-extern crate time;
 
 use getopts::Options;
 use std::collections::HashMap;
 use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn timestamp() -> i64 {
-    return time::get_time().sec;
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
 }
 
 // A Value contains some string stored in the key/value store with
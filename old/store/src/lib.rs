@@ -50,13 +50,13 @@
 //! }
 //! ```
 extern crate rustc_serialize;
-extern crate time;
 
 use std::collections::HashMap;
 use rustc_serialize::json;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn timestamp() -> i64 {
-    return time::get_time().sec;
+    return SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
 }
 
 /// A Value contains some string stored in the key/value store with
@@ -0,0 +1,179 @@
+//! store benchmarks the basic store operations against stores of 1K,
+//! 100K, and 1M keys, plus mixed read/write workloads.
+//!
+//! Run the whole suite with `cargo bench`, or pick out a group with a
+//! filter (`cargo bench -- update`). To compare a change against the
+//! current code, save a baseline before making it and compare against
+//! that baseline afterwards:
+//!
+//! ```text
+//! cargo bench -- --save-baseline before
+//! # ... make the change ...
+//! cargo bench -- --baseline before
+//! ```
+//!
+//! Criterion reports the change for each benchmark, and flags the
+//! ones that are statistically significant; HTML reports are written
+//! under `target/criterion`.
+#[macro_use]
+extern crate criterion;
+extern crate skvs;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use skvs::store::Store;
+use std::time::{Duration, Instant};
+
+const SIZES: [usize; 3] = [1_000, 100_000, 1_000_000];
+
+fn key(i: usize) -> String {
+    format!("key{}", i)
+}
+
+fn store_of(n: usize) -> Store {
+    let mut kvs = skvs::store::new("".to_string());
+    kvs.bulk_load((0..n).map(|i| (key(i), format!("value{}", i))), false).unwrap();
+    kvs
+}
+
+/// `Lcg` is a tiny pseudo-random generator for picking keys; the
+/// benchmarks need a repeatable spread of keys, not good randomness.
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    for &n in &SIZES {
+        let mut kvs = store_of(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            // Only the insert is timed; deleting the key again keeps
+            // the store at n keys.
+            b.iter_custom(|iters| {
+                let mut total = Duration::default();
+                for i in 0..iters {
+                    let k = format!("new{}", i);
+                    let start = Instant::now();
+                    black_box(kvs.insert(k.clone(), "value".to_string()));
+                    total += start.elapsed();
+                    kvs.delete(k);
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+    for &n in &SIZES {
+        let mut kvs = store_of(n);
+        let mut i = 0;
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| {
+                i += 1;
+                kvs.update(key(i % n), format!("value{}", i))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for &n in &SIZES {
+        let kvs = store_of(n);
+        let mut rng = Lcg(n as u64);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| kvs.get(key(rng.below(n))))
+        });
+    }
+    group.finish();
+}
+
+fn bench_delete(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delete");
+    for &n in &SIZES {
+        let mut kvs = store_of(n);
+        let mut rng = Lcg(n as u64);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            // Only the delete is timed; the key is put back afterwards.
+            b.iter_custom(|iters| {
+                let mut total = Duration::default();
+                for _ in 0..iters {
+                    let k = key(rng.below(n));
+                    let start = Instant::now();
+                    black_box(kvs.delete(k.clone()));
+                    total += start.elapsed();
+                    kvs.insert(k, "value".to_string());
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(name).to_str().unwrap().to_string()
+}
+
+fn bench_flush(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flush");
+    group.sample_size(10);
+    for &n in &SIZES {
+        let mut kvs = store_of(n);
+        kvs.path = temp_path("skvs-bench-flush.json");
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| kvs.flush().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    for &n in &SIZES {
+        let mut kvs = store_of(n);
+        kvs.path = temp_path("skvs-bench-load.json");
+        kvs.flush().unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| Store::load(kvs.path.clone()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// `bench_mixed` runs gets and updates against random keys, with
+/// `reads` out of every 100 operations being gets.
+fn bench_mixed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mixed");
+    for &reads in &[50, 90, 99] {
+        for &n in &SIZES {
+            let mut kvs = store_of(n);
+            let mut rng = Lcg(n as u64);
+            let id = BenchmarkId::new(format!("{}% reads", reads), n);
+            group.bench_with_input(id, &n, |b, &n| {
+                b.iter(|| {
+                    let k = key(rng.below(n));
+                    if rng.below(100) < reads {
+                        black_box(kvs.get(k));
+                    } else {
+                        black_box(kvs.update(k, "value".to_string()));
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_update, bench_get, bench_delete,
+                 bench_flush, bench_load, bench_mixed);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! skvs_derive provides `#[derive(StoreModel)]`, which implements
+//! `skvs::store::StoreModel` for a struct with named fields. One field
+//! must be marked `#[store(id)]`; the prefix is given with
+//! `#[store(prefix = "...")]` on the struct, and defaults to the
+//! struct's name in lower case. Field types must implement `ToString`
+//! and `FromStr`.
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use syn::{Attribute, Body, Lit, MetaItem, NestedMetaItem, VariantData};
+
+#[proc_macro_derive(StoreModel, attributes(store))]
+pub fn derive_store_model(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let expanded = impl_store_model(&ast);
+    expanded.parse().unwrap()
+}
+
+/// `store_attrs` returns the items inside each `#[store(...)]`
+/// attribute.
+fn store_attrs(attrs: &[Attribute]) -> Vec<&MetaItem> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if let MetaItem::List(ref name, ref nested) = attr.value {
+            if name == "store" {
+                for item in nested {
+                    match *item {
+                        NestedMetaItem::MetaItem(ref item) => items.push(item),
+                        NestedMetaItem::Literal(_)         => panic!("unexpected literal in #[store]"),
+                    }
+                }
+            }
+        }
+    }
+    items
+}
+
+fn impl_store_model(ast: &syn::DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let fields = match ast.body {
+        Body::Struct(VariantData::Struct(ref fields)) => fields,
+        _                                             => panic!("StoreModel needs a struct with named fields"),
+    };
+
+    let mut prefix = name.to_string().to_lowercase();
+    for item in store_attrs(&ast.attrs) {
+        match *item {
+            MetaItem::NameValue(ref k, Lit::Str(ref v, _)) if k == "prefix" => prefix = v.clone(),
+            _ => panic!("unknown #[store] attribute on {}", name),
+        }
+    }
+
+    let mut id = None;
+    let mut names = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let mut is_id = false;
+        for item in store_attrs(&field.attrs) {
+            match *item {
+                MetaItem::Word(ref w) if w == "id" => is_id = true,
+                _ => panic!("unknown #[store] attribute on {}.{}", name, ident),
+            }
+        }
+
+        if !is_id {
+            names.push(ident);
+        } else if id.is_some() {
+            panic!("{} has more than one #[store(id)] field", name);
+        } else {
+            id = Some(ident);
+        }
+    }
+    let id = match id {
+        Some(id) => id,
+        None     => panic!("{} needs a field marked #[store(id)]", name),
+    };
+
+    let saves: Vec<quote::Tokens> = names.iter().map(|ident| {
+        let key = ident.to_string();
+        quote! { (#key, self.#ident.to_string()) }
+    }).collect();
+    let loads: Vec<quote::Tokens> = names.iter().map(|ident| {
+        let key = ident.to_string();
+        quote! {
+            #ident: ::skvs::store::model::field(store, <#name as ::skvs::store::StoreModel>::key(id, #key))?
+        }
+    }).collect();
+
+    quote! {
+        impl ::skvs::store::StoreModel for #name {
+            fn prefix() -> &'static str {
+                #prefix
+            }
+
+            fn id(&self) -> String {
+                self.#id.to_string()
+            }
+
+            fn fields(&self) -> Vec<(&'static str, String)> {
+                vec![#(#saves),*]
+            }
+
+            fn load(store: &::skvs::store::Store, id: &str)
+                    -> Result<#name, ::skvs::store::ModelError> {
+                Ok(#name {
+                    #id: ::skvs::store::model::parse_id(id)?,
+                    #(#loads,)*
+                })
+            }
+        }
+    }
+}
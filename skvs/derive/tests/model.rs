@@ -0,0 +1,36 @@
+extern crate skvs;
+#[macro_use]
+extern crate skvs_derive;
+
+use skvs::store::{ModelError, StoreModel};
+
+#[derive(Debug, PartialEq, StoreModel)]
+#[store(prefix = "user")]
+struct User {
+    #[store(id)]
+    id: u64,
+    name: String,
+    email: String,
+}
+
+#[derive(Debug, PartialEq, StoreModel)]
+struct Session {
+    #[store(id)]
+    token: String,
+    user: u64,
+}
+
+#[test]
+fn test_derive() {
+    let mut kvs = skvs::store::new("".to_string());
+    let user = User { id: 42, name: "kyle".to_string(), email: "kyle@imap.cc".to_string() };
+    user.save(&mut kvs).unwrap();
+    assert_eq!(kvs.get("user/42/email".to_string()).unwrap(), "kyle@imap.cc");
+    assert_eq!(User::load(&kvs, "42"), Ok(user));
+
+    let session = Session { token: "abc".to_string(), user: 42 };
+    session.save(&mut kvs).unwrap();
+    assert_eq!(kvs.get("session/abc/user".to_string()).unwrap(), "42");
+    assert_eq!(Session::load(&kvs, "abc"), Ok(session));
+    assert_eq!(Session::load(&kvs, "xyz"), Err(ModelError::Missing("session/xyz/user".to_string())));
+}
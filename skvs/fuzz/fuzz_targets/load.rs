@@ -0,0 +1,18 @@
+//! load feeds arbitrary bytes to `Store::from_reader`, which must
+//! return an error for malformed input rather than panicking.
+//!
+//! Run it from the skvs directory with `cargo +nightly fuzz run load`.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate skvs;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut kvs) = skvs::store::Store::from_reader(data) {
+        // Whatever loaded must be usable.
+        let keys: Vec<String> = kvs.values.keys().map(|k| k.to_string()).collect();
+        for k in keys {
+            kvs.update(k, "fuzz".to_string());
+        }
+    }
+});
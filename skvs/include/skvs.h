@@ -0,0 +1,124 @@
+#ifndef SKVS_H
+#define SKVS_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Status is the result of a call through the C API.
+ */
+typedef enum skvs_status {
+  /**
+   * Ok is returned when the call succeeded.
+   */
+  SKVS_STATUS_OK = 0,
+  /**
+   * NotFound is returned by `skvs_get` for a key that isn't in the
+   * store.
+   */
+  SKVS_STATUS_NOT_FOUND = 1,
+  /**
+   * InvalidArgument is returned for a null pointer or a string
+   * that isn't valid UTF-8.
+   */
+  SKVS_STATUS_INVALID_ARGUMENT = 2,
+  /**
+   * Io is returned when reading or writing the store's file fails.
+   */
+  SKVS_STATUS_IO = 3,
+  /**
+   * Locked is returned by `skvs_open` when another process has the
+   * store open.
+   */
+  SKVS_STATUS_LOCKED = 4,
+  /**
+   * Rejected is returned by `skvs_set` when the store's
+   * configuration doesn't allow the write.
+   */
+  SKVS_STATUS_REJECTED = 5,
+  /**
+   * Unrepresentable is returned by `skvs_get` for a value holding
+   * a NUL byte, which can't be passed back as a C string.
+   */
+  SKVS_STATUS_UNREPRESENTABLE = 6,
+} skvs_status;
+
+typedef struct skvs_store skvs_store;
+
+/**
+ * `skvs_open` opens the store at `path`, locking it for writing, and
+ * stores it in `*out`. If there's no file at `path`, a new, empty
+ * store is opened, and is written there when it's flushed.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string, and `out` must be valid
+ * for writing a pointer.
+ */
+skvs_status skvs_open(const char *path, skvs_store **out);
+
+/**
+ * `skvs_get` looks up `key`, storing a copy of its value in `*value`.
+ * The copy must be freed with `skvs_string_free`. If the key isn't
+ * present, `*value` is set to null and `SKVS_STATUS_NOT_FOUND` is
+ * returned.
+ *
+ * # Safety
+ *
+ * `kvs` must come from `skvs_open`, `key` must be a NUL-terminated
+ * string, and `value` must be valid for writing a pointer.
+ */
+skvs_status skvs_get(const skvs_store *kvs, const char *key, char **value);
+
+/**
+ * `skvs_set` sets `key` to `value`, inserting it if it isn't present.
+ *
+ * # Safety
+ *
+ * `kvs` must come from `skvs_open`, and `key` and `value` must be
+ * NUL-terminated strings.
+ */
+skvs_status skvs_set(skvs_store *kvs, const char *key, const char *value);
+
+/**
+ * `skvs_flush` writes the store to its file.
+ *
+ * # Safety
+ *
+ * `kvs` must come from `skvs_open`.
+ */
+skvs_status skvs_flush(skvs_store *kvs);
+
+/**
+ * `skvs_close` releases the store and its lock. It doesn't flush the
+ * store; changes since the last `skvs_flush` are discarded. Passing
+ * null does nothing.
+ *
+ * # Safety
+ *
+ * `kvs` must come from `skvs_open`, and mustn't be used afterwards.
+ */
+void skvs_close(skvs_store *kvs);
+
+/**
+ * `skvs_string_free` frees a string returned by `skvs_get`. Passing
+ * null does nothing.
+ *
+ * # Safety
+ *
+ * `s` must come from `skvs_get`, and mustn't be used afterwards.
+ */
+void skvs_string_free(char *s);
+
+/**
+ * `skvs_last_error` returns a description of the last error on this
+ * thread, or null if there hasn't been one. The string belongs to
+ * the library and is valid until the next call on this thread.
+ */
+const char *skvs_last_error(void);
+
+#endif  /* SKVS_H */
@@ -0,0 +1,212 @@
+//! aio provides an asynchronous interface to the store for use in
+//! async services. Reads and writes only touch memory and complete
+//! immediately; operations that do disk I/O (`load` and `flush`) run
+//! on a separate thread, so they never block the executor.
+//!
+//! The futures returned here only rely on `std::future::Future`, so
+//! they work with any executor, including tokio. From an async
+//! function (in a crate using the 2018 edition or later):
+//!
+//! ```ignore
+//! let kvs = skvs::aio::Store::new("".to_string());
+//! kvs.insert("key".to_string(), "value".to_string()).await;
+//! assert_eq!(kvs.get("key".to_string()).await.unwrap(), "value");
+//! kvs.flush().await.unwrap();
+//! ```
+use store;
+use store::WriteResult;
+use std::future::{ready, Future, Ready};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+struct Shared<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Blocking is a future for work running on its own thread.
+pub struct Blocking<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// `spawn_blocking` runs `f` on a new thread, returning a future for
+/// its result.
+fn spawn_blocking<T, F>(f: F) -> Blocking<T>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static {
+    let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+    let theirs = shared.clone();
+    thread::spawn(move || {
+        let result = f();
+        let mut shared = theirs.lock().unwrap_or_else(|err| err.into_inner());
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    Blocking { shared }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None         => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+/// Store is a handle to a store shared between tasks; clones of a
+/// handle refer to the same store.
+#[derive(Clone, Debug)]
+pub struct Store {
+    inner: Arc<Mutex<store::Store>>,
+}
+
+impl Store {
+    /// `new` returns a handle to a new, empty store.
+    pub fn new(path: String) -> Store {
+        Store::from_store(store::new(path))
+    }
+
+    /// `from_store` wraps an existing store.
+    pub fn from_store(kvs: store::Store) -> Store {
+        Store { inner: Arc::new(Mutex::new(kvs)) }
+    }
+
+    /// `load` reads the store at `path` on a separate thread.
+    pub fn load(path: String) -> Blocking<Result<Store, io::Error>> {
+        spawn_blocking(move || store::Store::load(path).map(Store::from_store))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, store::Store> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// `get` works like `store::Store::get`.
+    pub fn get(&self, k: String) -> Ready<Option<String>> {
+        ready(self.lock().get(k))
+    }
+
+    /// `insert` works like `store::Store::insert`.
+    pub fn insert(&self, k: String, v: String) -> Ready<WriteResult> {
+        ready(self.lock().insert(k, v))
+    }
+
+    /// `update` works like `store::Store::update`.
+    pub fn update(&self, k: String, v: String) -> Ready<WriteResult> {
+        ready(self.lock().update(k, v))
+    }
+
+    /// `delete` works like `store::Store::delete`.
+    pub fn delete(&self, k: String) -> Ready<WriteResult> {
+        ready(self.lock().delete(k))
+    }
+
+    /// `len` returns the number of entries in the store.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// `is_empty` returns true if the store has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `flush` writes the store to disk with
+    /// `store::Store::flush_background`, so other tasks can keep using
+    /// the store while it's written. The outcome is collected into the
+    /// store once the write is done.
+    pub fn flush(&self) -> Blocking<Result<(), io::Error>> {
+        let handle = self.lock().flush_background();
+        let inner = self.inner.clone();
+        spawn_blocking(move || {
+            let result = handle.wait();
+            inner.lock().unwrap_or_else(|err| err.into_inner()).collect_flush();
+            result
+        })
+    }
+
+    /// `with_store` calls `f` with the underlying store, for
+    /// operations without an async wrapper. `f` runs under the
+    /// store's lock, so it shouldn't do anything slow.
+    pub fn with_store<R, F: FnOnce(&mut store::Store) -> R>(&self, f: F) -> R {
+        f(&mut self.lock())
+    }
+}
+
+
+#[cfg(test)]
+fn block_on<F: Future>(f: F) -> F::Output {
+    use std::task::Wake;
+
+    struct Unparker(thread::Thread);
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => return result,
+            Poll::Pending       => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn test_aio() {
+    use store::WriteResult::*;
+
+    let kvs = Store::new("".to_string());
+    let other = kvs.clone();
+    assert_eq!(block_on(kvs.insert("a".to_string(), "1".to_string())), Inserted);
+    assert_eq!(block_on(other.update("a".to_string(), "2".to_string())), Updated);
+    assert_eq!(block_on(kvs.get("a".to_string())), Some("2".to_string()));
+    assert_eq!(kvs.len(), 1);
+
+    // The store has no path, so flushing is a no-op.
+    block_on(kvs.flush()).unwrap();
+    assert_eq!(block_on(kvs.delete("a".to_string())), Updated);
+    assert!(other.is_empty());
+}
+
+#[test]
+fn test_aio_flush() {
+    use testutil::TempStore;
+
+    let path = TempStore::new("aio");
+    let kvs = Store::new(path.to_string());
+    block_on(kvs.insert("a".to_string(), "1".to_string()));
+    block_on(kvs.flush()).unwrap();
+
+    kvs.with_store(|kvs| {
+        assert_eq!(kvs.counters().flushes(), 1);
+        assert!(kvs.write_error.is_none());
+        assert!(kvs.metrics.last_write > store::Timestamp::default());
+
+        // The store knows the file is its own.
+        assert_eq!(kvs.reload_if_changed().unwrap(), None);
+    });
+}
+
+#[test]
+fn test_spawn_blocking() {
+    let f = spawn_blocking(|| {
+        thread::sleep(std::time::Duration::from_millis(10));
+        42
+    });
+    assert_eq!(block_on(f), 42);
+}
@@ -0,0 +1,332 @@
+//! skvsctl is an administration tool that operates directly on a
+//! store file.
+extern crate rustyline;
+extern crate skvs;
+
+mod repl;
+
+use skvs::store::entry::Entry;
+use skvs::store::{self, ConflictPolicy, Format, OpenOptions, RedactionPolicy, Store};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::process;
+
+const USAGE: &str = "Usage: skvsctl [-f FILE] [-r PREFIX]... COMMAND [ARGS...]
+
+Commands:
+    get KEY                      print the value stored under KEY
+    set KEY VALUE                store VALUE under KEY
+    del KEY                      delete KEY
+    list [PREFIX]                list the keys, optionally only those
+                                 starting with PREFIX
+    glob PATTERN                 list the keys matching PATTERN, where
+                                 * and ** match within and across
+                                 '/'-separated segments
+    dump [FORMAT]                write the store to standard output as
+                                 json (JSON lines, the default), csv,
+                                 env, etcd, or consul
+    import FORMAT FILE [POLICY]  import FILE, which is json, csv, env,
+                                 or a redis AOF; POLICY decides what
+                                 happens to existing keys and is skip,
+                                 overwrite (the default), or error
+    diff OTHER                   list the keys added, removed, and
+                                 changed in OTHER relative to the store
+    patch FILE                   apply the JSON patch in FILE, if all
+                                 of its preconditions hold
+    sync OTHER                   merge the store file and OTHER into
+                                 each other, keeping the newest entries
+    compact                      rewrite the store file
+    verify                       check the store file for consistency
+    stats                        print the store's metrics, and tables
+                                 of its keys, value sizes, and entries
+    repl                         start an interactive shell on the
+                                 store, with line editing and tab
+                                 completion of commands and keys
+
+FILE defaults to store.json. Values of keys starting with any PREFIX
+given with -r, and secret values, are shown as *** by dump, diff, and
+repl.
+";
+
+fn die(msg: &str) -> ! {
+    eprintln!("skvsctl: {}", msg);
+    process::exit(1)
+}
+
+fn usage() -> ! {
+    eprint!("{}", USAGE);
+    process::exit(2)
+}
+
+/// `open` loads the store at `path`, locking it for writing. If
+/// `create` is true and there is no such file, a new, empty store is
+/// returned instead.
+fn open(path: &str, create: bool) -> Store {
+    opened(path, Store::options().path(path).create(create))
+}
+
+/// `open_readonly` loads the store at `path` for reading, which other
+/// readers may do at the same time.
+fn open_readonly(path: &str) -> Store {
+    opened(path, Store::options().path(path).read_only(true))
+}
+
+fn opened(path: &str, options: OpenOptions) -> Store {
+    match options.open() {
+        Ok(kvs)  => kvs,
+        Err(err) => die(&format!("{}: {}", path, err)),
+    }
+}
+
+fn save(kvs: &mut Store) {
+    if let Err(err) = kvs.flush() {
+        die(&format!("{}: {}", kvs.path, err));
+    }
+}
+
+fn file_size(path: &str) -> u64 {
+    match fs::metadata(path) {
+        Ok(md) => md.len(),
+        Err(_) => 0,
+    }
+}
+
+fn dump(kvs: &Store, format: &str) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut w = stdout.lock();
+    match format {
+        "json"   => kvs.export(Format::JsonLines, &mut w),
+        "csv"    => kvs.export(Format::Csv, &mut w),
+        "env"    => kvs.export(Format::DotEnv, &mut w),
+        "etcd"   => kvs.export_etcd(&mut w),
+        "consul" => kvs.export_consul(&mut w),
+        _        => die(&format!("unknown format {}", format)),
+    }
+}
+
+/// `shown` returns the value of `ent`, the entry for `k`, as it should
+/// be printed: `REDACTED` if `redaction` covers it.
+fn shown<'a>(redaction: &RedactionPolicy, k: &str, ent: &'a Entry) -> &'a str {
+    if redaction.redacts(k, ent) { store::REDACTED } else { &ent.value }
+}
+
+fn import(kvs: &mut Store, format: &str, path: &str, policy: &str) {
+    let policy = match policy {
+        "skip"      => ConflictPolicy::Skip,
+        "overwrite" => ConflictPolicy::Overwrite,
+        "error"     => ConflictPolicy::Error,
+        _           => die(&format!("unknown conflict policy {}", policy)),
+    };
+
+    let file = match File::open(path) {
+        Ok(f)    => f,
+        Err(err) => die(&format!("{}: {}", path, err)),
+    };
+
+    let format = match format {
+        "json"  => Format::JsonLines,
+        "csv"   => Format::Csv,
+        "env"   => Format::DotEnv,
+        "redis" => {
+            match kvs.import_redis_aof(file) {
+                Ok(report) => {
+                    println!("inserted {}, updated {}", report.inserted, report.updated);
+                    for (k, reason) in report.skipped_keys {
+                        println!("skipped key {}: {}", k, reason);
+                    }
+                    for (cmd, n) in report.skipped_commands {
+                        println!("skipped {} {} command(s)", n, cmd);
+                    }
+                },
+                Err(err)   => die(&format!("{}: {}", path, err)),
+            }
+            return;
+        },
+        _       => die(&format!("unknown format {}", format)),
+    };
+
+    match kvs.import(format, file, policy) {
+        Ok(report) => println!("inserted {}, updated {}, skipped {}",
+                               report.inserted, report.updated, report.skipped),
+        Err(err)   => die(&format!("{}: {}", path, err)),
+    }
+}
+
+/// `verify` checks the invariants of a loaded store, returning a
+/// description of each violation.
+fn verify(kvs: &Store) -> Vec<String> {
+    let mut problems = Vec::new();
+    if kvs.metrics.size != kvs.len() {
+        problems.push(format!("metrics report {} entries, but there are {}",
+                              kvs.metrics.size, kvs.len()));
+    }
+
+    let mut keys: Vec<&str> = kvs.values.keys().map(|k| &**k).collect();
+    keys.sort();
+    for k in keys {
+        let ent = &kvs.values[k];
+        if ent.version < 1 {
+            problems.push(format!("{}: invalid version {}", k, ent.version));
+        }
+        if ent.time.nanos <= 0 {
+            problems.push(format!("{}: invalid timestamp {:?}", k, ent.time));
+        }
+    }
+    problems
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut path = "store.json".to_string();
+    let mut redaction = RedactionPolicy::new();
+    while !args.is_empty() && args[0].starts_with('-') {
+        if args.len() < 2 {
+            usage();
+        }
+        match args[0].as_str() {
+            "-f" | "--file"   => path = args[1].clone(),
+            "-r" | "--redact" => redaction = redaction.prefix(&args[1]),
+            _                 => usage(),
+        }
+        args.drain(..2);
+    }
+
+    if args.is_empty() {
+        usage();
+    }
+    let cmd = args.remove(0);
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    match (cmd.as_str(), &args[..]) {
+        ("get", &[k]) => {
+            match open_readonly(&path).get(k.to_string()) {
+                Some(v) => println!("{}", v),
+                None    => die(&format!("{}: key not found", k)),
+            }
+        },
+        ("set", &[k, v]) => {
+            let mut kvs = open(&path, true);
+            match kvs.update(k.to_string(), v.to_string()) {
+                store::WriteResult::Inserted | store::WriteResult::Updated => (),
+                wr                                                       => die(&format!("{}: {}", k, wr.to_string())),
+            }
+            save(&mut kvs);
+        },
+        ("del", &[k]) => {
+            let mut kvs = open(&path, false);
+            let wr = kvs.delete(k.to_string());
+            if wr == store::WriteResult::DoesNotExist {
+                die(&format!("{}: {}", k, wr.to_string()));
+            }
+            save(&mut kvs);
+        },
+        ("list", rest) if rest.len() <= 1 => {
+            let kvs = open_readonly(&path);
+            let prefix = rest.first().cloned().unwrap_or("");
+            let mut keys: Vec<&str> = kvs.values.keys()
+                .map(|k| &**k)
+                .filter(|k| k.starts_with(prefix))
+                .collect();
+            keys.sort();
+            for k in keys {
+                println!("{}", k);
+            }
+        },
+        ("glob", &[pattern]) => {
+            match open_readonly(&path).scan_glob(pattern) {
+                Ok(selected) => for (k, _) in selected {
+                    println!("{}", k);
+                },
+                Err(err)     => die(&err.to_string()),
+            }
+        },
+        ("dump", rest) if rest.len() <= 1 => {
+            let mut kvs = open_readonly(&path);
+            kvs.config.redaction = redaction;
+            if let Err(err) = dump(&kvs, rest.first().cloned().unwrap_or("json")) {
+                die(&err.to_string());
+            }
+        },
+        ("import", rest) if rest.len() == 2 || rest.len() == 3 => {
+            let mut kvs = open(&path, true);
+            import(&mut kvs, rest[0], rest[1], rest.get(2).cloned().unwrap_or("overwrite"));
+            save(&mut kvs);
+        },
+        ("diff", &[other]) => {
+            let diff = match open_readonly(&path).diff(&open_readonly(other)) {
+                Ok(diff) => diff,
+                Err(err) => die(&err.to_string()),
+            };
+            for (k, ent) in &diff.added {
+                println!("+ {} = {}", k, shown(&redaction, k, ent));
+            }
+            for (k, ent) in &diff.removed {
+                println!("- {} = {}", k, shown(&redaction, k, ent));
+            }
+            for change in &diff.changed {
+                let k = &change.key;
+                println!("~ {} = {} -> {}", k, shown(&redaction, k, &change.old), shown(&redaction, k, &change.new));
+            }
+            if !diff.is_empty() {
+                process::exit(1);
+            }
+        },
+        ("patch", &[file]) => {
+            let mut kvs = open(&path, false);
+            let f = match File::open(file) {
+                Ok(f)    => f,
+                Err(err) => die(&format!("{}: {}", file, err)),
+            };
+            match kvs.apply_patch(f) {
+                Ok(n)    => println!("applied {} operation(s)", n),
+                Err(err) => die(&format!("{}: {}", file, err)),
+            }
+            save(&mut kvs);
+        },
+        ("sync", &[other]) => {
+            let mut kvs = open(&path, false);
+            let mut remote = open(other, false);
+            let (here, there) = store::sync(&mut kvs, &mut remote);
+            for (name, report) in &[(&path[..], here), (other, there)] {
+                println!("{}: {} added, {} overwritten", name,
+                         report.added.len(), report.overwritten.len());
+                for k in &report.overwritten {
+                    println!("    overwrote {}", k);
+                }
+            }
+            save(&mut kvs);
+            save(&mut remote);
+        },
+        ("compact", &[]) => {
+            let before = file_size(&path);
+            let mut kvs = open(&path, false);
+            save(&mut kvs);
+            println!("{}: {} -> {} bytes", path, before, file_size(&path));
+        },
+        ("verify", &[]) => {
+            let problems = verify(&open_readonly(&path));
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                process::exit(1);
+            }
+            println!("{}: ok", path);
+        },
+        ("stats", &[]) => {
+            let kvs = open_readonly(&path);
+            print!("{}", kvs.report(10));
+
+            println!();
+            print!("{}", kvs.analyze());
+        },
+        ("repl", &[]) => {
+            if let Err(err) = repl::run(&path, redaction) {
+                die(&format!("{}: {}", path, err));
+            }
+        },
+        _ => usage(),
+    }
+}
@@ -0,0 +1,391 @@
+//! repl implements `skvsctl repl`, a line-oriented shell for poking
+//! at a store file. Lines are read with rustyline, which provides line
+//! editing, the arrow keys for earlier lines, and tab completion of
+//! commands and of the keys they take.
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use skvs::store::{self, RedactionPolicy, Store};
+use skvs::store::WriteResult::*;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+pub const HELP: &str = "Commands:
+    get KEY          print the value stored under KEY
+    set KEY VALUE    store VALUE (the rest of the line) under KEY
+    del KEY          delete KEY
+    scan [PREFIX]    print the entries whose keys start with PREFIX,
+                     redacting sensitive values
+    history          list the commands entered so far
+    .save [FILE]     write the store to disk, optionally to FILE
+    .load [FILE]     reload the store from disk, discarding changes
+    .help            print this message
+    .quit            leave the shell
+";
+
+/// COMMANDS are the commands offered by tab completion.
+const COMMANDS: &[&str] = &["get", "set", "del", "scan", "history",
+                            ".save", ".load", ".help", ".quit"];
+
+/// KEYED are the commands whose first argument is a key.
+const KEYED: &[&str] = &["get", "set", "del", "scan"];
+
+/// `complete` returns where the word under the cursor at `pos` in
+/// `line` starts, and the words that could replace it: the commands
+/// starting with it if it's the first word, or the `keys` starting
+/// with it if it's the key a command takes.
+fn complete(keys: &[String], line: &str, pos: usize) -> (usize, Vec<String>) {
+    let line = &line[..pos];
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &line[start..];
+    let before: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let candidates: Vec<String> = match before[..] {
+        []                            => COMMANDS.iter()
+            .filter(|cmd| cmd.starts_with(word))
+            .map(|cmd| cmd.to_string())
+            .collect(),
+        [cmd] if KEYED.contains(&cmd) => keys.iter()
+            .filter(|k| k.starts_with(word))
+            .cloned()
+            .collect(),
+        _                             => Vec::new(),
+    };
+    (start, candidates)
+}
+
+/// Completion completes lines read by rustyline; it holds the store's
+/// keys as of the last prompt.
+struct Completion {
+    keys: Vec<String>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.keys, line, pos))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// Repl holds the state of an interactive session.
+pub struct Repl {
+    kvs: Store,
+    history: Vec<String>,
+    dirty: bool,
+    redaction: RedactionPolicy,
+}
+
+fn load(path: &str) -> Result<Store, io::Error> {
+    if !Path::new(path).exists() {
+        let mut kvs = store::new(path.to_string());
+        kvs.lock()?;
+        return Ok(kvs);
+    }
+
+    let mut kvs = Store::load(path.to_string())?;
+    kvs.path = path.to_string();
+    Ok(kvs)
+}
+
+impl Repl {
+    /// `new` starts a session on the store at `path`, which is
+    /// created on the first `.save` if it doesn't exist.
+    pub fn new(path: &str) -> Result<Repl, io::Error> {
+        Ok(Repl { kvs: load(path)?, history: Vec::new(), dirty: false, redaction: RedactionPolicy::new() })
+    }
+
+    /// `set_redaction` sets the policy used to redact values shown by
+    /// `scan`; it's kept across `.load`.
+    pub fn set_redaction(&mut self, policy: RedactionPolicy) {
+        self.kvs.config.redaction = policy.clone();
+        self.redaction = policy;
+    }
+
+    /// `keys` returns the store's keys, sorted.
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.kvs.values.keys().map(|k| k.to_string()).collect();
+        keys.sort();
+        keys
+    }
+
+    /// `exec` runs a single command line, writing any output to
+    /// `out`. It returns false once the session should end.
+    pub fn exec<W: Write>(&mut self, line: &str, out: &mut W) -> Result<bool, io::Error> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(true);
+        }
+        if line != "history" {
+            self.history.push(line.to_string());
+        }
+
+        let (cmd, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim_start()),
+            None    => (line, ""),
+        };
+        let (arg, value) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim_start()),
+            None    => (rest, ""),
+        };
+
+        match (cmd, arg.is_empty(), value.is_empty()) {
+            ("get", false, true) => {
+                match self.kvs.shown_value(arg) {
+                    Some(v) => writeln!(out, "{}", v)?,
+                    None    => writeln!(out, "(not found)")?,
+                }
+            },
+            ("set", false, false) => {
+                let wr = self.kvs.update(arg.to_string(), value.to_string());
+                self.dirty = true;
+                writeln!(out, "{}", wr.to_string())?;
+            },
+            ("del", false, true) => {
+                let wr = self.kvs.delete(arg.to_string());
+                if wr != DoesNotExist {
+                    self.dirty = true;
+                }
+                writeln!(out, "{}", wr.to_string())?;
+            },
+            ("scan", _, true) => {
+                let mut keys: Vec<&str> = self.kvs.values.keys()
+                    .map(|k| &**k)
+                    .filter(|k| k.starts_with(arg))
+                    .collect();
+                keys.sort();
+                for k in keys {
+                    writeln!(out, "{} = {}", k, self.kvs.shown_value(k).unwrap_or_default())?;
+                }
+            },
+            ("history", true, true) => {
+                for (i, cmd) in self.history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", i + 1, cmd)?;
+                }
+            },
+            (".save", _, true) => {
+                if !arg.is_empty() {
+                    if let Err(err) = self.kvs.set_path(arg.to_string()) {
+                        writeln!(out, "{}: {}", arg, err)?;
+                        return Ok(true);
+                    }
+                }
+                match self.kvs.flush() {
+                    Ok(_)    => {
+                        self.dirty = false;
+                        writeln!(out, "saved to {}", self.kvs.path)?;
+                    },
+                    Err(err) => writeln!(out, "{}: {}", self.kvs.path, err)?,
+                }
+            },
+            (".load", _, true) => {
+                let path = if arg.is_empty() { self.kvs.path.clone() } else { arg.to_string() };
+                // The store's own lock would keep it from being loaded
+                // again, so it's given up first, and taken back if the
+                // load fails.
+                self.kvs.unlock();
+                match load(&path) {
+                    Ok(kvs)  => {
+                        self.kvs = kvs;
+                        self.kvs.config.redaction = self.redaction.clone();
+                        self.dirty = false;
+                        writeln!(out, "loaded {} entries from {}", self.kvs.len(), path)?;
+                    },
+                    Err(err) => {
+                        writeln!(out, "{}: {}", path, err)?;
+                        if let Err(err) = self.kvs.lock() {
+                            writeln!(out, "{}: {}", self.kvs.path, err)?;
+                        }
+                    },
+                }
+            },
+            (".help", _, _) => write!(out, "{}", HELP)?,
+            (".quit", _, _) | (".exit", _, _) => {
+                if self.dirty {
+                    writeln!(out, "discarding unsaved changes")?;
+                }
+                return Ok(false);
+            },
+            _ => writeln!(out, "invalid command; try .help")?,
+        }
+
+        Ok(true)
+    }
+}
+
+/// `run` reads commands from standard input until `.quit` or the end
+/// of input, redacting values according to `redaction`.
+pub fn run(path: &str, redaction: RedactionPolicy) -> Result<(), io::Error> {
+    let mut repl = Repl::new(path)?;
+    repl.set_redaction(redaction);
+    let mut editor: Editor<Completion, DefaultHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(Completion { keys: Vec::new() }));
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    loop {
+        if let Some(completion) = editor.helper_mut() {
+            completion.keys = repl.keys();
+        }
+
+        let line = match editor.readline("skvs> ") {
+            Ok(line)                        => line,
+            // ^C abandons the line being edited, as in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof)         => {
+                writeln!(out)?;
+                repl.exec(".quit", &mut out)?;
+                return Ok(());
+            },
+            Err(err)                        => return Err(readline_error(err)),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        if !repl.exec(&line, &mut out)? {
+            return Ok(());
+        }
+    }
+}
+
+fn readline_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err                    => io::Error::other(err.to_string()),
+    }
+}
+
+#[test]
+fn test_repl() {
+    let mut repl = Repl::new("").unwrap();
+    let mut out: Vec<u8> = Vec::new();
+
+    for line in &["set greeting hello, world", "get greeting", "get missing",
+                  "set other 1", "scan gr", "del other", "bogus", "history"] {
+        assert!(repl.exec(line, &mut out).unwrap());
+    }
+    assert!(!repl.exec(".quit", &mut out).unwrap());
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "new entry inserted");
+    assert_eq!(lines[1], "hello, world");
+    assert_eq!(lines[2], "(not found)");
+    assert_eq!(lines[4], "greeting = hello, world");
+    assert_eq!(lines[5], "entry was updated");
+    assert_eq!(lines[6], "invalid command; try .help");
+    assert_eq!(lines[7], "   1  set greeting hello, world");
+    assert_eq!(lines.last(), Some(&"discarding unsaved changes"));
+}
+
+#[test]
+fn test_repl_redaction() {
+    let mut repl = Repl::new("").unwrap();
+    repl.set_redaction(RedactionPolicy::new().prefix("auth/"));
+    let mut out: Vec<u8> = Vec::new();
+
+    for line in &["set auth/token abc123", "set name skvs", "scan", "get auth/token", "get name"] {
+        assert!(repl.exec(line, &mut out).unwrap());
+    }
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(&lines[2..], &["auth/token = ***", "name = skvs", "***", "skvs"]);
+}
+
+/// `temp_path` returns a path for a test's store file, with any files
+/// left from a previous run removed.
+#[cfg(test)]
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("skvsctl-{}-{}.json", name, std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    remove_store(&path);
+    path
+}
+
+#[cfg(test)]
+fn remove_store(path: &str) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(format!("{}.lock", path));
+}
+
+#[test]
+fn test_repl_load() {
+    let path = temp_path("load");
+    let mut repl = Repl::new(&path).unwrap();
+    let mut out: Vec<u8> = Vec::new();
+
+    for line in &["set a 1", ".save", "set b 2", ".load"] {
+        assert!(repl.exec(line, &mut out).unwrap());
+    }
+    assert!(repl.exec(".load missing/store.json", &mut out).unwrap());
+    assert!(repl.exec("set c 3", &mut out).unwrap());
+    assert!(repl.exec(".save", &mut out).unwrap());
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[3], format!("loaded 1 entries from {}", path));
+    assert!(lines[4].starts_with("missing/store.json: "));
+    assert_eq!(lines[6], format!("saved to {}", path));
+    drop(repl);
+    remove_store(&path);
+}
+
+#[test]
+fn test_repl_save_as() {
+    let path = temp_path("save");
+    let other = temp_path("save-as");
+    let mut repl = Repl::new(&path).unwrap();
+    let mut out: Vec<u8> = Vec::new();
+
+    // A file someone else has locked isn't saved to.
+    let held = store::lock::Lock::acquire(&other, true, None).unwrap();
+    assert!(repl.exec("set a 1", &mut out).unwrap());
+    assert!(repl.exec(&format!(".save {}", other), &mut out).unwrap());
+    assert!(std::fs::metadata(&other).is_err());
+    drop(held);
+
+    assert!(repl.exec(&format!(".save {}", other), &mut out).unwrap());
+    assert!(std::fs::metadata(&other).is_ok());
+    assert!(store::lock::Lock::acquire(&path, true, None).is_ok());
+    assert!(store::lock::Lock::acquire(&other, true, None).is_err());
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert!(lines[1].contains("is locked by another process"));
+    assert_eq!(lines[2], format!("saved to {}", other));
+    drop(repl);
+    remove_store(&path);
+    remove_store(&other);
+}
+
+#[test]
+fn test_repl_complete() {
+    let keys: Vec<String> = vec!["app/name".to_string(), "app/port".to_string(), "auth/token".to_string()];
+
+    assert_eq!(complete(&keys, "g", 1), (0, vec!["get".to_string()]));
+    assert_eq!(complete(&keys, ".s", 2), (0, vec![".save".to_string()]));
+    assert_eq!(complete(&keys, "get app/", 8), (4, vec!["app/name".to_string(), "app/port".to_string()]));
+    assert_eq!(complete(&keys, "set  au", 7), (5, vec!["auth/token".to_string()]));
+    assert_eq!(complete(&keys, "get a", 3), (0, vec!["get".to_string()]));
+    assert!(complete(&keys, "set app/name a", 14).1.is_empty());
+    assert!(complete(&keys, "history a", 9).1.is_empty());
+
+    let mut repl = Repl::new("").unwrap();
+    repl.exec("set b 1", &mut Vec::new()).unwrap();
+    repl.exec("set a 2", &mut Vec::new()).unwrap();
+    assert_eq!(repl.keys(), vec!["a".to_string(), "b".to_string()]);
+}
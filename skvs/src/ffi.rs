@@ -0,0 +1,273 @@
+//! ffi is a C API for embedding a store in programs written in other
+//! languages. It's built with the `cdylib` feature:
+//!
+//! ```text
+//! cargo rustc --release --features cdylib --crate-type cdylib
+//! ```
+//!
+//! and declared in `include/skvs.h`, which is generated from this
+//! module with `cbindgen --config cbindgen.toml --output include/skvs.h`.
+//!
+//! Every function returns an `skvs_status`. Anything other than
+//! `SKVS_STATUS_OK` or `SKVS_STATUS_NOT_FOUND` leaves a description of
+//! the error for `skvs_last_error`, which is kept per thread.
+//!
+//! The store returned by `skvs_open` belongs to the caller until it's
+//! passed to `skvs_close`; it may be used from one thread at a time.
+//! Strings passed in are borrowed for the length of the call. Strings
+//! handed out by `skvs_get` belong to the caller, and must be freed
+//! with `skvs_string_free` rather than `free`.
+//!
+//! From Python, with ctypes:
+//!
+//! ```text
+//! lib = ctypes.CDLL("libskvs.so")
+//! kvs = ctypes.c_void_p()
+//! lib.skvs_open(b"store.json", ctypes.byref(kvs))
+//! lib.skvs_set(kvs, b"name", b"skvs")
+//! value = ctypes.c_void_p()
+//! if lib.skvs_get(kvs, b"name", ctypes.byref(value)) == 0:
+//!     print(ctypes.string_at(value).decode())
+//!     lib.skvs_string_free(value)
+//! lib.skvs_flush(kvs)
+//! lib.skvs_close(kvs)
+//! ```
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::ptr;
+use store::{self, Store, StoreLocked, WriteResult};
+
+/// Status is the result of a call through the C API.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// Ok is returned when the call succeeded.
+    Ok = 0,
+
+    /// NotFound is returned by `skvs_get` for a key that isn't in the
+    /// store.
+    NotFound = 1,
+
+    /// InvalidArgument is returned for a null pointer or a string
+    /// that isn't valid UTF-8.
+    InvalidArgument = 2,
+
+    /// Io is returned when reading or writing the store's file fails.
+    Io = 3,
+
+    /// Locked is returned by `skvs_open` when another process has the
+    /// store open.
+    Locked = 4,
+
+    /// Rejected is returned by `skvs_set` when the store's
+    /// configuration doesn't allow the write.
+    Rejected = 5,
+
+    /// Unrepresentable is returned by `skvs_get` for a value holding
+    /// a NUL byte, which can't be passed back as a C string.
+    Unrepresentable = 6,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// `fail` records `message` for `skvs_last_error`, returning `status`.
+fn fail(status: Status, message: String) -> Status {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+/// `io_failure` records `err` and returns the status for it.
+fn io_failure(err: io::Error) -> Status {
+    let status = if StoreLocked::from_io(&err).is_some() { Status::Locked } else { Status::Io };
+    fail(status, err.to_string())
+}
+
+/// `borrow_str` borrows the C string at `s` as a `&str`.
+unsafe fn borrow_str<'a>(s: *const c_char, what: &str) -> Result<&'a str, Status> {
+    if s.is_null() {
+        return Err(fail(Status::InvalidArgument, format!("{} is null", what)));
+    }
+    CStr::from_ptr(s).to_str()
+        .map_err(|_| fail(Status::InvalidArgument, format!("{} isn't valid UTF-8", what)))
+}
+
+/// `skvs_open` opens the store at `path`, locking it for writing, and
+/// stores it in `*out`. If there's no file at `path`, a new, empty
+/// store is opened, and is written there when it's flushed.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string, and `out` must be valid
+/// for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_open(path: *const c_char, out: *mut *mut Store) -> Status {
+    let path = match borrow_str(path, "path") {
+        Ok(path) => path,
+        Err(st)  => return st,
+    };
+    if out.is_null() {
+        return fail(Status::InvalidArgument, "out is null".to_string());
+    }
+
+    let result = if Path::new(path).exists() {
+        Store::load(path.to_string())
+    } else {
+        let mut kvs = store::new(path.to_string());
+        kvs.lock().map(|_| kvs)
+    };
+    match result {
+        Ok(mut kvs) => {
+            kvs.path = path.to_string();
+            *out = Box::into_raw(Box::new(kvs));
+            Status::Ok
+        },
+        Err(err)    => io_failure(err),
+    }
+}
+
+/// `skvs_get` looks up `key`, storing a copy of its value in `*value`.
+/// The copy must be freed with `skvs_string_free`. If the key isn't
+/// present, `*value` is set to null and `SKVS_STATUS_NOT_FOUND` is
+/// returned.
+///
+/// # Safety
+///
+/// `kvs` must come from `skvs_open`, `key` must be a NUL-terminated
+/// string, and `value` must be valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_get(kvs: *const Store, key: *const c_char, value: *mut *mut c_char) -> Status {
+    let key = match borrow_str(key, "key") {
+        Ok(key) => key,
+        Err(st) => return st,
+    };
+    if kvs.is_null() || value.is_null() {
+        return fail(Status::InvalidArgument, "store or value is null".to_string());
+    }
+
+    *value = ptr::null_mut();
+    match (*kvs).get(key.to_string()) {
+        Some(v) => match CString::new(v) {
+            Ok(v)  => {
+                *value = v.into_raw();
+                Status::Ok
+            },
+            Err(_) => fail(Status::Unrepresentable, format!("{}: value holds a NUL byte", key)),
+        },
+        None    => Status::NotFound,
+    }
+}
+
+/// `skvs_set` sets `key` to `value`, inserting it if it isn't present.
+///
+/// # Safety
+///
+/// `kvs` must come from `skvs_open`, and `key` and `value` must be
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_set(kvs: *mut Store, key: *const c_char, value: *const c_char) -> Status {
+    let (key, value) = match (borrow_str(key, "key"), borrow_str(value, "value")) {
+        (Ok(key), Ok(value)) => (key, value),
+        (Err(st), _)         => return st,
+        (_, Err(st))         => return st,
+    };
+    if kvs.is_null() {
+        return fail(Status::InvalidArgument, "store is null".to_string());
+    }
+
+    match (*kvs).update(key.to_string(), value.to_string()) {
+        WriteResult::Inserted | WriteResult::Updated | WriteResult::AlreadyExists => Status::Ok,
+        wr => fail(Status::Rejected, format!("{}: {}", key, wr.to_string())),
+    }
+}
+
+/// `skvs_flush` writes the store to its file.
+///
+/// # Safety
+///
+/// `kvs` must come from `skvs_open`.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_flush(kvs: *mut Store) -> Status {
+    if kvs.is_null() {
+        return fail(Status::InvalidArgument, "store is null".to_string());
+    }
+    match (*kvs).flush() {
+        Ok(())   => Status::Ok,
+        Err(err) => io_failure(err),
+    }
+}
+
+/// `skvs_close` releases the store and its lock. It doesn't flush the
+/// store; changes since the last `skvs_flush` are discarded. Passing
+/// null does nothing.
+///
+/// # Safety
+///
+/// `kvs` must come from `skvs_open`, and mustn't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_close(kvs: *mut Store) {
+    if !kvs.is_null() {
+        drop(Box::from_raw(kvs));
+    }
+}
+
+/// `skvs_string_free` frees a string returned by `skvs_get`. Passing
+/// null does nothing.
+///
+/// # Safety
+///
+/// `s` must come from `skvs_get`, and mustn't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn skvs_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// `skvs_last_error` returns a description of the last error on this
+/// thread, or null if there hasn't been one. The string belongs to
+/// the library and is valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn skvs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None              => ptr::null(),
+    })
+}
+
+
+#[test]
+fn test_ffi() {
+    use testutil::TempStore;
+    let path = TempStore::new("ffi");
+    let path = CString::new(path.to_string()).unwrap();
+    let key = CString::new("name").unwrap();
+    unsafe {
+        let mut kvs = ptr::null_mut();
+        assert_eq!(skvs_open(path.as_ptr(), &mut kvs), Status::Ok);
+        let mut other = ptr::null_mut();
+        assert_eq!(skvs_open(path.as_ptr(), &mut other), Status::Locked);
+        assert!(!skvs_last_error().is_null());
+
+        let mut value = ptr::null_mut();
+        assert_eq!(skvs_get(kvs, key.as_ptr(), &mut value), Status::NotFound);
+        assert!(value.is_null());
+        assert_eq!(skvs_set(kvs, key.as_ptr(), CString::new("skvs").unwrap().as_ptr()), Status::Ok);
+        assert_eq!(skvs_get(kvs, key.as_ptr(), &mut value), Status::Ok);
+        assert_eq!(CStr::from_ptr(value).to_str().unwrap(), "skvs");
+        skvs_string_free(value);
+
+        assert_eq!(skvs_set(kvs, ptr::null(), key.as_ptr()), Status::InvalidArgument);
+        assert_eq!(CStr::from_ptr(skvs_last_error()).to_str().unwrap(), "key is null");
+        (*kvs).config.max_value_bytes = Some(2);
+        assert_eq!(skvs_set(kvs, key.as_ptr(), key.as_ptr()), Status::Rejected);
+
+        assert_eq!(skvs_flush(kvs), Status::Ok);
+        skvs_close(kvs);
+    }
+}
@@ -0,0 +1,16 @@
+//! skvs is a simple key-value store that persists to disk. The
+//! `store` module contains the store itself; the binaries in this
+//! crate build on top of it. With the `cdylib` feature, `ffi` exposes
+//! the store to C.
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+
+pub mod aio;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod store;
+#[cfg(test)]
+mod testutil;
@@ -1,8 +1,3 @@
-#[macro_use]
-extern crate serde_derive;
-
-pub mod store;
-
 fn main() {
     panic!("not ready yet")
 }
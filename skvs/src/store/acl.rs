@@ -0,0 +1,181 @@
+//! acl implements access control lists mapping identities to the key
+//! prefixes they may read, write, or delete. The ACL is persisted with
+//! the store; access is checked through the `Access` view returned by
+//! `Store::access`, which is what a server should hand out once it
+//! has authenticated a client.
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::collections::HashMap;
+
+/// Op is an operation that can be granted on a prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Op {
+    Read,
+    Write,
+    Delete,
+}
+
+/// Rule grants a set of operations on the keys starting with
+/// `prefix`; the empty prefix covers every key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub prefix: String,
+    pub ops: Vec<Op>,
+}
+
+/// Acl holds the rules for each identity. Anything not explicitly
+/// granted is denied, including everything for unknown identities.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Acl {
+    rules: HashMap<String, Vec<Rule>>,
+}
+
+impl Acl {
+    /// `new` returns an ACL that denies everything.
+    pub fn new() -> Acl {
+        Acl { rules: HashMap::new() }
+    }
+
+    /// `grant` allows `identity` to perform `ops` on keys starting
+    /// with `prefix`, in addition to anything granted before.
+    pub fn grant(&mut self, identity: &str, prefix: &str, ops: &[Op]) {
+        let rules = self.rules.entry(identity.to_string()).or_default();
+        match rules.iter_mut().find(|r| r.prefix == prefix) {
+            Some(rule) => {
+                for op in ops {
+                    if !rule.ops.contains(op) {
+                        rule.ops.push(*op);
+                    }
+                }
+            },
+            None       => rules.push(Rule { prefix: prefix.to_string(), ops: ops.to_vec() }),
+        }
+    }
+
+    /// `revoke` removes the rule for `prefix` from `identity`,
+    /// returning true if there was one.
+    pub fn revoke(&mut self, identity: &str, prefix: &str) -> bool {
+        let rules = match self.rules.get_mut(identity) {
+            Some(rules) => rules,
+            None        => return false,
+        };
+
+        let before = rules.len();
+        rules.retain(|r| r.prefix != prefix);
+        before != rules.len()
+    }
+
+    /// `rules` returns the rules granted to `identity`.
+    pub fn rules(&self, identity: &str) -> &[Rule] {
+        self.rules.get(identity).map_or(&[], |rules| &rules[..])
+    }
+
+    /// `allows` returns true if `identity` may perform `op` on `key`.
+    pub fn allows(&self, identity: &str, op: Op, key: &str) -> bool {
+        self.rules(identity).iter()
+            .any(|r| key.starts_with(&r.prefix) && r.ops.contains(&op))
+    }
+}
+
+/// Access is a view of the store that checks every operation against
+/// the store's ACL on behalf of one identity. Denied operations
+/// return `WriteResult::Denied` (as an error, for reads).
+#[derive(Debug)]
+pub struct Access<'a> {
+    store: &'a mut Store,
+    identity: String,
+}
+
+impl Store {
+    /// `access` returns a view of the store restricted to what the
+    /// ACL grants `identity`.
+    pub fn access(&mut self, identity: &str) -> Access<'_> {
+        Access { store: self, identity: identity.to_string() }
+    }
+}
+
+impl<'a> Access<'a> {
+    fn allows(&self, op: Op, k: &str) -> bool {
+        self.store.acl.allows(&self.identity, op, k)
+    }
+
+    /// `identity` returns the identity operations are checked for.
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// `get` works like `Store::get` if the identity may read `k`
+    /// and, if `k` is an alias, the key it resolves to.
+    pub fn get(&self, k: String) -> Result<Option<String>, WriteResult> {
+        if !self.allows(Op::Read, &k) || !self.allows(Op::Read, self.store.resolve(&k)) {
+            return Err(Denied);
+        }
+        Ok(self.store.get(k))
+    }
+
+    /// `insert` works like `Store::insert` if the identity may write
+    /// `k`.
+    pub fn insert(&mut self, k: String, v: String) -> WriteResult {
+        if !self.allows(Op::Write, &k) {
+            return Denied;
+        }
+        self.store.insert(k, v)
+    }
+
+    /// `update` works like `Store::update` if the identity may write
+    /// `k`.
+    pub fn update(&mut self, k: String, v: String) -> WriteResult {
+        if !self.allows(Op::Write, &k) {
+            return Denied;
+        }
+        self.store.update(k, v)
+    }
+
+    /// `delete` works like `Store::delete` if the identity may delete
+    /// `k`.
+    pub fn delete(&mut self, k: String) -> WriteResult {
+        if !self.allows(Op::Delete, &k) {
+            return Denied;
+        }
+        self.store.delete(k)
+    }
+}
+
+
+#[test]
+fn test_acl() {
+    let mut acl = Acl::new();
+    acl.grant("alice", "app1/", &[Op::Read]);
+    acl.grant("alice", "app1/", &[Op::Write, Op::Read]);
+    acl.grant("bob", "", &[Op::Read]);
+
+    assert!(acl.allows("alice", Op::Read, "app1/name"));
+    assert!(acl.allows("alice", Op::Write, "app1/name"));
+    assert!(!acl.allows("alice", Op::Delete, "app1/name"));
+    assert!(!acl.allows("alice", Op::Read, "app2/name"));
+    assert!(acl.allows("bob", Op::Read, "anything"));
+    assert!(!acl.allows("mallory", Op::Read, "anything"));
+    assert_eq!(acl.rules("alice")[0].ops, vec![Op::Read, Op::Write]);
+
+    assert!(acl.revoke("alice", "app1/"));
+    assert!(!acl.revoke("alice", "app1/"));
+    assert!(!acl.allows("alice", Op::Read, "app1/name"));
+}
+
+#[test]
+fn test_access() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("app2/secret".to_string(), "hunter2".to_string());
+    kvs.acl.grant("alice", "app1/", &[Op::Read, Op::Write]);
+
+    let mut alice = kvs.access("alice");
+    assert_eq!(alice.insert("app1/name".to_string(), "demo".to_string()), Inserted);
+    assert_eq!(alice.update("app2/secret".to_string(), "oops".to_string()), Denied);
+    assert_eq!(alice.get("app1/name".to_string()), Ok(Some("demo".to_string())));
+    assert_eq!(alice.get("app2/secret".to_string()), Err(Denied));
+    assert_eq!(alice.delete("app1/name".to_string()), Denied);
+
+    // Aliases don't reach past the ACL.
+    kvs.alias("app1/leak", "app2/secret").unwrap();
+    assert_eq!(kvs.access("alice").get("app1/leak".to_string()), Err(Denied));
+}
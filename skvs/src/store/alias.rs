@@ -0,0 +1,160 @@
+//! alias gives keys other names. An alias points at a key, or at
+//! another alias, and every read of a single key reads through it:
+//! `get`, `get_or`, `get_or_insert_with`, `with_value`, `get_as`,
+//! `get_secret`, `shown_value`, model loads, and snapshots' `get` and
+//! `entry`. So consumers can read a stable name (say,
+//! `current-config`) while operators repoint it from one version to
+//! the next in a single step. Listings, such as `keys`, scans, and
+//! iterators, only list keys. A scoped view only follows an alias to
+//! a key inside its prefix.
+//!
+//! A key holding a value hides an alias of the same name, and writes
+//! aren't redirected: writing to an alias's name makes a key that
+//! hides it. Aliases can't form cycles, and chains of them are at
+//! most `MAX_ALIAS_DEPTH` long. They're persisted with the store.
+//!
+//! ```
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.insert("config-v41".to_string(), "old".to_string());
+//! kvs.insert("config-v42".to_string(), "new".to_string());
+//! kvs.alias("current-config", "config-v41").unwrap();
+//! kvs.alias("current-config", "config-v42").unwrap();
+//! assert_eq!(kvs.get("current-config".to_string()), Some("new".to_string()));
+//! ```
+use super::config::KeyError;
+use super::Store;
+use std::error::Error;
+use std::fmt;
+
+/// `MAX_ALIAS_DEPTH` is the longest chain of aliases that's followed.
+pub const MAX_ALIAS_DEPTH: usize = 8;
+
+/// AliasError explains why an alias couldn't be made.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AliasError {
+    /// InvalidKey is returned for names the store's key policy
+    /// rejects.
+    InvalidKey(KeyError),
+
+    /// KeyExists is returned when the name is already a key.
+    KeyExists,
+
+    /// Cycle is returned when the alias would lead back to itself.
+    Cycle,
+
+    /// TooDeep is returned when the alias would make a chain longer
+    /// than `MAX_ALIAS_DEPTH`.
+    TooDeep,
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AliasError::InvalidKey(err) => write!(f, "invalid alias: {}", err),
+            AliasError::KeyExists       => write!(f, "alias names an existing key"),
+            AliasError::Cycle           => write!(f, "alias would form a cycle"),
+            AliasError::TooDeep         => write!(f, "alias chain would be longer than {}", MAX_ALIAS_DEPTH),
+        }
+    }
+}
+
+impl Error for AliasError {}
+
+impl Store {
+    /// `alias` points `name` at `target`, replacing any alias `name`
+    /// already had. The target doesn't have to exist yet; until it
+    /// does, reading the alias finds nothing.
+    pub fn alias(&mut self, name: &str, target: &str) -> Result<(), AliasError> {
+        self.config.key_policy.check(name).map_err(AliasError::InvalidKey)?;
+        if self.values.contains_key(name) {
+            return Err(AliasError::KeyExists);
+        }
+
+        let mut k = target;
+        for _ in 0..MAX_ALIAS_DEPTH {
+            if k == name {
+                return Err(AliasError::Cycle);
+            }
+            match self.aliases.get(k) {
+                Some(next) if !self.values.contains_key(k) => k = next,
+                _                                           => {
+                    self.aliases.insert(name.to_string(), target.to_string());
+                    return Ok(());
+                },
+            }
+        }
+        Err(AliasError::TooDeep)
+    }
+
+    /// `unalias` removes the alias `name`, returning its target.
+    pub fn unalias(&mut self, name: &str) -> Option<String> {
+        self.aliases.remove(name)
+    }
+
+    /// `resolve` returns the key that reading `k` reads: `k` itself,
+    /// unless it's an alias.
+    pub fn resolve<'a>(&'a self, k: &'a str) -> &'a str {
+        let mut k = k;
+        for _ in 0..MAX_ALIAS_DEPTH {
+            if self.aliases.is_empty() || self.values.contains_key(k) {
+                break;
+            }
+            match self.aliases.get(k) {
+                Some(target) => k = target,
+                None         => break,
+            }
+        }
+        k
+    }
+}
+
+
+#[test]
+fn test_alias() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("config-v1".to_string(), "one".to_string());
+    kvs.insert("config-v2".to_string(), "two".to_string());
+
+    assert_eq!(kvs.alias("config-v1", "config-v2"), Err(AliasError::KeyExists));
+    assert_eq!(kvs.alias("", "config-v2"), Err(AliasError::InvalidKey(KeyError::Empty)));
+    kvs.alias("current", "config-v1").unwrap();
+    kvs.alias("latest", "current").unwrap();
+    assert_eq!(kvs.get("latest".to_string()), Some("one".to_string()));
+    assert_eq!(kvs.get_or("current", "none"), "one");
+    assert_eq!(kvs.snapshot().get("latest").as_deref(), Some("one"));
+
+    kvs.alias("current", "config-v2").unwrap();
+    assert_eq!(kvs.get("latest".to_string()), Some("two".to_string()));
+    assert_eq!(kvs.alias("current", "latest"), Err(AliasError::Cycle));
+    assert_eq!(kvs.alias("current", "current"), Err(AliasError::Cycle));
+
+    kvs.alias("dangling", "config-v3").unwrap();
+    assert_eq!(kvs.get("dangling".to_string()), None);
+    kvs.insert("config-v3".to_string(), "three".to_string());
+    assert_eq!(kvs.get("dangling".to_string()), Some("three".to_string()));
+
+    // A key hides an alias of the same name.
+    kvs.insert("current".to_string(), "shadow".to_string());
+    assert_eq!(kvs.get("latest".to_string()), Some("shadow".to_string()));
+    assert_eq!(kvs.unalias("current"), Some("config-v2".to_string()));
+    assert_eq!(kvs.unalias("current"), None);
+
+    for i in 0..MAX_ALIAS_DEPTH {
+        kvs.alias(&format!("chain{}", i + 1), &format!("chain{}", i)).unwrap();
+    }
+    assert_eq!(kvs.alias("chain9", "chain8"), Err(AliasError::TooDeep));
+}
+
+#[test]
+fn test_alias_reads() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("retries-v1".to_string(), "3".to_string());
+    kvs.alias("retries", "retries-v1").unwrap();
+
+    assert_eq!(kvs.get_as::<u32>("retries").unwrap(), 3);
+    assert_eq!(kvs.get_or_insert_with("retries".to_string(), || "9".to_string()), Ok("3".to_string()));
+    assert!(!kvs.values.contains_key("retries"));
+    assert_eq!(kvs.snapshot().entry("retries").map(|ent| &*ent.value), Some("3"));
+    assert_eq!(kvs.snapshot().keys(), vec!["retries-v1"]);
+    assert_eq!(super::model::field::<u32>(&kvs, "retries".to_string()), Ok(3));
+}
@@ -0,0 +1,99 @@
+//! bulk provides a fast path for loading many key-value pairs at once,
+//! such as when seeding a new store, where the per-write bookkeeping
+//! done by `insert` and `update` would dominate.
+use super::entry::Entry;
+use super::history::History;
+use super::{quota, spill};
+use super::Store;
+use std::io;
+use std::sync::Arc;
+
+impl Store {
+    /// `bulk_load` writes every pair from `pairs`, replacing any
+    /// existing entry for the key without comparing values; a replaced
+    /// entry's version is bumped, and a new one starts at 1. The
+    /// metrics are updated once at the end, and the store is flushed
+    /// afterwards if `flush` is true. It returns the number of pairs
+    /// written. The loaded pairs aren't recorded for `undo`, so the
+    /// undo history is cleared instead.
+    ///
+    /// Loading stops at the first pair the store's configuration
+    /// doesn't allow, whose key is leased, or that would exceed a
+    /// quota, with an `InvalidInput` error; the pairs before it are
+    /// kept.
+    pub fn bulk_load<I>(&mut self, pairs: I, flush: bool) -> Result<usize, io::Error>
+        where I: IntoIterator<Item = (String, String)> {
+        let pairs = pairs.into_iter();
+        Arc::make_mut(&mut self.values).reserve(pairs.size_hint().0);
+
+        let mut count = 0;
+        let mut result = Ok(());
+        for (k, v) in pairs {
+            let mut checked = self.config.check_write(&k, &v);
+            if checked.is_ok() {
+                checked = self.check_writable(&k);
+            }
+            if checked.is_ok() {
+                checked = quota::check(&self.config, &mut self.usages, &self.values, &k, v.len());
+            }
+            if let Err(wr) = checked {
+                result = Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                            format!("{:?}: {}", k, wr.to_string())));
+                break;
+            }
+            let mut ent = Entry::with_clock(v, &*self.clock);
+            ent.version = self.values.get(k.as_str()).map_or(1, |old| old.version + 1);
+            self.journal.record(&k, ent.version);
+            if !self.tombstones.is_empty() {
+                self.tombstones.remove(&k);
+            }
+            let len = ent.value.len();
+            let before = Arc::make_mut(&mut self.values).insert(Arc::from(k.as_str()), ent);
+            self.usages.wrote(&k, before.as_ref().map(spill::value_len), Some(len));
+            count += 1;
+        }
+
+        if count > 0 {
+            self.history = History::new();
+        }
+        self.rebuild_index();
+        self.update_metrics(true, false);
+        self.metrics.counters.inserted(count as u64);
+        result?;
+        if flush {
+            self.flush()?;
+        }
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_bulk_load() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k0".to_string(), "old".to_string());
+    kvs.update("k0".to_string(), "older".to_string());
+    let keys = kvs.subscribe_keys();
+
+    let pairs = (0..1000).map(|i| (format!("k{}", i), format!("v{}", i)));
+    assert_eq!(kvs.bulk_load(pairs, false).unwrap(), 1000);
+    assert_eq!(kvs.len(), 1000);
+    assert_eq!(kvs.metrics.size, 1000);
+    assert_eq!(kvs.get("k0".to_string()).unwrap(), "v0");
+    assert_eq!(kvs.values["k0"].version, 3);
+    assert_eq!(kvs.values["k1"].version, 1);
+    assert_eq!(keys.try_iter().count(), 1000);
+
+    let pairs = vec![("new".to_string(), "v".to_string()), ("".to_string(), "v".to_string())];
+    assert_eq!(kvs.bulk_load(pairs, false).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(kvs.len(), 1001);
+    assert_eq!(kvs.metrics.size, 1001);
+
+    kvs.config = super::StoreConfig::new().undo_depth(8);
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.acquire_lease("k1", std::time::Duration::from_secs(10)).unwrap();
+    let pairs = vec![("k2".to_string(), "v".to_string()), ("k1".to_string(), "v".to_string())];
+    assert_eq!(kvs.bulk_load(pairs, false).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(kvs.get("k1".to_string()).unwrap(), "v1");
+    assert_eq!(kvs.values["k2"].version, 2);
+    assert_eq!(kvs.undo(), super::WriteResult::DoesNotExist);
+}
@@ -0,0 +1,278 @@
+//! cache fronts a slow backend, such as a database or a remote store,
+//! with an in-memory `Store`. Reads that miss the cache are read
+//! through to the backend and cached; writes either go straight
+//! through to the backend or are held until the next `flush`,
+//! depending on the `WritePolicy`.
+//!
+//! When something else writes to the backend, the cache has to be
+//! told: `invalidate` drops a key, and `watch` drops every key named
+//! by a journal (such as one from `Store::subscribe_keys` on the
+//! backing store).
+use super::journal::JournalRecord;
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::Receiver;
+
+/// PersistenceBackend is the slow storage a `CachedStore` fronts.
+pub trait PersistenceBackend {
+    /// `get` returns the value stored under `k`, if any.
+    fn get(&mut self, k: &str) -> Result<Option<String>, io::Error>;
+
+    /// `put` stores `v` under `k`.
+    fn put(&mut self, k: &str, v: &str) -> Result<(), io::Error>;
+
+    /// `delete` removes `k`; deleting a missing key isn't an error.
+    fn delete(&mut self, k: &str) -> Result<(), io::Error>;
+}
+
+/// A `Store` can serve as a backend, which is mostly useful for tests
+/// and for layering a cache over a store that's flushed elsewhere.
+/// Writes the store rejects are reported as `InvalidInput` errors.
+impl PersistenceBackend for Store {
+    fn get(&mut self, k: &str) -> Result<Option<String>, io::Error> {
+        Ok(Store::get(self, k.to_string()))
+    }
+
+    fn put(&mut self, k: &str, v: &str) -> Result<(), io::Error> {
+        match self.update(k.to_string(), v.to_string()) {
+            Inserted | Updated => Ok(()),
+            wr                 => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                     format!("{}: {}", k, wr.to_string()))),
+        }
+    }
+
+    fn delete(&mut self, k: &str) -> Result<(), io::Error> {
+        Store::delete(self, k.to_string());
+        Ok(())
+    }
+}
+
+/// WritePolicy decides when writes reach the backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WritePolicy {
+    /// WriteThrough writes to the backend before updating the cache,
+    /// so a failed write leaves the cache unchanged.
+    WriteThrough,
+
+    /// WriteBack updates the cache and holds the write until `flush`.
+    /// Writes that haven't been flushed are lost if the cache is
+    /// dropped.
+    WriteBack,
+}
+
+/// CachedStore is an in-memory cache in front of a backend.
+#[derive(Debug)]
+pub struct CachedStore<B: PersistenceBackend> {
+    backend: B,
+    policy: WritePolicy,
+    cache: Store,
+
+    /// dirty holds the writes not yet made to the backend, under the
+    /// write-back policy; `None` is a pending delete.
+    dirty: HashMap<String, Option<String>>,
+}
+
+impl<B: PersistenceBackend> CachedStore<B> {
+    /// `new` returns an empty cache in front of `backend`.
+    pub fn new(backend: B, policy: WritePolicy) -> CachedStore<B> {
+        CachedStore {
+            backend,
+            policy,
+            cache: super::new("".to_string()),
+            dirty: HashMap::new(),
+        }
+    }
+
+    /// `get` returns the value for `k`, reading it from the backend
+    /// and caching it if it isn't cached.
+    pub fn get(&mut self, k: &str) -> Result<Option<String>, io::Error> {
+        if let Some(v) = self.cache.get(k.to_string()) {
+            return Ok(Some(v));
+        }
+        if let Some(&None) = self.dirty.get(k) {
+            return Ok(None);
+        }
+
+        let v = self.backend.get(k)?;
+        if let Some(ref v) = v {
+            self.cache.update(k.to_string(), v.clone());
+        }
+        Ok(v)
+    }
+
+    /// `update` writes `v` under `k`. Writes the cache's
+    /// configuration rejects are returned without reaching the
+    /// backend; otherwise `Updated` is returned, since the cache
+    /// can't tell whether the backend already had the key.
+    pub fn update(&mut self, k: String, v: String) -> Result<WriteResult, io::Error> {
+        if let Err(wr) = self.cache.config.check_write(&k, &v) {
+            return Ok(wr);
+        }
+
+        match self.policy {
+            WritePolicy::WriteThrough => self.backend.put(&k, &v)?,
+            WritePolicy::WriteBack    => { self.dirty.insert(k.clone(), Some(v.clone())); },
+        }
+        self.cache.update(k, v);
+        Ok(Updated)
+    }
+
+    /// `delete` removes `k` from the cache and the backend.
+    pub fn delete(&mut self, k: String) -> Result<(), io::Error> {
+        match self.policy {
+            WritePolicy::WriteThrough => self.backend.delete(&k)?,
+            WritePolicy::WriteBack    => { self.dirty.insert(k.clone(), None); },
+        }
+        self.cache.delete(k);
+        Ok(())
+    }
+
+    /// `flush` makes the pending writes to the backend. Writes that
+    /// fail stay pending, and the first error is returned.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        let mut keys: Vec<String> = self.dirty.keys().cloned().collect();
+        keys.sort();
+        for k in keys {
+            let result = match self.dirty[&k] {
+                Some(ref v) => self.backend.put(&k, v),
+                None        => self.backend.delete(&k),
+            };
+            result?;
+            self.dirty.remove(&k);
+        }
+        Ok(())
+    }
+
+    /// `pending` returns the number of writes waiting for `flush`.
+    pub fn pending(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// `invalidate` drops `k` from the cache, so the next read goes
+    /// to the backend. Keys with pending writes are kept, since the
+    /// cache holds the newest value.
+    pub fn invalidate(&mut self, k: &str) {
+        if !self.dirty.contains_key(k) {
+            self.cache.delete(k.to_string());
+        }
+    }
+
+    /// `invalidate_all` drops every key without a pending write.
+    pub fn invalidate_all(&mut self) {
+        let keys: Vec<String> = self.cache.values.keys()
+            .filter(|k| !self.dirty.contains_key(&***k))
+            .map(|k| k.to_string())
+            .collect();
+        for k in keys {
+            self.cache.delete(k);
+        }
+    }
+
+    /// `watch` invalidates every key named by the journal records
+    /// waiting on `rx`, returning how many were received. Call it
+    /// before reading to pick up writes made to the backend by others.
+    pub fn watch(&mut self, rx: &Receiver<JournalRecord>) -> usize {
+        let mut n = 0;
+        for rec in rx.try_iter() {
+            self.invalidate(&rec.key);
+            n += 1;
+        }
+        n
+    }
+
+    /// `cache` returns the in-memory store holding the cached
+    /// entries.
+    pub fn cache(&self) -> &Store {
+        &self.cache
+    }
+
+    /// `backend` returns the backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// `backend_mut` returns the backend, for writes the cache
+    /// shouldn't see; invalidate the keys they change.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+}
+
+
+/// Counting is a backend that counts the reads that reach it.
+#[cfg(test)]
+struct Counting {
+    kvs: Store,
+    reads: usize,
+}
+
+#[cfg(test)]
+impl PersistenceBackend for Counting {
+    fn get(&mut self, k: &str) -> Result<Option<String>, io::Error> {
+        self.reads += 1;
+        PersistenceBackend::get(&mut self.kvs, k)
+    }
+
+    fn put(&mut self, k: &str, v: &str) -> Result<(), io::Error> {
+        self.kvs.put(k, v)
+    }
+
+    fn delete(&mut self, k: &str) -> Result<(), io::Error> {
+        PersistenceBackend::delete(&mut self.kvs, k)
+    }
+}
+
+#[test]
+fn test_read_through() {
+    let mut backend = Counting { kvs: super::new("".to_string()), reads: 0 };
+    backend.kvs.insert("a".to_string(), "1".to_string());
+    let rx = backend.kvs.subscribe_keys();
+    let mut cached = CachedStore::new(backend, WritePolicy::WriteThrough);
+
+    assert_eq!(cached.get("a").unwrap(), Some("1".to_string()));
+    assert_eq!(cached.get("a").unwrap(), Some("1".to_string()));
+    assert_eq!(cached.get("b").unwrap(), None);
+    assert_eq!(cached.backend().reads, 2);
+
+    cached.update("b".to_string(), "2".to_string()).unwrap();
+    assert_eq!(cached.backend().kvs.get("b".to_string()).unwrap(), "2");
+    assert_eq!(cached.get("b").unwrap(), Some("2".to_string()));
+    assert_eq!(cached.backend().reads, 2);
+
+    // A write the cache didn't see is picked up once the journal is
+    // watched.
+    cached.backend_mut().kvs.update("a".to_string(), "one".to_string());
+    assert_eq!(cached.get("a").unwrap(), Some("1".to_string()));
+    assert_eq!(cached.watch(&rx), 2);
+    assert_eq!(cached.get("a").unwrap(), Some("one".to_string()));
+    assert_eq!(cached.backend().reads, 3);
+
+    cached.delete("a".to_string()).unwrap();
+    assert!(cached.backend().kvs.get("a".to_string()).is_none());
+    assert_eq!(cached.update("".to_string(), "v".to_string()).unwrap(),
+               InvalidKey(super::KeyError::Empty));
+}
+
+#[test]
+fn test_write_back() {
+    let mut backend = super::new("".to_string());
+    backend.insert("a".to_string(), "1".to_string());
+    let mut cached = CachedStore::new(backend, WritePolicy::WriteBack);
+
+    cached.update("b".to_string(), "2".to_string()).unwrap();
+    cached.delete("a".to_string()).unwrap();
+    assert_eq!(cached.pending(), 2);
+    assert_eq!(cached.get("a").unwrap(), None);
+    assert!(cached.backend().get("b".to_string()).is_none());
+
+    // Pending writes survive invalidation.
+    cached.invalidate_all();
+    assert_eq!(cached.get("b").unwrap(), Some("2".to_string()));
+
+    cached.flush().unwrap();
+    assert_eq!(cached.pending(), 0);
+    assert!(cached.backend().get("a".to_string()).is_none());
+    assert_eq!(cached.backend().get("b".to_string()).unwrap(), "2");
+}
@@ -0,0 +1,124 @@
+//! canonical gives a store a stable written form. Exports already list
+//! keys in sorted order; with `StoreConfig::canonical` set, the store
+//! file (and, for a sharded store, each shard) is written that way too:
+//! as indented JSON with every object's keys sorted, so that dumps
+//! kept in version control diff cleanly.
+//!
+//! `canonical_hash` digests the store's entries in canonical order, so
+//! two copies of a store (say, on two nodes after a sync) can be
+//! compared without sending either over. It covers each entry's key,
+//! value, version, timestamp, and `secret` flag, and nothing else:
+//! metrics, history, and configuration don't change it. Spilled values
+//! are hashed by their contents, so a store whose side files can't be
+//! read can't be hashed.
+//!
+//! ```
+//! let mut a = skvs::store::new("".to_string());
+//! a.insert("k".to_string(), "v".to_string());
+//! let mut b = skvs::store::new("".to_string());
+//! skvs::store::sync(&mut a, &mut b);
+//! assert_eq!(a.canonical_hash().unwrap(), b.canonical_hash().unwrap());
+//! ```
+extern crate serde_json;
+extern crate sha2;
+
+use self::sha2::{Digest, Sha256};
+use super::serde::Serialize;
+use super::Store;
+use std::io::{self, Write};
+
+impl Store {
+    /// `canonical_hash` returns the SHA-256 digest, in hex, of the
+    /// store's entries in sorted order. It fails if a spilled value
+    /// can't be read.
+    pub fn canonical_hash(&self) -> io::Result<String> {
+        let mut keys: Vec<&str> = self.values.keys().map(|k| &**k).collect();
+        keys.sort();
+
+        let mut h = Sha256::new();
+        for k in keys {
+            let ent = &self.values[k];
+            let value = self.shared_value(ent)?;
+            field(&mut h, k.as_bytes());
+            field(&mut h, value.as_bytes());
+            h.update(ent.version.to_be_bytes());
+            h.update(ent.time.nanos.to_be_bytes());
+            h.update(ent.time.counter.to_be_bytes());
+            h.update([ent.secret as u8]);
+        }
+        Ok(h.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+/// `field` adds `data` to `h`, prefixed with its length so that fields
+/// can't run into each other.
+fn field(h: &mut Sha256, data: &[u8]) {
+    h.update((data.len() as u64).to_be_bytes());
+    h.update(data);
+}
+
+/// `write_json` writes `v` to `w` as JSON, in canonical form if
+/// `canonical` is set.
+pub(super) fn write_json<T: Serialize>(w: &mut dyn Write, v: &T, canonical: bool) -> Result<(), io::Error> {
+    let invalid = |err: serde_json::Error| io::Error::other(err.to_string());
+    if !canonical {
+        return serde_json::to_writer(w, v).map_err(invalid);
+    }
+
+    // serde_json's objects keep their keys sorted.
+    let doc = serde_json::to_value(v).map_err(invalid)?;
+    serde_json::to_writer_pretty(&mut *w, &doc).map_err(invalid)?;
+    w.write_all(b"\n")
+}
+
+
+#[test]
+fn test_canonical_hash() {
+    let mut a = super::new("".to_string());
+    let mut b = super::new("".to_string());
+    assert_eq!(a.canonical_hash().unwrap(), b.canonical_hash().unwrap());
+
+    a.insert("x".to_string(), "1".to_string());
+    a.insert("y".to_string(), "2".to_string());
+    assert_ne!(a.canonical_hash().unwrap(), b.canonical_hash().unwrap());
+    super::sync(&mut a, &mut b);
+    assert_eq!(a.canonical_hash().unwrap(), b.canonical_hash().unwrap());
+
+    // Only the entries count.
+    let before = a.canonical_hash().unwrap();
+    a.get("x".to_string());
+    a.config.tombstones = true;
+    assert_eq!(a.canonical_hash().unwrap(), before);
+
+    b.update("x".to_string(), "1".to_string());
+    assert_eq!(b.canonical_hash().unwrap(), before);
+    b.update("x".to_string(), "3".to_string());
+    assert_ne!(b.canonical_hash().unwrap(), before);
+    assert_eq!(b.canonical_hash().unwrap().len(), 64);
+
+    let mut empty = super::new("".to_string());
+    assert_eq!(empty.canonical_hash().unwrap(),
+               "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    empty.insert("k".to_string(), "v".to_string());
+    empty.values_mut().get_mut("k").unwrap().blob = Some("missing".to_string());
+    assert!(empty.canonical_hash().is_err());
+}
+
+#[test]
+fn test_canonical_form() {
+    let mut kvs = super::new("".to_string());
+    for k in &["b", "c", "a"] {
+        kvs.insert(k.to_string(), "v".to_string());
+    }
+
+    let mut first = Vec::new();
+    write_json(&mut first, &kvs, true).unwrap();
+    let mut second = Vec::new();
+    write_json(&mut second, &kvs.clone(), true).unwrap();
+    assert_eq!(first, second);
+
+    let text = String::from_utf8(first).unwrap();
+    let at = |k: &str| text.find(&format!("\"{}\": {{", k)).unwrap();
+    assert!(at("a") < at("b") && at("b") < at("c"));
+    assert!(text.ends_with("}\n"));
+}
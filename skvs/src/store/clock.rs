@@ -0,0 +1,161 @@
+//! clock supplies a store's timestamps: entry times, tombstones, the
+//! trash, and the metrics. By default a store uses `SystemClock`, the
+//! process's hybrid logical clock. Tests can give a store a
+//! `MockClock` with `Store::set_clock`, and move time forward by hand
+//! instead of sleeping:
+//!
+//! ```
+//! use skvs::store::{MockClock, Timestamp};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let clock = Arc::new(MockClock::new(Timestamp::from_secs(1500000000)));
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.set_clock(clock.clone());
+//!
+//! kvs.insert("k".to_string(), "v".to_string());
+//! clock.advance(Duration::from_secs(60));
+//! kvs.update("k".to_string(), "v2".to_string());
+//! assert_eq!(kvs.values["k"].time.secs(), 1500000060);
+//! ```
+use super::hlc;
+use super::hlc::Timestamp;
+use super::Store;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Clock produces strictly increasing timestamps.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// `now` returns a timestamp later than any the clock has
+    /// returned before.
+    fn now(&self) -> Timestamp;
+
+    /// `observe` notes a timestamp from another clock, such as one on
+    /// a merged entry, so that later timestamps sort after it.
+    fn observe(&self, remote: Timestamp);
+}
+
+/// SystemClock is the process's hybrid logical clock, which follows
+/// the wall clock; see the `hlc` module.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+
+    fn observe(&self, remote: Timestamp) {
+        hlc::observe(remote);
+    }
+}
+
+/// `system` returns the clock a store uses unless it's given another.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// MockClock is a clock that only moves when it's told to. Its
+/// timestamps are still strictly increasing: successive calls to
+/// `now` at the same time are ordered by the counter.
+#[derive(Debug)]
+pub struct MockClock {
+    next: Mutex<Timestamp>,
+}
+
+impl MockClock {
+    /// `new` returns a clock stopped at `start`.
+    pub fn new(start: Timestamp) -> MockClock {
+        MockClock { next: Mutex::new(start) }
+    }
+
+    /// `advance` moves the clock forward by `d`. If that doesn't move
+    /// it past the last nanosecond it returned, as when `d` is zero,
+    /// the counter carries on from where it was.
+    pub fn advance(&self, d: Duration) {
+        let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+        let nanos = next.nanos.saturating_add(d.as_nanos().min(i64::MAX as u128) as i64);
+        if nanos > next.nanos {
+            *next = Timestamp { nanos, counter: 0 };
+        }
+    }
+
+    /// `set` moves the clock to `time`, which may be in the past;
+    /// timestamps from before the move may then sort after later
+    /// ones, as they would if the wall clock were stepped back.
+    pub fn set(&self, time: Timestamp) {
+        *self.next.lock().unwrap_or_else(|err| err.into_inner()) = time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+        let now = *next;
+        *next = now.next();
+        now
+    }
+
+    fn observe(&self, remote: Timestamp) {
+        let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+        if remote >= *next {
+            *next = remote.next();
+        }
+    }
+}
+
+impl Store {
+    /// `set_clock` makes the store take its timestamps from `clock`.
+    /// Clones of the store made afterwards share the clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+
+#[test]
+fn test_mock_clock() {
+    let start = Timestamp::from_secs(1500000000);
+    let clock = Arc::new(MockClock::new(start));
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(clock.clone());
+    kvs.config.tombstones = true;
+
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.insert("b".to_string(), "2".to_string());
+    assert_eq!(kvs.values["a"].time, start);
+    assert!(kvs.values["b"].time > kvs.values["a"].time);
+    assert_eq!(kvs.metrics.last_update.secs(), 1500000000);
+
+    clock.advance(Duration::from_secs(2));
+    kvs.update("a".to_string(), "one".to_string());
+    assert_eq!(kvs.values["a"].time, Timestamp::from_secs(1500000002));
+    assert_eq!(kvs.metrics.last_update.secs(), 1500000002);
+
+    kvs.delete("b".to_string());
+    clock.advance(Duration::from_secs(60));
+    kvs.delete("a".to_string());
+    assert_eq!(kvs.gc_tombstones(Timestamp::from_secs(1500000060)), 1);
+    assert_eq!(kvs.tombstones.len(), 1);
+
+    let remote = Timestamp::from_secs(1600000000);
+    clock.observe(remote);
+    assert!(clock.now() > remote);
+}
+
+#[test]
+fn test_mock_clock_counter() {
+    let clock = MockClock::new(Timestamp::from_secs(1500000000));
+    let first = clock.now();
+    clock.advance(Duration::ZERO);
+    assert!(clock.now() > first);
+
+    // The counter carries into the nanoseconds rather than
+    // overflowing.
+    let remote = Timestamp { nanos: first.nanos, counter: u32::MAX };
+    clock.observe(remote);
+    let ts = clock.now();
+    assert!(ts > remote);
+    assert_eq!(ts, Timestamp { nanos: first.nanos + 1, counter: 0 });
+}
@@ -0,0 +1,424 @@
+//! config holds the settings that control what a store accepts. The
+//! configuration belongs to the running program rather than the data,
+//! so it isn't persisted: set it again after loading a store.
+use super::quota::Quota;
+use super::redact::RedactionPolicy;
+use super::slowlog::SLOW_LOG_LEN;
+use super::WriteResult;
+use std::fmt;
+use std::time::Duration;
+
+/// Charset restricts the characters allowed in keys.
+#[derive(Clone, Copy)]
+pub enum Charset {
+    /// Any allows every character.
+    Any,
+
+    /// NoControl allows everything except control characters, such
+    /// as newlines and NUL.
+    NoControl,
+
+    /// AsciiGraphic allows only visible ASCII characters: no spaces,
+    /// control characters, or anything outside ASCII.
+    AsciiGraphic,
+
+    /// Custom allows the characters the function returns true for.
+    Custom(fn(char) -> bool),
+}
+
+impl Charset {
+    /// `allows` returns true if `c` may appear in a key.
+    pub fn allows(&self, c: char) -> bool {
+        match *self {
+            Charset::Any          => true,
+            Charset::NoControl    => !c.is_control(),
+            Charset::AsciiGraphic => c.is_ascii_graphic(),
+            Charset::Custom(f)    => f(c),
+        }
+    }
+}
+
+impl fmt::Debug for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Charset::Any          => write!(f, "Any"),
+            Charset::NoControl    => write!(f, "NoControl"),
+            Charset::AsciiGraphic => write!(f, "AsciiGraphic"),
+            Charset::Custom(_)    => write!(f, "Custom"),
+        }
+    }
+}
+
+/// KeyError explains why a key was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyError {
+    /// Empty keys aren't allowed.
+    Empty,
+
+    /// TooLong is returned for keys longer than the policy's maximum
+    /// length, in bytes.
+    TooLong { len: usize, max: usize },
+
+    /// InvalidChar is returned for the first character the policy's
+    /// charset doesn't allow.
+    InvalidChar(char),
+
+    /// Reserved is returned for keys under a reserved prefix.
+    Reserved,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyError::Empty                => write!(f, "key is empty"),
+            KeyError::TooLong { len, max } => write!(f, "key is {} bytes, the limit is {}", len, max),
+            KeyError::InvalidChar(c)       => write!(f, "key contains {:?}", c),
+            KeyError::Reserved             => write!(f, "key has a reserved prefix"),
+        }
+    }
+}
+
+/// KeyPolicy is the set of constraints every written key must meet.
+/// The default policy rejects empty keys, keys longer than
+/// `DEFAULT_MAX_KEY_LEN` bytes, and keys containing control
+/// characters.
+#[derive(Clone, Debug)]
+pub struct KeyPolicy {
+    max_len: usize,
+    charset: Charset,
+    reserved: Vec<String>,
+}
+
+/// DEFAULT_MAX_KEY_LEN is the longest key, in bytes, the default key
+/// policy accepts.
+pub const DEFAULT_MAX_KEY_LEN: usize = 1024;
+
+impl Default for KeyPolicy {
+    fn default() -> KeyPolicy {
+        KeyPolicy {
+            max_len: DEFAULT_MAX_KEY_LEN,
+            charset: Charset::NoControl,
+            reserved: Vec::new(),
+        }
+    }
+}
+
+impl KeyPolicy {
+    /// `new` returns the default key policy.
+    pub fn new() -> KeyPolicy {
+        KeyPolicy::default()
+    }
+
+    /// `max_len` sets the longest key allowed, in bytes.
+    pub fn max_len(mut self, max_len: usize) -> KeyPolicy {
+        self.max_len = max_len;
+        self
+    }
+
+    /// `charset` sets the characters allowed in keys.
+    pub fn charset(mut self, charset: Charset) -> KeyPolicy {
+        self.charset = charset;
+        self
+    }
+
+    /// `reserve` rejects keys starting with `prefix`, such as a
+    /// namespace the application keeps for itself.
+    pub fn reserve(mut self, prefix: &str) -> KeyPolicy {
+        self.reserved.push(prefix.to_string());
+        self
+    }
+
+    /// `check` returns an error if `k` doesn't meet the policy.
+    pub fn check(&self, k: &str) -> Result<(), KeyError> {
+        if k.is_empty() {
+            return Err(KeyError::Empty);
+        }
+        if k.len() > self.max_len {
+            return Err(KeyError::TooLong { len: k.len(), max: self.max_len });
+        }
+        if let Some(c) = k.chars().find(|&c| !self.charset.allows(c)) {
+            return Err(KeyError::InvalidChar(c));
+        }
+        if self.reserved.iter().any(|prefix| k.starts_with(prefix.as_str())) {
+            return Err(KeyError::Reserved);
+        }
+        Ok(())
+    }
+}
+
+/// DurabilityPolicy decides when writes reach the disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DurabilityPolicy {
+    /// Manual keeps writes in memory until the store is flushed. It's
+    /// the fastest, and the default.
+    #[default]
+    Manual,
+
+    /// Flush writes the store to disk after every write, so that a
+    /// crash of the program loses nothing; the operating system may
+    /// still lose recent writes if the machine goes down.
+    Flush,
+
+    /// Fsync flushes after every write and waits for the disk to
+    /// confirm it, which survives power loss but is by far the
+    /// slowest.
+    Fsync,
+}
+
+/// StoreConfig collects a store's settings.
+///
+/// ```
+/// use skvs::store::{Charset, KeyPolicy, StoreConfig};
+///
+/// let mut kvs = skvs::store::new("".to_string());
+/// kvs.config = StoreConfig::new()
+///     .key_policy(KeyPolicy::new().max_len(64).charset(Charset::AsciiGraphic))
+///     .allow_empty_values(false)
+///     .max_value_bytes(64 * 1024);
+/// ```
+#[derive(Clone, Debug)]
+pub struct StoreConfig {
+    /// key_policy is the policy keys must meet to be written.
+    pub key_policy: KeyPolicy,
+
+    /// allow_empty_values controls whether a key may be written with
+    /// an empty value. It's allowed by default; an empty value is
+    /// still distinct from a missing key (`Some("")` rather than
+    /// `None` from `get`).
+    pub allow_empty_values: bool,
+
+    /// max_value_bytes is the largest value, in bytes, that may be
+    /// written; `None` means there's no limit, which is the default.
+    pub max_value_bytes: Option<usize>,
+
+    /// tombstones controls whether deleting a key leaves a tombstone,
+    /// so that the deletion can be synced to other stores; see the
+    /// `tombstone` module. It's off by default.
+    pub tombstones: bool,
+
+    /// undo_depth is the number of recent writes kept for `undo`; 0,
+    /// the default, keeps none. See the `history` module.
+    pub undo_depth: usize,
+
+    /// persist_counters controls whether the store's operation
+    /// counters are written with it, so they survive a reload; see
+    /// the `counters` module. It's off by default.
+    pub persist_counters: bool,
+
+    /// redaction decides which values are shown as `***` in exports
+    /// and debug output; by default, only secrets are. See the
+    /// `redact` module.
+    pub redaction: RedactionPolicy,
+
+    /// quotas limits the buckets of the keyspace, as pairs of a key
+    /// prefix and its quota; see the `quota` module. There are none
+    /// by default.
+    pub quotas: Vec<(String, Quota)>,
+
+    /// spill_bytes is the size above which values are moved out of
+    /// memory into side files when the store is flushed; see the
+    /// `spill` module. By default, nothing is spilled.
+    pub spill_bytes: Option<usize>,
+
+    /// durability decides whether writes are persisted as they're
+    /// made. Failures to persist a write don't reject it; they're
+    /// recorded in the store's `write_error`, like those of any flush.
+    pub durability: DurabilityPolicy,
+
+    /// canonical controls whether the store is written in canonical
+    /// form, with its keys sorted; see the `canonical` module. It's off
+    /// by default, since it's slower.
+    pub canonical: bool,
+
+    /// blob_cache is the number of spilled values kept in memory once
+    /// they've been read; see the `lazy` module. By default, none are.
+    pub blob_cache: usize,
+
+    /// slow_threshold turns on the slow log, recording operations
+    /// that take at least this long; see the `slowlog` module. It's
+    /// off by default.
+    pub slow_threshold: Option<Duration>,
+
+    /// slow_log_len is the number of slow operations kept.
+    pub slow_log_len: usize,
+}
+
+impl Default for StoreConfig {
+    fn default() -> StoreConfig {
+        StoreConfig {
+            key_policy: KeyPolicy::default(),
+            allow_empty_values: true,
+            max_value_bytes: None,
+            tombstones: false,
+            undo_depth: 0,
+            persist_counters: false,
+            redaction: RedactionPolicy::new(),
+            quotas: Vec::new(),
+            spill_bytes: None,
+            durability: DurabilityPolicy::Manual,
+            canonical: false,
+            blob_cache: 0,
+            slow_threshold: None,
+            slow_log_len: SLOW_LOG_LEN,
+        }
+    }
+}
+
+impl StoreConfig {
+    /// `new` returns the default configuration.
+    pub fn new() -> StoreConfig {
+        StoreConfig::default()
+    }
+
+    /// `key_policy` sets the policy keys must meet to be written.
+    pub fn key_policy(mut self, policy: KeyPolicy) -> StoreConfig {
+        self.key_policy = policy;
+        self
+    }
+
+    /// `allow_empty_values` sets whether empty values may be written;
+    /// when they may not, writing one returns `EmptyValue`.
+    pub fn allow_empty_values(mut self, allow: bool) -> StoreConfig {
+        self.allow_empty_values = allow;
+        self
+    }
+
+    /// `max_value_bytes` limits values to `max` bytes; writing a
+    /// larger value returns `ValueTooLarge`. A server should report
+    /// that as 413 (Payload Too Large).
+    pub fn max_value_bytes(mut self, max: usize) -> StoreConfig {
+        self.max_value_bytes = Some(max);
+        self
+    }
+
+    /// `tombstones` sets whether deletions leave tombstones.
+    pub fn tombstones(mut self, keep: bool) -> StoreConfig {
+        self.tombstones = keep;
+        self
+    }
+
+    /// `undo_depth` sets the number of recent writes that can be
+    /// undone.
+    pub fn undo_depth(mut self, depth: usize) -> StoreConfig {
+        self.undo_depth = depth;
+        self
+    }
+
+    /// `persist_counters` sets whether the operation counters are
+    /// written with the store.
+    pub fn persist_counters(mut self, persist: bool) -> StoreConfig {
+        self.persist_counters = persist;
+        self
+    }
+
+    /// `redaction` sets the policy for redacting values.
+    pub fn redaction(mut self, policy: RedactionPolicy) -> StoreConfig {
+        self.redaction = policy;
+        self
+    }
+
+    /// `quota` limits the keys starting with `bucket` to `quota`.
+    pub fn quota(mut self, bucket: &str, quota: Quota) -> StoreConfig {
+        self.quotas.push((bucket.to_string(), quota));
+        self
+    }
+
+    /// `spill_values_over` spills values larger than `bytes` to side
+    /// files.
+    pub fn spill_values_over(mut self, bytes: usize) -> StoreConfig {
+        self.spill_bytes = Some(bytes);
+        self
+    }
+
+    /// `durability` sets when writes are persisted.
+    pub fn durability(mut self, policy: DurabilityPolicy) -> StoreConfig {
+        self.durability = policy;
+        self
+    }
+
+    /// `canonical` sets whether the store is written in canonical form.
+    pub fn canonical(mut self, canonical: bool) -> StoreConfig {
+        self.canonical = canonical;
+        self
+    }
+
+    /// `blob_cache` keeps the `n` most recently read spilled values in
+    /// memory.
+    pub fn blob_cache(mut self, n: usize) -> StoreConfig {
+        self.blob_cache = n;
+        self
+    }
+
+    /// `slow_log` records the latest `len` operations that take at
+    /// least `threshold`.
+    pub fn slow_log(mut self, threshold: Duration, len: usize) -> StoreConfig {
+        self.slow_threshold = Some(threshold);
+        self.slow_log_len = len;
+        self
+    }
+
+    /// `check_write` returns the `WriteResult` a write of `v` under
+    /// `k` should be rejected with, if any.
+    pub fn check_write(&self, k: &str, v: &str) -> Result<(), WriteResult> {
+        self.key_policy.check(k).map_err(WriteResult::InvalidKey)?;
+        if v.is_empty() && !self.allow_empty_values {
+            return Err(WriteResult::EmptyValue);
+        }
+        if self.max_value_bytes.is_some_and(|max| v.len() > max) {
+            return Err(WriteResult::ValueTooLarge);
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_key_policy() {
+    let policy = KeyPolicy::new();
+    assert_eq!(policy.check("users/kyle"), Ok(()));
+    assert_eq!(policy.check("tricky, key"), Ok(()));
+    assert_eq!(policy.check(""), Err(KeyError::Empty));
+    assert_eq!(policy.check("a\nb"), Err(KeyError::InvalidChar('\n')));
+    let long = "k".repeat(DEFAULT_MAX_KEY_LEN + 1);
+    assert_eq!(policy.check(&long), Err(KeyError::TooLong { len: long.len(), max: DEFAULT_MAX_KEY_LEN }));
+
+    let policy = KeyPolicy::new().max_len(8).charset(Charset::AsciiGraphic).reserve("_sys/");
+    assert_eq!(policy.check("a b"), Err(KeyError::InvalidChar(' ')));
+    assert_eq!(policy.check("naïve"), Err(KeyError::InvalidChar('ï')));
+    assert_eq!(policy.check("_sys/x"), Err(KeyError::Reserved));
+    assert_eq!(policy.check("123456789"), Err(KeyError::TooLong { len: 9, max: 8 }));
+
+    let digits = KeyPolicy::new().charset(Charset::Custom(|c| c.is_ascii_digit()));
+    assert_eq!(digits.check("42"), Ok(()));
+    assert_eq!(digits.check("4a"), Err(KeyError::InvalidChar('a')));
+}
+
+#[test]
+fn test_check_write() {
+    let config = StoreConfig::new();
+    assert_eq!(config.check_write("k", ""), Ok(()));
+    assert_eq!(config.check_write("", "v"), Err(WriteResult::InvalidKey(KeyError::Empty)));
+
+    let config = config.allow_empty_values(false);
+    assert_eq!(config.check_write("k", ""), Err(WriteResult::EmptyValue));
+    assert_eq!(config.check_write("k", "v"), Ok(()));
+
+    let config = config.max_value_bytes(4);
+    assert_eq!(config.check_write("k", "1234"), Ok(()));
+    assert_eq!(config.check_write("k", "12345"), Err(WriteResult::ValueTooLarge));
+}
+
+#[test]
+fn test_rejected_writes() {
+    use super::WriteResult::*;
+
+    let mut kvs = super::new("".to_string());
+    kvs.insert("D800".to_string(), "Nikon".to_string());
+    assert_eq!(kvs.insert("".to_string(), "Leica".to_string()), InvalidKey(KeyError::Empty));
+    assert_eq!(kvs.update("M\n10".to_string(), "Leica".to_string()), InvalidKey(KeyError::InvalidChar('\n')));
+    assert_eq!(kvs.metrics.size, 1);
+
+    kvs.config.max_value_bytes = Some(5);
+    assert_eq!(kvs.update("D800".to_string(), "Nikon Corporation".to_string()), ValueTooLarge);
+    assert_eq!(kvs.get("D800".to_string()).unwrap(), "Nikon");
+}
@@ -0,0 +1,199 @@
+//! counters count the operations on a store, for capacity planning
+//! and for tuning stores used as caches. They only ever increase, and
+//! are kept in the store's metrics.
+//!
+//! Counters are reset when a store is loaded, unless the store is
+//! configured with `persist_counters`, in which case they're written
+//! with the store and carry on from where they were.
+use super::Store;
+use super::WriteResult;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters hold the number of each kind of operation on a store.
+/// They're updated through shared references, since reads count too.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Counters {
+    gets: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    deletes: AtomicU64,
+    flushes: AtomicU64,
+    flush_failures: AtomicU64,
+    #[serde(default)]
+    blob_errors: AtomicU64,
+    #[serde(default)]
+    scheduled_rejections: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+impl Counters {
+    /// `gets` returns the number of reads.
+    pub fn gets(&self) -> u64 {
+        self.gets.load(Ordering::Relaxed)
+    }
+
+    /// `hits` returns the number of reads that found their key.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// `misses` returns the number of reads that didn't find their
+    /// key.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// `inserts` returns the number of writes that added a key.
+    pub fn inserts(&self) -> u64 {
+        self.inserts.load(Ordering::Relaxed)
+    }
+
+    /// `updates` returns the number of writes to an existing key.
+    pub fn updates(&self) -> u64 {
+        self.updates.load(Ordering::Relaxed)
+    }
+
+    /// `deletes` returns the number of keys deleted.
+    pub fn deletes(&self) -> u64 {
+        self.deletes.load(Ordering::Relaxed)
+    }
+
+    /// `flushes` returns the number of successful flushes, including
+    /// background flushes once they've been collected.
+    pub fn flushes(&self) -> u64 {
+        self.flushes.load(Ordering::Relaxed)
+    }
+
+    /// `flush_failures` returns the number of flushes that failed.
+    pub fn flush_failures(&self) -> u64 {
+        self.flush_failures.load(Ordering::Relaxed)
+    }
+
+    /// `blob_errors` returns the number of spilled values that
+    /// couldn't be read from their side files; see the `spill` module.
+    pub fn blob_errors(&self) -> u64 {
+        self.blob_errors.load(Ordering::Relaxed)
+    }
+
+    /// `scheduled_rejections` returns the number of scheduled writes
+    /// that were rejected when they fell due; see the `schedule`
+    /// module.
+    pub fn scheduled_rejections(&self) -> u64 {
+        self.scheduled_rejections.load(Ordering::Relaxed)
+    }
+
+    /// `is_zero` returns true if nothing has been counted, so there's
+    /// nothing worth persisting.
+    pub fn is_zero(&self) -> bool {
+        [&self.gets, &self.hits, &self.misses, &self.inserts, &self.updates,
+         &self.deletes, &self.flushes, &self.flush_failures, &self.blob_errors,
+         &self.scheduled_rejections]
+            .iter()
+            .all(|counter| counter.load(Ordering::Relaxed) == 0)
+    }
+
+    /// `read` counts a read, which found its key if `found` is true.
+    pub(super) fn read(&self, found: bool) {
+        bump(&self.gets, 1);
+        bump(if found { &self.hits } else { &self.misses }, 1);
+    }
+
+    /// `wrote` counts a write that had the result `wr`; rejected
+    /// writes aren't counted.
+    pub(super) fn wrote(&self, wr: WriteResult) {
+        match wr {
+            WriteResult::Inserted => bump(&self.inserts, 1),
+            WriteResult::Updated  => bump(&self.updates, 1),
+            _                     => (),
+        }
+    }
+
+    /// `inserted` counts `n` keys added at once.
+    pub(super) fn inserted(&self, n: u64) {
+        bump(&self.inserts, n);
+    }
+
+    /// `deleted` counts a deleted key.
+    pub(super) fn deleted(&self) {
+        bump(&self.deletes, 1);
+    }
+
+    /// `flushed` counts a flush, which succeeded if `ok` is true.
+    pub(super) fn flushed(&self, ok: bool) {
+        bump(if ok { &self.flushes } else { &self.flush_failures }, 1);
+    }
+
+    /// `blob_failed` counts a spilled value that couldn't be read.
+    pub(super) fn blob_failed(&self) {
+        bump(&self.blob_errors, 1);
+    }
+
+    /// `scheduled_rejected` counts a scheduled write that was
+    /// rejected.
+    pub(super) fn scheduled_rejected(&self) {
+        bump(&self.scheduled_rejections, 1);
+    }
+}
+
+/// Cloning counters copies their current values.
+impl Clone for Counters {
+    fn clone(&self) -> Counters {
+        let copy = |counter: &AtomicU64| AtomicU64::new(counter.load(Ordering::Relaxed));
+        Counters {
+            gets: copy(&self.gets),
+            hits: copy(&self.hits),
+            misses: copy(&self.misses),
+            inserts: copy(&self.inserts),
+            updates: copy(&self.updates),
+            deletes: copy(&self.deletes),
+            flushes: copy(&self.flushes),
+            flush_failures: copy(&self.flush_failures),
+            blob_errors: copy(&self.blob_errors),
+            scheduled_rejections: copy(&self.scheduled_rejections),
+        }
+    }
+}
+
+impl Store {
+    /// `counters` returns the store's operation counters.
+    pub fn counters(&self) -> &Counters {
+        &self.metrics.counters
+    }
+}
+
+
+#[test]
+fn test_counters() {
+    let mut kvs = super::new("".to_string());
+    assert!(kvs.counters().is_zero());
+
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.update("b".to_string(), "3".to_string());
+    kvs.bulk_load(vec![("c".to_string(), "4".to_string())], false).unwrap();
+    kvs.delete("b".to_string());
+    kvs.delete("b".to_string());
+
+    kvs.get("a".to_string());
+    kvs.get("b".to_string());
+    kvs.with_value("c", |v| v.len());
+
+    let counters = kvs.counters();
+    assert_eq!((counters.inserts(), counters.updates(), counters.deletes()), (3, 1, 1));
+    assert_eq!((counters.gets(), counters.hits(), counters.misses()), (3, 2, 1));
+    assert!(!counters.is_zero());
+
+    kvs.config.max_value_bytes = Some(0);
+    kvs.update("a".to_string(), "rejected".to_string());
+    assert_eq!(kvs.counters().updates(), 1);
+
+    kvs.path = "/nonexistent/skvs/store.json".to_string();
+    assert!(kvs.flush().is_err());
+    assert_eq!((kvs.counters().flushes(), kvs.counters().flush_failures()), (0, 1));
+}
@@ -0,0 +1,288 @@
+//! diff compares the values in two stores, and replays the
+//! differences onto another store. Only values are compared: entries
+//! with the same value but different versions or timestamps, such as
+//! a store and a re-imported export of it, are considered equal.
+//!
+//! Spilled values (see the `spill` module) are read back, so a diff
+//! holds every value it names in memory. A diff can't be taken if one
+//! of them can't be read. When a diff is applied, a value in the store
+//! that can't be read is compared by its side file's name, so it only
+//! ever conflicts. Secret values are compared and copied as
+//! ciphertext, and stay secrets when the diff is applied.
+use super::entry::Entry;
+use super::quota::Planned;
+use super::spill;
+use super::{Store, WriteResult};
+use std::fmt;
+use std::io;
+
+/// Changed describes a key whose value differs between two stores,
+/// with its entry in each.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Changed {
+    pub key: String,
+    pub old: Entry,
+    pub new: Entry,
+}
+
+/// StoreDiff lists the changes that turn one store's values into
+/// another's, each sorted by key.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoreDiff {
+    /// added lists keys only present in the new store.
+    pub added: Vec<(String, Entry)>,
+
+    /// removed lists keys only present in the old store.
+    pub removed: Vec<(String, Entry)>,
+
+    /// changed lists keys present in both stores with different
+    /// values.
+    pub changed: Vec<Changed>,
+}
+
+impl StoreDiff {
+    /// `len` returns the number of keys that differ.
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.changed.len()
+    }
+
+    /// `is_empty` returns true if the stores hold the same values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// ApplyError explains why a diff couldn't be applied. The store is
+/// left unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApplyError {
+    /// Conflict lists, in sorted order, the keys whose values in the
+    /// store match neither side of the diff.
+    Conflict(Vec<String>),
+
+    /// Rejected is returned for a write the store would refuse: one
+    /// its configuration doesn't allow, one that would exceed a quota,
+    /// or one to a leased key.
+    Rejected(String, WriteResult),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApplyError::Conflict(ref keys)  => write!(f, "conflicting keys: {}", keys.join(", ")),
+            ApplyError::Rejected(ref k, wr) => write!(f, "{}: {}", k, wr.to_string()),
+        }
+    }
+}
+
+/// `holds` returns true if the value of `ent` is `v`, where `None`
+/// means the key is missing.
+fn holds(ent: Option<&Entry>, v: Option<&Entry>) -> bool {
+    ent.map(|ent| (&ent.value, &ent.blob)) == v.map(|v| (&v.value, &v.blob))
+}
+
+impl Store {
+    /// `unspilled_or_same` returns `ent` with its value in memory, or
+    /// as it is if its side file can't be read.
+    fn unspilled_or_same(&self, ent: &Entry) -> Entry {
+        self.unspilled(ent).unwrap_or_else(|_| ent.clone())
+    }
+
+    /// `diff` returns the changes that turn this store's values into
+    /// `other`'s. It fails if a spilled value in either store can't be
+    /// read.
+    pub fn diff(&self, other: &Store) -> Result<StoreDiff, io::Error> {
+        let mut diff = StoreDiff::default();
+        for (k, old) in self.values.iter() {
+            let old = self.unspilled(old)?;
+            let new = match other.values.get(k) {
+                Some(new) => Some(other.unspilled(new)?),
+                None      => None,
+            };
+            match new {
+                None                                        => diff.removed.push((k.to_string(), old)),
+                Some(new) if !holds(Some(&old), Some(&new)) => diff.changed.push(Changed {
+                    key: k.to_string(),
+                    old,
+                    new,
+                }),
+                Some(_)                                     => (),
+            }
+        }
+        for (k, new) in other.values.iter() {
+            if !self.values.contains_key(k) {
+                diff.added.push((k.to_string(), other.unspilled(new)?));
+            }
+        }
+
+        diff.added.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.removed.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.changed.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(diff)
+    }
+
+    /// `apply_diff` replays `diff` onto this store, returning the
+    /// number of keys it changed. Keys that already hold the diff's
+    /// new value are left alone, so applying a diff twice is
+    /// harmless. If any key holds a value matching neither side of
+    /// the diff, or the store would refuse any of the writes, nothing
+    /// is changed. Should the store still refuse a write once they're
+    /// under way, `Rejected` is returned for it.
+    pub fn apply_diff(&mut self, diff: &StoreDiff) -> Result<usize, ApplyError> {
+        // Each write is a key, the value the diff expects it to hold,
+        // and the value to give it.
+        let mut writes: Vec<(&str, Option<&Entry>, Option<&Entry>)> = Vec::with_capacity(diff.len());
+        writes.extend(diff.added.iter().map(|(k, new)| (k.as_str(), None, Some(new))));
+        writes.extend(diff.removed.iter().map(|(k, old)| (k.as_str(), Some(old), None)));
+        writes.extend(diff.changed.iter().map(|c| (c.key.as_str(), Some(&c.old), Some(&c.new))));
+
+        let mut conflicts: Vec<String> = writes.iter()
+            .filter(|&&(k, old, new)| {
+                let cur = self.values.get(k).map(|cur| self.unspilled_or_same(cur));
+                !holds(cur.as_ref(), old) && !holds(cur.as_ref(), new)
+            })
+            .map(|&(k, _, _)| k.to_string())
+            .collect();
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            return Err(ApplyError::Conflict(conflicts));
+        }
+        // Keys already holding their new value are left alone.
+        writes.retain(|&(k, _, new)| {
+            let cur = self.values.get(k).map(|cur| self.unspilled_or_same(cur));
+            !holds(cur.as_ref(), new)
+        });
+
+        let mut planned = Planned::new(self);
+        for &(k, _, new) in &writes {
+            let rejected = |wr| ApplyError::Rejected(k.to_string(), wr);
+            if let Some(new) = new {
+                self.config.check_write(k, &new.value).map_err(rejected)?;
+            }
+            if self.leased(k) {
+                return Err(rejected(WriteResult::Leased));
+            }
+            planned.write(k, self.values.get(k).map(spill::value_len), new.map(|new| new.value.len()))
+                .map_err(rejected)?;
+        }
+
+        let changed = writes.len();
+        for (k, _, new) in writes {
+            let wr = match new {
+                Some(new) => self.update_entry(k.to_string(), new.value.to_string(), new.secret),
+                None      => self.delete(k.to_string()),
+            };
+            match wr {
+                WriteResult::Inserted | WriteResult::Updated => (),
+                wr                                           => return Err(ApplyError::Rejected(k.to_string(), wr)),
+            }
+        }
+        Ok(changed)
+    }
+}
+
+
+#[test]
+fn test_diff() {
+    let mut prod = super::new("".to_string());
+    prod.insert("a".to_string(), "1".to_string());
+    prod.insert("b".to_string(), "2".to_string());
+    prod.insert("c".to_string(), "3".to_string());
+
+    let mut tracked = super::new("".to_string());
+    tracked.insert("a".to_string(), "1".to_string());
+    tracked.insert("b".to_string(), "two".to_string());
+    tracked.insert("d".to_string(), "4".to_string());
+
+    let diff = prod.diff(&tracked).unwrap();
+    assert_eq!(diff.len(), 3);
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].0, "d");
+    assert_eq!(diff.removed[0].0, "c");
+    assert_eq!(diff.changed[0].key, "b");
+    assert_eq!(&*diff.changed[0].old.value, "2");
+    assert_eq!(&*diff.changed[0].new.value, "two");
+    assert!(tracked.diff(&tracked.clone()).unwrap().is_empty());
+
+    let mut staging = prod.clone();
+    assert_eq!(staging.apply_diff(&diff), Ok(3));
+    assert!(staging.diff(&tracked).unwrap().is_empty());
+    assert_eq!(staging.apply_diff(&diff), Ok(0));
+    assert_eq!(staging.metrics.size, 3);
+
+    prod.update("b".to_string(), "II".to_string());
+    prod.update("c".to_string(), "III".to_string());
+    assert_eq!(prod.apply_diff(&diff),
+               Err(ApplyError::Conflict(vec!["b".to_string(), "c".to_string()])));
+    assert_eq!(prod.get("b".to_string()).unwrap(), "II");
+    assert!(prod.get("d".to_string()).is_none());
+}
+
+#[test]
+fn test_apply_diff_rejected() {
+    let mut kvs = super::new("".to_string());
+    let mut other = super::new("".to_string());
+    other.insert("a".to_string(), "1".to_string());
+    other.insert("b".to_string(), "".to_string());
+
+    kvs.config.allow_empty_values = false;
+    let diff = kvs.diff(&other).unwrap();
+    assert_eq!(kvs.apply_diff(&diff),
+               Err(ApplyError::Rejected("b".to_string(), WriteResult::EmptyValue)));
+    assert_eq!(kvs.len(), 0);
+}
+
+#[test]
+fn test_apply_diff_refused() {
+    use super::{Quota, StoreConfig};
+    use std::time::Duration;
+
+    let mut kvs = super::new("".to_string());
+    let mut other = super::new("".to_string());
+    other.insert("a".to_string(), "1".to_string());
+    other.insert("q/1".to_string(), "1".to_string());
+    other.insert("q/2".to_string(), "2".to_string());
+    let diff = kvs.diff(&other).unwrap();
+
+    kvs.config = StoreConfig::new().quota("q/", Quota::new().max_keys(1));
+    assert_eq!(kvs.apply_diff(&diff), Err(ApplyError::Rejected("q/2".to_string(), WriteResult::QuotaExceeded)));
+    assert_eq!(kvs.len(), 0);
+
+    kvs.config = StoreConfig::new();
+    kvs.acquire_lease("q/1", Duration::from_secs(60)).unwrap();
+    assert_eq!(kvs.apply_diff(&diff), Err(ApplyError::Rejected("q/1".to_string(), WriteResult::Leased)));
+    assert_eq!(kvs.len(), 0);
+}
+
+#[test]
+fn test_diff_unreadable() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k".to_string(), "v".to_string());
+    let mut other = kvs.clone();
+    other.values_mut().get_mut("k").unwrap().blob = Some("missing".to_string());
+    assert!(kvs.diff(&other).is_err());
+    assert!(other.diff(&kvs).is_err());
+    assert!(other.diff(&super::new("".to_string())).is_err());
+}
+
+#[test]
+fn test_diff_secret() {
+    use super::SecretKey;
+
+    let key = SecretKey::generate();
+    let mut kvs = super::new("".to_string());
+    kvs.set_secret_key(key.clone());
+    kvs.insert_secret("token".to_string(), "ghp_0123456789".to_string()).unwrap();
+    let empty = super::new("".to_string());
+    let diff = empty.diff(&kvs).unwrap();
+
+    let mut copy = super::new("".to_string());
+    copy.set_secret_key(key.clone());
+    assert_eq!(copy.apply_diff(&diff), Ok(1));
+    assert_eq!(copy.get_secret("token").unwrap(), "ghp_0123456789");
+
+    let mut patched = super::new("".to_string());
+    patched.set_secret_key(key);
+    assert_eq!(diff.to_patch().apply(&mut patched), Ok(1));
+    assert_eq!(patched.get_secret("token").unwrap(), "ghp_0123456789");
+}
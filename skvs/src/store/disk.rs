@@ -0,0 +1,254 @@
+//! disk is the file system as the store's persistence layer sees it.
+//! Store files are written through a `Disk` so that tests can swap in
+//! one that fails the way real disks do, and check that a store always
+//! reloads to the state of its last complete flush.
+//!
+//! Each file is written atomically: to a temporary file next to it,
+//! which then replaces it with a rename. A crash part way through
+//! leaves the old file in place. With `DurabilityPolicy::Fsync`, the
+//! temporary file is synced before the rename, so that the rename
+//! can't reach the disk ahead of the data. A sharded store's files
+//! are each replaced atomically, but not all together.
+use super::Store;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+
+/// DiskFile is a file being written.
+pub trait DiskFile: Write + Send {
+    /// `sync` waits until the file's contents have reached the disk.
+    fn sync(&mut self) -> Result<(), io::Error>;
+}
+
+/// Disk creates, replaces, and removes files.
+pub trait Disk: Send + Sync {
+    /// `create` creates or truncates the file at `path`.
+    fn create(&self, path: &str) -> Result<Box<dyn DiskFile>, io::Error>;
+
+    /// `rename` replaces the file at `to` with the one at `from`.
+    fn rename(&self, from: &str, to: &str) -> Result<(), io::Error>;
+
+    /// `remove` removes the file at `path`.
+    fn remove(&self, path: &str) -> Result<(), io::Error>;
+}
+
+/// OsDisk is the real file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsDisk;
+
+impl DiskFile for File {
+    fn sync(&mut self) -> Result<(), io::Error> {
+        self.sync_all()
+    }
+}
+
+impl Disk for OsDisk {
+    fn create(&self, path: &str) -> Result<Box<dyn DiskFile>, io::Error> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), io::Error> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), io::Error> {
+        fs::remove_file(path)
+    }
+}
+
+/// `os` returns the disk a store uses unless it's given another.
+pub fn os() -> Arc<dyn Disk> {
+    Arc::new(OsDisk)
+}
+
+impl Store {
+    /// `set_disk` makes the store write its files through `disk`.
+    pub fn set_disk(&mut self, disk: Arc<dyn Disk>) {
+        self.disk = disk;
+    }
+}
+
+/// `write_atomic` replaces the file at `path` with what `write` writes,
+/// syncing it first if `sync` is true. If anything fails, the file is
+/// left as it was.
+pub(super) fn write_atomic<F>(disk: &dyn Disk, path: &str, sync: bool, write: F) -> Result<(), io::Error>
+    where F: FnOnce(&mut dyn Write) -> Result<(), io::Error> {
+    let tmp = format!("{}.tmp", path);
+    let result = disk.create(&tmp).and_then(|file| {
+        let mut w = BufWriter::new(file);
+        write(&mut w)?;
+        let mut file = w.into_inner().map_err(|err| err.into_error())?;
+        if sync {
+            file.sync()?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(())   => disk.rename(&tmp, path),
+        Err(err) => {
+            let _ = disk.remove(&tmp);
+            Err(err)
+        },
+    }
+}
+
+
+/// Fault is a failure a `FaultyDisk` injects.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// PartialWrite fails the write that crosses the offset, after
+    /// writing the bytes before it.
+    PartialWrite(usize),
+
+    /// SyncFailure fails every sync.
+    SyncFailure,
+
+    /// PowerCut silently drops everything written from the offset
+    /// on, and fails everything after that, as if the machine had
+    /// lost power.
+    PowerCut(usize),
+}
+
+/// FaultyDisk is the real file system, with a fault injected into the
+/// files it writes.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct FaultyDisk {
+    fault: Fault,
+    down: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+impl FaultyDisk {
+    pub fn new(fault: Fault) -> FaultyDisk {
+        FaultyDisk { fault, down: Default::default() }
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        if self.down.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(io::Error::other("power cut"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct FaultyFile {
+    file: File,
+    written: usize,
+    disk: FaultyDisk,
+}
+
+#[cfg(test)]
+impl Write for FaultyFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.disk.check()?;
+        match self.disk.fault {
+            Fault::PartialWrite(at) if self.written + buf.len() > at => {
+                self.file.write_all(&buf[..at.saturating_sub(self.written)])?;
+                self.written = at;
+                Err(io::Error::other("partial write"))
+            },
+            Fault::PowerCut(at) if self.written + buf.len() > at => {
+                self.file.write_all(&buf[..at.saturating_sub(self.written)])?;
+                self.disk.down.store(true, std::sync::atomic::Ordering::SeqCst);
+                self.written += buf.len();
+                Ok(buf.len())
+            },
+            _ => {
+                self.file.write_all(buf)?;
+                self.written += buf.len();
+                Ok(buf.len())
+            },
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.disk.check()?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+impl DiskFile for FaultyFile {
+    fn sync(&mut self) -> Result<(), io::Error> {
+        self.disk.check()?;
+        if self.disk.fault == Fault::SyncFailure {
+            return Err(io::Error::other("sync failed"));
+        }
+        self.file.sync_all()
+    }
+}
+
+#[cfg(test)]
+impl Disk for FaultyDisk {
+    fn create(&self, path: &str) -> Result<Box<dyn DiskFile>, io::Error> {
+        self.check()?;
+        Ok(Box::new(FaultyFile { file: File::create(path)?, written: 0, disk: self.clone() }))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), io::Error> {
+        self.check()?;
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), io::Error> {
+        self.check()?;
+        fs::remove_file(path)
+    }
+}
+
+
+#[test]
+fn test_write_atomic() {
+    use testutil::TempStore;
+    let store = TempStore::new("disk");
+    let path: &str = &store;
+    write_atomic(&OsDisk, path, true, |w| w.write_all(b"old")).unwrap();
+
+    let faults = [Fault::PartialWrite(0), Fault::PartialWrite(2), Fault::SyncFailure,
+                  Fault::PowerCut(0), Fault::PowerCut(2)];
+    for &fault in &faults {
+        let disk = FaultyDisk::new(fault);
+        assert!(write_atomic(&disk, path, true, |w| w.write_all(b"new")).is_err(), "{:?}", fault);
+        assert_eq!(fs::read(path).unwrap(), b"old", "{:?}", fault);
+    }
+
+    write_atomic(&FaultyDisk::new(Fault::PowerCut(100)), path, false, |w| w.write_all(b"new")).unwrap();
+    assert_eq!(fs::read(path).unwrap(), b"new");
+}
+
+#[test]
+fn test_flush_faults() {
+    use testutil::TempStore;
+    use super::{DurabilityPolicy, StoreConfig};
+
+    let path = TempStore::new("faults");
+    let config = StoreConfig::new().durability(DurabilityPolicy::Fsync);
+    let mut kvs = super::new(path.to_string());
+    kvs.config = config.clone();
+    for i in 0..50 {
+        kvs.insert(format!("key/{}", i), i.to_string());
+    }
+    let flushed = fs::read(&path).unwrap();
+
+    let mut offsets: Vec<usize> = (0..flushed.len()).step_by(97).collect();
+    offsets.push(flushed.len() - 1);
+    for at in offsets {
+        for &fault in &[Fault::PartialWrite(at), Fault::PowerCut(at), Fault::SyncFailure] {
+            let mut kvs = Store::load(path.to_string()).unwrap();
+            kvs.config = config.clone();
+            kvs.set_disk(Arc::new(FaultyDisk::new(fault)));
+            kvs.update("key/0".to_string(), "changed".to_string());
+            assert!(kvs.write_error.is_some(), "{:?}", fault);
+            drop(kvs);
+
+            // Whatever went wrong, the store reloads as last flushed.
+            assert_eq!(fs::read(&path).unwrap(), flushed, "{:?}", fault);
+            let reloaded = Store::load(path.to_string()).unwrap();
+            assert_eq!(reloaded.get("key/0".to_string()), Some("0".to_string()));
+            assert_eq!(reloaded.len(), 50);
+        }
+    }
+}
@@ -1,19 +1,17 @@
 //! The Entry structure is used as the value in the simple key-value
 //! store's hash map.
-extern crate time;
-
-#[allow(unused_imports)]
-use std::thread;
-#[allow(unused_imports)]
-use std::time::Duration;
+use super::clock::{Clock, SystemClock};
+use super::hlc::Timestamp;
+use std::fmt;
+use std::sync::Arc;
 
 
 /// Entry combines metadata with the actual value to be stored.
 ///
-/// The metadata stored in an Entry is current the Unix timestamp of
-/// the last write operation (create or update), the version, and the
-/// actual string value. Note that versions start at 1 when the
-/// structure is first created.
+/// The metadata stored in an Entry is currently the hybrid logical
+/// clock timestamp of the last write operation (create or update),
+/// the version, and the actual string value. Note that versions start
+/// at 1 when the structure is first created.
 ///
 /// The `new` or `from_string` static methods should be called to
 /// obtain a new `Entry`.
@@ -21,29 +19,62 @@ use std::time::Duration;
 /// An example of the use of the `&str` functions:
 ///
 /// ```
+/// use skvs::store::clock::SystemClock;
+/// use skvs::store::entry::Entry;
+///
 /// let old = Entry::new("hello, world");
-/// assert_eq!(ent.version, 1);
-/// assert_eq!(ent.value, "hello, world");
-/// assert!(ent.time > 0);
+/// assert_eq!(old.version, 1);
+/// assert_eq!(&*old.value, "hello, world");
+/// assert!(old.time.secs() > 0);
 ///
-/// let new = Entry::update(&ent1, "goodbye, world");
+/// let new = Entry::update(&old, "goodbye, world", &SystemClock);
 /// assert_ne!(old.value, new.value);
 /// assert_eq!(new.version, old.version + 1);
-/// assert!(new.time >= old.time);
+/// assert!(new.time > old.time);
 /// ```
 ///
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     /// time stores the timestamp from the last write on the entry,
     /// whether that write is creation (version = 1) or modification
-    /// (version > 1);
-    pub time: i64,
+    /// (version > 1).
+    pub time: Timestamp,
 
     /// version is incremented on each write to the entry.
     pub version: i64,
 
-    /// value is the current value of the entry.
-    pub value: String,
+    /// value is the current value of the entry. It's reference
+    /// counted so that copies of the entry (and of the store) share
+    /// the value rather than copying it.
+    pub value: Arc<str>,
+
+    /// secret is true if the value was encrypted with the store's
+    /// secret key; see the `secret` module.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub secret: bool,
+
+    /// blob names the side file holding the value, if it was spilled
+    /// out of memory; `value` is then empty. See the `spill` module.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Secret values are shown as `***` rather than as their ciphertext.
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value: &dyn fmt::Debug = if self.secret { &"***" } else { &self.value };
+        f.debug_struct("Entry")
+            .field("time", &self.time)
+            .field("version", &self.version)
+            .field("value", value)
+            .field("secret", &self.secret)
+            .field("blob", &self.blob)
+            .finish()
+    }
 }
 
 impl Entry {
@@ -53,51 +84,69 @@ impl Entry {
         Entry::from_string(value.to_string())
     }
 
-    /// `from_string` clones the string argument and initialises a new
-    /// entry with the current time and a starting version.
+    /// `from_string` takes ownership of the string argument and
+    /// initialises a new entry with the current time and a starting
+    /// version.
     pub fn from_string(s: String) -> Entry {
+        Entry::with_clock(s, &SystemClock)
+    }
+
+    /// `with_clock` works like `from_string`, taking the time from
+    /// `clock`.
+    pub fn with_clock(s: String, clock: &dyn Clock) -> Entry {
         Entry {
-            time: time::get_time().sec,
+            time: clock.now(),
             version: 1,
-            value: s.clone(),
+            value: Arc::from(s),
+            secret: false,
+            blob: None,
         }
     }
 
     /// `update` returns a new entry with the new value, incrementing
     /// the version number if the new value differs from the old
-    /// value.
-    pub fn update(old: &Entry, nval: &str) -> Entry {
+    /// value, and taking the time of a changed entry from `clock`.
+    /// Spilled values are always taken to differ.
+    pub fn update(old: &Entry, nval: &str, clock: &dyn Clock) -> Entry {
         // TODO: there should be a way to return `old` instead of
         // reconstructing an `Entry`.
-        if old.value == nval.to_string() {
+        if old.blob.is_none() && &*old.value == nval {
             Entry {
                 time: old.time,
                 version: old.version,
                 value: old.value.clone(),
+                secret: old.secret,
+                blob: None,
             }
         } else {
             Entry {
-                time: time::get_time().sec,
+                time: clock.now(),
                 version: old.version + 1,
-                value: nval.to_string(),
+                value: Arc::from(nval),
+                secret: false,
+                blob: None,
             }
         }
     }
 
-    /// `update_from_string` works like update, except it clones the
-    /// string argument.
-    pub fn update_from_string(old: &Entry, s: String) -> Entry {
-        if old.value == s {
+    /// `update_from_string` works like update, except it takes
+    /// ownership of the string argument.
+    pub fn update_from_string(old: &Entry, s: String, clock: &dyn Clock) -> Entry {
+        if old.blob.is_none() && *old.value == *s {
             Entry {
                 time: old.time,
                 version: old.version,
                 value: old.value.clone(),
+                secret: old.secret,
+                blob: None,
             }
         } else {
             Entry {
-                time: time::get_time().sec,
+                time: clock.now(),
                 version: old.version + 1,
-                value: s.clone(),
+                value: Arc::from(s),
+                secret: false,
+                blob: None,
             }
         }
     }
@@ -107,32 +156,38 @@ impl Entry {
 fn test_new_entry() {
     let ent = Entry::new("hello, world");
     assert_eq!(ent.version, 1);
-    assert_eq!(ent.value, "hello, world");
-    assert!(ent.time > 0);
+    assert_eq!(&*ent.value, "hello, world");
+    assert!(ent.time.secs() > 0);
 }
 
 #[test]
 fn test_update_entry() {
+    use super::clock::MockClock;
+
     let ent1 = Entry::new("hello, world");
-    thread::sleep(Duration::new(1, 0));
-    
-    let ent2 = Entry::update(&ent1, "goodbye, world");
+    let ent2 = Entry::update(&ent1, "goodbye, world", &SystemClock);
     assert_ne!(ent1.value, ent2.value);
     assert_eq!(ent2.version, ent1.version + 1);
-    assert!(ent2.time >= ent1.time);
+    assert!(ent2.time > ent1.time);
+
+    let ent3 = Entry::update(&ent2, "goodbye, world", &SystemClock);
+    assert_eq!(ent3.version, ent2.version);
+    assert_eq!(ent3.time, ent2.time);
+
+    let clock = MockClock::new(Timestamp::from_secs(1500000000));
+    let ent4 = Entry::update(&ent3, "hello again", &clock);
+    assert_eq!(ent4.time, Timestamp::from_secs(1500000000));
 }
 
 #[test]
 fn test_string_variants() {
     let ent1 = Entry::from_string("hello, world".to_string());
     assert_eq!(ent1.version, 1);
-    assert_eq!(ent1.value, "hello, world".to_string());
-    assert!(ent1.time > 0);
+    assert_eq!(&*ent1.value, "hello, world");
+    assert!(ent1.time.secs() > 0);
 
-    thread::sleep(Duration::new(1, 0));
-    
-    let ent2 = Entry::update_from_string(&ent1, "goodbye, world".to_string());
+    let ent2 = Entry::update_from_string(&ent1, "goodbye, world".to_string(), &SystemClock);
     assert_ne!(ent1.value, ent2.value);
     assert_eq!(ent2.version, ent1.version + 1);
-    assert!(ent2.time >= ent1.time);    
+    assert!(ent2.time > ent1.time);
 }
@@ -0,0 +1,308 @@
+//! events forwards the store's change events to other systems, so
+//! that downstream services hear about writes (to configuration, say)
+//! without polling. An `EventSink` delivers journal records somewhere;
+//! `Store::forward_events` feeds one from a background thread, handing
+//! it the records in batches of up to `MAX_BATCH` as they arrive.
+//!
+//! `WebhookSink` is the built-in sink: it POSTs each batch as a JSON
+//! array of records to an `http://` URL, retrying with exponential
+//! backoff when the endpoint can't be reached or answers with a server
+//! error. There's no TLS support; point it at a local relay to reach
+//! an `https://` endpoint.
+//!
+//! A batch the sink can't deliver is dropped and counted in
+//! `Forwarder::failed`, so that a dead endpoint can't hold up the
+//! store or grow a backlog without bound.
+//!
+//! Sinks may also hold records back, to coalesce or rate limit them;
+//! the forwarder polls them every `POLL_INTERVAL` so held records go
+//! out even when the store is quiet. See the `webhook` module.
+extern crate serde_json;
+
+use super::journal::JournalRecord;
+use super::Store;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// `MAX_BATCH` is the most records handed to a sink at once.
+pub const MAX_BATCH: usize = 100;
+
+/// `POLL_INTERVAL` is how often a forwarder polls its sink.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// EventSink delivers the store's change events to another system.
+pub trait EventSink: Send {
+    /// `send` delivers `batch`, whose records are in the order the
+    /// writes were made.
+    fn send(&mut self, batch: &[JournalRecord]) -> Result<(), io::Error>;
+
+    /// `poll` delivers whatever the sink has held back and is due,
+    /// returning how many records it failed to deliver. `last` is set
+    /// for the final poll, once the store is gone, when everything
+    /// held back should be delivered. Sinks that deliver each batch
+    /// as it's sent needn't implement it.
+    fn poll(&mut self, _last: bool) -> usize {
+        0
+    }
+}
+
+/// Forwarder tracks the thread feeding an `EventSink`. The thread
+/// stops once the store it's forwarding from is dropped.
+#[derive(Debug)]
+pub struct Forwarder {
+    thread: thread::JoinHandle<()>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl Forwarder {
+    /// `failed` returns how many records the sink has failed to
+    /// deliver.
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// `wait` blocks until every record has been handed to the sink,
+    /// which happens after the store is dropped, and returns how many
+    /// it failed to deliver.
+    pub fn wait(self) -> usize {
+        let _ = self.thread.join();
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+impl Store {
+    /// `forward_events` hands the journal record for every write from
+    /// now on to `sink`, from a background thread.
+    pub fn forward_events<S: EventSink + 'static>(&mut self, mut sink: S) -> Forwarder {
+        let records = self.journal.subscribe();
+        let failed = Arc::new(AtomicUsize::new(0));
+        let counted = failed.clone();
+        let thread = thread::spawn(move || {
+            loop {
+                match records.recv_timeout(POLL_INTERVAL) {
+                    Ok(rec)                             => {
+                        let mut batch = vec![rec];
+                        batch.extend(records.try_iter().take(MAX_BATCH - 1));
+                        if sink.send(&batch).is_err() {
+                            counted.fetch_add(batch.len(), Ordering::SeqCst);
+                        }
+                    },
+                    Err(RecvTimeoutError::Timeout)      => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                counted.fetch_add(sink.poll(false), Ordering::SeqCst);
+            }
+            counted.fetch_add(sink.poll(true), Ordering::SeqCst);
+        });
+        Forwarder { thread, failed }
+    }
+}
+
+/// WebhookSink POSTs batches of records, as JSON, to a URL.
+#[derive(Clone, Debug)]
+pub struct WebhookSink {
+    host: String,
+    addr: String,
+    path: String,
+
+    /// retries is how many times a failed delivery is retried.
+    pub retries: u32,
+
+    /// backoff is how long to wait before the first retry; the wait
+    /// doubles before each retry after that.
+    pub backoff: Duration,
+
+    /// timeout limits how long connecting, sending, and waiting for
+    /// the response can each take.
+    pub timeout: Duration,
+}
+
+impl WebhookSink {
+    /// `new` returns a sink for `url`, which must be an `http://` URL,
+    /// retrying three times after waiting 100ms, 200ms, and 400ms.
+    pub fn new(url: &str) -> Result<WebhookSink, io::Error> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None       => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: only http:// URLs are supported", url))),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None    => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: missing host", url)));
+        }
+        let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+
+        Ok(WebhookSink {
+            host: host.to_string(),
+            addr,
+            path: path.to_string(),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            timeout: Duration::from_secs(10),
+        })
+    }
+
+    /// `retries` sets how many times a failed delivery is retried.
+    pub fn retries(mut self, retries: u32) -> WebhookSink {
+        self.retries = retries;
+        self
+    }
+
+    /// `backoff` sets the wait before the first retry.
+    pub fn backoff(mut self, backoff: Duration) -> WebhookSink {
+        self.backoff = backoff;
+        self
+    }
+
+    /// `timeout` sets the network timeout.
+    pub fn timeout(mut self, timeout: Duration) -> WebhookSink {
+        self.timeout = timeout;
+        self
+    }
+
+    /// `post` makes one attempt at delivering `body`, returning the
+    /// response's status code.
+    fn post(&self, body: &[u8]) -> Result<u16, io::Error> {
+        let addr = match self.addr.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None       => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no address", self.host))),
+        };
+        let mut conn = TcpStream::connect_timeout(&addr, self.timeout)?;
+        conn.set_read_timeout(Some(self.timeout))?;
+        conn.set_write_timeout(Some(self.timeout))?;
+
+        write!(conn, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+               self.path, self.host, body.len())?;
+        conn.write_all(body)?;
+        conn.flush()?;
+
+        let mut status = String::new();
+        BufReader::new(conn).read_line(&mut status)?;
+        match status.split_whitespace().nth(1).and_then(|code| code.parse().ok()) {
+            Some(code) => Ok(code),
+            None       => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad response: {:?}", status.trim_end()))),
+        }
+    }
+}
+
+impl EventSink for WebhookSink {
+    /// Client errors (4xx) aren't retried, since sending the same
+    /// batch again won't help.
+    fn send(&mut self, batch: &[JournalRecord]) -> Result<(), io::Error> {
+        let body = serde_json::to_vec(batch)?;
+        let mut wait = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let err = match self.post(&body) {
+                Ok(code) if (200..300).contains(&code) => return Ok(()),
+                Ok(code) if (400..500).contains(&code) => return Err(io::Error::other(format!("webhook returned {}", code))),
+                Ok(code)                               => io::Error::other(format!("webhook returned {}", code)),
+                Err(err)                               => err,
+            };
+            if attempt == self.retries {
+                return Err(err);
+            }
+            thread::sleep(wait);
+            wait *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+
+#[test]
+fn test_forward_events() {
+    use std::sync::Mutex;
+
+    struct Collect(Arc<Mutex<Vec<JournalRecord>>>);
+    impl EventSink for Collect {
+        fn send(&mut self, batch: &[JournalRecord]) -> Result<(), io::Error> {
+            assert!(!batch.is_empty() && batch.len() <= MAX_BATCH);
+            if batch.iter().any(|rec| rec.key == "unsendable") {
+                return Err(io::Error::other("rejected"));
+            }
+            self.0.lock().unwrap().extend_from_slice(batch);
+            Ok(())
+        }
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut kvs = super::new("".to_string());
+    let forwarder = kvs.forward_events(Collect(seen.clone()));
+    for i in 0..250 {
+        kvs.insert(format!("key/{}", i), i.to_string());
+    }
+    kvs.delete("key/0".to_string());
+    drop(kvs);
+
+    assert_eq!(forwarder.wait(), 0);
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 251);
+    assert_eq!(seen[0], JournalRecord { key: "key/0".to_string(), version: 1 });
+    assert_eq!(seen[250], JournalRecord { key: "key/0".to_string(), version: 0 });
+
+    let mut kvs = super::new("".to_string());
+    let forwarder = kvs.forward_events(Collect(Arc::new(Mutex::new(Vec::new()))));
+    kvs.insert("unsendable".to_string(), "v".to_string());
+    drop(kvs);
+    assert_eq!(forwarder.wait(), 1);
+}
+
+/// `serve_http` answers a request on a local port with each of
+/// `statuses` in turn, returning the port's address and a handle that
+/// yields the requests once they've all been answered.
+#[cfg(test)]
+pub(super) fn serve_http(statuses: &'static [&'static str])
+                         -> (std::net::SocketAddr, thread::JoinHandle<Vec<String>>) {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buf = [0; 4096];
+            while !request.find("\r\n\r\n").is_some_and(|end| {
+                let length = request.lines().find_map(|l| l.strip_prefix("Content-Length: "));
+                request.len() >= end + 4 + length.map_or(0, |n| n.parse().unwrap())
+            }) {
+                let n = conn.read(&mut buf).unwrap();
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            write!(conn, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+    (addr, server)
+}
+
+#[test]
+fn test_webhook_sink() {
+    assert!(WebhookSink::new("https://example.com/hook").is_err());
+    assert!(WebhookSink::new("http:///hook").is_err());
+
+    // The endpoint fails once, then accepts the batch.
+    let (addr, server) = serve_http(&["503 Service Unavailable", "204 No Content", "400 Bad Request"]);
+    let url = format!("http://{}/hooks/skvs", addr);
+
+    let mut sink = WebhookSink::new(&url).unwrap().backoff(Duration::from_millis(1));
+    let batch = [JournalRecord { key: "flags/dark-mode".to_string(), version: 2 }];
+    sink.send(&batch).unwrap();
+    assert!(sink.send(&batch).is_err());
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[0].starts_with("POST /hooks/skvs HTTP/1.1\r\n"));
+    assert!(requests[0].contains("Content-Type: application/json\r\n"));
+}
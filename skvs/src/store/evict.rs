@@ -0,0 +1,128 @@
+//! evict removes entries that haven't been written for a while, such
+//! as abandoned session keys. Evicting an entry works like deleting
+//! it: journal subscribers are told, and a tombstone is left if the
+//! store keeps them. Evictions aren't recorded in the undo history,
+//! and leased keys aren't evicted.
+//!
+//! `evict_older_than` does a single pass; an `Evictor` runs one
+//! periodically on another thread for a store shared behind a mutex.
+use super::hlc::Timestamp;
+use super::Store;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+impl Store {
+    /// `evict_older_than` removes every entry last written more than
+    /// `age` ago, returning how many were removed. Leased keys are
+    /// kept, and nothing is evicted from a read-only store.
+    pub fn evict_older_than(&mut self, age: Duration) -> usize {
+        if self.is_read_only() {
+            return 0;
+        }
+        let now = self.clock.now();
+        let age = age.as_nanos().min(i64::MAX as u128) as i64;
+        let cutoff = Timestamp { nanos: now.nanos.saturating_sub(age), counter: 0 };
+
+        let stale: Vec<(String, i64)> = self.values.iter()
+            .filter(|&(k, ent)| ent.time < cutoff && !self.leased(k))
+            .map(|(k, ent)| (k.to_string(), ent.version))
+            .collect();
+        if stale.is_empty() {
+            return 0;
+        }
+
+        for &(ref k, version) in &stale {
+            if let Some(old) = self.values_mut().remove(k.as_str()) {
+                self.reindex(k, Some(&old));
+            }
+            self.bury(k, version + 1);
+            self.journal.record(k, 0);
+            self.metrics.counters.deleted();
+        }
+        self.update_metrics(true, false);
+        stale.len()
+    }
+}
+
+/// Evictor periodically evicts old entries from a shared store. The
+/// thread stops when the evictor or the store is dropped.
+#[derive(Debug)]
+pub struct Evictor {
+    stop: Arc<AtomicBool>,
+}
+
+impl Evictor {
+    /// `start` evicts entries older than `age` from `store` every
+    /// `interval`.
+    pub fn start(store: &Arc<Mutex<Store>>, age: Duration, interval: Duration) -> Evictor {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let store: Weak<Mutex<Store>> = Arc::downgrade(store);
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let store = match store.upgrade() {
+                    Some(store) => store,
+                    None        => return,
+                };
+                let mut kvs = store.lock().unwrap_or_else(|err| err.into_inner());
+                if !stopped.load(Ordering::Relaxed) {
+                    kvs.evict_older_than(age);
+                }
+            }
+        });
+        Evictor { stop }
+    }
+}
+
+impl Drop for Evictor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+
+#[test]
+fn test_evict_older_than() {
+    use super::clock::MockClock;
+
+    let clock = Arc::new(MockClock::new(Timestamp::from_secs(1500000000)));
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(clock.clone());
+    kvs.config.tombstones = true;
+    let keys = kvs.subscribe_keys();
+
+    kvs.insert("session/old".to_string(), "1".to_string());
+    kvs.insert("session/leased".to_string(), "1".to_string());
+    kvs.acquire_lease("session/leased", Duration::from_secs(7200)).unwrap();
+    clock.advance(Duration::from_secs(3600));
+    kvs.insert("session/new".to_string(), "2".to_string());
+    clock.advance(Duration::from_secs(60));
+
+    assert_eq!(kvs.evict_older_than(Duration::from_secs(600)), 1);
+    assert!(kvs.get("session/old".to_string()).is_none());
+    assert!(kvs.get("session/new".to_string()).is_some());
+    assert!(kvs.get("session/leased".to_string()).is_some());
+    assert_eq!(kvs.tombstones["session/old"].version, 2);
+    assert_eq!(kvs.metrics.size, 2);
+    assert_eq!(keys.try_iter().last().map(|rec| rec.version), Some(0));
+    assert_eq!(kvs.evict_older_than(Duration::from_secs(600)), 0);
+}
+
+#[test]
+fn test_evictor() {
+    let kvs = Arc::new(Mutex::new(super::new("".to_string())));
+    kvs.lock().unwrap().insert("k".to_string(), "v".to_string());
+
+    let evictor = Evictor::start(&kvs, Duration::from_secs(0), Duration::from_millis(1));
+    for _ in 0..1000 {
+        if kvs.lock().unwrap().len() == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(evictor);
+    assert_eq!(kvs.lock().unwrap().len(), 0);
+}
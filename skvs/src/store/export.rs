@@ -0,0 +1,567 @@
+//! export implements reading and writing the store's key-value pairs
+//! in portable formats (JSON lines, CSV, and dotenv files), so that a
+//! store can be seeded from or dumped to files that other tools (and
+//! git) understand. Only keys and values are exported; the entry
+//! metadata is regenerated on import. Values covered by the store's
+//! redaction policy, which include secrets, are exported as `***`; see
+//! the `redact` module.
+//!
+//! There are also exporters producing the JSON that etcd and Consul
+//! work with, so a store used for service configuration can be moved
+//! to either without a custom converter.
+extern crate serde_json;
+
+use super::Store;
+use super::WriteResult::*;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Format selects the file format used by `Store::export` and
+/// `Store::import`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// JsonLines writes one `{"key": ..., "value": ...}` object per
+    /// line.
+    JsonLines,
+    /// Csv writes a `key,value` header followed by one quoted record
+    /// per entry.
+    Csv,
+    /// DotEnv writes `key="value"` lines, as used by env files.
+    DotEnv,
+}
+
+/// ConflictPolicy determines what `Store::import` does when an
+/// imported key already exists in the store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    /// Skip leaves the existing entry alone.
+    Skip,
+    /// Overwrite updates the existing entry with the imported value.
+    Overwrite,
+    /// Error aborts the import before any key is written.
+    Error,
+}
+
+/// ImportReport counts what happened to each record during an
+/// import.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// inserted is the number of keys that didn't exist before.
+    pub inserted: usize,
+    /// updated is the number of existing keys that were overwritten.
+    pub updated: usize,
+    /// skipped is the number of existing keys that were left alone,
+    /// either because of the conflict policy or because they already
+    /// had the imported value.
+    pub skipped: usize,
+}
+
+/// Record is the JSON lines representation of a key-value pair.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    value: String,
+}
+
+/// ConsulPair is an entry in the array read by `consul kv import`.
+#[derive(Serialize)]
+struct ConsulPair {
+    key: String,
+    flags: u64,
+    value: String,
+}
+
+/// EtcdKeyValue is a key-value pair as etcd encodes it in JSON.
+#[derive(Serialize)]
+struct EtcdKeyValue {
+    key: String,
+    version: i64,
+    value: String,
+}
+
+/// EtcdRange mirrors the output of `etcdctl get --prefix -w json`.
+#[derive(Serialize)]
+struct EtcdRange {
+    kvs: Vec<EtcdKeyValue>,
+    count: usize,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Store {
+    /// `export` writes every key-value pair in the store to `w` in
+    /// the given format. Keys are written in sorted order so that
+    /// exports of the same store are identical and diff cleanly.
+    pub fn export<W: Write>(&self, format: Format, w: &mut W) -> Result<(), io::Error> {
+        let keys = self.sorted_keys();
+        if format == Format::Csv {
+            writeln!(w, "key,value")?;
+        }
+
+        for k in keys {
+            let v = self.shown(k, &self.values[k])?;
+            match format {
+                Format::JsonLines => {
+                    let rec = Record { key: k.to_string(), value: v.to_string() };
+                    match serde_json::to_writer(&mut *w, &rec) {
+                        Ok(_)    => (),
+                        Err(err) => return Err(io::Error::other(err.to_string())),
+                    }
+                    writeln!(w)?;
+                },
+                Format::Csv       => writeln!(w, "{},{}", csv_quote(k), csv_quote(&v))?,
+                Format::DotEnv    => {
+                    if k.is_empty() || k.contains('=') || k.contains('\n') || k.starts_with('#') {
+                        return Err(invalid_data(format!("key {:?} can't be written to an env file", k)));
+                    }
+                    writeln!(w, "{}={}", k, env_quote(&v))?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `export_consul` writes the store as the JSON array produced by
+    /// `consul kv export` and read by `consul kv import`: one
+    /// `{"key", "flags", "value"}` object per entry, with the value
+    /// base64-encoded.
+    pub fn export_consul<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        let pairs = self.sorted_keys().into_iter()
+            .map(|k| Ok(ConsulPair {
+                key: k.to_string(),
+                flags: 0,
+                value: base64(self.shown(k, &self.values[k])?.as_bytes()),
+            }))
+            .collect::<Result<Vec<ConsulPair>, io::Error>>()?;
+
+        match serde_json::to_writer(w, &pairs) {
+            Ok(_)    => Ok(()),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        }
+    }
+
+    /// `export_etcd` writes the store in the shape etcd uses for
+    /// JSON range responses (`etcdctl get --prefix -w json`): a
+    /// `kvs` array whose keys and values are base64-encoded, along
+    /// with each entry's version.
+    pub fn export_etcd<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        let kvs = self.sorted_keys().into_iter()
+            .map(|k| Ok(EtcdKeyValue {
+                key: base64(k.as_bytes()),
+                version: self.values[k].version,
+                value: base64(self.shown(k, &self.values[k])?.as_bytes()),
+            }))
+            .collect::<Result<Vec<EtcdKeyValue>, io::Error>>()?;
+        let range = EtcdRange { count: kvs.len(), kvs };
+
+        match serde_json::to_writer(w, &range) {
+            Ok(_)    => Ok(()),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        }
+    }
+
+    fn sorted_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.values.keys().map(|k| &**k).collect();
+        keys.sort();
+        keys
+    }
+
+    /// `import` reads key-value pairs in the given format from `r`
+    /// and writes them to the store. Keys that already exist are
+    /// handled according to `policy`; with `ConflictPolicy::Error`,
+    /// the store is left untouched if any key already exists. The
+    /// whole input is parsed, and every pair checked against the
+    /// store's configuration and its leases, before anything is
+    /// written, so malformed input never results in a partial import.
+    /// Quotas are checked as each pair is written, so an import that
+    /// would exceed one stops with an error, keeping the pairs before
+    /// it. Existing keys whose value wouldn't change aren't written,
+    /// and are counted as skipped.
+    pub fn import<R: Read>(&mut self, format: Format, r: R, policy: ConflictPolicy)
+                           -> Result<ImportReport, io::Error> {
+        let records = match format {
+            Format::JsonLines => parse_json_lines(r)?,
+            Format::Csv       => parse_csv(r)?,
+            Format::DotEnv    => parse_env(r)?,
+        };
+
+        for (k, v) in &records {
+            if let Err(wr) = self.config.check_write(k, v) {
+                return Err(invalid_data(format!("{:?}: {}", k, wr.to_string())));
+            }
+            let skipped = policy == ConflictPolicy::Skip && self.values.contains_key(k.as_str());
+            if !skipped && !self.holds(k, v)? && self.leased(k) {
+                return Err(invalid_data(format!("{:?}: {}", k, Leased.to_string())));
+            }
+        }
+
+        if policy == ConflictPolicy::Error {
+            for (k, _) in &records {
+                if self.values.contains_key(k.as_str()) {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                              format!("key {:?} already exists", k)));
+                }
+            }
+        }
+
+        let mut report = ImportReport::default();
+        for (k, v) in records {
+            let skipped = policy == ConflictPolicy::Skip && self.values.contains_key(k.as_str());
+            if skipped || self.holds(&k, &v)? {
+                report.skipped += 1;
+                continue;
+            }
+
+            match self.update(k.clone(), v) {
+                Inserted => report.inserted += 1,
+                Updated  => report.updated += 1,
+                wr       => return Err(invalid_data(format!("{:?}: {}", k, wr.to_string()))),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// `holds` returns true if `k` exists and already has the value `v`.
+    fn holds(&self, k: &str, v: &str) -> Result<bool, io::Error> {
+        match self.values.get(k) {
+            Some(ent) => Ok(&*self.shared_value(ent)? == v),
+            None      => Ok(false),
+        }
+    }
+}
+
+/// `base64` encodes `data` using the standard, padded alphabet.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn parse_json_lines<R: Read>(r: R) -> Result<Vec<(String, String)>, io::Error> {
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(r).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<Record>(&line) {
+            Ok(rec)  => records.push((rec.key, rec.value)),
+            Err(err) => return Err(invalid_data(format!("line {}: {}", n + 1, err))),
+        }
+    }
+    Ok(records)
+}
+
+/// `csv_quote` quotes a CSV field if it contains a delimiter, quote,
+/// or line break; embedded quotes are doubled.
+fn csv_quote(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// `parse_csv` reads RFC 4180 style records with exactly two fields.
+/// A leading `key,value` header is skipped.
+fn parse_csv<R: Read>(mut r: R) -> Result<Vec<(String, String)>, io::Error> {
+    let mut input = String::new();
+    r.read_to_string(&mut input)?;
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                _   => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ','  => row.push(field.split_off(0)),
+            '\r' => (),
+            '\n' => {
+                row.push(field.split_off(0));
+                rows.push(row.split_off(0));
+            },
+            _    => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(invalid_data("unterminated quoted field".to_string()));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    let mut records = Vec::new();
+    for (n, mut row) in rows.into_iter().enumerate() {
+        if row.len() == 1 && row[0].is_empty() {
+            continue;
+        }
+        if row.len() != 2 {
+            return Err(invalid_data(format!("record {}: expected 2 fields, found {}",
+                                            n + 1, row.len())));
+        }
+        if n == 0 && row[0] == "key" && row[1] == "value" {
+            continue;
+        }
+
+        let v = row.pop().unwrap();
+        let k = row.pop().unwrap();
+        records.push((k, v));
+    }
+    Ok(records)
+}
+
+/// `env_quote` double-quotes a value, escaping backslashes, quotes,
+/// and line breaks.
+fn env_quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"'  => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            _    => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `parse_env` reads `key=value` lines, ignoring blank lines,
+/// comments, and a leading `export`. Double-quoted values have their
+/// escapes processed; single-quoted values are taken literally.
+fn parse_env<R: Read>(r: R) -> Result<Vec<(String, String)>, io::Error> {
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(r).lines().enumerate() {
+        let line = line?;
+        let mut line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with("export ") {
+            line = line["export ".len()..].trim_start();
+        }
+
+        let (k, v) = match line.find('=') {
+            Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            None    => return Err(invalid_data(format!("line {}: missing '='", n + 1))),
+        };
+
+        let value = if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') {
+            env_unquote(&v[1..v.len() - 1])
+        } else if v.len() >= 2 && v.starts_with('\'') && v.ends_with('\'') {
+            v[1..v.len() - 1].to_string()
+        } else {
+            v.to_string()
+        };
+        records.push((k.to_string(), value));
+    }
+    Ok(records)
+}
+
+fn env_unquote(s: &str) -> String {
+    let mut value = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some(c)   => value.push(c),
+            None      => value.push('\\'),
+        }
+    }
+    value
+}
+
+
+#[test]
+fn test_csv_round_trip() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("plain".to_string(), "value".to_string());
+    kvs.insert("tricky, key".to_string(), "say \"hi\"\nthen leave".to_string());
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export(Format::Csv, &mut buf).unwrap();
+    assert!(buf.starts_with(b"key,value\n"));
+
+    let mut kvs2 = super::new("".to_string());
+    let report = kvs2.import(Format::Csv, &buf[..], ConflictPolicy::Error).unwrap();
+    assert_eq!(report.inserted, 2);
+    assert_eq!(kvs2.get("plain".to_string()).unwrap(), "value");
+    assert_eq!(kvs2.get("tricky, key".to_string()).unwrap(), "say \"hi\"\nthen leave");
+}
+
+#[test]
+fn test_env_round_trip() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("HOME".to_string(), "/home/kyle".to_string());
+    kvs.insert("GREETING".to_string(), "hello\n\"world\"".to_string());
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export(Format::DotEnv, &mut buf).unwrap();
+
+    let mut kvs2 = super::new("".to_string());
+    kvs2.import(Format::DotEnv, &buf[..], ConflictPolicy::Error).unwrap();
+    assert_eq!(kvs2.get("HOME".to_string()).unwrap(), "/home/kyle");
+    assert_eq!(kvs2.get("GREETING".to_string()).unwrap(), "hello\n\"world\"");
+
+    let env = "# comment\n\nexport SHELL=/bin/ksh\nQUOTED='a \\n b'\n";
+    kvs2.import(Format::DotEnv, env.as_bytes(), ConflictPolicy::Error).unwrap();
+    assert_eq!(kvs2.get("SHELL".to_string()).unwrap(), "/bin/ksh");
+    assert_eq!(kvs2.get("QUOTED".to_string()).unwrap(), "a \\n b");
+}
+
+#[test]
+fn test_import_conflicts() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+
+    let csv = "a,2\nb,3\n";
+    assert!(kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Error).is_err());
+    assert_eq!(kvs.len(), 1);
+
+    let mut report = kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Skip).unwrap();
+    assert_eq!(report, ImportReport { inserted: 1, updated: 0, skipped: 1 });
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "1");
+
+    report = kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Overwrite).unwrap();
+    assert_eq!(report, ImportReport { inserted: 0, updated: 1, skipped: 1 });
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "2");
+    assert_eq!(kvs.values["b"].version, 1);
+
+    // Importing the same values again changes nothing.
+    report = kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Overwrite).unwrap();
+    assert_eq!(report, ImportReport { inserted: 0, updated: 0, skipped: 2 });
+    assert_eq!(kvs.values["a"].version, 2);
+
+    // An invalid key anywhere in the input means nothing is imported.
+    assert!(kvs.import(Format::Csv, "c,4\n\"\",5\n".as_bytes(), ConflictPolicy::Overwrite).is_err());
+    assert!(kvs.get("c".to_string()).is_none());
+}
+
+#[test]
+fn test_import_leased() {
+    use std::time::Duration;
+
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.acquire_lease("a", Duration::from_secs(60)).unwrap();
+
+    let csv = "b,2\na,2\n";
+    let err = kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Overwrite).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "1");
+    assert!(kvs.get("b".to_string()).is_none());
+
+    // Skipped keys aren't written, so their leases don't matter.
+    let report = kvs.import(Format::Csv, csv.as_bytes(), ConflictPolicy::Skip).unwrap();
+    assert_eq!(report, ImportReport { inserted: 1, updated: 0, skipped: 1 });
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+}
+
+#[test]
+fn test_export_consul() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("service/port".to_string(), "8080".to_string());
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export_consul(&mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(),
+               r#"[{"key":"service/port","flags":0,"value":"ODA4MA=="}]"#);
+}
+
+#[test]
+fn test_json_lines_round_trip() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("X-Pro2".to_string(), "Fujifilm".to_string());
+    kvs.insert("D800".to_string(), "Nikon".to_string());
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export(Format::JsonLines, &mut buf).unwrap();
+    assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 2);
+
+    let mut kvs2 = super::new("".to_string());
+    kvs2.import(Format::JsonLines, &buf[..], ConflictPolicy::Error).unwrap();
+    assert_eq!(kvs2.get("D800".to_string()).unwrap(), "Nikon");
+}
+
+#[test]
+fn test_export_masks_secrets() {
+    let mut kvs = super::new("".to_string());
+    kvs.set_secret_key(super::SecretKey::generate());
+    kvs.insert_secret("API_TOKEN".to_string(), "hunter2".to_string()).unwrap();
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export(Format::DotEnv, &mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "API_TOKEN=\"***\"\n");
+}
+
+#[test]
+fn test_export_redacts_prefixes() {
+    let mut kvs = super::new("".to_string());
+    kvs.config.redaction = super::RedactionPolicy::new().prefix("db/");
+    kvs.insert("db/password".to_string(), "hunter2".to_string());
+    kvs.insert("name".to_string(), "skvs".to_string());
+
+    let mut buf: Vec<u8> = Vec::new();
+    kvs.export(Format::Csv, &mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "key,value\ndb/password,***\nname,skvs\n");
+}
+
+#[test]
+fn test_export_unreadable() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.values_mut().get_mut("k").unwrap().blob = Some("missing".to_string());
+
+    assert!(kvs.export(Format::JsonLines, &mut Vec::new()).is_err());
+    assert!(kvs.export_consul(&mut Vec::new()).is_err());
+    assert!(kvs.export_etcd(&mut Vec::new()).is_err());
+}
@@ -0,0 +1,243 @@
+//! flush writes the store to disk in the background. The store is
+//! copied when the flush starts, which is the only time the caller
+//! waits; the copy is serialized on another thread while the store
+//! keeps taking reads and writes. Writes made after the flush starts
+//! aren't included in it.
+//!
+//! Flushes are written in the order they were started: each one waits
+//! for the one before it to finish, as does a plain `flush`, so an
+//! older copy of the store never replaces a newer one on disk.
+//!
+//! The outcome is reported twice: `FlushHandle::wait` returns it to
+//! whoever started the flush, and the store picks it up the next time
+//! it's written to (or when `collect_flush` is called), updating
+//! `metrics.last_write` or `write_error`.
+use super::hlc::Timestamp;
+use super::reload::FileStamp;
+use super::slowlog::SlowOp;
+use super::Store;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Outcome is what a finished background flush reports: the
+/// `last_write` time and the stamp of the written file on success, or
+/// the error, along with the flush itself if it was slow.
+struct Outcome {
+    result: Result<(Timestamp, Option<FileStamp>), String>,
+    slow: Option<SlowOp>,
+}
+
+/// Flushes holds the outcomes of the store's background flushes, in
+/// the order they finished, until the store collects them, along with
+/// what the next flush waits on. Like the journal, it belongs to one
+/// store: cloning it yields one with nothing pending.
+#[derive(Default)]
+pub struct Flushes {
+    outcomes: Arc<Mutex<Vec<Outcome>>>,
+
+    /// last is disconnected once the latest flush is done.
+    last: Mutex<Option<Receiver<()>>>,
+}
+
+impl Flushes {
+    /// `new` returns a `Flushes` with nothing pending.
+    pub fn new() -> Flushes {
+        Flushes::default()
+    }
+
+    fn take(&self) -> Vec<Outcome> {
+        std::mem::take(&mut *self.outcomes.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// `follow` makes `done` the signal of the latest flush, returning
+    /// the previous one's.
+    fn follow(&self, done: Receiver<()>) -> Option<Receiver<()>> {
+        self.last.lock().unwrap_or_else(|err| err.into_inner()).replace(done)
+    }
+
+    /// `wait` blocks until every flush started so far is done.
+    pub(super) fn wait(&self) {
+        if let Some(last) = self.last.lock().unwrap_or_else(|err| err.into_inner()).take() {
+            let _ = last.recv();
+        }
+    }
+
+    /// `in_progress` returns true if a background flush is still
+    /// running; its thread holds a reference to the outcomes.
+    pub fn in_progress(&self) -> bool {
+        Arc::strong_count(&self.outcomes) > 1
+    }
+}
+
+impl Clone for Flushes {
+    fn clone(&self) -> Flushes {
+        Flushes::new()
+    }
+}
+
+impl fmt::Debug for Flushes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Flushes").finish()
+    }
+}
+
+/// FlushHandle tracks a background flush.
+#[derive(Debug)]
+pub struct FlushHandle {
+    thread: Option<thread::JoinHandle<Result<(), io::Error>>>,
+}
+
+impl FlushHandle {
+    /// `is_finished` returns true if the flush is done, successfully
+    /// or not.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    /// `wait` blocks until the flush is done and returns its result.
+    pub fn wait(self) -> Result<(), io::Error> {
+        match self.thread {
+            Some(t) => t.join().unwrap_or_else(|_| Err(io::Error::other("flush thread panicked"))),
+            None    => Ok(()),
+        }
+    }
+}
+
+impl Store {
+    /// `flush_background` starts writing a copy of the store to disk
+    /// on another thread and returns a handle to it. The copy is
+    /// written once any flush started before it is done. Stores
+    /// without a path have nothing to write, so the returned handle is
+    /// already finished.
+    pub fn flush_background(&mut self) -> FlushHandle {
+        self.collect_flush();
+        if self.path.is_empty() {
+            return FlushHandle { thread: None };
+        }
+
+        let start = self.slow_start();
+        let mut snapshot = self.clone();
+        let outcomes = self.flushes.outcomes.clone();
+        let (done, finished) = mpsc::channel::<()>();
+        let previous = self.flushes.follow(finished);
+        let thread = thread::spawn(move || {
+            // done is dropped when the thread ends, however it ends,
+            // which lets the next flush go ahead.
+            let _done = done;
+            if let Some(previous) = previous {
+                let _ = previous.recv();
+            }
+            let result = snapshot.write();
+            let slow = snapshot.time_slow(start, "flush", "", snapshot.len());
+            outcomes.lock().unwrap_or_else(|err| err.into_inner()).push(Outcome {
+                result: match result {
+                    Ok(())       => Ok((snapshot.metrics.last_write, snapshot.stamp)),
+                    Err(ref err) => Err(err.to_string()),
+                },
+                slow,
+            });
+            result
+        });
+        FlushHandle { thread: Some(thread) }
+    }
+
+    /// `collect_flush` applies the outcomes of finished background
+    /// flushes to the store, in the order they finished, returning
+    /// true if there were any.
+    pub fn collect_flush(&mut self) -> bool {
+        let outcomes = self.flushes.take();
+        let collected = !outcomes.is_empty();
+        for outcome in outcomes {
+            if let Some(slow) = outcome.slow {
+                self.push_slow(slow);
+            }
+            match outcome.result {
+                Ok((last_write, stamp)) => {
+                    if last_write > self.metrics.last_write {
+                        self.metrics.last_write = last_write;
+                    }
+                    self.stamp = stamp;
+                    self.write_error = None;
+                    self.metrics.counters.flushed(true);
+                },
+                Err(err)                => {
+                    self.write_error = Some(err);
+                    self.metrics.counters.flushed(false);
+                },
+            }
+        }
+        collected
+    }
+}
+
+
+#[test]
+fn test_flush_background() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k".to_string(), "v".to_string());
+    let handle = kvs.flush_background();
+    assert!(handle.is_finished());
+    handle.wait().unwrap();
+    assert!(!kvs.collect_flush());
+
+    kvs.path = "/nonexistent/skvs/store.json".to_string();
+    let handle = kvs.flush_background();
+    kvs.insert("k2".to_string(), "v2".to_string());
+    assert!(handle.wait().is_err());
+    // The insert may have collected the outcome already.
+    kvs.collect_flush();
+    assert!(kvs.write_error.is_some());
+    assert_eq!(kvs.metrics.last_write, Timestamp::default());
+}
+
+#[test]
+fn test_flush_background_order() {
+    use testutil::TempStore;
+    let path = TempStore::new("flush-order");
+    let mut kvs = super::new(path.to_string());
+    for i in 0..1000 {
+        kvs.insert(format!("key/{}", i), i.to_string());
+    }
+    kvs.collect_flush();
+    let flushes = kvs.metrics.counters.flushes();
+
+    let mut handles = Vec::new();
+    for i in 0..8 {
+        kvs.update("key/0".to_string(), format!("flush {}", i));
+        handles.push(kvs.flush_background());
+    }
+    kvs.update("key/1".to_string(), "last".to_string());
+    kvs.flush().unwrap();
+    assert!(handles.iter().all(|handle| handle.is_finished()));
+    for handle in handles {
+        handle.wait().unwrap();
+    }
+    kvs.collect_flush();
+    assert!(kvs.metrics.counters.flushes() >= flushes + 9);
+
+    let loaded = Store::load(path.to_string()).unwrap();
+    assert_eq!(loaded.get("key/0".to_string()), Some("flush 7".to_string()));
+    assert_eq!(loaded.get("key/1".to_string()), Some("last".to_string()));
+}
+
+#[test]
+fn test_flush_background_slow() {
+    use super::StoreConfig;
+    use std::time::Duration;
+    use testutil::TempStore;
+
+    let path = TempStore::new("flush-slow");
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().slow_log(Duration::from_secs(0), 8);
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.reset_slow_log();
+
+    kvs.flush_background().wait().unwrap();
+    assert!(kvs.slow_log().is_empty());
+    kvs.collect_flush();
+    let ops: Vec<(&str, usize)> = kvs.slow_log().into_iter().map(|slow| (slow.op, slow.size)).collect();
+    assert_eq!(ops, vec![("flush", 1)]);
+}
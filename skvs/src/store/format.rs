@@ -0,0 +1,148 @@
+//! format upgrades stores written in older versions of the on-disk
+//! format as they're loaded. A store's JSON is read as a generic value
+//! first; if its `format_version` is older than `FORMAT_VERSION`, the
+//! upgrades from its version onwards are applied in turn before it's
+//! decoded. Stores written by a newer version of skvs are refused
+//! rather than misread.
+//!
+//! This is separate from the `migrations` module: migrations change
+//! the keys and values an application keeps in the store, while
+//! upgrades only change how the store is written down.
+//!
+//! Adding a format version means bumping `FORMAT_VERSION` and adding
+//! the upgrade to it to the end of `UPGRADES`.
+extern crate serde_json;
+
+use self::serde_json::{json, Value};
+use super::hlc::Timestamp;
+use super::FORMAT_VERSION;
+use std::convert::TryFrom;
+use std::io;
+
+/// Upgrade rewrites a store from one format version to the next.
+pub struct Upgrade {
+    /// description says what the upgrade changes.
+    pub description: &'static str,
+
+    /// apply rewrites the store's JSON in place. The shard files of a
+    /// sharded store are upgraded by the same function, with each
+    /// file's values wrapped as `{"values": ...}`.
+    pub apply: fn(&mut Value) -> Result<(), String>,
+}
+
+/// UPGRADES lists the upgrades in order: `UPGRADES[n]` takes a store
+/// from version `n` to version `n + 1`.
+pub static UPGRADES: [Upgrade; FORMAT_VERSION as usize] = [
+    Upgrade { description: "record times as hybrid logical clock timestamps", apply: seconds_to_hlc },
+    Upgrade { description: "record migration times as timestamps",            apply: migration_times },
+];
+
+/// `version_of` returns the format version recorded in `doc`. Stores
+/// written before versions were recorded are version 0.
+pub fn version_of(doc: &Value) -> Result<u32, io::Error> {
+    match doc.get("format_version") {
+        None    => Ok(0),
+        Some(v) => v.as_u64().and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("invalid format version {}", v))),
+    }
+}
+
+/// `upgrade` brings `doc`, written in format `version`, up to
+/// `FORMAT_VERSION`. The `format_version` field is left as it was, so
+/// the loaded store records the version it was read in.
+pub fn upgrade(doc: &mut Value, version: u32) -> Result<(), io::Error> {
+    if version > FORMAT_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("store format version {} is newer than the latest this version of skvs supports ({})",
+                                          version, FORMAT_VERSION)));
+    }
+
+    for (from, step) in UPGRADES.iter().enumerate().skip(version as usize) {
+        if let Err(err) = (step.apply)(doc) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("upgrading from format version {} ({}): {}",
+                                              from, step.description, err)));
+        }
+    }
+    Ok(())
+}
+
+/// `seconds_to_hlc` upgrades from version 0, which recorded entry and
+/// metrics times as whole seconds since the Unix epoch. Entries
+/// written by the hybrid logical clock before format versions were
+/// recorded already have structured times, and are left alone.
+fn seconds_to_hlc(doc: &mut Value) -> Result<(), String> {
+    if let Some(metrics) = doc.get_mut("metrics") {
+        for field in &["last_update", "last_write"] {
+            if let Some(time) = metrics.get_mut(field) {
+                seconds_to_timestamp(time)?;
+            }
+        }
+    }
+    if let Some(values) = doc.get_mut("values").and_then(Value::as_object_mut) {
+        for ent in values.values_mut() {
+            if let Some(time) = ent.get_mut("time") {
+                seconds_to_timestamp(time)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `migration_times` upgrades from version 1, which recorded the time
+/// each migration was applied as whole seconds since the Unix epoch.
+fn migration_times(doc: &mut Value) -> Result<(), String> {
+    if let Some(migrations) = doc.get_mut("migrations").and_then(Value::as_array_mut) {
+        for migration in migrations {
+            if let Some(time) = migration.get_mut("time") {
+                seconds_to_timestamp(time)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn seconds_to_timestamp(time: &mut Value) -> Result<(), String> {
+    if time.is_object() {
+        return Ok(());
+    }
+    let ts = match time.as_i64() {
+        Some(secs) => Timestamp::from_secs(secs),
+        None       => return Err(format!("invalid time {}", time)),
+    };
+    *time = json!({ "nanos": ts.nanos, "counter": ts.counter });
+    Ok(())
+}
+
+
+#[test]
+fn test_upgrade() {
+    let mut doc = json!({
+        "metrics": { "last_update": 1500000000, "last_write": 0, "size": 2 },
+        "values": {
+            "old": { "time": 1500000000, "version": 1, "value": "a" },
+            "new": { "time": { "nanos": 5, "counter": 1 }, "version": 1, "value": "b" },
+        },
+    });
+    assert_eq!(version_of(&doc).unwrap(), 0);
+    upgrade(&mut doc, 0).unwrap();
+    assert_eq!(doc["metrics"]["last_update"], json!({ "nanos": 1500000000000000000i64, "counter": 0 }));
+    assert_eq!(doc["values"]["old"]["time"], json!({ "nanos": 1500000000000000000i64, "counter": 0 }));
+    assert_eq!(doc["values"]["new"]["time"], json!({ "nanos": 5, "counter": 1 }));
+    assert!(doc.get("format_version").is_none());
+
+    let mut doc = json!({ "format_version": 1, "migrations": [{ "version": 1, "time": 1500000000 }] });
+    let version = version_of(&doc).unwrap();
+    upgrade(&mut doc, version).unwrap();
+    assert_eq!(doc["migrations"][0]["time"], json!({ "nanos": 1500000000000000000i64, "counter": 0 }));
+
+    let mut doc = json!({ "format_version": FORMAT_VERSION + 1 });
+    let version = version_of(&doc).unwrap();
+    let err = upgrade(&mut doc, version).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("newer"));
+
+    let mut doc = json!({ "values": { "k": { "time": "noon" } } });
+    assert!(upgrade(&mut doc, 0).is_err());
+}
@@ -0,0 +1,164 @@
+//! glob selects keys by shell-style patterns, treating keys as
+//! '/'-separated paths:
+//!
+//! - `*` matches any run of characters within a path segment;
+//! - `**` matches any run of characters, including '/';
+//! - `?` matches a single character other than '/';
+//! - `\` makes the character after it match literally.
+//!
+//! So `users/*/email` matches `users/kyle/email` but not
+//! `users/kyle/work/email`, which `users/**/email` matches. Only keys
+//! starting with the pattern's literal prefix (`users/` here) are
+//! tried against the rest of it.
+//!
+//! With the `regex` feature, `scan_regex` selects keys by regular
+//! expression instead.
+#[cfg(feature = "regex")]
+extern crate regex;
+
+use super::Store;
+use std::io;
+
+/// `literal_prefix` returns the part of `pattern` before its first
+/// wildcard or escape.
+fn literal_prefix(pattern: &str) -> &str {
+    match pattern.find(['*', '?', '\\']) {
+        Some(i) => &pattern[..i],
+        None    => pattern,
+    }
+}
+
+/// `glob_match` returns true if `key` matches all of `pattern`.
+pub fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    matches(&pattern, &key)
+}
+
+/// `matches` walks `pattern` and `key` together, remembering the last
+/// `*` and the last `**` seen. When the rest of the pattern fails to
+/// match, the last `*` takes one more character and the match resumes
+/// after it; if that character is a '/', the `*` can't take it, and
+/// the last `**` does instead. A later `**` can take anything an
+/// earlier wildcard could, so the earlier ones are forgotten, and the
+/// match takes at most O(n·m) steps.
+fn matches(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    while k < key.len() {
+        let (width, ok) = match pattern.get(p) {
+            Some(&'*') if pattern.get(p + 1) == Some(&'*') => {
+                globstar = Some((p + 2, k));
+                star = None;
+                p += 2;
+                continue;
+            },
+            Some(&'*')                           => {
+                star = Some((p + 1, k));
+                p += 1;
+                continue;
+            },
+            Some(&'?')                           => (1, key[k] != '/'),
+            Some(&'\\') if p + 1 < pattern.len() => (2, pattern[p + 1] == key[k]),
+            Some(&c)                             => (1, c == key[k]),
+            None                                 => (0, false),
+        };
+        if ok {
+            p += width;
+            k += 1;
+            continue;
+        }
+
+        match (star, globstar) {
+            (Some((sp, sk)), _) if key[sk] != '/' => {
+                star = Some((sp, sk + 1));
+                p = sp;
+                k = sk + 1;
+            },
+            (_, Some((gp, gk)))                   => {
+                globstar = Some((gp, gk + 1));
+                star = None;
+                p = gp;
+                k = gk + 1;
+            },
+            _                                     => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Store {
+    /// `scan_glob` returns the key-value pairs whose keys match the
+    /// glob `pattern`, in key order. If a spilled value can't be read,
+    /// the error is returned.
+    pub fn scan_glob(&self, pattern: &str) -> Result<Vec<(String, String)>, io::Error> {
+        let prefix = literal_prefix(pattern);
+        self.select(|k| k.starts_with(prefix) && glob_match(pattern, k))
+    }
+
+    /// `scan_regex` returns the key-value pairs whose keys match the
+    /// regular expression `pattern`, in key order. The pattern isn't
+    /// anchored unless it says so. A pattern that doesn't compile is
+    /// returned as an `InvalidInput` error.
+    #[cfg(feature = "regex")]
+    pub fn scan_regex(&self, pattern: &str) -> Result<Vec<(String, String)>, io::Error> {
+        let re = regex::Regex::new(pattern).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.select(|k| re.is_match(k))
+    }
+
+    fn select<F: Fn(&str) -> bool>(&self, wanted: F) -> Result<Vec<(String, String)>, io::Error> {
+        let mut selected: Vec<(String, String)> = self.values.iter()
+            .filter(|&(k, _)| wanted(k))
+            .map(|(k, ent)| self.shared_value(ent).map(|v| (k.to_string(), v.to_string())))
+            .collect::<Result<_, io::Error>>()?;
+        selected.sort();
+        Ok(selected)
+    }
+}
+
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("users/*/email", "users/kyle/email"));
+    assert!(!glob_match("users/*/email", "users/kyle/work/email"));
+    assert!(glob_match("users/**/email", "users/kyle/work/email"));
+    assert!(glob_match("users/**", "users/kyle/work/email"));
+    assert!(glob_match("user?/*", "users/kyle"));
+    assert!(!glob_match("user?/*", "user/kyle"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("a\\*b", "a*b"));
+    assert!(!glob_match("a\\*b", "axb"));
+    assert!(!glob_match("users/*", "users/kyle/email"));
+    assert!(glob_match("**/a*", "x/a/y/ab"));
+    assert!(!glob_match("**/a*", "x/a/y/b"));
+    assert!(glob_match("users/**/e*l", "users/kyle/work/email"));
+    assert!(!glob_match("users/*l", "users/kyle/email"));
+    assert!(glob_match("*a*b", "xaxaxb"));
+    assert!(!glob_match("a\\b", "a\\b"));
+    assert!(glob_match("a\\", "a\\"));
+
+    // Patterns with many wildcards match in time proportional to the
+    // pattern and key lengths multiplied, rather than exponentially.
+    let key = "a".repeat(100);
+    assert!(!glob_match("*a*a*a*a*a*a*a*a*a*a*a*a*b", &key));
+    assert!(!glob_match("**a**a**a**a**a**a**a**a**a**a**a**a**b", &key));
+    assert_eq!(literal_prefix("users/*/email"), "users/");
+}
+
+#[test]
+fn test_scan_glob() {
+    let mut kvs = super::new("".to_string());
+    for k in &["users/kyle/email", "users/kyle/name", "users/ana/email", "groups/ops/email"] {
+        kvs.insert(k.to_string(), k.to_string());
+    }
+
+    let keys: Vec<String> = kvs.scan_glob("users/*/email").unwrap().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec!["users/ana/email", "users/kyle/email"]);
+    assert_eq!(kvs.scan_glob("*/*/email").unwrap().len(), 3);
+    assert!(kvs.scan_glob("nobody/*").unwrap().is_empty());
+
+    kvs.values_mut().get_mut("groups/ops/email").unwrap().blob = Some("missing".to_string());
+    assert!(kvs.scan_glob("groups/*/email").is_err());
+    assert_eq!(kvs.scan_glob("users/**").unwrap().len(), 3);
+}
@@ -0,0 +1,190 @@
+//! hasher lets users choose how the store hashes its keys, trading
+//! speed against resistance to hash flooding.
+//!
+//! - `HashKind::SipHash`, the default, is the standard library's keyed
+//!   hash. Each store gets random keys, so someone choosing keys can't
+//!   predict which of them collide. Use it whenever keys come from
+//!   people or programs you don't trust, such as clients of a server:
+//!   otherwise they can pick keys that all land in one bucket, turning
+//!   every lookup into a scan of them.
+//! - `HashKind::Fx` is the much faster, unkeyed hash used by rustc.
+//!   It's a good choice for keys the program makes up itself, and a
+//!   poor one for anything else.
+//!
+//! Like the configuration, the choice belongs to the running program
+//! and isn't persisted: loaded stores use SipHash until `set_hasher`
+//! says otherwise.
+//!
+//! ```
+//! use skvs::store::HashKind;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.insert("counter/1".to_string(), "0".to_string());
+//! kvs.set_hasher(HashKind::Fx);
+//! assert_eq!(kvs.hasher(), HashKind::Fx);
+//! assert!(kvs.get("counter/1".to_string()).is_some());
+//! ```
+use super::entry::Entry;
+use super::Store;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+/// Values is the map holding a store's entries.
+pub type Values = HashMap<Arc<str>, Entry, KeyHasher>;
+
+/// HashKind names a hash function for keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HashKind {
+    /// SipHash is slower, but resists hash flooding.
+    #[default]
+    SipHash,
+
+    /// Fx is fast, but its collisions are easy to find.
+    Fx,
+}
+
+/// KeyHasher builds hashers of the chosen kind for the values map.
+#[derive(Clone, Debug)]
+pub struct KeyHasher {
+    kind: HashKind,
+    keys: RandomState,
+}
+
+impl KeyHasher {
+    /// `new` returns a builder for `kind` hashers. SipHash builders
+    /// get fresh random keys.
+    pub fn new(kind: HashKind) -> KeyHasher {
+        KeyHasher { kind, keys: RandomState::new() }
+    }
+
+    /// `kind` returns the kind of hasher built.
+    pub fn kind(&self) -> HashKind {
+        self.kind
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> KeyHasher {
+        KeyHasher::new(HashKind::default())
+    }
+}
+
+impl BuildHasher for KeyHasher {
+    type Hasher = KeyHash;
+
+    fn build_hasher(&self) -> KeyHash {
+        match self.kind {
+            HashKind::SipHash => KeyHash::SipHash(self.keys.build_hasher()),
+            HashKind::Fx      => KeyHash::Fx(FxHasher::default()),
+        }
+    }
+}
+
+/// KeyHash is a hasher built by `KeyHasher`.
+#[derive(Clone, Debug)]
+pub enum KeyHash {
+    SipHash(DefaultHasher),
+    Fx(FxHasher),
+}
+
+impl Hasher for KeyHash {
+    fn write(&mut self, bytes: &[u8]) {
+        match *self {
+            KeyHash::SipHash(ref mut h) => h.write(bytes),
+            KeyHash::Fx(ref mut h)      => h.write(bytes),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match *self {
+            KeyHash::SipHash(ref h) => h.finish(),
+            KeyHash::Fx(ref h)      => h.finish(),
+        }
+    }
+}
+
+const FX_SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// FxHasher is the hash function from rustc: it mixes in a word at a
+/// time with a rotate, xor, and multiply.
+#[derive(Clone, Debug, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(word);
+            self.add(u64::from_le_bytes(buf));
+        }
+        for &b in words.remainder() {
+            self.add(u64::from(b));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Store {
+    /// `hasher` returns the kind of hash used for the store's keys.
+    pub fn hasher(&self) -> HashKind {
+        self.values.hasher().kind()
+    }
+
+    /// `set_hasher` switches the store to hashing its keys with
+    /// `kind`, rehashing the entries it already has.
+    pub fn set_hasher(&mut self, kind: HashKind) {
+        if self.hasher() == kind {
+            return;
+        }
+        let mut values = Values::with_capacity_and_hasher(self.values.len(), KeyHasher::new(kind));
+        values.extend(self.values.iter().map(|(k, ent)| (k.clone(), ent.clone())));
+        self.values = Arc::new(values);
+    }
+}
+
+
+#[test]
+fn test_fx_hasher() {
+    let hash = |s: &str| {
+        let mut h = FxHasher::default();
+        h.write(s.as_bytes());
+        h.finish()
+    };
+    assert_eq!(hash("users/kyle"), hash("users/kyle"));
+    assert_ne!(hash("users/kyle"), hash("users/kylf"));
+    assert_ne!(hash("a"), hash("b"));
+}
+
+#[test]
+fn test_set_hasher() {
+    let mut kvs = super::new("".to_string());
+    assert_eq!(kvs.hasher(), HashKind::SipHash);
+    for i in 0..100 {
+        kvs.insert(format!("key/{}", i), i.to_string());
+    }
+
+    kvs.set_hasher(HashKind::Fx);
+    assert_eq!(kvs.hasher(), HashKind::Fx);
+    assert_eq!(kvs.len(), 100);
+    assert_eq!(kvs.get("key/42".to_string()).unwrap(), "42");
+    kvs.insert("key/100".to_string(), "100".to_string());
+    assert_eq!(kvs.clone().hasher(), HashKind::Fx);
+
+    kvs.set_hasher(HashKind::SipHash);
+    assert_eq!(kvs.len(), 101);
+    assert!(kvs.get("key/100".to_string()).is_some());
+}
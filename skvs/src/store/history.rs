@@ -0,0 +1,238 @@
+//! history keeps a bounded record of recent writes so that they can be
+//! undone and redone, for the REPL and for applications that expose
+//! an edit history. It's off unless `StoreConfig::undo_depth` is set,
+//! and it isn't persisted.
+//!
+//! Only `insert`, `update`, and `delete` (including the delete done by
+//! `soft_delete`) are recorded. Undoing or redoing a change is itself a
+//! write: the key gets the next version, rather than going back to an
+//! old one, so journal subscribers and merges see it like any other
+//! write. Making a new change clears the redo history.
+use super::entry::Entry;
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::collections::VecDeque;
+use std::mem;
+
+/// Change records a single write: the entry for `key` before and
+/// after it, where `None` means the key wasn't present. Spilled values
+/// are kept in memory, so the change can be undone after their side
+/// files are gone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub key: String,
+    pub before: Option<Entry>,
+    pub after: Option<Entry>,
+}
+
+/// History holds the changes that can be undone, oldest first, and
+/// the changes that have been undone and can be redone.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    undo: VecDeque<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// `new` returns an empty history.
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// `push` records a new change, forgetting the oldest changes so
+    /// that at most `depth` are kept, and clears the redo history.
+    fn push(&mut self, change: Change, depth: usize) {
+        self.redo.clear();
+        self.undo.push_back(change);
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// `is_empty` returns true if there's nothing to undo or redo.
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty() && self.redo.is_empty()
+    }
+}
+
+/// `rejected` returns true if `wr` means the store wasn't changed
+/// because the write wasn't allowed.
+fn rejected(wr: WriteResult) -> bool {
+    matches!(wr, Denied | InvalidKey(_) | EmptyValue | ValueTooLarge | QuotaExceeded | Leased | VersionConflict)
+}
+
+impl Store {
+    /// `remember` records a write to `k`, whose entry was `before`,
+    /// in the undo history; it's called after the write is made. A
+    /// write whose values can't be read back (see the `spill` module)
+    /// can't be undone, nor can the changes before it, so the history
+    /// is cleared instead.
+    pub(super) fn remember(&mut self, k: &str, before: Option<&Entry>) {
+        if self.config.undo_depth == 0 && self.history.is_empty() {
+            return;
+        }
+
+        let read = |ent: Option<&Entry>| ent.map(|ent| self.unspilled(ent)).transpose();
+        let change = match (read(before), read(self.values.get(k))) {
+            (Ok(before), Ok(after)) => Change { key: k.to_string(), before, after },
+            _                       => {
+                self.history = History::new();
+                return;
+            },
+        };
+        self.history.push(change, self.config.undo_depth);
+    }
+
+    /// `undo` reverts the most recent change, returning the result of
+    /// the write that reverts it, or `DoesNotExist` if there's nothing
+    /// to undo. If the store's configuration no longer allows the old
+    /// value, the rejection is returned and the change stays in the
+    /// history.
+    pub fn undo(&mut self) -> WriteResult {
+        let change = match self.history.undo.pop_back() {
+            Some(change) => change,
+            None         => return DoesNotExist,
+        };
+
+        let wr = self.set(&change.key, change.before.clone());
+        if rejected(wr) {
+            self.history.undo.push_back(change);
+        } else {
+            self.history.redo.push(change);
+        }
+        wr
+    }
+
+    /// `redo` reapplies the most recently undone change, returning
+    /// the result of the write, or `DoesNotExist` if there's nothing
+    /// to redo.
+    pub fn redo(&mut self) -> WriteResult {
+        let change = match self.history.redo.pop() {
+            Some(change) => change,
+            None         => return DoesNotExist,
+        };
+
+        let wr = self.set(&change.key, change.after.clone());
+        if rejected(wr) {
+            self.history.redo.push(change);
+        } else {
+            self.history.undo.push_back(change);
+        }
+        wr
+    }
+
+    /// `can_undo` returns true if there's a change to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    /// `can_redo` returns true if there's an undone change to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    /// `set` writes the value of `ent` to `k`, keeping it a secret if
+    /// it was one, or deletes `k` if `ent` is `None`, without
+    /// recording the write in the history.
+    fn set(&mut self, k: &str, ent: Option<Entry>) -> WriteResult {
+        let history = mem::replace(&mut self.history, History::new());
+        let wr = match ent {
+            Some(ent) => self.update_entry(k.to_string(), ent.value.to_string(), ent.secret),
+            None      => self.delete(k.to_string()),
+        };
+        self.history = history;
+        wr
+    }
+}
+
+
+#[test]
+fn test_undo_redo() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    assert!(!kvs.can_undo());
+    assert_eq!(kvs.undo(), DoesNotExist);
+
+    kvs.config.undo_depth = 2;
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.insert("b".to_string(), "3".to_string());
+    kvs.insert("b".to_string(), "4".to_string());
+
+    assert_eq!(kvs.undo(), Updated);
+    assert!(kvs.get("b".to_string()).is_none());
+    assert_eq!(kvs.undo(), Updated);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "1");
+    assert_eq!(kvs.values["a"].version, 3);
+    assert!(!kvs.can_undo());
+
+    assert_eq!(kvs.redo(), Updated);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "2");
+    assert!(kvs.can_redo());
+
+    // A new change clears the redo history.
+    kvs.delete("a".to_string());
+    assert!(!kvs.can_redo());
+    assert_eq!(kvs.redo(), DoesNotExist);
+    assert_eq!(kvs.undo(), Inserted);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "2");
+
+    // Only undo_depth changes are kept.
+    for i in 0..5 {
+        kvs.update("c".to_string(), i.to_string());
+    }
+    assert_eq!(kvs.undo(), Updated);
+    assert_eq!(kvs.undo(), Updated);
+    assert_eq!(kvs.get("c".to_string()).unwrap(), "2");
+    assert!(!kvs.can_undo());
+}
+
+#[test]
+fn test_undo_rejected() {
+    let mut kvs = super::new("".to_string());
+    kvs.config.undo_depth = 8;
+    kvs.insert("a".to_string(), "a long value".to_string());
+    kvs.update("a".to_string(), "short".to_string());
+
+    kvs.config.max_value_bytes = Some(8);
+    assert_eq!(kvs.undo(), ValueTooLarge);
+    assert!(kvs.can_undo());
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "short");
+}
+
+#[test]
+fn test_undo_unreadable() {
+    let mut kvs = super::new("".to_string());
+    kvs.config.undo_depth = 8;
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.insert("b".to_string(), "2".to_string());
+    kvs.values_mut().get_mut("b").unwrap().blob = Some("missing".to_string());
+
+    // Undoing the delete would otherwise delete b again rather than
+    // bring it back.
+    kvs.delete("b".to_string());
+    assert!(!kvs.can_undo());
+    assert!(kvs.get("a".to_string()).is_some());
+}
+
+#[test]
+fn test_undo_secret() {
+    use super::SecretKey;
+
+    let mut kvs = super::new("".to_string());
+    kvs.config.undo_depth = 8;
+    kvs.set_secret_key(SecretKey::generate());
+    kvs.insert_secret("token".to_string(), "ghp_0123456789".to_string()).unwrap();
+    kvs.update("token".to_string(), "plain".to_string());
+    assert!(kvs.get_secret("token").is_err());
+
+    assert_eq!(kvs.undo(), Updated);
+    assert_eq!(kvs.get_secret("token").unwrap(), "ghp_0123456789");
+    assert_eq!(kvs.redo(), Updated);
+    assert_eq!(kvs.get("token".to_string()).unwrap(), "plain");
+    assert_eq!(kvs.undo(), Updated);
+    assert_eq!(kvs.undo(), Updated);
+    assert!(kvs.get("token".to_string()).is_none());
+    assert_eq!(kvs.redo(), Inserted);
+    assert_eq!(kvs.get_secret("token").unwrap(), "ghp_0123456789");
+}
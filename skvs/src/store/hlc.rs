@@ -0,0 +1,223 @@
+//! hlc implements a hybrid logical clock, which produces timestamps
+//! that follow the wall clock as closely as possible while still
+//! being strictly increasing: two writes in the same nanosecond (or
+//! while the wall clock is stepped backwards) are ordered by a logical
+//! counter instead of being indistinguishable.
+//!
+//! There is a single clock per process. Timestamps received from
+//! other stores (e.g. by `Store::merge`) should be passed to
+//! `observe`, so that later local timestamps sort after them.
+//!
+//! In the browser (on `wasm32`), the wall clock is JavaScript's
+//! `Date`, which only has millisecond precision.
+#[cfg(target_arch = "wasm32")]
+extern crate js_sys;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Timestamp is a point in time from the hybrid logical clock:
+/// nanoseconds since the Unix epoch, plus a counter ordering
+/// timestamps that share the same nanosecond. Timestamps compare by
+/// `nanos`, then `counter`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+         Serialize, Deserialize)]
+pub struct Timestamp {
+    pub nanos: i64,
+    pub counter: u32,
+}
+
+static CLOCK: Mutex<Timestamp> = Mutex::new(Timestamp { nanos: 0, counter: 0 });
+
+#[cfg(not(target_arch = "wasm32"))]
+fn wall_nanos() -> i64 {
+    Timestamp::from_system_time(SystemTime::now()).nanos
+}
+
+#[cfg(target_arch = "wasm32")]
+fn wall_nanos() -> i64 {
+    (js_sys::Date::now() * 1_000_000.0) as i64
+}
+
+/// `advance` moves the clock to at least `floor`, ticking it forward
+/// from the wall clock, and returns the new time.
+fn advance(floor: Timestamp) -> Timestamp {
+    let mut last = CLOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let wall = Timestamp { nanos: wall_nanos(), counter: 0 };
+    let latest = if floor > *last { floor } else { *last };
+
+    *last = if wall.nanos > latest.nanos { wall } else { latest.next() };
+    *last
+}
+
+/// `observe` notes a timestamp from another clock, so that every
+/// timestamp subsequently returned by `Timestamp::now` is later.
+pub fn observe(remote: Timestamp) {
+    advance(remote);
+}
+
+impl Timestamp {
+    /// `now` returns a timestamp later than any returned before.
+    pub fn now() -> Timestamp {
+        advance(Timestamp::default())
+    }
+
+    /// `next` returns the earliest timestamp after this one: the next
+    /// counter value, or the next nanosecond once the counter runs
+    /// out. The latest representable timestamp is its own successor,
+    /// rather than wrapping around to an earlier one.
+    pub fn next(&self) -> Timestamp {
+        match self.counter.checked_add(1) {
+            Some(counter)                 => Timestamp { nanos: self.nanos, counter },
+            None if self.nanos < i64::MAX => Timestamp { nanos: self.nanos + 1, counter: 0 },
+            None                          => *self,
+        }
+    }
+
+    /// `from_secs` returns the timestamp for the start of second
+    /// `secs` since the Unix epoch, clamped to the representable
+    /// range.
+    pub fn from_secs(secs: i64) -> Timestamp {
+        Timestamp { nanos: secs.saturating_mul(1_000_000_000), counter: 0 }
+    }
+
+    /// `secs` returns the number of whole seconds since the Unix
+    /// epoch.
+    pub fn secs(&self) -> i64 {
+        self.nanos.div_euclid(1_000_000_000)
+    }
+
+    /// `from_millis` returns the timestamp for the start of
+    /// millisecond `millis` since the Unix epoch, clamped to the
+    /// representable range.
+    pub fn from_millis(millis: i64) -> Timestamp {
+        Timestamp { nanos: millis.saturating_mul(1_000_000), counter: 0 }
+    }
+
+    /// `millis` returns the number of whole milliseconds since the
+    /// Unix epoch.
+    pub fn millis(&self) -> i64 {
+        self.nanos.div_euclid(1_000_000)
+    }
+
+    /// `from_system_time` returns the timestamp for `t`, clamped to
+    /// the representable range (roughly the years 1678 to 2262).
+    pub fn from_system_time(t: SystemTime) -> Timestamp {
+        let nanos = match t.duration_since(UNIX_EPOCH) {
+            Ok(d)    => i64::try_from(d.as_nanos()).unwrap_or(i64::MAX),
+            Err(err) => i64::try_from(err.duration().as_nanos()).map(|n| -n).unwrap_or(i64::MIN),
+        };
+        Timestamp { nanos, counter: 0 }
+    }
+
+    /// `to_system_time` returns the wall clock time of the timestamp,
+    /// dropping the counter.
+    pub fn to_system_time(&self) -> SystemTime {
+        if self.nanos >= 0 {
+            UNIX_EPOCH + Duration::from_nanos(self.nanos as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_nanos(self.nanos.unsigned_abs())
+        }
+    }
+
+    /// `to_rfc3339` formats the timestamp as an RFC 3339 time in UTC,
+    /// with only as many fractional digits as it needs, such as
+    /// `2017-07-14T02:40:00.5Z`. The counter isn't included.
+    pub fn to_rfc3339(&self) -> String {
+        let secs = self.secs();
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let sod = secs.rem_euclid(86400);
+        let mut s = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                            year, month, day, sod / 3600, sod / 60 % 60, sod % 60);
+        let frac = self.nanos.rem_euclid(1_000_000_000);
+        if frac > 0 {
+            s.push('.');
+            s.push_str(format!("{:09}", frac).trim_end_matches('0'));
+        }
+        s.push('Z');
+        s
+    }
+}
+
+/// A timestamp is displayed in RFC 3339 form; see `to_rfc3339`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_rfc3339())
+    }
+}
+
+/// `civil_from_days` returns the year, month, and day of the date
+/// `days` days after the Unix epoch, in the proleptic Gregorian
+/// calendar. This is Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+#[test]
+fn test_now_is_monotonic() {
+    let mut last = Timestamp::now();
+    for _ in 0..10000 {
+        let ts = Timestamp::now();
+        assert!(ts > last);
+        last = ts;
+    }
+    assert!(last.secs() > 1500000000);
+}
+
+#[test]
+fn test_observe() {
+    let future = Timestamp { nanos: Timestamp::now().nanos + 1_000_000, counter: 7 };
+    observe(future);
+    let ts = Timestamp::now();
+    assert!(ts > future);
+}
+
+#[test]
+fn test_next() {
+    let ts = Timestamp { nanos: 5, counter: 1 };
+    assert_eq!(ts.next(), Timestamp { nanos: 5, counter: 2 });
+    let ts = Timestamp { nanos: 5, counter: u32::MAX };
+    assert_eq!(ts.next(), Timestamp { nanos: 6, counter: 0 });
+    let max = Timestamp { nanos: i64::MAX, counter: u32::MAX };
+    assert_eq!(max.next(), max);
+}
+
+#[test]
+fn test_secs() {
+    assert_eq!(Timestamp::from_secs(42).secs(), 42);
+    assert_eq!(Timestamp { nanos: -1, counter: 0 }.secs(), -1);
+    assert_eq!(Timestamp::from_secs(i64::MAX).nanos, i64::MAX);
+}
+
+#[test]
+fn test_conversions() {
+    let ts = Timestamp::from_millis(1500000000500);
+    assert_eq!(ts.millis(), 1500000000500);
+    assert_eq!(ts.secs(), 1500000000);
+    assert_eq!(Timestamp::from_system_time(ts.to_system_time()), ts);
+
+    let before = Timestamp { nanos: -1, counter: 0 };
+    assert_eq!(Timestamp::from_system_time(before.to_system_time()), before);
+    assert_eq!(Timestamp::from_system_time(UNIX_EPOCH), Timestamp::default());
+}
+
+#[test]
+fn test_rfc3339() {
+    assert_eq!(Timestamp::default().to_rfc3339(), "1970-01-01T00:00:00Z");
+    assert_eq!(Timestamp::from_secs(1500000000).to_rfc3339(), "2017-07-14T02:40:00Z");
+    assert_eq!(Timestamp::from_millis(1500000000500).to_string(), "2017-07-14T02:40:00.5Z");
+    assert_eq!(Timestamp::from_secs(951782400).to_rfc3339(), "2000-02-29T00:00:00Z");
+    assert_eq!(Timestamp { nanos: -1, counter: 0 }.to_rfc3339(), "1969-12-31T23:59:59.999999999Z");
+}
@@ -0,0 +1,134 @@
+//! index keeps an optional reverse index from values to the keys
+//! holding them, so that `find_keys_by_value` doesn't have to scan the
+//! whole store. It's off by default, since it costs memory and a
+//! little work on every write; `set_reverse_index` turns it on.
+//!
+//! The index shares the store's keys and values rather than copying
+//! them, and is itself shared with clones of the store, such as
+//! snapshots, until one of them writes. It's kept up to date by
+//! `insert`, `update`, `delete`, and the other writes the store makes,
+//! and rebuilt after bulk loads, merges, and reloads. It isn't
+//! persisted. Changes made directly through `values_mut` bypass it;
+//! turn it off and on again afterwards.
+//!
+//! Spilled values (see the `spill` module) are read back from their
+//! side files to be indexed, and the index then keeps them in memory.
+use super::entry::Entry;
+use super::Store;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// ReverseIndex maps each value to the keys holding it.
+#[derive(Clone, Debug, Default)]
+pub struct ReverseIndex {
+    keys: HashMap<Arc<str>, BTreeSet<Arc<str>>>,
+}
+
+impl ReverseIndex {
+    fn build(kvs: &Store) -> ReverseIndex {
+        let mut index = ReverseIndex::default();
+        for (k, ent) in kvs.values.iter() {
+            if let Ok(v) = kvs.shared_value(ent) {
+                index.add(k, &v);
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, k: &Arc<str>, v: &Arc<str>) {
+        self.keys.entry(v.clone()).or_default().insert(k.clone());
+    }
+
+    fn remove(&mut self, k: &str, v: &str) {
+        if let Some(keys) = self.keys.get_mut(v) {
+            keys.remove(k);
+            if keys.is_empty() {
+                self.keys.remove(v);
+            }
+        }
+    }
+}
+
+impl Store {
+    /// `set_reverse_index` turns the reverse index on, building it
+    /// from the store's values, or off, dropping it.
+    pub fn set_reverse_index(&mut self, enabled: bool) {
+        self.index = if enabled { Some(Arc::new(ReverseIndex::build(self))) } else { None };
+    }
+
+    /// `find_keys_by_value` returns the keys whose value is `v`, in
+    /// sorted order. Without the reverse index, it scans the store.
+    pub fn find_keys_by_value(&self, v: &str) -> Vec<String> {
+        if let Some(ref index) = self.index {
+            return index.keys.get(v)
+                .map(|keys| keys.iter().map(|k| k.to_string()).collect())
+                .unwrap_or_default();
+        }
+
+        let mut keys: Vec<String> = self.values.iter()
+            .filter(|&(_, ent)| self.shared_value(ent).is_ok_and(|value| &*value == v))
+            .map(|(k, _)| k.to_string())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// `reindex` updates the reverse index, if there is one, after a
+    /// write to `k`, whose entry was `before`.
+    pub(super) fn reindex(&mut self, k: &str, before: Option<&Entry>) {
+        if self.index.is_none() {
+            return;
+        }
+        let before = before.and_then(|ent| self.shared_value(ent).ok());
+        let after = self.values.get_key_value(k)
+            .and_then(|(k, ent)| self.shared_value(ent).ok().map(|v| (k.clone(), v)));
+        let index = match self.index {
+            Some(ref mut index) => Arc::make_mut(index),
+            None                => return,
+        };
+        if let Some(before) = before {
+            index.remove(k, &before);
+        }
+        if let Some((k, v)) = after {
+            index.add(&k, &v);
+        }
+    }
+
+    /// `rebuild_index` rebuilds the reverse index, if there is one,
+    /// after wholesale changes to the values.
+    pub(super) fn rebuild_index(&mut self) {
+        if self.index.is_some() {
+            self.index = Some(Arc::new(ReverseIndex::build(self)));
+        }
+    }
+}
+
+
+#[test]
+fn test_reverse_index() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "dup".to_string());
+    kvs.insert("b".to_string(), "dup".to_string());
+    kvs.insert("c".to_string(), "unique".to_string());
+    assert_eq!(kvs.find_keys_by_value("dup"), vec!["a", "b"]);
+
+    kvs.set_reverse_index(true);
+    assert_eq!(kvs.find_keys_by_value("dup"), vec!["a", "b"]);
+
+    kvs.update("a".to_string(), "unique".to_string());
+    kvs.delete("b".to_string());
+    kvs.insert("d".to_string(), "dup".to_string());
+    kvs.bulk_load(vec![("e".to_string(), "dup".to_string())], false).unwrap();
+    assert_eq!(kvs.find_keys_by_value("dup"), vec!["d", "e"]);
+    assert_eq!(kvs.find_keys_by_value("unique"), vec!["a", "c"]);
+    assert!(kvs.find_keys_by_value("missing").is_empty());
+
+    let copy = kvs.clone();
+    assert!(Arc::ptr_eq(kvs.index.as_ref().unwrap(), copy.index.as_ref().unwrap()));
+    kvs.soft_delete("d".to_string());
+    kvs.restore("d".to_string()).unwrap();
+    assert!(!Arc::ptr_eq(kvs.index.as_ref().unwrap(), copy.index.as_ref().unwrap()));
+    assert_eq!(copy.find_keys_by_value("dup"), vec!["d", "e"]);
+    kvs.set_reverse_index(false);
+    assert_eq!(kvs.find_keys_by_value("dup"), vec!["d", "e"]);
+}
@@ -0,0 +1,108 @@
+//! journal provides a compact record of which keys changed in the
+//! store. Journal records carry only the key and its new version, not
+//! the value, which makes them cheap to hand to external systems
+//! (CDNs, application caches) that only need to know what to
+//! invalidate.
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// JournalRecord notes that `key` was written. `version` is the
+/// entry's version after the write; deletions are recorded with a
+/// version of 0, since versions of live entries start at 1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub key: String,
+    pub version: i64,
+}
+
+/// Journal fans journal records out to any number of subscribers.
+/// Subscribers whose receiving end has been dropped are forgotten the
+/// next time a record is sent.
+///
+/// A journal belongs to one store: cloning a store (or its journal)
+/// yields a journal with no subscribers, and journals are never
+/// persisted.
+#[derive(Default)]
+pub struct Journal {
+    subscribers: Vec<Sender<JournalRecord>>,
+}
+
+impl Journal {
+    /// `new` returns a journal with no subscribers.
+    pub fn new() -> Journal {
+        Journal { subscribers: Vec::new() }
+    }
+
+    /// `subscribe` returns a receiver for every record sent from now
+    /// on.
+    pub fn subscribe(&mut self) -> Receiver<JournalRecord> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// `record` sends a record for `key` at `version` to every
+    /// subscriber.
+    pub fn record(&mut self, key: &str, version: i64) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let rec = JournalRecord { key: key.to_string(), version };
+        self.subscribers.retain(|tx| tx.send(rec.clone()).is_ok());
+    }
+
+    /// `len` returns the number of subscribers.
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// `is_empty` returns true if nobody is subscribed.
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+impl Clone for Journal {
+    fn clone(&self) -> Journal {
+        Journal::new()
+    }
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Journal {{ subscribers: {} }}", self.subscribers.len())
+    }
+}
+
+
+#[test]
+fn test_journal() {
+    let mut kvs = super::new("".to_string());
+    let rx = kvs.subscribe_keys();
+
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.insert("a".to_string(), "3".to_string());
+    kvs.delete("a".to_string());
+
+    let records: Vec<JournalRecord> = rx.try_iter().collect();
+    assert_eq!(records, vec![
+        JournalRecord { key: "a".to_string(), version: 1 },
+        JournalRecord { key: "a".to_string(), version: 2 },
+        JournalRecord { key: "a".to_string(), version: 0 },
+    ]);
+}
+
+#[test]
+fn test_journal_drops_subscribers() {
+    let mut journal = Journal::new();
+    let rx = journal.subscribe();
+    assert_eq!(journal.len(), 1);
+
+    drop(rx);
+    journal.record("a", 1);
+    assert!(journal.is_empty());
+    assert!(journal.clone().is_empty());
+}
@@ -0,0 +1,171 @@
+//! lazy keeps recently read spilled values in memory. A store that
+//! spills its values (see the `spill` module) loads only its keys and
+//! their metadata at startup; each spilled value is read from its side
+//! file when it's first asked for. With `StoreConfig::blob_cache` set,
+//! the values read are kept in a least-recently-used cache of that
+//! many values, so hot keys are served from memory without holding
+//! the whole store there.
+//!
+//! Side files are named for their contents and never change, so the
+//! cache needs no invalidation: a write to a key gives it a new side
+//! file, or none. Like the journal, the cache belongs to one store,
+//! and cloning a store yields an empty one.
+use super::Store;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Lru is the cache's contents: each side file's value and when it
+/// was last used, along with the clock those times are read from.
+/// used orders the side files by that time, so that the least
+/// recently used one is found without a scan.
+#[derive(Default)]
+struct Lru {
+    tick: u64,
+    values: HashMap<String, (Arc<str>, u64)>,
+    used: BTreeMap<u64, String>,
+    hits: u64,
+    misses: u64,
+}
+
+/// BlobCache holds recently read spilled values.
+#[derive(Default)]
+pub struct BlobCache {
+    lru: Mutex<Lru>,
+}
+
+impl BlobCache {
+    /// `new` returns an empty cache.
+    pub fn new() -> BlobCache {
+        BlobCache::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// `get` returns the cached value of the side file `name`.
+    pub(super) fn get(&self, name: &str) -> Option<Arc<str>> {
+        let mut lru = self.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        let (v, last) = match lru.values.get_mut(name) {
+            Some(cached) => (cached.0.clone(), std::mem::replace(&mut cached.1, tick)),
+            None         => {
+                lru.misses += 1;
+                return None;
+            },
+        };
+        lru.hits += 1;
+        if let Some(name) = lru.used.remove(&last) {
+            lru.used.insert(tick, name);
+        }
+        Some(v)
+    }
+
+    /// `put` caches `v` as the value of the side file `name`, evicting
+    /// the least recently used value if there are more than `max`.
+    pub(super) fn put(&self, name: &str, v: Arc<str>, max: usize) {
+        let mut lru = self.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((_, last)) = lru.values.insert(name.to_string(), (v, tick)) {
+            lru.used.remove(&last);
+        }
+        lru.used.insert(tick, name.to_string());
+        while lru.values.len() > max {
+            let oldest = match lru.used.pop_first() {
+                Some((_, name)) => name,
+                None            => break,
+            };
+            lru.values.remove(&oldest);
+        }
+    }
+
+    /// `len` returns the number of values cached.
+    pub fn len(&self) -> usize {
+        self.lock().values.len()
+    }
+
+    /// `is_empty` returns true if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `stats` returns the number of spilled values read from the
+    /// cache and the number read from disk, in that order.
+    pub fn stats(&self) -> (u64, u64) {
+        let lru = self.lock();
+        (lru.hits, lru.misses)
+    }
+}
+
+impl Clone for BlobCache {
+    fn clone(&self) -> BlobCache {
+        BlobCache::new()
+    }
+}
+
+impl fmt::Debug for BlobCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlobCache {{ values: {} }}", self.len())
+    }
+}
+
+impl Store {
+    /// `blob_cache` returns the cache of spilled values.
+    pub fn blob_cache(&self) -> &BlobCache {
+        &self.blobs
+    }
+}
+
+
+#[test]
+fn test_blob_cache() {
+    let cache = BlobCache::new();
+    cache.put("a", Arc::from("1"), 2);
+    cache.put("b", Arc::from("2"), 2);
+    assert_eq!(cache.get("a").as_deref(), Some("1"));
+    cache.put("c", Arc::from("3"), 2);
+
+    // b was used least recently.
+    assert!(cache.get("b").is_none());
+    assert_eq!(cache.get("c").as_deref(), Some("3"));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.stats(), (2, 1));
+
+    // Replacing a value counts as using it.
+    cache.put("a", Arc::from("4"), 2);
+    cache.put("d", Arc::from("5"), 2);
+    assert!(cache.get("c").is_none());
+    assert_eq!(cache.get("a").as_deref(), Some("4"));
+    assert_eq!(cache.lock().used.len(), 2);
+    assert!(cache.clone().is_empty());
+}
+
+#[test]
+fn test_lazy_values() {
+    use testutil::TempStore;
+    use super::StoreConfig;
+    use std::fs;
+
+    let path = TempStore::new("lazy");
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(10).blob_cache(1);
+    kvs.insert("a".to_string(), "a".repeat(100));
+    kvs.insert("b".to_string(), "b".repeat(100));
+    kvs.flush().unwrap();
+    assert!(kvs.values["a"].blob.is_some());
+    assert!(kvs.blob_cache().is_empty());
+    assert_eq!(kvs.get("a".to_string()), Some("a".repeat(100)));
+    assert_eq!(kvs.get("a".to_string()), Some("a".repeat(100)));
+    assert_eq!(kvs.get("b".to_string()), Some("b".repeat(100)));
+    assert_eq!(kvs.blob_cache().stats(), (1, 2));
+    assert_eq!(kvs.blob_cache().len(), 1);
+
+    // A cached value is served even once its side file is gone.
+    fs::remove_dir_all(super::spill::blob_dir(&path)).unwrap();
+    assert_eq!(kvs.with_value("b", |v| v.len()), Some(100));
+    assert!(kvs.get("a".to_string()).is_none());
+}
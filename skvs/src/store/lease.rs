@@ -0,0 +1,214 @@
+//! lease gives a caller exclusive use of a key for a while, which is
+//! enough for a simple lock. While a key is leased, `insert`, `update`,
+//! and `delete` reject writes to it with `Leased`, unless they're made
+//! inside `with_lease` by the lease's holder. A lease ends when it's
+//! released or its time runs out, whichever comes first; renewing it
+//! extends the time.
+//!
+//! Diffs and patches that would change a leased key are refused as a
+//! whole, and merges skip leased keys, listing them in the
+//! `MergeReport` as rejected. Restores from the trash are rejected
+//! like inserts. Leases aren't persisted.
+//!
+//! ```
+//! use skvs::store::WriteResult;
+//! use std::time::Duration;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! let token = kvs.acquire_lease("jobs/nightly", Duration::from_secs(30)).unwrap();
+//! assert_eq!(kvs.update("jobs/nightly".to_string(), "me".to_string()), WriteResult::Leased);
+//!
+//! let wr = kvs.with_lease("jobs/nightly", &token, |kvs| {
+//!     kvs.update("jobs/nightly".to_string(), "running".to_string())
+//! });
+//! assert_eq!(wr, Ok(WriteResult::Inserted));
+//! kvs.release_lease("jobs/nightly", &token).unwrap();
+//! ```
+extern crate chacha20poly1305;
+
+use self::chacha20poly1305::aead::OsRng;
+use self::chacha20poly1305::aead::rand_core::RngCore;
+use super::hlc::Timestamp;
+use super::{Store, WriteResult};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// LeaseToken proves that its holder acquired a lease. Tokens are
+/// random, so they can't be guessed from one another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeaseToken(u64);
+
+impl LeaseToken {
+    /// `value` returns the token as a number, for passing to another
+    /// process.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// `from_value` returns the token with the number `value`.
+    pub fn from_value(value: u64) -> LeaseToken {
+        LeaseToken(value)
+    }
+}
+
+/// Lease is a current lease on a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lease {
+    pub token: LeaseToken,
+    pub expires: Timestamp,
+}
+
+/// LeaseError explains why a lease couldn't be acquired or used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeaseError {
+    /// Held is returned when acquiring a key that someone else holds
+    /// a lease on; it says when that lease expires.
+    Held(Timestamp),
+
+    /// NotHeld is returned when the token isn't for a current lease
+    /// on the key: it was released, it expired, or it's for another
+    /// key.
+    NotHeld,
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LeaseError::Held(expires) => write!(f, "key is leased until {}", expires),
+            LeaseError::NotHeld       => write!(f, "lease isn't held"),
+        }
+    }
+}
+
+impl Error for LeaseError {}
+
+/// `random_token` returns a new, unpredictable token, drawn from the
+/// operating system's random number generator.
+fn random_token() -> LeaseToken {
+    LeaseToken(OsRng.next_u64())
+}
+
+/// `expiry` returns the time `ttl` after `now`.
+fn expiry(now: Timestamp, ttl: Duration) -> Timestamp {
+    let ttl = ttl.as_nanos().min(i64::MAX as u128) as i64;
+    Timestamp { nanos: now.nanos.saturating_add(ttl), counter: 0 }
+}
+
+impl Store {
+    /// `lease` returns the current lease on `k`, if there is one.
+    pub fn lease(&self, k: &str) -> Option<Lease> {
+        let now = self.clock.now();
+        self.leases.get(k).filter(|lease| lease.expires > now).copied()
+    }
+
+    /// `acquire_lease` leases `k` for `ttl`, returning the token that
+    /// writes to it need. The key doesn't have to exist.
+    pub fn acquire_lease(&mut self, k: &str, ttl: Duration) -> Result<LeaseToken, LeaseError> {
+        if let Some(lease) = self.lease(k) {
+            return Err(LeaseError::Held(lease.expires));
+        }
+
+        let token = random_token();
+        let expires = expiry(self.clock.now(), ttl);
+        self.leases.insert(k.to_string(), Lease { token, expires });
+        Ok(token)
+    }
+
+    /// `renew_lease` extends the lease on `k` to `ttl` from now.
+    pub fn renew_lease(&mut self, k: &str, token: &LeaseToken, ttl: Duration) -> Result<(), LeaseError> {
+        self.check_token(k, token)?;
+        let expires = expiry(self.clock.now(), ttl);
+        if let Some(lease) = self.leases.get_mut(k) {
+            lease.expires = expires;
+        }
+        Ok(())
+    }
+
+    /// `release_lease` ends the lease on `k`.
+    pub fn release_lease(&mut self, k: &str, token: &LeaseToken) -> Result<(), LeaseError> {
+        self.check_token(k, token)?;
+        self.leases.remove(k);
+        Ok(())
+    }
+
+    /// `with_lease` calls `f` with the store, allowing it to write to
+    /// `k` as long as `token` is for the current lease on it.
+    pub fn with_lease<R, F>(&mut self, k: &str, token: &LeaseToken, f: F) -> Result<R, LeaseError>
+        where F: FnOnce(&mut Store) -> R {
+        self.check_token(k, token)?;
+        let outer = self.lease_holder.replace(k.to_string());
+        let result = f(self);
+        self.lease_holder = outer;
+        Ok(result)
+    }
+
+    fn check_token(&self, k: &str, token: &LeaseToken) -> Result<(), LeaseError> {
+        match self.lease(k) {
+            Some(lease) if lease.token == *token => Ok(()),
+            _                                    => Err(LeaseError::NotHeld),
+        }
+    }
+
+    /// `leased` returns true if a write to `k` would be rejected
+    /// with `Leased`: the key is leased, and the write isn't being
+    /// made by the holder.
+    pub(super) fn leased(&self, k: &str) -> bool {
+        !self.leases.is_empty() && self.lease_holder.as_deref() != Some(k) && self.lease(k).is_some()
+    }
+
+    /// `check_lease` returns `Leased` if `k` is leased and the write
+    /// isn't being made by the holder. Expired leases are forgotten
+    /// here.
+    pub(super) fn check_lease(&mut self, k: &str) -> Result<(), WriteResult> {
+        if self.leases.is_empty() || self.lease_holder.as_deref() == Some(k) {
+            return Ok(());
+        }
+        if self.leased(k) {
+            return Err(WriteResult::Leased);
+        }
+        self.leases.remove(k);
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_leases() {
+    use super::clock::MockClock;
+    use super::WriteResult::*;
+    use std::sync::Arc;
+
+    let clock = Arc::new(MockClock::new(Timestamp::from_secs(1500000000)));
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(clock.clone());
+    kvs.insert("lock".to_string(), "free".to_string());
+    kvs.insert("other".to_string(), "v".to_string());
+
+    let token = kvs.acquire_lease("lock", Duration::from_secs(10)).unwrap();
+    assert!(matches!(kvs.acquire_lease("lock", Duration::from_secs(10)), Err(LeaseError::Held(_))));
+    assert_eq!(kvs.update("lock".to_string(), "mine".to_string()), Leased);
+    assert_eq!(kvs.delete("lock".to_string()), Leased);
+    assert_eq!(kvs.soft_delete("lock".to_string()), Leased);
+    assert!(kvs.trash.is_empty());
+
+    let wr = kvs.with_lease("lock", &token, |kvs| {
+        (kvs.update("lock".to_string(), "held".to_string()), kvs.update("other".to_string(), "w".to_string()))
+    });
+    assert_eq!(wr, Ok((Updated, Updated)));
+    assert_eq!(kvs.with_lease("other", &token, |_| ()), Err(LeaseError::NotHeld));
+
+    clock.advance(Duration::from_secs(8));
+    kvs.renew_lease("lock", &token, Duration::from_secs(10)).unwrap();
+    clock.advance(Duration::from_secs(8));
+    assert_eq!(kvs.update("lock".to_string(), "mine".to_string()), Leased);
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(kvs.lease("lock"), None);
+    assert_eq!(kvs.release_lease("lock", &token), Err(LeaseError::NotHeld));
+    assert_eq!(kvs.update("lock".to_string(), "mine".to_string()), Updated);
+
+    let token = kvs.acquire_lease("lock", Duration::from_secs(10)).unwrap();
+    kvs.release_lease("lock", &token).unwrap();
+    assert_eq!(kvs.delete("lock".to_string()), Updated);
+}
@@ -0,0 +1,222 @@
+//! lock keeps two processes from clobbering each other's flushes to
+//! the same store. Locks are advisory, and are taken on a lock file
+//! next to the store (`store.json.lock` for `store.json`) rather than
+//! on the store file itself, so they cover a sharded store's files
+//! too.
+//!
+//! `Store::load` takes an exclusive lock, and `Store::open_readonly` a
+//! shared one; writes to a store opened read-only return `Denied`,
+//! and it can't be flushed. Either fails straight away with a
+//! `StoreLocked` error if another process holds a conflicting lock,
+//! or the `_wait` variants retry until a timeout.
+//! The lock is released when the store and every clone of it (such as
+//! a snapshot) have been dropped.
+//!
+//! A store made with `new` isn't locked until `Store::lock` is called.
+use super::{Store, WriteResult};
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// StoreLocked is the error returned when another process has the
+/// store locked. It's wrapped in an `io::Error` of kind `WouldBlock`;
+/// use `StoreLocked::from_io` to pick it out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoreLocked {
+    pub path: String,
+}
+
+impl fmt::Display for StoreLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is locked by another process", self.path)
+    }
+}
+
+impl Error for StoreLocked {}
+
+impl StoreLocked {
+    /// `from_io` returns the `StoreLocked` error wrapped in `err`, if
+    /// there is one.
+    pub fn from_io(err: &io::Error) -> Option<&StoreLocked> {
+        err.get_ref().and_then(|err| err.downcast_ref::<StoreLocked>())
+    }
+}
+
+/// POLL_INTERVAL is how often a waiting lock is retried.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Lock is a held lock on a store's lock file.
+#[derive(Debug)]
+pub struct Lock {
+    // The lock is released when the file is closed.
+    _file: File,
+    exclusive: bool,
+}
+
+impl Lock {
+    /// `acquire` locks the store at `path`, exclusively or shared,
+    /// retrying for up to `wait` if it's locked.
+    pub fn acquire(path: &str, exclusive: bool, wait: Option<Duration>) -> Result<Lock, io::Error> {
+        let file = OpenOptions::new().read(true).write(true).create(true)
+            .truncate(false).open(format!("{}.lock", path))?;
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        loop {
+            let result = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+            match result {
+                Ok(())                        => return Ok(Lock { _file: file, exclusive }),
+                Err(TryLockError::Error(err)) => return Err(err),
+                Err(TryLockError::WouldBlock) => (),
+            }
+
+            match deadline {
+                Some(deadline) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                _                                           => {
+                    let locked = StoreLocked { path: path.to_string() };
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, locked));
+                },
+            }
+        }
+    }
+
+    /// `is_exclusive` returns true if this is an exclusive lock.
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+}
+
+impl Store {
+    /// `load_wait` works like `load`, but waits up to `timeout` for
+    /// another process to release the store.
+    pub fn load_wait(path: String, timeout: Duration) -> Result<Store, io::Error> {
+        Store::load_locked(path, true, Some(timeout))
+    }
+
+    /// `open_readonly` reads the store at `path` under a shared lock,
+    /// which other readers may also hold but which keeps anyone from
+    /// loading it for writing. The store can be changed in memory,
+    /// but not flushed.
+    pub fn open_readonly(path: String) -> Result<Store, io::Error> {
+        Store::load_locked(path, false, None)
+    }
+
+    /// `open_readonly_wait` works like `open_readonly`, but waits up
+    /// to `timeout` for a writer to release the store.
+    pub fn open_readonly_wait(path: String, timeout: Duration) -> Result<Store, io::Error> {
+        Store::load_locked(path, false, Some(timeout))
+    }
+
+    /// `lock` takes an exclusive lock on the store's path, for a
+    /// store made with `new`. It does nothing if the store is already
+    /// locked exclusively, or has no path. A read-only store gives up
+    /// its shared lock first, and is left unlocked if the exclusive
+    /// lock can't be had.
+    pub fn lock(&mut self) -> Result<(), io::Error> {
+        if self.path.is_empty() || self.lock.as_ref().is_some_and(|lock| lock.exclusive) {
+            return Ok(());
+        }
+        self.lock = None;
+        self.lock = Some(Arc::new(Lock::acquire(&self.path, true, None)?));
+        Ok(())
+    }
+
+    /// `set_path` moves the store to `path`, where it's written on the
+    /// next flush. The store takes an exclusive lock on `path` before
+    /// giving up its lock on the old one; if `path` is locked by
+    /// someone else, the store is left as it was.
+    pub fn set_path(&mut self, path: String) -> Result<(), io::Error> {
+        if path == self.path {
+            return self.lock();
+        }
+        let lock = if path.is_empty() { None } else { Some(Arc::new(Lock::acquire(&path, true, None)?)) };
+        self.lock = lock;
+        self.path = path;
+        Ok(())
+    }
+
+    /// `unlock` gives up the store's lock, if it holds one. Clones
+    /// sharing the lock keep it held until they're dropped.
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    /// `is_read_only` returns true if the store was opened with
+    /// `open_readonly`.
+    pub fn is_read_only(&self) -> bool {
+        self.lock.as_ref().is_some_and(|lock| !lock.exclusive)
+    }
+
+    /// `check_writable` returns `Denied` if the store is read-only,
+    /// and otherwise checks that `k` isn't leased. Every write to a
+    /// key is checked with it before anything changes.
+    pub(super) fn check_writable(&mut self, k: &str) -> Result<(), WriteResult> {
+        if self.is_read_only() {
+            return Err(WriteResult::Denied);
+        }
+        self.check_lease(k)
+    }
+}
+
+
+#[test]
+fn test_lock() {
+    use testutil::TempStore;
+    let path = TempStore::new("lock");
+
+    let mut kvs = super::new(path.to_string());
+    kvs.lock().unwrap();
+    kvs.lock().unwrap();
+    let err = Lock::acquire(&path, false, None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(StoreLocked::from_io(&err), Some(&StoreLocked { path: path.to_string() }));
+
+    let start = Instant::now();
+    assert!(Lock::acquire(&path, true, Some(Duration::from_millis(50))).is_err());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // Snapshots share the lock.
+    let snapshot = kvs.snapshot();
+    drop(kvs);
+    assert!(Lock::acquire(&path, true, None).is_err());
+    drop(snapshot);
+
+    let reader = Lock::acquire(&path, false, None).unwrap();
+    let other = Lock::acquire(&path, false, None).unwrap();
+    assert!(!other.is_exclusive());
+    assert!(Lock::acquire(&path, true, None).is_err());
+    drop(reader);
+    drop(other);
+
+    let mut kvs = super::new(path.to_string());
+    kvs.lock = Some(Arc::new(Lock::acquire(&path, false, None).unwrap()));
+    assert!(kvs.is_read_only());
+    assert_eq!(kvs.flush().unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert!(std::fs::metadata(&path).is_err());
+
+    // Nor can it be written to, even in memory.
+    assert_eq!(kvs.insert("a".to_string(), "1".to_string()), super::WriteResult::Denied);
+    assert_eq!(kvs.update("a".to_string(), "1".to_string()), super::WriteResult::Denied);
+    assert_eq!(kvs.delete("a".to_string()), super::WriteResult::Denied);
+    assert_eq!(kvs.rename("a", "b", true), super::WriteResult::Denied);
+    assert_eq!(kvs.len(), 0);
+    assert_eq!(kvs.metrics.last_update, super::hlc::Timestamp::default());
+    drop(kvs);
+
+    // Moving a store takes the new path's lock and gives up the old.
+    let other = format!("{}.moved", path);
+    let mut kvs = super::new(path.to_string());
+    kvs.lock().unwrap();
+    let held = Lock::acquire(&other, true, None).unwrap();
+    assert_eq!(kvs.set_path(other.clone()).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(kvs.path, *path);
+    assert!(Lock::acquire(&path, false, None).is_err());
+    drop(held);
+    kvs.set_path(other.clone()).unwrap();
+    assert_eq!(kvs.path, other);
+    assert!(Lock::acquire(&path, true, None).is_ok());
+    assert!(Lock::acquire(&other, false, None).is_err());
+    drop(kvs);
+}
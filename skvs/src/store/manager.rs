@@ -0,0 +1,238 @@
+//! manager keeps several named stores under one data directory, so a
+//! service with separate stores for, say, sessions and configuration
+//! doesn't have to track each store's file, loading, and flushing
+//! itself. The store named `sessions` lives in `sessions.json` in the
+//! data directory; stores are loaded the first time they're opened.
+use super::hlc::Timestamp;
+use super::journal::JournalRecord;
+use super::Store;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// Managed is an open store, along with a journal subscription that
+/// tells the manager whether it's been written since its last flush.
+#[derive(Debug)]
+struct Managed {
+    store: Store,
+    changes: Receiver<JournalRecord>,
+}
+
+impl Managed {
+    fn new(mut store: Store) -> Managed {
+        let changes = store.subscribe_keys();
+        Managed { store, changes }
+    }
+
+    /// `is_dirty` returns true if the store has been written since
+    /// this was last called.
+    fn is_dirty(&self) -> bool {
+        self.changes.try_iter().count() > 0
+    }
+}
+
+/// ManagerMetrics summarises the open stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ManagerMetrics {
+    /// stores is the number of open stores.
+    pub stores: usize,
+
+    /// entries is the total number of entries in the open stores.
+    pub entries: usize,
+
+    /// last_update is the most recent update to any store.
+    pub last_update: Timestamp,
+
+    /// oldest_write is the least recent flush of any store, or the
+    /// zero timestamp if one has never been flushed.
+    pub oldest_write: Timestamp,
+}
+
+/// StoreManager owns the stores in a data directory.
+#[derive(Debug)]
+pub struct StoreManager {
+    dir: PathBuf,
+    stores: HashMap<String, Managed>,
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+}
+
+/// `check_name` returns an error if `name` can't be used as a store
+/// name: names must be non-empty, can't start with a dot, and can't
+/// contain path separators.
+fn check_name(name: &str) -> Result<(), io::Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("invalid store name {:?}", name)));
+    }
+    Ok(())
+}
+
+impl StoreManager {
+    /// `new` returns a manager for the stores in `dir`, creating the
+    /// directory if needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<StoreManager, io::Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(StoreManager {
+            dir: dir.as_ref().to_path_buf(),
+            stores: HashMap::new(),
+            flush_interval: None,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// `path` returns the file the store called `name` is kept in.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// `open` returns the store called `name`, loading it from the
+    /// data directory if it isn't open yet, or creating it if it
+    /// doesn't exist.
+    pub fn open(&mut self, name: &str) -> Result<&mut Store, io::Error> {
+        check_name(name)?;
+        if !self.stores.contains_key(name) {
+            let path = self.path(name).to_str().unwrap_or("").to_string();
+            let store = if Path::new(&path).exists() {
+                let mut store = Store::load(path.clone())?;
+                // The store records the path it was written to, which
+                // may not be where the data directory is now.
+                store.path = path;
+                store
+            } else {
+                let mut store = super::new(path);
+                store.lock()?;
+                store
+            };
+            self.stores.insert(name.to_string(), Managed::new(store));
+        }
+        Ok(&mut self.stores.get_mut(name).unwrap().store)
+    }
+
+    /// `get` returns the store called `name` if it's open.
+    pub fn get(&self, name: &str) -> Option<&Store> {
+        self.stores.get(name).map(|managed| &managed.store)
+    }
+
+    /// `close` flushes the store called `name` and closes it. Closing
+    /// a store that isn't open does nothing.
+    pub fn close(&mut self, name: &str) -> Result<(), io::Error> {
+        if let Some(mut managed) = self.stores.remove(name) {
+            managed.store.flush()?;
+        }
+        Ok(())
+    }
+
+    /// `names` returns the names of every store, open or on disk, in
+    /// sorted order.
+    pub fn names(&self) -> Result<Vec<String>, io::Error> {
+        let mut names: Vec<String> = self.stores.keys().cloned().collect();
+        for dirent in fs::read_dir(&self.dir)? {
+            let path = dirent?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    if check_name(name).is_ok() && !self.stores.contains_key(name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// `flush_interval` sets how often `tick` flushes the stores.
+    pub fn flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = Some(interval);
+    }
+
+    /// `flush_all` flushes every open store that's been written since
+    /// it was last flushed, returning how many were flushed. Every
+    /// store is tried; the first error is returned, and the stores
+    /// that failed are tried again next time.
+    pub fn flush_all(&mut self) -> Result<usize, io::Error> {
+        self.last_flush = Instant::now();
+        let mut flushed = 0;
+        let mut first_err = None;
+        for managed in self.stores.values_mut() {
+            if !managed.is_dirty() && managed.store.write_error.is_none() {
+                continue;
+            }
+            match managed.store.flush() {
+                Ok(())   => flushed += 1,
+                Err(err) => { first_err.get_or_insert(err); },
+            }
+        }
+
+        match first_err {
+            Some(err) => Err(err),
+            None      => Ok(flushed),
+        }
+    }
+
+    /// `tick` calls `flush_all` if the flush interval has passed since
+    /// the last flush; a service calls it periodically, such as from
+    /// its main loop. It returns the number of stores flushed.
+    pub fn tick(&mut self) -> Result<usize, io::Error> {
+        match self.flush_interval {
+            Some(interval) if self.last_flush.elapsed() >= interval => self.flush_all(),
+            _                                                       => Ok(0),
+        }
+    }
+
+    /// `metrics` summarises the open stores' metrics.
+    pub fn metrics(&self) -> ManagerMetrics {
+        let mut metrics = ManagerMetrics::default();
+        for (i, managed) in self.stores.values().enumerate() {
+            let m = &managed.store.metrics;
+            metrics.stores += 1;
+            metrics.entries += managed.store.len();
+            metrics.last_update = metrics.last_update.max(m.last_update);
+            metrics.oldest_write = if i == 0 { m.last_write } else { metrics.oldest_write.min(m.last_write) };
+        }
+        metrics
+    }
+}
+
+
+#[test]
+fn test_manager() {
+    use testutil::TempStore;
+    let dir = TempStore::dir("manager");
+    let mut manager = StoreManager::new(&dir).unwrap();
+    assert!(manager.open("../escape").is_err());
+    assert!(manager.open("").is_err());
+
+    manager.open("sessions").unwrap().insert("s1".to_string(), "kyle".to_string());
+    manager.open("sessions").unwrap().insert("s2".to_string(), "ana".to_string());
+    manager.open("config").unwrap().insert("debug".to_string(), "false".to_string());
+    assert!(manager.get("cache").is_none());
+    assert_eq!(manager.names().unwrap(), vec!["config".to_string(), "sessions".to_string()]);
+
+    let metrics = manager.metrics();
+    assert_eq!(metrics.stores, 2);
+    assert_eq!(metrics.entries, 3);
+    assert_eq!(metrics.oldest_write, Timestamp::default());
+    assert_eq!(manager.tick().unwrap(), 0);
+}
+
+#[test]
+fn test_manager_flush() {
+    use testutil::TempStore;
+    let dir = TempStore::dir("manager-flush");
+    let mut manager = StoreManager::new(&dir).unwrap();
+    manager.open("a").unwrap().insert("k".to_string(), "v".to_string());
+    manager.open("b").unwrap();
+
+    // Only stores written since their last flush are flushed.
+    assert_eq!(manager.flush_all().unwrap(), 1);
+    assert_eq!(manager.flush_all().unwrap(), 0);
+
+    manager.close("a").unwrap();
+    assert!(manager.get("a").is_none());
+    assert_eq!(manager.names().unwrap(), vec!["a", "b"]);
+    assert_eq!(manager.open("a").unwrap().get("k".to_string()).unwrap(), "v");
+}
@@ -0,0 +1,418 @@
+//! merge combines stores that have been written to independently,
+//! such as copies of the same store on a laptop and a server. Conflicts
+//! are resolved by last writer wins: the entry with the later
+//! timestamp is kept, with the version and then the value itself
+//! breaking ties.
+//!
+//! Because that order is total, each key behaves as a last-writer-wins
+//! register CRDT: merging is commutative, associative, and idempotent,
+//! so any number of diverged copies converge on the same entries no
+//! matter which order they're merged in.
+//!
+//! Merged timestamps are passed to the store's clock, so entries written
+//! after a merge are always newer than the entries it brought in.
+//!
+//! Deleting a key in one copy only removes it from the other if the
+//! deleting store keeps tombstones (see the `tombstone` module);
+//! otherwise a merge can't tell a deleted key from one that was never
+//! there. Tombstones are merged too, and count as writes when
+//! resolving conflicts.
+//!
+//! `merge_with` resolves conflicts with a `ConflictResolver` instead,
+//! so that applications can combine values that mean something to
+//! them, rather than losing one. The convergence guarantees above
+//! only hold if the resolver is itself commutative, associative, and
+//! idempotent, as a set union is:
+//!
+//! ```
+//! use skvs::store::entry::Entry;
+//! use skvs::store::Resolution;
+//!
+//! fn union(ours: &Entry, theirs: &Entry) -> Resolution {
+//!     let mut tags: Vec<&str> = ours.value.split(',').chain(theirs.value.split(',')).collect();
+//!     tags.sort();
+//!     tags.dedup();
+//!     Resolution::Value(tags.join(","))
+//! }
+//!
+//! let mut laptop = skvs::store::new("".to_string());
+//! let mut server = skvs::store::new("".to_string());
+//! laptop.insert("tags".to_string(), "rust,kv".to_string());
+//! server.insert("tags".to_string(), "kv,store".to_string());
+//! laptop.merge_with(&server, union);
+//! assert_eq!(laptop.get("tags".to_string()), Some("kv,rust,store".to_string()));
+//! ```
+use super::entry::Entry;
+use super::quota;
+use super::Store;
+use std::sync::Arc;
+
+/// Resolution is a `ConflictResolver`'s decision about a key that
+/// both stores hold.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    /// Ours keeps this store's entry.
+    Ours,
+
+    /// Theirs replaces this store's entry with the other store's.
+    Theirs,
+
+    /// Value replaces both with a new value, written as a new version
+    /// of the entry.
+    Value(String),
+}
+
+/// ConflictResolver decides between this store's entry for a key and
+/// the other store's, which differ. Both are passed with their values
+/// in memory, even if they were spilled; see the `spill` module.
+pub type ConflictResolver = fn(&Entry, &Entry) -> Resolution;
+
+/// `last_writer_wins` is the resolver `merge` uses: it keeps the entry
+/// with the later timestamp, breaking ties by version and then value.
+pub fn last_writer_wins(ours: &Entry, theirs: &Entry) -> Resolution {
+    if newer(ours, theirs) { Resolution::Theirs } else { Resolution::Ours }
+}
+
+/// MergeReport lists the keys a merge changed, in sorted order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergeReport {
+    /// added lists keys copied from the other store that weren't
+    /// present before.
+    pub added: Vec<String>,
+
+    /// overwritten lists keys whose entries were replaced by newer
+    /// entries from the other store.
+    pub overwritten: Vec<String>,
+
+    /// deleted lists keys removed because the other store has a
+    /// newer tombstone for them.
+    pub deleted: Vec<String>,
+
+    /// rejected lists keys from the other store that weren't copied
+    /// because this store's configuration doesn't allow them, because
+    /// they'd take a bucket over its quota, because they're leased, or
+    /// because a spilled value couldn't be read. Leased keys the other
+    /// store's tombstones would have deleted are listed too.
+    pub rejected: Vec<String>,
+}
+
+impl MergeReport {
+    /// `is_empty` returns true if the merge didn't change anything;
+    /// rejected keys don't count as changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.overwritten.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// `newer` returns true if `theirs` should replace `ours`.
+fn newer(ours: &Entry, theirs: &Entry) -> bool {
+    (theirs.time, theirs.version, &theirs.value) > (ours.time, ours.version, &ours.value)
+}
+
+impl Store {
+    /// `merge` copies into this store every entry from `other` that
+    /// is missing here or newer than the entry here, unless this store
+    /// has a newer tombstone for it, and then applies `other`'s
+    /// tombstones. Copied entries keep their timestamps and versions.
+    pub fn merge(&mut self, other: &Store) -> MergeReport {
+        self.merge_with(other, last_writer_wins)
+    }
+
+    /// `merge_with` works like `merge`, asking `resolve` what to do
+    /// with each key whose entries differ between the stores. A new
+    /// value it returns is written with the next version after both
+    /// entries, and is reported as overwritten unless it's the value
+    /// this store already has.
+    pub fn merge_with(&mut self, other: &Store, resolve: ConflictResolver) -> MergeReport {
+        let mut report = MergeReport::default();
+        for (k, theirs) in other.values.iter() {
+            let theirs = match other.unspilled(theirs) {
+                Ok(theirs) => theirs,
+                Err(_)     => {
+                    report.rejected.push(k.to_string());
+                    continue;
+                },
+            };
+            if self.config.check_write(k, &theirs.value).is_err() {
+                report.rejected.push(k.to_string());
+                continue;
+            }
+
+            self.clock.observe(theirs.time);
+            if self.tombstones.get(&**k).is_some_and(|tomb| tomb.buries(&theirs)) {
+                continue;
+            }
+            let ours = match self.values.get(k) {
+                Some(ours) => match self.unspilled(ours) {
+                    Ok(ours) => Some(ours),
+                    Err(_)   => {
+                        report.rejected.push(k.to_string());
+                        continue;
+                    },
+                },
+                None       => None,
+            };
+            let merged = match ours {
+                None                         => theirs,
+                Some(ours) if ours == theirs => continue,
+                Some(ours)                   => match resolve(&ours, &theirs) {
+                    Resolution::Ours     => continue,
+                    Resolution::Theirs   => theirs,
+                    Resolution::Value(v) => {
+                        if *ours.value == *v {
+                            continue;
+                        }
+                        if self.config.check_write(k, &v).is_err() {
+                            report.rejected.push(k.to_string());
+                            continue;
+                        }
+                        let mut ent = Entry::new(&v);
+                        ent.version = ours.version.max(theirs.version) + 1;
+                        ent.time = self.clock.now();
+                        ent
+                    },
+                },
+            };
+            if self.leased(k) ||
+                quota::check(&self.config, &mut self.usages, &self.values, k, merged.value.len()).is_err() {
+                report.rejected.push(k.to_string());
+                continue;
+            }
+            if self.values.contains_key(k) {
+                report.overwritten.push(k.to_string());
+            } else {
+                report.added.push(k.to_string());
+            }
+
+            self.journal.record(k, merged.version);
+            self.unbury(k);
+            let before = Arc::make_mut(&mut self.values).insert(k.clone(), merged);
+            self.count_usage(k, before.as_ref());
+        }
+
+        for (k, theirs) in &other.tombstones {
+            self.clock.observe(theirs.time);
+            if self.tombstones.get(k).is_some_and(|ours| (ours.time, ours.version) >= (theirs.time, theirs.version)) {
+                continue;
+            }
+            match self.values.get(k.as_str()) {
+                Some(ours) if !theirs.buries(ours) => continue,
+                Some(_) if self.leased(k)          => {
+                    report.rejected.push(k.clone());
+                    continue;
+                },
+                Some(_)                            => {
+                    self.values_mut().remove(k.as_str());
+                    self.journal.record(k, 0);
+                    report.deleted.push(k.clone());
+                },
+                None                               => (),
+            }
+            self.tombstones.insert(k.clone(), *theirs);
+        }
+
+        if !report.is_empty() {
+            self.rebuild_index();
+            self.usages.clear();
+            self.update_metrics(true, false);
+        }
+        report.added.sort();
+        report.overwritten.sort();
+        report.deleted.sort();
+        report.rejected.sort();
+        report
+    }
+}
+
+/// `sync` merges `a` and `b` into each other, after which both hold
+/// the same entries. It returns the reports for the merges into `a`
+/// and into `b`, in that order.
+pub fn sync(a: &mut Store, b: &mut Store) -> (MergeReport, MergeReport) {
+    let into_a = a.merge(b);
+    let into_b = b.merge(a);
+    (into_a, into_b)
+}
+
+
+#[test]
+fn test_merge() {
+    let mut laptop = super::new("".to_string());
+    let mut server = super::new("".to_string());
+
+    laptop.insert("editor".to_string(), "vi".to_string());
+    server.insert("shell".to_string(), "ksh".to_string());
+    server.insert("editor".to_string(), "emacs".to_string());
+    laptop.update("editor".to_string(), "nvi".to_string());
+
+    // The laptop's copy of editor was written last.
+    let report = server.merge(&laptop);
+    assert_eq!(report.added, Vec::<String>::new());
+    assert_eq!(report.overwritten, vec!["editor".to_string()]);
+    assert_eq!(server.get("editor".to_string()).unwrap(), "nvi");
+    assert_eq!(server.values["editor"].version, 2);
+
+    let (into_laptop, into_server) = sync(&mut laptop, &mut server);
+    assert_eq!(into_laptop.added, vec!["shell".to_string()]);
+    assert!(into_server.is_empty());
+    assert_eq!(laptop.len(), 2);
+    assert_eq!(laptop.metrics.size, 2);
+    assert!(laptop.merge(&server).is_empty());
+
+    laptop.insert("ssh/key".to_string(), "secret".to_string());
+    server.config.key_policy = super::KeyPolicy::new().reserve("ssh/");
+    let report = server.merge(&laptop);
+    assert!(report.is_empty());
+    assert_eq!(report.rejected, vec!["ssh/key".to_string()]);
+}
+
+#[test]
+fn test_merge_converges() {
+    // Three replicas wrote the same key at the same time and version.
+    let mut replicas: Vec<Store> = Vec::new();
+    for v in &["b", "c", "a"] {
+        let mut kvs = super::new("".to_string());
+        let mut ent = Entry::new(v);
+        ent.time = super::hlc::Timestamp::from_secs(1500000000);
+        kvs.values_mut().insert("k".into(), ent);
+        replicas.push(kvs);
+    }
+
+    let orders = [[0, 1, 2], [2, 1, 0], [1, 0, 2], [1, 2, 0]];
+    for order in &orders {
+        let mut kvs = replicas[order[0]].clone();
+        kvs.merge(&replicas[order[1]]);
+        kvs.merge(&replicas[order[2]]);
+        assert_eq!(&*kvs.values["k"].value, "c");
+    }
+}
+
+#[test]
+fn test_merge_tombstones() {
+    let mut laptop = super::new("".to_string());
+    let mut server = super::new("".to_string());
+    laptop.config.tombstones = true;
+    server.config.tombstones = true;
+
+    laptop.insert("a".to_string(), "1".to_string());
+    laptop.insert("b".to_string(), "2".to_string());
+    sync(&mut laptop, &mut server);
+
+    // A deletion propagates...
+    let stale = laptop.clone();
+    laptop.delete("a".to_string());
+    let report = server.merge(&laptop);
+    assert_eq!(report.deleted, vec!["a".to_string()]);
+    assert!(server.get("a".to_string()).is_none());
+    assert_eq!(server.metrics.size, 1);
+
+    // ...and isn't undone by merging a copy from before it.
+    assert!(server.merge(&stale).is_empty());
+    assert!(server.get("a".to_string()).is_none());
+
+    // A write made after the deletion wins over it.
+    server.insert("a".to_string(), "3".to_string());
+    let report = laptop.merge(&server);
+    assert_eq!(report.added, vec!["a".to_string()]);
+    assert!(laptop.tombstones.is_empty());
+    assert_eq!(laptop.get("a".to_string()).unwrap(), "3");
+}
+
+#[test]
+fn test_merge_with() {
+    fn longest(ours: &Entry, theirs: &Entry) -> Resolution {
+        if theirs.value.len() > ours.value.len() { Resolution::Theirs } else { Resolution::Ours }
+    }
+    fn concat(ours: &Entry, theirs: &Entry) -> Resolution {
+        Resolution::Value(format!("{}+{}", ours.value, theirs.value))
+    }
+
+    let mut laptop = super::new("".to_string());
+    let mut server = super::new("".to_string());
+    server.insert("motd".to_string(), "welcome to the server".to_string());
+    laptop.insert("motd".to_string(), "hi".to_string());
+    server.insert("new".to_string(), "n".to_string());
+
+    // The laptop's motd is newer, but shorter.
+    let report = laptop.merge_with(&server, longest);
+    assert_eq!(report.added, vec!["new".to_string()]);
+    assert_eq!(report.overwritten, vec!["motd".to_string()]);
+    assert_eq!(laptop.get("motd".to_string()).unwrap(), "welcome to the server");
+    assert!(laptop.merge_with(&server, longest).is_empty());
+
+    let mut laptop = super::new("".to_string());
+    laptop.insert("motd".to_string(), "hi".to_string());
+    laptop.update("motd".to_string(), "hello".to_string());
+    let report = laptop.merge_with(&server, concat);
+    assert_eq!(report.overwritten, vec!["motd".to_string()]);
+    assert_eq!(laptop.get("motd".to_string()).unwrap(), "hello+welcome to the server");
+    assert_eq!(laptop.values["motd"].version, 3);
+    assert!(laptop.values["motd"].time > server.values["motd"].time);
+
+    laptop.config.max_value_bytes = Some(25);
+    let report = laptop.merge_with(&server, concat);
+    assert!(report.is_empty());
+    assert_eq!(report.rejected, vec!["motd".to_string()]);
+}
+
+#[test]
+fn test_merge_quota() {
+    use super::{Quota, StoreConfig, Usage};
+
+    let mut server = super::new("".to_string());
+    server.insert("q/1".to_string(), "1".to_string());
+    server.insert("q/2".to_string(), "2".to_string());
+    server.insert("other".to_string(), "3".to_string());
+
+    let mut laptop = super::new("".to_string());
+    laptop.config = StoreConfig::new().quota("q/", Quota::new().max_keys(1));
+    let report = laptop.merge(&server);
+    assert_eq!(report.added.len(), 2);
+    assert_eq!(report.rejected.len(), 1);
+    assert_eq!(laptop.usage("q/"), Usage { keys: 1, bytes: 4 });
+}
+
+#[test]
+fn test_merge_leased() {
+    use std::time::Duration;
+
+    let mut server = super::new("".to_string());
+    server.config.tombstones = true;
+    server.insert("lock".to_string(), "server".to_string());
+    server.insert("gone".to_string(), "1".to_string());
+    let mut laptop = server.clone();
+    server.delete("gone".to_string());
+    server.update("lock".to_string(), "changed".to_string());
+
+    laptop.acquire_lease("lock", Duration::from_secs(60)).unwrap();
+    laptop.acquire_lease("gone", Duration::from_secs(60)).unwrap();
+    let report = laptop.merge(&server);
+    assert!(report.is_empty());
+    assert_eq!(report.rejected, vec!["gone".to_string(), "lock".to_string()]);
+    assert_eq!(laptop.get("lock".to_string()).unwrap(), "server");
+    assert!(laptop.get("gone".to_string()).is_some());
+}
+
+#[test]
+fn test_merge_spilled() {
+    use testutil::TempStore;
+    use super::StoreConfig;
+
+    let path = TempStore::new("merge");
+    let large = "x".repeat(1000);
+
+    let mut server = super::new(path.to_string());
+    server.config = StoreConfig::new().spill_values_over(100);
+    server.insert("large".to_string(), large.clone());
+    server.spill().unwrap();
+    assert!(server.values["large"].blob.is_some());
+
+    // Spilled values are checked and copied by their contents.
+    let mut laptop = super::new("".to_string());
+    laptop.config.max_value_bytes = Some(500);
+    assert_eq!(laptop.merge(&server).rejected, vec!["large".to_string()]);
+    laptop.config.max_value_bytes = None;
+    assert_eq!(laptop.merge(&server).added, vec!["large".to_string()]);
+    assert!(laptop.values["large"].blob.is_none());
+    assert_eq!(&*laptop.values["large"].value, large);
+    assert!(server.merge(&laptop).is_empty());
+}
@@ -0,0 +1,126 @@
+//! migrations manages changes to an application's key layout across
+//! releases. The application registers ordered migration functions,
+//! each keyed by the schema version it upgrades the store to; the
+//! store records its current schema version and the migrations that
+//! have been applied, so each migration runs exactly once.
+//!
+//! ```
+//! use skvs::store::migrations::Migrations;
+//!
+//! let mut migrations = Migrations::new();
+//! migrations.register(1, |store| {
+//!     store.update("config/version".to_string(), "1".to_string());
+//!     Ok(())
+//! });
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! assert_eq!(migrations.apply(&mut kvs).unwrap(), vec![1]);
+//! assert_eq!(kvs.schema_version, 1);
+//! ```
+use super::hlc::Timestamp;
+use super::Store;
+use std::collections::BTreeMap;
+use std::io;
+
+/// AppliedMigration records when the store was migrated to a schema
+/// version.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub time: Timestamp,
+}
+
+type Migration = Box<dyn Fn(&mut Store) -> Result<(), io::Error>>;
+
+/// Migrations is a registry of migration functions.
+#[derive(Default)]
+pub struct Migrations {
+    steps: BTreeMap<u32, Migration>,
+}
+
+impl Migrations {
+    /// `new` returns an empty registry.
+    pub fn new() -> Migrations {
+        Migrations { steps: BTreeMap::new() }
+    }
+
+    /// `register` adds the migration that brings the store to schema
+    /// `version`, replacing any migration previously registered for
+    /// that version.
+    pub fn register<F>(&mut self, version: u32, f: F) -> &mut Migrations
+        where F: Fn(&mut Store) -> Result<(), io::Error> + 'static {
+        self.steps.insert(version, Box::new(f));
+        self
+    }
+
+    /// `latest` returns the highest registered schema version.
+    pub fn latest(&self) -> u32 {
+        self.steps.keys().next_back().cloned().unwrap_or(0)
+    }
+
+    /// `apply` runs, in order, every registered migration for a
+    /// version newer than the store's schema version, returning the
+    /// versions that were applied. If a migration fails, the store is
+    /// left at the last version that succeeded; changes the failing
+    /// migration made before returning its error are not undone.
+    pub fn apply(&self, store: &mut Store) -> Result<Vec<u32>, io::Error> {
+        let mut applied = Vec::new();
+        for (&version, migrate) in self.steps.range(store.schema_version + 1..) {
+            migrate(store)?;
+            store.schema_version = version;
+            store.migrations.push(AppliedMigration { version, time: store.clock.now() });
+            applied.push(version);
+        }
+        Ok(applied)
+    }
+}
+
+impl Store {
+    /// `load_migrated` loads the store at `path` and applies any
+    /// pending migrations. If any were applied, the migrated store is
+    /// flushed before it is returned.
+    pub fn load_migrated(path: String, migrations: &Migrations) -> Result<Store, io::Error> {
+        let mut store = Store::load(path)?;
+        if !migrations.apply(&mut store)?.is_empty() {
+            store.flush()?;
+        }
+        Ok(store)
+    }
+}
+
+
+#[test]
+fn test_migrations() {
+    use super::clock::MockClock;
+    use std::sync::Arc;
+
+    let mut migrations = Migrations::new();
+    migrations
+        .register(2, |store| {
+            let v = store.get("name".to_string()).unwrap_or_default();
+            store.delete("name".to_string());
+            store.insert("user/name".to_string(), v);
+            Ok(())
+        })
+        .register(1, |store| {
+            store.insert("name".to_string(), "kyle".to_string());
+            Ok(())
+        });
+    assert_eq!(migrations.latest(), 2);
+
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(Arc::new(MockClock::new(Timestamp::from_secs(1500000000))));
+    assert_eq!(kvs.schema_version, 0);
+    assert_eq!(migrations.apply(&mut kvs).unwrap(), vec![1, 2]);
+    assert_eq!(kvs.schema_version, 2);
+    assert_eq!(kvs.migrations.len(), 2);
+    assert_eq!(kvs.migrations[0].time.secs(), 1500000000);
+    assert_eq!(kvs.get("user/name".to_string()).unwrap(), "kyle");
+
+    // Migrations only run once.
+    assert!(migrations.apply(&mut kvs).unwrap().is_empty());
+
+    migrations.register(3, |_| Err(io::Error::other("boom")));
+    assert!(migrations.apply(&mut kvs).is_err());
+    assert_eq!(kvs.schema_version, 2);
+}
@@ -1,19 +1,124 @@
 //! store implements the backing key-value store for the simple
-//! key-value store. At its core, it is a hash map linking a `String`
-//! key to an `Entry`.
+//! key-value store. At its core, it is a hash map linking a key to
+//! an `Entry`. Keys and values are reference-counted strings, so
+//! cloning a store doesn't copy them; the public API still takes and
+//! returns `String`s.
+pub mod acl;
+pub mod alias;
+pub mod bulk;
+pub mod cache;
+pub mod canonical;
+pub mod clock;
+pub mod config;
+pub mod counters;
+pub mod diff;
+pub mod disk;
 pub mod entry;
+pub mod events;
+pub mod evict;
+pub mod export;
+pub mod flush;
+pub mod format;
+pub mod glob;
+pub mod history;
+pub mod hasher;
+pub mod hlc;
+pub mod index;
+pub mod journal;
+pub mod lazy;
+pub mod lease;
+pub mod lock;
+pub mod manager;
+pub mod merge;
+pub mod migrations;
+pub mod model;
+pub mod open;
+pub mod patch;
+pub mod preload;
+pub mod quota;
+pub mod redact;
+pub mod redis;
+pub mod reload;
+pub mod rename;
+pub mod scan;
+pub mod schedule;
+pub mod scoped;
+pub mod secret;
+pub mod shard;
+pub mod sim;
+pub mod slowlog;
+pub mod snapshot;
+pub mod spill;
+pub mod stats;
+pub mod tombstone;
+pub mod trash;
+pub mod typed;
+pub mod webhook;
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub mod web;
 
 extern crate serde;
 extern crate serde_json;
-extern crate time;
 
+pub use self::acl::{Access, Acl, Op};
+pub use self::alias::{AliasError, MAX_ALIAS_DEPTH};
+pub use self::cache::{CachedStore, PersistenceBackend, WritePolicy};
+pub use self::clock::{Clock, MockClock, SystemClock};
+pub use self::config::{Charset, DurabilityPolicy, KeyError, KeyPolicy, StoreConfig};
+pub use self::counters::Counters;
+pub use self::diff::{ApplyError, Changed, StoreDiff};
+pub use self::disk::{Disk, DiskFile, OsDisk};
 use self::entry::Entry;
+pub use self::events::{EventSink, Forwarder, WebhookSink};
+pub use self::evict::Evictor;
+pub use self::export::{ConflictPolicy, Format, ImportReport};
+pub use self::flush::FlushHandle;
+use self::flush::Flushes;
+pub use self::hasher::{HashKind, KeyHasher, Values};
+pub use self::history::Change;
+use self::history::History;
+pub use self::hlc::Timestamp;
+use self::index::ReverseIndex;
+use self::quota::Usages;
+use self::journal::{Journal, JournalRecord};
+use self::lock::Lock;
+pub use self::lazy::BlobCache;
+pub use self::lease::{Lease, LeaseError, LeaseToken};
+pub use self::lock::StoreLocked;
+pub use self::manager::{ManagerMetrics, StoreManager};
+pub use self::merge::{last_writer_wins, sync, ConflictResolver, MergeReport, Resolution};
+use self::migrations::AppliedMigration;
+pub use self::model::{ModelError, StoreModel};
+pub use self::open::OpenOptions;
+pub use self::patch::{Patch, PatchError};
+pub use self::quota::{BucketUsage, Quota, Usage};
+pub use self::redact::{RedactionPolicy, REDACTED};
+pub use self::redis::{AofReport, SkipReason};
+pub use self::reload::FileWatcher;
+use self::reload::FileStamp;
+use self::snapshot::Snapshots;
+pub use self::scan::{Cursor, ScanOptions, ScanPage};
+pub use self::schedule::{Schedule, ScheduledWrite, Scheduler};
+pub use self::scoped::Scoped;
+pub use self::secret::{SecretError, SecretKey};
+pub use self::slowlog::{SlowLog, SlowOp};
+pub use self::snapshot::Snapshot;
+pub use self::stats::{EntrySize, PrefixSize, Report, SizeBucket, StoreStats};
+pub use self::tombstone::Tombstone;
+pub use self::trash::Deleted;
+pub use self::typed::TypedError;
+pub use self::webhook::{WebhookRule, Webhooks};
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub use self::web::LocalStorage;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::string::ToString;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 /// Result contains results for write operations on the SKVS.
@@ -31,6 +136,30 @@ pub enum WriteResult {
     /// DoesNotExist is returned when deleting a key that doesn't
     /// exist.
     DoesNotExist,
+    /// Denied is returned when the store's ACL doesn't allow the
+    /// operation, or the store was opened read-only; the store is left
+    /// unchanged.
+    Denied,
+    /// InvalidKey is returned when the key doesn't meet the store's
+    /// key policy; the store is left unchanged.
+    InvalidKey(KeyError),
+    /// EmptyValue is returned when writing an empty value to a store
+    /// configured not to allow them; the store is left unchanged.
+    EmptyValue,
+    /// ValueTooLarge is returned when the value is larger than the
+    /// store's configured limit; the store is left unchanged.
+    ValueTooLarge,
+    /// QuotaExceeded is returned when the write would take a bucket
+    /// over its quota; the store is left unchanged. See the `quota`
+    /// module.
+    QuotaExceeded,
+    /// Leased is returned when writing a key someone else holds a
+    /// lease on; the store is left unchanged. See the `lease` module.
+    Leased,
+    /// VersionConflict is returned by conditional writes when the
+    /// entry isn't at the expected version, because someone else
+    /// has written it since; the store is left unchanged.
+    VersionConflict,
 }
 
 use self::WriteResult::*;
@@ -38,97 +167,436 @@ use self::WriteResult::*;
 impl ToString for WriteResult {
     fn to_string(&self) -> String {
         match *self {
-            AlreadyExists => return "key already exists".to_string(),
-            Inserted      => return "new entry inserted".to_string(),
-            Updated       => return "entry was updated".to_string(),
-            DoesNotExist  => return "key doesn't exist".to_string(),
+            AlreadyExists   => return "key already exists".to_string(),
+            Inserted        => return "new entry inserted".to_string(),
+            Updated         => return "entry was updated".to_string(),
+            DoesNotExist    => return "key doesn't exist".to_string(),
+            Denied          => return "permission denied".to_string(),
+            InvalidKey(err) => return format!("invalid key: {}", err),
+            EmptyValue      => return "empty values aren't allowed".to_string(),
+            ValueTooLarge   => return "value is too large".to_string(),
+            QuotaExceeded   => return "quota exceeded".to_string(),
+            Leased          => return "key is leased".to_string(),
+            VersionConflict => return "entry has a different version".to_string(),
         }
     }
 }
 
 /// metrics contains information about the SKVS.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Metrics {
     /// last_update stores the timestamp for the last time the store
     /// was updated; a call to insert, update, or delete will update
     /// this field.
-    pub last_update: i64,
+    pub last_update: Timestamp,
 
     /// last_write stores the timestamp for the last time the store
     /// was written to disk.
-    pub last_write: i64,
+    pub last_write: Timestamp,
 
     /// size stores the current number of keys in the store.
     pub size: usize,
+
+    /// counters count the operations on the store; see the
+    /// `counters` module.
+    #[serde(default, skip_serializing_if = "Counters::is_zero")]
+    pub counters: Counters,
+
+    /// preloaded counts the spilled values read back into memory by
+    /// `preload`; see the `preload` module. It isn't persisted.
+    #[serde(skip)]
+    pub preloaded: usize,
 }
 
 impl Metrics {
     /// new returns initialises an empty Metrics structure.
     pub fn new() -> Metrics {
-        Metrics { last_update: 0, last_write: 0, size: 0 }
+        Metrics {
+            last_update: Timestamp::default(),
+            last_write: Timestamp::default(),
+            size: 0,
+            counters: Counters::default(),
+            preloaded: 0,
+        }
     }
 }
 
-/// A `Store` is a simple key value store that persists to disk.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} entries, last updated {}, last written {}",
+               self.size, stats::or_never(self.last_update), stats::or_never(self.last_write))
+    }
+}
+
+/// FORMAT_VERSION is the version of the format stores are written
+/// in. Version 1 records times as hybrid logical clock timestamps;
+/// stores written before the version was recorded read as version 0,
+/// and record times as whole seconds since the Unix epoch. Version 2
+/// does the same for the times migrations were applied. Older stores
+/// are upgraded as they're loaded; see the `format` module.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A `Store` is a simple key value store that persists to disk. Its
+/// `Debug` output is redacted; see the `redact` module.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Store {
+    /// format_version is the format the store was read in, and is
+    /// set to `FORMAT_VERSION` when it's written.
+    #[serde(default)]
+    pub format_version: u32,
+
     /// path is the location on disk of the persisted SKVS.
     pub path: String,
 
     pub metrics: Metrics,
-    pub values: HashMap<String, Entry>,
+
+    /// values maps keys to their entries. It's shared with any
+    /// snapshots of the store, and copied on the first write after a
+    /// snapshot is taken; use `values_mut` to change it. Its keys are
+    /// hashed as chosen by `set_hasher`.
+    pub values: Arc<Values>,
+
+    /// shards is the number of files the values are spread across
+    /// when the store is persisted; 0 or 1 means the whole store is
+    /// written to `path`. See the `shard` module.
+    #[serde(default)]
+    pub shards: usize,
+
+    /// acl controls what each identity may do through
+    /// `Store::access`; it's persisted with the store.
+    #[serde(default)]
+    pub acl: Acl,
+
+    /// schema_version is the application-defined version of the
+    /// store's key layout; see the `migrations` module.
+    #[serde(default)]
+    pub schema_version: u32,
+
+    /// migrations records each migration applied to the store.
+    #[serde(default)]
+    pub migrations: Vec<AppliedMigration>,
+
+    /// tombstones records deleted keys, when the store is configured
+    /// to keep them; see the `tombstone` module.
+    #[serde(default)]
+    pub tombstones: HashMap<String, Tombstone>,
+
+    /// trash holds soft-deleted entries until they're restored or
+    /// purged; see the `trash` module.
+    #[serde(default)]
+    pub trash: HashMap<String, Deleted>,
+
+    /// aliases maps alternative names to the keys they stand for; see
+    /// the `alias` module.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    /// webhooks lists the rules for notifying URLs of writes; see the
+    /// `webhook` module.
+    #[serde(default)]
+    pub webhooks: Vec<WebhookRule>,
+
+    /// schedule holds writes waiting to be made; see the `schedule`
+    /// module.
+    #[serde(default)]
+    pub schedule: Schedule,
+
+    /// journal notifies subscribers of changed keys; it isn't
+    /// persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    journal: Journal,
+
+    /// write_error holds the error from the last failed flush, and is
+    /// cleared by the next successful one; it isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub write_error: Option<String>,
+
+    /// config controls what the store accepts; it isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub config: StoreConfig,
+
+    /// flushes receives the outcome of background flushes; see the
+    /// `flush` module.
+    #[serde(skip_serializing, skip_deserializing)]
+    flushes: Flushes,
+
+    /// history records recent writes for `undo` and `redo`; it isn't
+    /// persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    history: History,
+
+    /// lock is the store's lock on its path, if it holds one; see
+    /// the `lock` module. It's shared with clones of the store.
+    #[serde(skip_serializing, skip_deserializing)]
+    lock: Option<Arc<Lock>>,
+
+    /// stamp identifies the version of the store's file it was
+    /// loaded from or last flushed to; see the `reload` module.
+    #[serde(skip_serializing, skip_deserializing)]
+    stamp: Option<FileStamp>,
+
+    /// clock supplies the store's timestamps; see the `clock` module.
+    #[serde(skip_serializing, skip_deserializing, default = "clock::system")]
+    clock: Arc<dyn Clock>,
+
+    /// disk is what the store's files are written through; see the
+    /// `disk` module.
+    #[serde(skip_serializing, skip_deserializing, default = "disk::os")]
+    disk: Arc<dyn Disk>,
+
+    /// secret_key encrypts the store's secret values; see the
+    /// `secret` module. It's never persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    secret_key: Option<SecretKey>,
+
+    /// leases holds the leases on keys, and lease_holder names the
+    /// key being written inside `with_lease`; see the `lease` module.
+    /// Neither is persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    leases: HashMap<String, Lease>,
+    #[serde(skip_serializing, skip_deserializing)]
+    lease_holder: Option<String>,
+
+    /// index is the reverse index from values to keys, if it's turned
+    /// on; see the `index` module. It's shared with clones of the
+    /// store, and isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    index: Option<Arc<ReverseIndex>>,
+
+    /// usages counts what each bucket with a quota holds; see the
+    /// `quota` module. It isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    usages: Usages,
+
+    /// slow_ops holds the slow log; see the `slowlog` module. It isn't
+    /// persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    slow_ops: SlowLog,
+
+    /// hot lists the prefixes given to `preload`, whose values are
+    /// kept in memory; it isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    hot: Vec<String>,
+
+    /// blobs caches spilled values once they've been read; see the
+    /// `lazy` module. It isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    blobs: BlobCache,
+
+    /// snapshots follows the values held by the store's snapshots; see
+    /// the `snapshot` module. It isn't persisted.
+    #[serde(skip_serializing, skip_deserializing)]
+    snapshots: Snapshots,
+}
+
+/// A store is displayed as a one-line summary of its path and
+/// metrics; see `Store::report` for more detail.
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() { "(in memory)" } else { &self.path };
+        write!(f, "{}: {}", path, self.metrics)
+    }
 }
 
 /// `new` returns an empty `Store`.
 pub fn new(store_path: String) -> Store {
     Store {
+        format_version: FORMAT_VERSION,
         path: store_path.clone(),
         metrics: Metrics::new(),
-        values: HashMap::new(),
+        values: Arc::new(Values::default()),
+        shards: 0,
+        acl: Acl::new(),
+        schema_version: 0,
+        migrations: Vec::new(),
+        tombstones: HashMap::new(),
+        trash: HashMap::new(),
+        aliases: HashMap::new(),
+        webhooks: Vec::new(),
+        schedule: Schedule::default(),
+        journal: Journal::new(),
+        write_error: None,
+        config: StoreConfig::new(),
+        flushes: Flushes::new(),
+        history: History::new(),
+        lock: None,
+        stamp: None,
+        clock: clock::system(),
+        disk: disk::os(),
+        secret_key: None,
+        leases: HashMap::new(),
+        lease_holder: None,
+        index: None,
+        usages: Usages::default(),
+        slow_ops: SlowLog::new(),
+        hot: Vec::new(),
+        blobs: BlobCache::new(),
+        snapshots: Snapshots::default(),
     }
 }
 
 impl Store {
+    /// `load` reads the store persisted at `path`, taking an
+    /// exclusive lock on it; if another process has it locked, a
+    /// `StoreLocked` error is returned. Malformed input is reported
+    /// as an `InvalidData` error.
     pub fn load(path: String) -> Result<Store, io::Error> {
-        let file = File::open(path.clone())?;
-        match serde_json::from_reader(file) {
-            Ok(store) => Ok(store),
-            Err(err)  => Err(io::Error::new(io::ErrorKind::Other, err.description())),
+        Store::load_locked(path, true, None)
+    }
+
+    /// `load_locked` does the work of `load` and its variants in the
+    /// `lock` module.
+    fn load_locked(path: String, exclusive: bool, wait: Option<Duration>) -> Result<Store, io::Error> {
+        let lock = Lock::acquire(&path, exclusive, wait)?;
+        let mut store = Store::read_file(&path)?;
+        store.lock = Some(Arc::new(lock));
+        Ok(store)
+    }
+
+    /// `read_file` reads the store at `path`, and its shards if it's
+    /// sharded, without locking it.
+    fn read_file(path: &str) -> Result<Store, io::Error> {
+        let stamp = FileStamp::of(path).ok();
+        let file = File::open(path)?;
+        let mut store = Store::parse(io::BufReader::new(file))?;
+        if store.is_sharded() {
+            store.load_shards(path)?;
+        }
+        store.stamp = stamp;
+        Ok(store)
+    }
+
+    /// `from_reader` reads a store from `r`, which must hold an
+    /// unsharded store; use `load` for sharded stores, whose values
+    /// are kept in files alongside the manifest.
+    pub fn from_reader<R: io::Read>(r: R) -> Result<Store, io::Error> {
+        let store = Store::parse(r)?;
+        if store.is_sharded() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "sharded stores must be loaded from a path"));
         }
+        Ok(store)
     }
 
-    /// `flush` writes the store to disk.
+    /// `parse` reads a store, upgrading it from an older format
+    /// version if need be; see the `format` module.
+    fn parse<R: io::Read>(r: R) -> Result<Store, io::Error> {
+        let invalid = |err: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+        let mut doc: serde_json::Value = serde_json::from_reader(r).map_err(invalid)?;
+        let version = format::version_of(&doc)?;
+        format::upgrade(&mut doc, version)?;
+        serde_json::from_value(doc).map_err(invalid)
+    }
+
+    /// `flush` writes the store to disk, recording the outcome in
+    /// `write_error`, once any background flush is done. See
+    /// `flush_background` to write without waiting.
     pub fn flush(&mut self) -> Result<(), io::Error> {
+        let start = self.slow_start();
+        self.flushes.wait();
+        self.collect_flush();
+        let result = self.write();
+        self.write_error = result.as_ref().err().map(|err| err.to_string());
+        self.metrics.counters.flushed(result.is_ok());
+        self.log_slow(start, "flush", "", self.len());
+        result
+    }
+
+    /// `write` does the work of `flush`.
+    fn write(&mut self) -> Result<(), io::Error> {
         if self.path == "" {
             return Ok(());
         }
+        if self.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("{} was opened read-only", self.path)));
+        }
+        // last_write is written with the store, but only stands if
+        // the write does.
+        let last_write = self.metrics.last_write;
         self.update_metrics(false, true);
-        
-        let file = File::create(self.path.clone())?;
-        match serde_json::to_writer(file, self) {
-            Ok(_)    => Ok(()),
-            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.description())),
+        self.format_version = FORMAT_VERSION;
+
+        // Counters are left out unless they're meant to be persisted.
+        let counters = if self.config.persist_counters {
+            None
+        } else {
+            Some(std::mem::take(&mut self.metrics.counters))
+        };
+        let result = self.write_file();
+        if let Some(counters) = counters {
+            self.metrics.counters = counters;
         }
+        if result.is_err() {
+            self.metrics.last_write = last_write;
+        }
+        result?;
+
+        self.stamp = FileStamp::of(&self.path).ok();
+        if self.config.spill_bytes.is_some() {
+            // The store is safely written; side files that couldn't
+            // be removed are only wasted space, and the next flush
+            // tries again.
+            let _ = self.collect_blobs();
+        }
+        Ok(())
+    }
+
+    /// `write_file` serializes the store to its path, or to its
+    /// shards.
+    fn write_file(&mut self) -> Result<(), io::Error> {
+        self.spill()?;
+        if self.is_sharded() {
+            self.flush_shards()?;
+        } else {
+            let sync = self.config.durability == DurabilityPolicy::Fsync;
+            let canonical = self.config.canonical;
+            disk::write_atomic(&*self.disk, &self.path, sync, |w| {
+                if canonical {
+                    return canonical::write_json(w, self, true);
+                }
+                match serde_json::to_writer(w, self) {
+                    Ok(())   => Ok(()),
+                    Err(err) => Err(io::Error::other(err.to_string())),
+                }
+            })?;
+        }
+        // Only once the store no longer names them can the shards it
+        // doesn't use be removed.
+        self.remove_stale_shards();
+        Ok(())
     }
     
     /// `update_metrics` makes sure the metrics field is up to
     /// date. if `write` is true, the `last_update` field is set to
     /// the current time stamp and the `size` field is set to the
     /// current HashMap size. If `persist` is true, the `last_write`
-    /// field is updated.
+    /// field is updated. The outcome of any finished background flush
+    /// is collected first. Every write calls it, so it's also where
+    /// writes are persisted if the durability policy asks for it.
     fn update_metrics(&mut self, write: bool, persist: bool) {
-        let mut metrics = self.metrics;
+        self.collect_flush();
 
         if write {
-            metrics.last_update = time::get_time().sec;
-            metrics.size = self.len();
+            self.metrics.last_update = self.clock.now();
+            self.metrics.size = self.len();
+            if self.config.durability != DurabilityPolicy::Manual {
+                // flush records any failure in write_error.
+                let _ = self.flush();
+            }
         }
 
         if persist {
-            metrics.last_write = time::get_time().sec;
+            self.metrics.last_write = self.clock.now();
         }
+    }
 
-        self.metrics = metrics;
+    /// `values_mut` returns the values map for writing, copying it
+    /// first if a snapshot still shares it. The buckets' usage is
+    /// counted again on the next write that's checked against a quota.
+    pub fn values_mut(&mut self) -> &mut Values {
+        self.usages.clear();
+        Arc::make_mut(&mut self.values)
     }
 
     /// len returns the number of entries in the key-value store.
@@ -138,13 +606,47 @@ impl Store {
 
     /// insert writes a new entry. The expectation is that the entry doesn't
     /// exist; if it does, `AlreadyExists` is returned. Otherwise, the entry
-    /// is inserted and `Inserted` is returned.
+    /// is inserted and `Inserted` is returned. Writes the store's
+    /// configuration doesn't allow are rejected with `InvalidKey`,
+    /// `EmptyValue`, `ValueTooLarge`, or `QuotaExceeded`, writes to
+    /// leased keys with `Leased`, and writes to a read-only store with
+    /// `Denied`.
     pub fn insert(&mut self, k: String, v: String) -> WriteResult {
-        if self.values.contains_key(&k) {
+        self.insert_entry(k, v, false)
+    }
+
+    /// `insert_entry` does the work of `insert`, marking the new entry
+    /// as a secret if `secret` is true.
+    pub(super) fn insert_entry(&mut self, k: String, v: String, secret: bool) -> WriteResult {
+        self.insert_version(k, v, secret, 1)
+    }
+
+    /// `insert_version` is `insert_entry` for an entry that starts at
+    /// `version`, such as one brought back from the trash.
+    pub(super) fn insert_version(&mut self, k: String, v: String, secret: bool, version: i64) -> WriteResult {
+        let start = self.slow_start();
+        let size = v.len();
+        if let Err(wr) = self.config.check_write(&k, &v) {
+            wr
+        } else if let Err(wr) = self.check_writable(&k) {
+            wr
+        } else if self.values.contains_key(k.as_str()) {
             AlreadyExists
+        } else if let Err(wr) = quota::check(&self.config, &mut self.usages, &self.values, &k, v.len()) {
+            wr
         } else {
-            self.values.insert(k, Entry::from_string(v));
+            self.journal.record(&k, version);
+            self.unbury(&k);
+            let mut ent = Entry::with_clock(v, &*self.clock);
+            ent.version = version;
+            ent.secret = secret;
+            Arc::make_mut(&mut self.values).insert(Arc::from(k.as_str()), ent);
+            self.reindex(&k, None);
+            self.count_usage(&k, None);
+            self.remember(&k, None);
             self.update_metrics(true, false);
+            self.metrics.counters.wrote(Inserted);
+            self.log_slow(start, "insert", &k, size);
             Inserted
         }
     }
@@ -153,59 +655,166 @@ impl Store {
     /// existing entry for `k`, `Inserted` is returned. Otherwise,
     /// `Updated` is returned. Note that if `v` is the same as the
     /// existing value, the entry will not be changed but `Updated` is
-    /// still returned.
+    /// still returned. Writes the store's configuration doesn't allow
+    /// are rejected with `InvalidKey`, `EmptyValue`, `ValueTooLarge`,
+    /// or `QuotaExceeded`, writes to leased keys with `Leased`, and
+    /// writes to a read-only store with `Denied`.
     pub fn update(&mut self, k: String, v: String) -> WriteResult {
-        // TODO(kyle): return AlreadyExists if v == old.value.
-        //
-        // pretty sure this function is an abomination.
-        let wr: WriteResult;
-        let old: Option<Entry>;
-        let mut tmp_values = self.values.clone();
-
-        match tmp_values.entry(k.clone()) {
-            Occupied(e) => {
-                old = Some(e.get().clone());
-                wr = Updated;
+        self.update_entry(k, v, false)
+    }
 
-            },
-            Vacant(_)   => {
-                old = None;
-                wr = Inserted;
-            }
+    /// `update_entry` does the work of `update`. If `secret` is true,
+    /// the entry is marked as a secret, so that undo, diffs, and
+    /// patches can put back a secret's ciphertext; otherwise a changed
+    /// entry isn't a secret.
+    pub(super) fn update_entry(&mut self, k: String, v: String, secret: bool) -> WriteResult {
+        // TODO(kyle): return AlreadyExists if v == old.value.
+        if let Err(wr) = self.config.check_write(&k, &v) {
+            return wr;
         }
-
-        match old {
-            Some(ref ent) => {
-                self.values.insert(k, Entry::update_from_string(ent, v));
-            },
-            None          => {
-                self.values.insert(k, Entry::from_string(v));
-            }
+        if let Err(wr) = self.check_writable(&k) {
+            return wr;
+        }
+        if let Err(wr) = quota::check(&self.config, &mut self.usages, &self.values, &k, v.len()) {
+            return wr;
         }
+        let start = self.slow_start();
+        let size = v.len();
+        let clock = self.clock.clone();
+        let (wr, version, before) = match Arc::make_mut(&mut self.values).entry(Arc::from(k.as_str())) {
+            Occupied(mut e) => {
+                let mut ent = Entry::update_from_string(e.get(), v, &*clock);
+                if secret && !ent.secret {
+                    if ent.version == e.get().version {
+                        ent.version += 1;
+                        ent.time = clock.now();
+                    }
+                    ent.secret = true;
+                }
+                let version = ent.version;
+                if version == e.get().version {
+                    (Updated, None, None)
+                } else {
+                    let old = e.insert(ent);
+                    (Updated, Some(version), Some(old))
+                }
+            },
+            Vacant(e)       => {
+                let mut ent = Entry::with_clock(v, &*clock);
+                ent.secret = secret;
+                e.insert(ent);
+                (Inserted, Some(1), None)
+            },
+        };
 
+        if let Some(version) = version {
+            self.journal.record(&k, version);
+            self.unbury(&k);
+            self.reindex(&k, before.as_ref());
+            self.count_usage(&k, before.as_ref());
+            self.remember(&k, before.as_ref());
+        }
         self.update_metrics(true, false);
-        return wr;
+        self.metrics.counters.wrote(wr);
+        self.log_slow(start, "update", &k, size);
+        wr
+    }
+
+    /// `subscribe_keys` returns a receiver for journal records, which
+    /// name each key written from now on along with its new version
+    /// (but not its value). This is much cheaper than following the
+    /// values themselves when a consumer only needs to know what to
+    /// invalidate.
+    pub fn subscribe_keys(&mut self) -> Receiver<JournalRecord> {
+        self.journal.subscribe()
+    }
+
+    /// `get` returns `Some(value)` if the key is present in the SKVS,
+    /// following aliases. A value that was spilled and can't be read
+    /// back is `None`, and counted as a miss.
+    pub fn get(&self, k: String) -> Option<String> {
+        let start = self.slow_start();
+        let v = self.values.get(self.resolve(&k))
+            .and_then(|ent| self.shared_value(ent).ok())
+            .map(|v| v.to_string());
+        self.metrics.counters.read(v.is_some());
+        self.log_slow(start, "get", &k, v.as_ref().map_or(0, |v| v.len()));
+        v
+    }
+
+    /// `get_or` returns the value for `k`, or `default` if `k` isn't
+    /// present.
+    pub fn get_or(&self, k: &str, default: &str) -> String {
+        self.with_value(k, |v| v.to_string()).unwrap_or_else(|| default.to_string())
     }
 
-    /// `get` returns `Some(value)` if the key is present in the SKVS.
-    pub fn get(&mut self, k: String) -> Option<String> {
-        match self.values.entry(k.clone()) {
-            Occupied(ent) => return Some(ent.get().value.clone()),
-            Vacant(_)     => return None,
+    /// `get_or_insert_with` returns the value for `k`, following
+    /// aliases, first inserting the value returned by `f` under `k` if
+    /// it isn't present. If the insert is rejected, its `WriteResult`
+    /// is returned instead.
+    pub fn get_or_insert_with<F: FnOnce() -> String>(&mut self, k: String, f: F)
+                                                     -> Result<String, WriteResult> {
+        let v = self.values.get(self.resolve(&k)).and_then(|ent| self.shared_value(ent).ok());
+        self.metrics.counters.read(v.is_some());
+        if let Some(v) = v {
+            return Ok(v.to_string());
+        }
+
+        let v = f();
+        match self.insert(k, v.clone()) {
+            Inserted => Ok(v),
+            wr       => Err(wr),
         }
     }
 
-    /// `delete` removes the key from the database.
+    /// `with_value` calls `f` with a borrowed view of the value for
+    /// `k`, following aliases, and returns its result, or `None` if
+    /// `k` isn't present. It avoids copying the value for callers that
+    /// only need to parse or compare it.
+    pub fn with_value<R, F: FnOnce(&str) -> R>(&self, k: &str, f: F) -> Option<R> {
+        let v = self.values.get(self.resolve(k)).and_then(|ent| self.shared_value(ent).ok());
+        self.metrics.counters.read(v.is_some());
+        v.map(|v| f(&v))
+    }
+
+    /// `delete` removes the key from the database, leaving a
+    /// tombstone if the store is configured to. Deleting a leased key
+    /// returns `Leased`, and deleting from a read-only store `Denied`.
     pub fn delete(&mut self, k: String) -> WriteResult {
-        if self.values.contains_key(&k) {
-            self.values.remove(&k);
+        if let Err(wr) = self.check_writable(&k) {
+            return wr;
+        }
+        if self.values.contains_key(k.as_str()) {
+            let start = self.slow_start();
+            let mut size = 0;
+            if let Some(old) = Arc::make_mut(&mut self.values).remove(k.as_str()) {
+                size = spill::value_len(&old);
+                self.bury(&k, old.version + 1);
+                self.reindex(&k, Some(&old));
+                self.count_usage(&k, Some(&old));
+                self.remember(&k, Some(&old));
+            }
+            self.journal.record(&k, 0);
             self.update_metrics(true, false);
+            self.metrics.counters.deleted();
+            self.log_slow(start, "delete", &k, size);
             Updated
         }
         else {
             DoesNotExist
         }
     }
+
+    /// `delete_if_version` deletes `k` like `delete`, but only if its
+    /// entry is at version `expected`, returning `VersionConflict`
+    /// if it isn't.
+    pub fn delete_if_version(&mut self, k: String, expected: i64) -> WriteResult {
+        match self.values.get(k.as_str()) {
+            Some(ent) if ent.version != expected => VersionConflict,
+            Some(_)                              => self.delete(k),
+            None                                 => DoesNotExist,
+        }
+    }
 }
 
 
@@ -213,15 +822,15 @@ impl Store {
 fn test_store() {
     let mut kvs = new("/tmp/kvs.json".to_string());
     assert_eq!(kvs.len(), 0);
-    assert_eq!(kvs.metrics.last_update, 0);
+    assert_eq!(kvs.metrics.last_update, Timestamp::default());
     assert_eq!(kvs.metrics.size, kvs.len());
 
     let mut wr: WriteResult;
-    let mut lastup: i64;
+    let mut lastup: Timestamp;
     wr = kvs.insert("X-Pro2".to_string(), "Fujifilm".to_string());
     assert_eq!(wr, Inserted);
     assert_eq!(kvs.len(), 1);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
 
@@ -229,7 +838,7 @@ fn test_store() {
     wr = kvs.insert("D800".to_string(), "Canon".to_string());
     assert_eq!(wr, Inserted);
     assert_eq!(kvs.len(), 2);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
@@ -238,7 +847,7 @@ fn test_store() {
     wr = kvs.insert("D800".to_string(), "Nikon".to_string());
     assert_eq!(wr, AlreadyExists);
     assert_eq!(kvs.len(), 2);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
@@ -246,7 +855,7 @@ fn test_store() {
     wr = kvs.update("D800".to_string(), "Nikon".to_string());
     assert_eq!(wr, Updated);
     assert_eq!(kvs.len(), 2);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
@@ -256,21 +865,21 @@ fn test_store() {
 
     v = kvs.get("X-Pro2".to_string());
     assert_eq!(v.expect("missing entry"), "Fujifilm".to_string());
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
 
     v = kvs.get("EOS 5D Mark II".to_string());
     assert!(v.is_none());
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     lastup = kvs.metrics.last_update;
 
     wr = kvs.insert("EOS 5D Mark II".to_string(), "Canon".to_string());
     assert_eq!(wr, Inserted);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     assert_eq!(kvs.metrics.size, 3);
@@ -279,7 +888,7 @@ fn test_store() {
     // I'd probably not buy a Canon, so...
     wr = kvs.delete("EOS 5D Mark II".to_string());
     assert_eq!(wr, Updated);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     assert_eq!(kvs.metrics.size, 2);
@@ -288,7 +897,7 @@ fn test_store() {
     // just to be certain, NIFO
     wr = kvs.delete("EOS 5D Mark II".to_string());
     assert_eq!(wr, DoesNotExist);
-    assert_ne!(kvs.metrics.last_update, 0);
+    assert_ne!(kvs.metrics.last_update, Timestamp::default());
     assert!(kvs.metrics.last_update >= lastup);
     assert_eq!(kvs.metrics.size, kvs.len());
     assert_eq!(kvs.metrics.size, 2);
@@ -298,3 +907,149 @@ fn test_store() {
     assert_eq!(kvs.metrics.last_write, kvs2.metrics.last_write);
 }
 
+#[test]
+fn test_empty_values() {
+    let mut kvs = new("".to_string());
+    kvs.insert("D800".to_string(), "Nikon".to_string());
+
+    // Empty values are allowed unless the store is configured
+    // otherwise, and are distinct from missing keys.
+    assert_eq!(kvs.insert("Q2".to_string(), "".to_string()), Inserted);
+    assert_eq!(kvs.get("Q2".to_string()), Some("".to_string()));
+    kvs.config.allow_empty_values = false;
+    assert_eq!(kvs.update("D800".to_string(), "".to_string()), EmptyValue);
+    assert_eq!(kvs.get("D800".to_string()), Some("Nikon".to_string()));
+    assert_eq!(kvs.delete("Q2".to_string()), Updated);
+}
+
+#[test]
+fn test_reads() {
+    let mut kvs = new("".to_string());
+    kvs.insert("D800".to_string(), "Nikon".to_string());
+
+    assert_eq!(kvs.with_value("D800", |v| v.len()), Some(5));
+    assert!(kvs.with_value("EOS 5D Mark II", |v| v.len()).is_none());
+    assert_eq!(kvs.get_or("D800", "Pentax"), "Nikon");
+    assert_eq!(kvs.get_or("EOS 5D Mark II", "Pentax"), "Pentax");
+    assert_eq!(kvs.metrics.size, 1);
+
+    assert_eq!(kvs.get_or_insert_with("D800".to_string(), || "Pentax".to_string()), Ok("Nikon".to_string()));
+    assert_eq!(kvs.get_or_insert_with("K-1".to_string(), || "Pentax".to_string()), Ok("Pentax".to_string()));
+    assert_eq!(kvs.get_or_insert_with("K-1".to_string(), || unreachable!()), Ok("Pentax".to_string()));
+    assert_eq!(kvs.metrics.size, 2);
+
+    assert_eq!(kvs.get_or_insert_with("".to_string(), || "Leica".to_string()),
+               Err(InvalidKey(KeyError::Empty)));
+    assert_eq!(kvs.metrics.size, 2);
+}
+
+#[test]
+fn test_durability() {
+    use testutil::TempStore;
+    let path = TempStore::new("durability");
+    let mut kvs = new(path.to_string());
+    kvs.insert("manual".to_string(), "1".to_string());
+    assert!(!path.as_path().exists());
+
+    for policy in &[DurabilityPolicy::Flush, DurabilityPolicy::Fsync] {
+        kvs.config = StoreConfig::new().durability(*policy);
+        let written = kvs.metrics.last_write;
+        kvs.update("manual".to_string(), format!("{:?}", policy));
+        assert!(path.as_path().exists());
+        assert!(kvs.metrics.last_write > written);
+        assert!(kvs.write_error.is_none());
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    // A failed flush doesn't count as a write.
+    let written = kvs.metrics.last_write;
+    kvs.path = path.as_path().join("missing").to_str().unwrap().to_string();
+    kvs.update("manual".to_string(), "failed".to_string());
+    assert!(kvs.write_error.is_some());
+    assert_eq!(kvs.metrics.last_write, written);
+}
+
+#[test]
+fn test_delete_if_version() {
+    let mut kvs = new("".to_string());
+    kvs.insert("k".to_string(), "1".to_string());
+    kvs.update("k".to_string(), "2".to_string());
+
+    assert_eq!(kvs.delete_if_version("k".to_string(), 1), VersionConflict);
+    assert_eq!(kvs.get("k".to_string()), Some("2".to_string()));
+    assert_eq!(kvs.delete_if_version("k".to_string(), 2), Updated);
+    assert_eq!(kvs.delete_if_version("k".to_string(), 2), DoesNotExist);
+}
+
+#[test]
+fn test_from_reader_rejects_malformed_input() {
+    for input in &["", "{", "[]", "{\"path\": 1}", "{\"shards\": 1000000000000}"] {
+        let err = Store::from_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_load_format_version_0() {
+    let input = r#"{"path": "", "metrics": {"last_update": 1500000000, "last_write": 1500000000, "size": 1},
+                    "values": {"k": {"time": 1500000000, "version": 2, "value": "v"}}}"#;
+    let kvs = Store::from_reader(input.as_bytes()).unwrap();
+    assert_eq!(kvs.format_version, 0);
+    assert_eq!(kvs.values["k"].time, Timestamp::from_secs(1500000000));
+    assert_eq!(kvs.metrics.last_write, Timestamp::from_secs(1500000000));
+    assert_eq!(new("".to_string()).format_version, FORMAT_VERSION);
+}
+
+/// PropOp is a write operation generated for the round-trip property
+/// test.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+enum PropOp {
+    Insert(String, String),
+    Update(String, String),
+    Delete(String),
+}
+
+#[cfg(test)]
+fn prop_op() -> impl proptest::strategy::Strategy<Value = PropOp> {
+    use proptest::prelude::*;
+
+    // A small key space makes collisions between operations likely;
+    // values include control and non-ASCII characters to exercise
+    // escaping.
+    let key = "[a-d]{1,2}";
+    let value = ".{0,8}";
+    prop_oneof![
+        (key, value).prop_map(|(k, v)| PropOp::Insert(k, v)),
+        (key, value).prop_map(|(k, v)| PropOp::Update(k, v)),
+        key.prop_map(PropOp::Delete),
+    ]
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_flush_load_round_trip(ops in proptest::collection::vec(prop_op(), 0..64)) {
+        use testutil::TempStore;
+        let path = TempStore::new("prop");
+        let mut kvs = new(path.to_string());
+        for op in ops {
+            match op {
+                PropOp::Insert(k, v) => { kvs.insert(k, v); },
+                PropOp::Update(k, v) => { kvs.update(k, v); },
+                PropOp::Delete(k)    => { kvs.delete(k); },
+            }
+        }
+
+        kvs.flush().unwrap();
+        let loaded = Store::load(kvs.path.clone()).unwrap();
+        prop_assert_eq!(loaded.len(), kvs.len());
+        prop_assert_eq!(loaded.metrics.size, kvs.len());
+        for (k, ent) in kvs.values.iter() {
+            let other = &loaded.values[k];
+            prop_assert_eq!(&other.value, &ent.value);
+            prop_assert_eq!(other.version, ent.version);
+            prop_assert_eq!(other.time, ent.time);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! model maps structs onto keys under a prefix, turning the store into
+//! a small object store. A `User` with id 42 and fields `name` and
+//! `email`, under the prefix `user`, is kept in the keys `user/42/name`
+//! and `user/42/email`; the id is part of each key rather than a key
+//! of its own.
+//!
+//! `StoreModel` is normally derived with the `skvs_derive` crate. The
+//! struct's id field is marked with `#[store(id)]`, and the prefix
+//! defaults to the struct's name in lower case:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate skvs_derive;
+//!
+//! #[derive(StoreModel)]
+//! #[store(prefix = "user")]
+//! struct User {
+//!     #[store(id)]
+//!     id: u64,
+//!     name: String,
+//!     email: String,
+//! }
+//!
+//! user.save(&mut kvs)?;
+//! let user = User::load(&kvs, "42")?;
+//! ```
+//!
+//! Field values are written with `ToString` and read back with
+//! `FromStr`.
+use super::quota::Planned;
+use super::spill;
+use super::{Store, WriteResult};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// ModelError explains why a model couldn't be loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModelError {
+    /// Missing is returned with the key of a field that isn't in the
+    /// store.
+    Missing(String),
+
+    /// Parse is returned with the key of a field whose value doesn't
+    /// parse as the field's type.
+    Parse(String),
+
+    /// InvalidId is returned for an id that doesn't parse as the id
+    /// field's type.
+    InvalidId(String),
+
+    /// Unreadable is returned with the key of a field whose spilled
+    /// value couldn't be read, along with the error.
+    Unreadable { key: String, error: String },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModelError::Missing(ref k)    => write!(f, "{}: key doesn't exist", k),
+            ModelError::Parse(ref k)      => write!(f, "{}: value didn't parse", k),
+            ModelError::InvalidId(ref id) => write!(f, "invalid id {:?}", id),
+            ModelError::Unreadable { ref key, ref error } => write!(f, "{}: {}", key, error),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+/// StoreModel is a struct kept in the store, one key per field.
+pub trait StoreModel: Sized {
+    /// `prefix` returns the prefix the struct's keys are kept under.
+    fn prefix() -> &'static str;
+
+    /// `id` returns the struct's id.
+    fn id(&self) -> String;
+
+    /// `fields` returns the name and value of each field except the
+    /// id.
+    fn fields(&self) -> Vec<(&'static str, String)>;
+
+    /// `load` reads the struct with the given id from `store`.
+    fn load(store: &Store, id: &str) -> Result<Self, ModelError>;
+
+    /// `key` returns the key for `field` of the struct with the given
+    /// id.
+    fn key(id: &str, field: &str) -> String {
+        format!("{}/{}/{}", Self::prefix(), id, field)
+    }
+
+    /// `save` writes every field to `store`. If the store would reject
+    /// any of the writes, nothing is written and the rejection is
+    /// returned.
+    fn save(&self, store: &mut Store) -> Result<(), WriteResult> {
+        let id = self.id();
+        let writes: Vec<(String, String)> = self.fields().into_iter()
+            .map(|(field, v)| (Self::key(&id, field), v))
+            .collect();
+        if store.is_read_only() {
+            return Err(WriteResult::Denied);
+        }
+        let mut planned = Planned::new(store);
+        for (k, v) in &writes {
+            store.config.check_write(k, v)?;
+            if store.leased(k) {
+                return Err(WriteResult::Leased);
+            }
+            planned.write(k, store.values.get(k.as_str()).map(spill::value_len), Some(v.len()))?;
+        }
+        for (k, v) in writes {
+            match store.update(k, v) {
+                WriteResult::Inserted | WriteResult::Updated => (),
+                wr                                           => return Err(wr),
+            }
+        }
+        Ok(())
+    }
+
+    /// `delete` removes every key under the struct's id from
+    /// `store`, returning how many were removed. Keys the store won't
+    /// delete, such as leased ones, are left alone and not counted.
+    fn delete(store: &mut Store, id: &str) -> usize {
+        let prefix = format!("{}/{}/", Self::prefix(), id);
+        let keys: Vec<String> = store.values.keys()
+            .filter(|k| k.starts_with(&prefix))
+            .map(|k| k.to_string())
+            .collect();
+        keys.into_iter()
+            .filter(|k| store.delete(k.clone()) == WriteResult::Updated)
+            .count()
+    }
+}
+
+/// `field` reads and parses the value of `k` for a derived `load`.
+pub fn field<T: FromStr>(store: &Store, k: String) -> Result<T, ModelError> {
+    let v = match store.values.get(store.resolve(&k)).map(|ent| store.shared_value(ent)) {
+        Some(Ok(v))    => v,
+        Some(Err(err)) => return Err(ModelError::Unreadable { key: k, error: err.to_string() }),
+        None           => return Err(ModelError::Missing(k)),
+    };
+    v.parse().map_err(|_| ModelError::Parse(k))
+}
+
+/// `parse_id` parses an id for a derived `load`.
+pub fn parse_id<T: FromStr>(id: &str) -> Result<T, ModelError> {
+    id.parse().map_err(|_| ModelError::InvalidId(id.to_string()))
+}
+
+
+/// User is written out the way `#[derive(StoreModel)]` would.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+struct User {
+    id: u64,
+    name: String,
+    age: u32,
+}
+
+#[cfg(test)]
+impl StoreModel for User {
+    fn prefix() -> &'static str {
+        "user"
+    }
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![("name", self.name.to_string()), ("age", self.age.to_string())]
+    }
+
+    fn load(store: &Store, id: &str) -> Result<User, ModelError> {
+        Ok(User {
+            id: parse_id(id)?,
+            name: field(store, User::key(id, "name"))?,
+            age: field(store, User::key(id, "age"))?,
+        })
+    }
+}
+
+#[test]
+fn test_model() {
+    let mut kvs = super::new("".to_string());
+    let user = User { id: 42, name: "kyle".to_string(), age: 36 };
+    user.save(&mut kvs).unwrap();
+    assert_eq!(kvs.get("user/42/name".to_string()).unwrap(), "kyle");
+    assert_eq!(User::load(&kvs, "42"), Ok(user));
+
+    assert_eq!(User::load(&kvs, "7"), Err(ModelError::Missing("user/7/name".to_string())));
+    assert_eq!(User::load(&kvs, "x"), Err(ModelError::InvalidId("x".to_string())));
+    kvs.update("user/42/age".to_string(), "old".to_string());
+    assert_eq!(User::load(&kvs, "42"), Err(ModelError::Parse("user/42/age".to_string())));
+    kvs.values_mut().get_mut("user/42/age").unwrap().blob = Some("missing".to_string());
+    assert!(matches!(User::load(&kvs, "42"), Err(ModelError::Unreadable { .. })));
+    kvs.update("user/42/age".to_string(), "36".to_string());
+
+    kvs.config.max_value_bytes = Some(3);
+    let long = User { id: 1, name: "a long name".to_string(), age: 1 };
+    assert_eq!(long.save(&mut kvs), Err(WriteResult::ValueTooLarge));
+    assert!(kvs.get("user/1/age".to_string()).is_none());
+    kvs.config.max_value_bytes = None;
+
+    // A write the store would refuse partway through leaves nothing
+    // written.
+    let token = kvs.acquire_lease("user/1/age", std::time::Duration::from_secs(10)).unwrap();
+    let leased = User { id: 1, name: "a".to_string(), age: 1 };
+    assert_eq!(leased.save(&mut kvs), Err(WriteResult::Leased));
+    assert!(kvs.get("user/1/name".to_string()).is_none());
+    kvs.config = super::StoreConfig::new().quota("user/1/", super::Quota::new().max_keys(1));
+    kvs.release_lease("user/1/age", &token).unwrap();
+    assert_eq!(leased.save(&mut kvs), Err(WriteResult::QuotaExceeded));
+    assert!(kvs.get("user/1/name".to_string()).is_none());
+
+    kvs.acquire_lease("user/42/age", std::time::Duration::from_secs(10)).unwrap();
+    assert_eq!(User::delete(&mut kvs, "42"), 1);
+    assert_eq!(kvs.len(), 1);
+    kvs.leases.clear();
+
+    assert_eq!(User::delete(&mut kvs, "42"), 1);
+    assert_eq!(kvs.len(), 0);
+}
@@ -0,0 +1,182 @@
+//! open decides, in one place, whether opening a store creates it,
+//! loads it, or fails. `new`, `Store::load`, and `Store::open_readonly`
+//! each do one of those; `OpenOptions` picks between them, so that
+//! "load the store if it's there, or start a new one" is a single
+//! call.
+//!
+//! ```
+//! use skvs::store::{Store, StoreConfig};
+//!
+//! let path = std::env::temp_dir().join(format!("skvs-doc-open-{}.json", std::process::id()));
+//! let kvs = Store::options()
+//!     .path(path.to_str().unwrap())
+//!     .create(true)
+//!     .config(StoreConfig::new().undo_depth(10))
+//!     .open()
+//!     .unwrap();
+//! assert_eq!(kvs.len(), 0);
+//! assert_eq!(kvs.config.undo_depth, 10);
+//! # let _ = std::fs::remove_file(format!("{}.lock", path.to_str().unwrap()));
+//! ```
+use super::lock::Lock;
+use super::{Store, StoreConfig};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// OpenOptions describes how to open a store. By default, it loads an
+/// existing store for writing, failing if there isn't one.
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    /// path is where the store lives; an empty path opens a new store
+    /// that's only kept in memory.
+    pub path: String,
+
+    /// create makes a new, empty store if there's none at the path.
+    pub create: bool,
+
+    /// create_new makes a new store, failing if there's already one
+    /// at the path.
+    pub create_new: bool,
+
+    /// read_only takes a shared lock rather than an exclusive one; see
+    /// `Store::open_readonly`.
+    pub read_only: bool,
+
+    /// wait is how long to wait for another process to release the
+    /// store; `None` fails straight away.
+    pub wait: Option<Duration>,
+
+    /// config is the configuration to give the store, which isn't
+    /// persisted with it; `None` leaves the default.
+    pub config: Option<StoreConfig>,
+}
+
+impl OpenOptions {
+    /// `new` returns the default options.
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// `path` sets where the store lives.
+    pub fn path(mut self, path: &str) -> OpenOptions {
+        self.path = path.to_string();
+        self
+    }
+
+    /// `create` sets whether a missing store is created.
+    pub fn create(mut self, create: bool) -> OpenOptions {
+        self.create = create;
+        self
+    }
+
+    /// `create_new` sets whether an existing store is an error.
+    pub fn create_new(mut self, create_new: bool) -> OpenOptions {
+        self.create_new = create_new;
+        self
+    }
+
+    /// `read_only` sets whether the store is opened read-only.
+    pub fn read_only(mut self, read_only: bool) -> OpenOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// `wait` waits up to `timeout` for the store's lock.
+    pub fn wait(mut self, timeout: Duration) -> OpenOptions {
+        self.wait = Some(timeout);
+        self
+    }
+
+    /// `config` sets the store's configuration.
+    pub fn config(mut self, config: StoreConfig) -> OpenOptions {
+        self.config = Some(config);
+        self
+    }
+
+    /// `open` opens the store. The store is locked unless it's only
+    /// in memory, and its path is the one it was opened at, even if
+    /// it was written somewhere else. Asking to create a read-only
+    /// store is an `InvalidInput` error; a missing store that isn't
+    /// to be created is `NotFound`, and an existing one that must be
+    /// new is `AlreadyExists`.
+    pub fn open(&self) -> Result<Store, io::Error> {
+        let creates = self.create || self.create_new;
+        if creates && self.read_only {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't create a read-only store"));
+        }
+
+        let mut store = if self.path.is_empty() {
+            super::new(String::new())
+        } else {
+            let exists = Path::new(&self.path).exists();
+            if !exists && !creates {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no such store", self.path)));
+            }
+            if exists && self.create_new {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{}: store exists", self.path)));
+            }
+
+            // Check again once the lock is held, in case another
+            // process made the store in the meantime.
+            let lock = Lock::acquire(&self.path, !self.read_only, self.wait)?;
+            let mut store = if Path::new(&self.path).exists() {
+                if self.create_new {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{}: store exists", self.path)));
+                }
+                Store::read_file(&self.path)?
+            } else {
+                super::new(self.path.clone())
+            };
+            store.path = self.path.clone();
+            store.lock = Some(Arc::new(lock));
+            store
+        };
+
+        if let Some(ref config) = self.config {
+            store.config = config.clone();
+        }
+        Ok(store)
+    }
+}
+
+impl Store {
+    /// `options` returns the default options for opening a store.
+    pub fn options() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+
+#[test]
+fn test_open_options() {
+    use testutil::TempStore;
+
+    let path = TempStore::new("open");
+
+    let err = Store::options().path(&path).open().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    let err = Store::options().path(&path).create(true).read_only(true).open().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let mut kvs = Store::options().path(&path).create_new(true).open().unwrap();
+    assert!(!kvs.is_read_only());
+    assert!(Store::options().path(&path).create(true).open().is_err());
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.flush().unwrap();
+    drop(kvs);
+
+    let err = Store::options().path(&path).create_new(true).open().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    let kvs = Store::options().path(&path).create(true).open().unwrap();
+    assert_eq!(kvs.get("k".to_string()), Some("v".to_string()));
+    drop(kvs);
+
+    let kvs = Store::options().path(&path).read_only(true).open().unwrap();
+    assert!(kvs.is_read_only());
+    assert!(Store::options().path(&path).read_only(true).open().is_ok());
+    drop(kvs);
+
+    assert_eq!(Store::options().create(true).open().unwrap().path, "");
+}
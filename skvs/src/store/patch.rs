@@ -0,0 +1,384 @@
+//! patch describes declarative changes to a store: a list of sets and
+//! deletes, each of which may require the key to be at a particular
+//! version. A patch is applied atomically: either every precondition
+//! holds and the store accepts every operation (leases and quotas
+//! included), and every operation is applied, or the store is left
+//! alone.
+//!
+//! Patches are JSON, so they can be kept under version control and
+//! reviewed like any other configuration:
+//!
+//! ```text
+//! {"ops": [
+//!     {"op": "set", "key": "feature/search", "value": "on", "expect_version": 3},
+//!     {"op": "set", "key": "feature/chat", "value": "off", "expect_version": 0},
+//!     {"op": "delete", "key": "feature/legacy"}
+//! ]}
+//! ```
+//!
+//! An `expect_version` of 0 requires the key to be absent; leaving it
+//! out applies the operation whatever the key's version. A set with
+//! `"secret": true` stores its value, the ciphertext of a secret, as a
+//! secret; patches made from diffs mark them so.
+extern crate serde_json;
+
+use super::diff::StoreDiff;
+use super::quota::Planned;
+use super::{Store, WriteResult};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// Op is a single operation in a patch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Op {
+    /// Set writes `value` under `key`. If `secret` is true, `value`
+    /// is the ciphertext of a secret, and is stored as one; see the
+    /// `secret` module.
+    Set {
+        key: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect_version: Option<i64>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        secret: bool,
+    },
+
+    /// Delete removes `key`.
+    Delete {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect_version: Option<i64>,
+    },
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl Op {
+    /// `key` returns the key the operation changes.
+    pub fn key(&self) -> &str {
+        match *self {
+            Op::Set { ref key, .. }    => key,
+            Op::Delete { ref key, .. } => key,
+        }
+    }
+
+    /// `expect_version` returns the version the key must be at for
+    /// the operation to apply, if any.
+    pub fn expect_version(&self) -> Option<i64> {
+        match *self {
+            Op::Set { expect_version, .. }    => expect_version,
+            Op::Delete { expect_version, .. } => expect_version,
+        }
+    }
+}
+
+/// Patch is a list of operations, applied in order.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+    pub ops: Vec<Op>,
+}
+
+/// PatchError explains why a patch wasn't applied. The store is left
+/// unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchError {
+    /// Malformed is returned for a patch that couldn't be read.
+    Malformed(String),
+
+    /// Precondition is returned for the first operation whose key
+    /// wasn't at the expected version; `actual` is 0 if the key was
+    /// absent. `index` counts operations from 0.
+    Precondition { index: usize, key: String, expected: i64, actual: i64 },
+
+    /// Rejected is returned for the first operation the store would
+    /// refuse: one its configuration doesn't allow, one that would
+    /// exceed a quota, or one to a leased key.
+    Rejected { index: usize, key: String, result: WriteResult },
+
+    /// Unreadable is returned for the first operation whose key's
+    /// spilled value couldn't be read to check it.
+    Unreadable { index: usize, key: String, error: String },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::Malformed(ref err) => write!(f, "malformed patch: {}", err),
+            PatchError::Precondition { index, ref key, expected, actual } =>
+                write!(f, "op {}: {} is at version {}, expected {}", index, key, actual, expected),
+            PatchError::Rejected { index, ref key, result } =>
+                write!(f, "op {}: {}: {}", index, key, result.to_string()),
+            PatchError::Unreadable { index, ref key, ref error } =>
+                write!(f, "op {}: {}: value couldn't be read: {}", index, key, error),
+        }
+    }
+}
+
+impl Patch {
+    /// `from_reader` reads a JSON patch from `r`.
+    pub fn from_reader<R: io::Read>(r: R) -> Result<Patch, PatchError> {
+        serde_json::from_reader(r).map_err(|err| PatchError::Malformed(err.to_string()))
+    }
+
+    /// `write_to` writes the patch to `w` as JSON.
+    pub fn write_to<W: io::Write>(&self, w: W) -> Result<(), io::Error> {
+        serde_json::to_writer_pretty(w, self).map_err(io::Error::other)
+    }
+
+    /// `check` verifies the patch against `kvs` without changing it,
+    /// returning the first operation that would fail: one whose
+    /// precondition doesn't hold, or one the store would refuse.
+    /// Earlier operations in the patch are taken into account, so a
+    /// patch may change the same key more than once.
+    pub fn check(&self, kvs: &Store) -> Result<(), PatchError> {
+        // pending holds each key's value and version as of the
+        // operations checked so far; None means it's deleted.
+        let mut pending: HashMap<&str, Option<(Arc<str>, i64)>> = HashMap::new();
+        let mut planned = Planned::new(kvs);
+        for (index, op) in self.ops.iter().enumerate() {
+            let key = op.key();
+            let rejected = |result| PatchError::Rejected { index, key: key.to_string(), result };
+            let cur = match (pending.get(key), kvs.values.get(key)) {
+                (Some(cur), _)    => cur.clone(),
+                (None, Some(ent)) => match kvs.shared_value(ent) {
+                    Ok(v)    => Some((v, ent.version)),
+                    Err(err) => return Err(PatchError::Unreadable { index, key: key.to_string(), error: err.to_string() }),
+                },
+                (None, None)      => None,
+            };
+
+            let actual = cur.as_ref().map_or(0, |&(_, version)| version);
+            if let Some(expected) = op.expect_version() {
+                if expected != actual {
+                    return Err(PatchError::Precondition { index, key: key.to_string(), expected, actual });
+                }
+            }
+
+            let before = cur.as_ref().map(|(v, _)| v.len());
+            let next = match *op {
+                Op::Set { ref value, .. } => {
+                    kvs.config.check_write(key, value).map_err(rejected)?;
+                    if kvs.leased(key) {
+                        return Err(rejected(WriteResult::Leased));
+                    }
+                    planned.write(key, before, Some(value.len())).map_err(rejected)?;
+                    match cur {
+                        Some((old, version)) if *old == **value => Some((old, version)),
+                        _                                       => Some((Arc::from(value.as_str()), actual + 1)),
+                    }
+                },
+                Op::Delete { .. }         => {
+                    if kvs.leased(key) {
+                        return Err(rejected(WriteResult::Leased));
+                    }
+                    planned.write(key, before, None).map_err(rejected)?;
+                    None
+                },
+            };
+            pending.insert(key, next);
+        }
+        Ok(())
+    }
+
+    /// `apply` applies the patch to `kvs` if `check` passes, returning
+    /// the number of operations applied. Deleting a key that's already
+    /// absent counts as applied. Should the store still refuse a write
+    /// that `check` passed, `Rejected` is returned for it.
+    pub fn apply(&self, kvs: &mut Store) -> Result<usize, PatchError> {
+        self.check(kvs)?;
+        for (index, op) in self.ops.iter().enumerate() {
+            let result = match *op {
+                Op::Set { ref key, ref value, secret, .. } => kvs.update_entry(key.clone(), value.clone(), secret),
+                Op::Delete { ref key, .. }         => match kvs.delete(key.clone()) {
+                    WriteResult::DoesNotExist => WriteResult::Updated,
+                    result                    => result,
+                },
+            };
+            match result {
+                WriteResult::Inserted | WriteResult::Updated => (),
+                result                                       =>
+                    return Err(PatchError::Rejected { index, key: op.key().to_string(), result }),
+            }
+        }
+        Ok(self.ops.len())
+    }
+}
+
+impl StoreDiff {
+    /// `to_patch` returns a patch that makes the changes in the diff,
+    /// requiring each key to be at the version it had in the old
+    /// store (or absent, for added keys).
+    pub fn to_patch(&self) -> Patch {
+        let mut ops = Vec::with_capacity(self.len());
+        for (k, new) in &self.added {
+            ops.push(Op::Set {
+                key: k.clone(),
+                value: new.value.to_string(),
+                expect_version: Some(0),
+                secret: new.secret,
+            });
+        }
+        for change in &self.changed {
+            ops.push(Op::Set {
+                key: change.key.clone(),
+                value: change.new.value.to_string(),
+                expect_version: Some(change.old.version),
+                secret: change.new.secret,
+            });
+        }
+        for (k, old) in &self.removed {
+            ops.push(Op::Delete { key: k.clone(), expect_version: Some(old.version) });
+        }
+        Patch { ops }
+    }
+}
+
+impl Store {
+    /// `apply_patch` reads a JSON patch from `r` and applies it
+    /// atomically, returning the number of operations applied; see
+    /// `Patch::apply`.
+    pub fn apply_patch<R: io::Read>(&mut self, r: R) -> Result<usize, PatchError> {
+        Patch::from_reader(r)?.apply(self)
+    }
+}
+
+
+#[test]
+fn test_patch() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.insert("b".to_string(), "3".to_string());
+
+    let patch = Patch { ops: vec![
+        Op::Set { key: "a".to_string(), value: "two".to_string(), expect_version: Some(2), secret: false },
+        Op::Set { key: "a".to_string(), value: "II".to_string(), expect_version: Some(3), secret: false },
+        Op::Set { key: "c".to_string(), value: "4".to_string(), expect_version: Some(0), secret: false },
+        Op::Delete { key: "b".to_string(), expect_version: None },
+    ]};
+    assert_eq!(patch.apply(&mut kvs), Ok(4));
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "II");
+    assert_eq!(kvs.values["a"].version, 4);
+    assert_eq!(kvs.get("c".to_string()).unwrap(), "4");
+    assert!(kvs.get("b".to_string()).is_none());
+
+    // Applying it again fails at the first operation, and changes
+    // nothing.
+    assert_eq!(patch.apply(&mut kvs), Err(PatchError::Precondition {
+        index: 0, key: "a".to_string(), expected: 2, actual: 4,
+    }));
+
+    let patch = Patch { ops: vec![
+        Op::Set { key: "d".to_string(), value: "5".to_string(), expect_version: None, secret: false },
+        Op::Delete { key: "c".to_string(), expect_version: Some(1) },
+        Op::Set { key: "c".to_string(), value: "6".to_string(), expect_version: Some(1), secret: false },
+    ]};
+    assert_eq!(patch.apply(&mut kvs), Err(PatchError::Precondition {
+        index: 2, key: "c".to_string(), expected: 1, actual: 0,
+    }));
+    assert!(kvs.get("d".to_string()).is_none());
+    assert_eq!(kvs.get("c".to_string()).unwrap(), "4");
+}
+
+#[test]
+fn test_diff_to_patch() {
+    let mut prod = super::new("".to_string());
+    prod.insert("a".to_string(), "1".to_string());
+    prod.insert("b".to_string(), "2".to_string());
+    let mut tracked = prod.clone();
+    tracked.update("a".to_string(), "one".to_string());
+    tracked.delete("b".to_string());
+    tracked.insert("c".to_string(), "3".to_string());
+
+    let patch = prod.diff(&tracked).unwrap().to_patch();
+    let mut staging = prod.clone();
+    assert_eq!(patch.apply(&mut staging), Ok(3));
+    assert!(staging.diff(&tracked).unwrap().is_empty());
+
+    // The patch was made against the old versions.
+    assert!(patch.check(&staging).is_err());
+    prod.update("a".to_string(), "uno".to_string());
+    assert!(patch.check(&prod).is_err());
+}
+
+#[test]
+fn test_apply_patch() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+
+    let patch = r#"{"ops": [
+        {"op": "set", "key": "a", "value": "2", "expect_version": 1},
+        {"op": "delete", "key": "b"}
+    ]}"#;
+    assert_eq!(kvs.apply_patch(patch.as_bytes()), Ok(2));
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "2");
+
+    let mut buf = Vec::new();
+    kvs.diff(&super::new("".to_string())).unwrap().to_patch().write_to(&mut buf).unwrap();
+    assert_eq!(Patch::from_reader(&buf[..]).unwrap().ops,
+               vec![Op::Delete { key: "a".to_string(), expect_version: Some(2) }]);
+
+    match kvs.apply_patch(&b"{\"ops\": [{\"op\": \"rename\"}]}"[..]) {
+        Err(PatchError::Malformed(_)) => (),
+        result                        => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_patch_rejected() {
+    use super::{Quota, StoreConfig};
+    use std::time::Duration;
+
+    let mut kvs = super::new("".to_string());
+    kvs.config = StoreConfig::new().quota("q/", Quota::new().max_keys(2));
+    kvs.insert("q/1".to_string(), "1".to_string());
+    kvs.insert("free".to_string(), "1".to_string());
+
+    // The third key in q/ would only go over once the first two ops
+    // are made.
+    let patch = Patch { ops: vec![
+        Op::Set { key: "free".to_string(), value: "2".to_string(), expect_version: None, secret: false },
+        Op::Set { key: "q/2".to_string(), value: "2".to_string(), expect_version: None, secret: false },
+        Op::Set { key: "q/3".to_string(), value: "3".to_string(), expect_version: None, secret: false },
+    ]};
+    assert_eq!(patch.apply(&mut kvs), Err(PatchError::Rejected {
+        index: 2, key: "q/3".to_string(), result: WriteResult::QuotaExceeded,
+    }));
+    assert_eq!(kvs.get("free".to_string()).unwrap(), "1");
+    assert!(kvs.get("q/2".to_string()).is_none());
+
+    // Deleting one first makes room.
+    let mut ops = vec![Op::Delete { key: "q/1".to_string(), expect_version: None }];
+    ops.extend(patch.ops.iter().cloned());
+    let patch = Patch { ops };
+    kvs.acquire_lease("free", Duration::from_secs(60)).unwrap();
+    assert_eq!(patch.apply(&mut kvs), Err(PatchError::Rejected {
+        index: 1, key: "free".to_string(), result: WriteResult::Leased,
+    }));
+    assert!(kvs.get("q/1".to_string()).is_some());
+
+    kvs.leases.clear();
+    assert_eq!(patch.apply(&mut kvs), Ok(4));
+    assert_eq!(kvs.usage("q/").keys, 2);
+}
+
+#[test]
+fn test_patch_unreadable() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.values_mut().get_mut("k").unwrap().blob = Some("missing".to_string());
+
+    let patch = Patch { ops: vec![
+        Op::Set { key: "k".to_string(), value: "".to_string(), expect_version: None, secret: false },
+    ]};
+    match patch.apply(&mut kvs) {
+        Err(PatchError::Unreadable { index: 0, ref key, .. }) if key == "k" => (),
+        result                                                               => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(kvs.values["k"].blob.as_deref(), Some("missing"));
+}
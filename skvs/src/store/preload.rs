@@ -0,0 +1,77 @@
+//! preload warms a store up before it takes traffic. Values spilled
+//! to side files (see the `spill` module) are read from disk each time
+//! they're asked for; `preload` reads the ones under hot prefixes back
+//! into memory up front, and keeps them there through later flushes,
+//! so the first requests for them don't pay for the disk.
+//!
+//! The number of values preloaded so far is kept in the store's
+//! metrics as `preloaded`.
+use super::Store;
+use std::io;
+use std::sync::Arc;
+
+impl Store {
+    /// `preload` reads the spilled values of the keys starting with
+    /// any of `prefixes` into memory, returning how many were read,
+    /// and stops values under those prefixes from being spilled again.
+    pub fn preload(&mut self, prefixes: &[&str]) -> Result<usize, io::Error> {
+        for prefix in prefixes {
+            if !self.hot.iter().any(|hot| hot == prefix) {
+                self.hot.push(prefix.to_string());
+            }
+        }
+
+        let spilled: Vec<Arc<str>> = self.values.iter()
+            .filter(|&(k, ent)| ent.blob.is_some() && prefixes.iter().any(|prefix| k.starts_with(prefix)))
+            .map(|(k, _)| k.clone())
+            .collect();
+        let mut loaded = 0;
+        for k in spilled {
+            let v = self.shared_value(&self.values[&k])?;
+            if let Some(ent) = self.values_mut().get_mut(&k) {
+                ent.value = v;
+                ent.blob = None;
+            }
+            loaded += 1;
+            self.metrics.preloaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// `is_hot` returns true if `k` is under a prefix given to
+    /// `preload`.
+    pub(super) fn is_hot(&self, k: &str) -> bool {
+        self.hot.iter().any(|prefix| k.starts_with(prefix.as_str()))
+    }
+}
+
+
+#[test]
+fn test_preload() {
+    use testutil::TempStore;
+    use super::StoreConfig;
+    use std::fs;
+
+    let path = TempStore::new("preload");
+    let large = "x".repeat(1000);
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(100);
+    kvs.insert("hot/a".to_string(), large.clone());
+    kvs.insert("cold/a".to_string(), "y".repeat(1000));
+    kvs.flush().unwrap();
+    assert!(kvs.values["hot/a"].blob.is_some());
+
+    assert_eq!(kvs.preload(&["hot/"]).unwrap(), 1);
+    assert_eq!(&*kvs.values["hot/a"].value, large);
+    assert!(kvs.values["hot/a"].blob.is_none());
+    assert!(kvs.values["cold/a"].blob.is_some());
+    assert_eq!(kvs.metrics.preloaded, 1);
+
+    // Hot values stay in memory, and their side files are collected.
+    kvs.insert("hot/b".to_string(), large.clone());
+    kvs.flush().unwrap();
+    assert!(kvs.values["hot/b"].blob.is_none());
+    assert_eq!(fs::read_dir(super::spill::blob_dir(&path)).unwrap().count(), 1);
+    assert_eq!(kvs.preload(&["hot/"]).unwrap(), 0);
+}
@@ -0,0 +1,278 @@
+//! quota limits how many keys, and how many bytes of keys and values,
+//! a bucket of the keyspace may hold. A bucket is a key prefix, such
+//! as a tenant's `tenant1/`, given a `Quota` with `StoreConfig::quota`;
+//! a write that would take any bucket holding its key over quota is
+//! rejected with `QuotaExceeded`.
+//!
+//! Quotas are checked by `insert`, `update`, `bulk_load`, `import`,
+//! `apply_diff`, and `Patch::apply`, the last two refusing the whole
+//! change if any write would exceed one. Merges copy what they can,
+//! rejecting keys that would exceed a quota just as they reject keys
+//! the store's configuration doesn't allow, and list them in the
+//! `MergeReport`; replicas with different quotas can diverge over
+//! them. Restores from the trash are checked like inserts, since the
+//! bucket may have filled up since the key was deleted.
+//!
+//! The store keeps a running count of what each bucket holds, so
+//! checking a write doesn't scan the bucket. A bucket is counted the
+//! first time a write to it is checked, and every write after that
+//! keeps its count up to date. Merges, reloads, and `values_mut` drop
+//! the counts, which are taken again as needed.
+//! Writes to keys outside every bucket cost nothing extra.
+//!
+//! ```
+//! use skvs::store::{Quota, StoreConfig, WriteResult};
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.config = StoreConfig::new().quota("team1/", Quota::new().max_keys(1));
+//! assert_eq!(kvs.insert("team1/a".to_string(), "1".to_string()), WriteResult::Inserted);
+//! assert_eq!(kvs.insert("team1/b".to_string(), "2".to_string()), WriteResult::QuotaExceeded);
+//! assert_eq!(kvs.usage("team1/").keys, 1);
+//! ```
+use super::config::StoreConfig;
+use super::entry::Entry;
+use super::spill;
+use super::{Store, Values, WriteResult};
+use std::collections::HashMap;
+
+/// Quota sets the limits for a bucket; `None` means no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quota {
+    /// max_keys is the most keys the bucket may hold.
+    pub max_keys: Option<usize>,
+
+    /// max_bytes is the most bytes of keys and values the bucket may
+    /// hold.
+    pub max_bytes: Option<usize>,
+}
+
+impl Quota {
+    /// `new` returns a quota with no limits.
+    pub fn new() -> Quota {
+        Quota::default()
+    }
+
+    /// `max_keys` limits the bucket to `max` keys.
+    pub fn max_keys(mut self, max: usize) -> Quota {
+        self.max_keys = Some(max);
+        self
+    }
+
+    /// `max_bytes` limits the bucket to `max` bytes of keys and
+    /// values.
+    pub fn max_bytes(mut self, max: usize) -> Quota {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// `allows` returns true if `usage` is within the quota.
+    pub fn allows(&self, usage: Usage) -> bool {
+        self.max_keys.is_none_or(|max| usage.keys <= max) &&
+            self.max_bytes.is_none_or(|max| usage.bytes <= max)
+    }
+}
+
+/// Usage is what a bucket holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// keys is the number of keys in the bucket.
+    pub keys: usize,
+
+    /// bytes is the number of bytes in those keys and their values.
+    pub bytes: usize,
+}
+
+impl Usage {
+    /// `change` accounts for a write to `k` that changed the length of
+    /// its value from `before` to `after`, `None` meaning there was no
+    /// value.
+    fn change(&mut self, k: &str, before: Option<usize>, after: Option<usize>) {
+        if let Some(len) = before {
+            self.keys -= 1;
+            self.bytes -= k.len() + len;
+        }
+        if let Some(len) = after {
+            self.keys += 1;
+            self.bytes += k.len() + len;
+        }
+    }
+}
+
+/// BucketUsage reports a bucket's usage against its quota.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BucketUsage {
+    pub bucket: String,
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+fn usage_of(values: &Values, bucket: &str) -> Usage {
+    let mut usage = Usage::default();
+    for (k, ent) in values.iter().filter(|(k, _)| k.starts_with(bucket)) {
+        usage.keys += 1;
+        usage.bytes += k.len() + spill::value_len(ent);
+    }
+    usage
+}
+
+/// Usages holds the running usage of each bucket that's been counted.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Usages {
+    buckets: HashMap<String, Usage>,
+}
+
+impl Usages {
+    /// `of` returns the usage of `bucket`, counting it if it hasn't
+    /// been.
+    fn of(&mut self, values: &Values, bucket: &str) -> Usage {
+        *self.buckets.entry(bucket.to_string()).or_insert_with(|| usage_of(values, bucket))
+    }
+
+    /// `wrote` updates the count of every bucket holding `k` after a
+    /// write that changed the length of its value from `before` to
+    /// `after`, `None` meaning there was no value.
+    pub(super) fn wrote(&mut self, k: &str, before: Option<usize>, after: Option<usize>) {
+        for (_, usage) in self.buckets.iter_mut().filter(|(bucket, _)| k.starts_with(bucket.as_str())) {
+            usage.change(k, before, after);
+        }
+    }
+
+    /// `clear` drops every count, after wholesale changes to the
+    /// values.
+    pub(super) fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+/// Planned follows the usage of a store's buckets through a series of
+/// writes that haven't been made yet, so that the whole series can be
+/// checked against their quotas before any of it is made.
+pub(super) struct Planned<'a> {
+    kvs: &'a Store,
+    buckets: HashMap<&'a str, Usage>,
+}
+
+impl<'a> Planned<'a> {
+    /// `new` returns a plan starting from what `kvs` holds now.
+    pub(super) fn new(kvs: &'a Store) -> Planned<'a> {
+        Planned { kvs, buckets: HashMap::new() }
+    }
+
+    /// `write` adds a write to `k` that changes the length of its
+    /// value from `before` to `after` to the plan, `None` meaning there
+    /// is no value. As with `check`, only writes that leave a value
+    /// can exceed a quota; those that would return `QuotaExceeded`.
+    pub(super) fn write(&mut self, k: &str, before: Option<usize>, after: Option<usize>)
+                        -> Result<(), WriteResult> {
+        let kvs = self.kvs;
+        for &(ref bucket, quota) in kvs.config.quotas.iter().filter(|(bucket, _)| k.starts_with(bucket.as_str())) {
+            let usage = self.buckets.entry(bucket.as_str()).or_insert_with(|| kvs.usage(bucket));
+            usage.change(k, before, after);
+            if after.is_some() && !quota.allows(*usage) {
+                return Err(WriteResult::QuotaExceeded);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `check` returns `QuotaExceeded` if writing a value of `len` bytes
+/// under `k` would take any bucket holding `k` over its quota. It takes
+/// the values and their usages rather than the store so that it can be
+/// used while they're borrowed for writing.
+pub(super) fn check(config: &StoreConfig, usages: &mut Usages, values: &Values, k: &str, len: usize)
+                    -> Result<(), WriteResult> {
+    for &(ref bucket, quota) in config.quotas.iter().filter(|(bucket, _)| k.starts_with(bucket.as_str())) {
+        let mut usage = usages.of(values, bucket);
+        match values.get(k) {
+            Some(old) => usage.bytes -= k.len() + spill::value_len(old),
+            None      => usage.keys += 1,
+        }
+        usage.bytes += k.len() + len;
+
+        if !quota.allows(usage) {
+            return Err(WriteResult::QuotaExceeded);
+        }
+    }
+    Ok(())
+}
+
+impl Store {
+    /// `usage` returns what's held in the bucket `bucket`, whether or
+    /// not it has a quota.
+    pub fn usage(&self, bucket: &str) -> Usage {
+        match self.usages.buckets.get(bucket) {
+            Some(&usage) => usage,
+            None         => usage_of(&self.values, bucket),
+        }
+    }
+
+    /// `count_usage` updates the usage of the buckets holding `k` after
+    /// a write to it, whose entry was `before`.
+    pub(super) fn count_usage(&mut self, k: &str, before: Option<&Entry>) {
+        let after = self.values.get(k).map(spill::value_len);
+        self.usages.wrote(k, before.map(spill::value_len), after);
+    }
+
+    /// `bucket_usage` reports the usage of every bucket with a quota,
+    /// in the order they were configured.
+    pub fn bucket_usage(&self) -> Vec<BucketUsage> {
+        self.config.quotas.iter()
+            .map(|&(ref bucket, quota)| BucketUsage {
+                bucket: bucket.clone(),
+                quota,
+                usage: self.usage(bucket),
+            })
+            .collect()
+    }
+}
+
+
+#[test]
+fn test_quotas() {
+    use super::WriteResult::*;
+
+    let mut kvs = super::new("".to_string());
+    kvs.config = StoreConfig::new()
+        .quota("a/", Quota::new().max_keys(2))
+        .quota("b/", Quota::new().max_bytes(10));
+
+    assert_eq!(kvs.insert("a/1".to_string(), "x".to_string()), Inserted);
+    assert_eq!(kvs.insert("a/2".to_string(), "x".to_string()), Inserted);
+    assert_eq!(kvs.insert("a/3".to_string(), "x".to_string()), QuotaExceeded);
+    assert_eq!(kvs.update("a/2".to_string(), "changed".to_string()), Updated);
+    assert_eq!(kvs.insert("c/1".to_string(), "x".to_string()), Inserted);
+
+    assert_eq!(kvs.update("b/1".to_string(), "12345".to_string()), Inserted);
+    assert_eq!(kvs.update("b/1".to_string(), "1234567".to_string()), Updated);
+    assert_eq!(kvs.update("b/1".to_string(), "12345678".to_string()), QuotaExceeded);
+    assert_eq!(kvs.get("b/1".to_string()).unwrap(), "1234567");
+    assert!(kvs.bulk_load(vec![("b/2".to_string(), "".to_string())], false).is_err());
+
+    let usage = kvs.bucket_usage();
+    assert_eq!(usage.len(), 2);
+    assert_eq!((usage[0].bucket.as_str(), usage[0].usage.keys), ("a/", 2));
+    assert_eq!(usage[1].usage, Usage { keys: 1, bytes: 10 });
+}
+
+#[test]
+fn test_quota_usage_counts() {
+    let mut kvs = super::new("".to_string());
+    kvs.config = StoreConfig::new().quota("a/", Quota::new().max_bytes(100));
+    kvs.insert("a/1".to_string(), "xx".to_string());
+    assert_eq!(kvs.usages.buckets["a/"], Usage { keys: 1, bytes: 5 });
+
+    kvs.insert("a/2".to_string(), "xxx".to_string());
+    kvs.update("a/1".to_string(), "x".to_string());
+    kvs.rename("a/2", "a/3", false);
+    kvs.bulk_load(vec![("a/4".to_string(), "xxxx".to_string())], false).unwrap();
+    kvs.delete("a/4".to_string());
+    kvs.soft_delete("a/1".to_string());
+    kvs.restore("a/1".to_string()).unwrap();
+    assert_eq!(kvs.usages.buckets["a/"], usage_of(&kvs.values, "a/"));
+    assert_eq!(kvs.usage("a/"), Usage { keys: 2, bytes: 10 });
+
+    kvs.values_mut().remove("a/3");
+    assert!(kvs.usages.buckets.is_empty());
+    assert_eq!(kvs.usage("a/"), Usage { keys: 1, bytes: 4 });
+}
@@ -0,0 +1,153 @@
+//! redact keeps sensitive values out of the store's exports and debug
+//! output, and out of anything else that shows values to people, such
+//! as the REPL's `scan`. An entry is redacted if it's a secret (see
+//! the `secret` module) or if its key starts with one of the prefixes
+//! in the store's `RedactionPolicy`; its value is then shown as `***`.
+//! Redaction only changes how values are shown: `get` still returns
+//! them as they are.
+//!
+//! ```
+//! use skvs::store::{Format, RedactionPolicy};
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.config.redaction = RedactionPolicy::new().prefix("auth/");
+//! kvs.insert("auth/password".to_string(), "hunter2".to_string());
+//!
+//! let mut buf: Vec<u8> = Vec::new();
+//! kvs.export(Format::DotEnv, &mut buf).unwrap();
+//! assert_eq!(String::from_utf8(buf).unwrap(), "auth/password=\"***\"\n");
+//! ```
+use super::entry::Entry;
+use super::Store;
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// REDACTED is shown in place of a redacted value.
+pub const REDACTED: &str = "***";
+
+/// RedactionPolicy lists the key prefixes whose values are redacted,
+/// in addition to secrets, which always are.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RedactionPolicy {
+    prefixes: Vec<String>,
+}
+
+impl RedactionPolicy {
+    /// `new` returns a policy that only redacts secrets.
+    pub fn new() -> RedactionPolicy {
+        RedactionPolicy::default()
+    }
+
+    /// `prefix` adds a prefix whose keys are redacted.
+    pub fn prefix(mut self, prefix: &str) -> RedactionPolicy {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// `redacts` returns true if the entry `ent` under `k` should be
+    /// shown as `REDACTED`.
+    pub fn redacts(&self, k: &str, ent: &Entry) -> bool {
+        ent.secret || self.prefixes.iter().any(|prefix| k.starts_with(prefix.as_str()))
+    }
+}
+
+impl Store {
+    /// `shown_value` returns the value for `k`, following aliases, as
+    /// it should be shown to people: `REDACTED` if the store's
+    /// redaction policy covers `k` or the key it resolves to, or the
+    /// value itself otherwise.
+    pub fn shown_value(&self, k: &str) -> Option<Cow<'_, str>> {
+        let target = self.resolve(k);
+        let ent = self.values.get(target)?;
+        if self.config.redaction.redacts(k, ent) {
+            return Some(Cow::Borrowed(REDACTED));
+        }
+        self.shown(target, ent).ok()
+    }
+
+    /// `shown` works like `shown_value` for `k`'s entry `ent`, failing
+    /// if its value was spilled and can't be read.
+    pub(super) fn shown<'a>(&'a self, k: &str, ent: &'a Entry) -> Result<Cow<'a, str>, io::Error> {
+        if self.config.redaction.redacts(k, ent) {
+            return Ok(Cow::Borrowed(REDACTED));
+        }
+        match ent.blob {
+            Some(_) => self.shared_value(ent).map(|v| Cow::Owned(v.to_string())),
+            None    => Ok(Cow::Borrowed(&ent.value)),
+        }
+    }
+}
+
+/// Entries shows a set of entries with their values redacted
+/// according to `policy`, in key order.
+struct Entries<'a, I: Clone + Iterator<Item = (&'a str, &'a Entry)>> {
+    entries: I,
+    policy: &'a RedactionPolicy,
+}
+
+impl<'a, I: Clone + Iterator<Item = (&'a str, &'a Entry)>> fmt::Debug for Entries<'a, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut entries: Vec<(&str, &Entry)> = self.entries.clone().collect();
+        entries.sort_by_key(|&(k, _)| k);
+
+        let mut map = f.debug_map();
+        for (k, ent) in entries {
+            if self.policy.redacts(k, ent) && !ent.secret {
+                let mut shown = ent.clone();
+                shown.value = Arc::from(REDACTED);
+                map.entry(&k, &shown);
+            } else {
+                map.entry(&k, ent);
+            }
+        }
+        map.finish()
+    }
+}
+
+/// A store's debug output redacts its values and those in its trash.
+/// The undo history holds values too, so it's left out, along with
+/// the rest of the store's runtime state.
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = &self.config.redaction;
+        let values = Entries { entries: self.values.iter().map(|(k, ent)| (&**k, ent)), policy };
+        let trash = Entries { entries: self.trash.iter().map(|(k, del)| (k.as_str(), &del.entry)), policy };
+        f.debug_struct("Store")
+            .field("format_version", &self.format_version)
+            .field("path", &self.path)
+            .field("metrics", &self.metrics)
+            .field("values", &values)
+            .field("shards", &self.shards)
+            .field("acl", &self.acl)
+            .field("schema_version", &self.schema_version)
+            .field("migrations", &self.migrations)
+            .field("tombstones", &self.tombstones)
+            .field("trash", &trash)
+            .field("write_error", &self.write_error)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+
+#[test]
+fn test_redaction() {
+    let mut kvs = super::new("".to_string());
+    kvs.config.redaction = RedactionPolicy::new().prefix("auth/");
+    kvs.insert("auth/password".to_string(), "hunter2".to_string());
+    kvs.insert("auth/user".to_string(), "kyle".to_string());
+    kvs.insert("name".to_string(), "skvs".to_string());
+    kvs.soft_delete("auth/user".to_string());
+
+    assert_eq!(kvs.shown_value("auth/password").as_deref(), Some(REDACTED));
+    assert_eq!(kvs.shown_value("name").as_deref(), Some("skvs"));
+    assert_eq!(kvs.shown_value("missing"), None);
+    assert_eq!(kvs.get("auth/password".to_string()).unwrap(), "hunter2");
+
+    let debug = format!("{:?}", kvs);
+    assert!(!debug.contains("hunter2"));
+    assert!(!debug.contains("kyle"));
+    assert!(debug.contains("skvs"));
+}
@@ -0,0 +1,474 @@
+//! redis imports the string keys from a Redis append-only file
+//! (AOF) into the store, which makes migrating a small Redis instance
+//! a matter of pointing skvs at its `appendonly.aof`.
+//!
+//! The AOF is replayed command by command: string commands (`SET`,
+//! `MSET`, `APPEND`, `INCRBY`, `DEL`, `RENAME`, ...) are applied to a
+//! scratch copy of the keyspace, and the surviving keys are written to
+//! the store at the end. Other commands, such as those on hashes,
+//! lists, and sets, are counted, and the keys they write are reported
+//! rather than imported, since their values can't be known. Expirations
+//! are ignored. AOF files with an RDB preamble aren't supported.
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+
+/// AofReport describes the outcome of `Store::import_redis_aof`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AofReport {
+    /// inserted is the number of keys that didn't exist in the
+    /// store before.
+    pub inserted: usize,
+
+    /// updated is the number of existing store keys that were
+    /// overwritten.
+    pub updated: usize,
+
+    /// skipped_keys lists the keys that weren't imported, with the
+    /// reason each was skipped.
+    pub skipped_keys: BTreeMap<String, SkipReason>,
+
+    /// skipped_commands counts, by (lowercased) name, the commands
+    /// that weren't applied.
+    pub skipped_commands: BTreeMap<String, usize>,
+}
+
+/// SkipReason explains why a key wasn't imported from an AOF.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkipReason {
+    /// Unsupported is given, with the (lowercased) command's name, for
+    /// a key last written by a command that isn't imported, such as
+    /// one on a hash or list.
+    Unsupported(String),
+
+    /// InvalidUtf8 is given for a key whose name or value isn't valid
+    /// UTF-8.
+    InvalidUtf8,
+
+    /// Rejected is given, with the write's result, for a key the store
+    /// wouldn't write.
+    Rejected(WriteResult),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SkipReason::Unsupported(ref cmd) => write!(f, "written by unsupported command {}", cmd),
+            SkipReason::InvalidUtf8          => write!(f, "not valid UTF-8"),
+            SkipReason::Rejected(wr)         => write!(f, "rejected: {}", wr.to_string()),
+        }
+    }
+}
+
+/// Commands that only affect expiry or transactions; they're accepted
+/// without being counted as skipped.
+const IGNORED: &[&str] = &[
+    "expire", "pexpire", "expireat", "pexpireat", "persist",
+    "multi", "exec", "select", "ping",
+];
+
+/// `MAX_BULK_LEN` is the longest bulk string accepted, which is also
+/// Redis's own default limit (`proto-max-bulk-len`).
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// `MAX_PREALLOCATED_ARGS` caps the arguments space is set aside for
+/// up front, however many a command claims to have.
+const MAX_PREALLOCATED_ARGS: usize = 1024;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// `read_line` reads a CRLF-terminated line, returning it without the
+/// line ending, or `None` at the end of the input.
+fn read_line<R: BufRead>(r: &mut R) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut line = Vec::new();
+    if r.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(line: &[u8]) -> Result<usize, io::Error> {
+    match String::from_utf8_lossy(line).parse::<usize>() {
+        Ok(n)  => Ok(n),
+        Err(_) => Err(invalid_data(format!("invalid length {:?}",
+                                           String::from_utf8_lossy(line)))),
+    }
+}
+
+/// `read_command` reads the next command, either as a RESP array of
+/// bulk strings (which is what Redis writes) or as an inline,
+/// whitespace-separated command.
+fn read_command<R: BufRead>(r: &mut R) -> Result<Option<Vec<Vec<u8>>>, io::Error> {
+    let line = loop {
+        match read_line(r)? {
+            None                       => return Ok(None),
+            Some(ref l) if l.is_empty() => continue,
+            Some(l)                    => break l,
+        }
+    };
+
+    if line.starts_with(b"REDIS") {
+        return Err(invalid_data("AOF files with an RDB preamble aren't supported".to_string()));
+    }
+    if line[0] != b'*' {
+        let args = line.split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_vec())
+            .collect();
+        return Ok(Some(args));
+    }
+
+    let argc = parse_len(&line[1..])?;
+    let mut args = Vec::with_capacity(argc.min(MAX_PREALLOCATED_ARGS));
+    for _ in 0..argc {
+        let header = match read_line(r)? {
+            Some(ref h) if h.first() == Some(&b'$') => parse_len(&h[1..])?,
+            _ => return Err(invalid_data("expected a bulk string".to_string())),
+        };
+        let len = match header.checked_add(2) {
+            Some(len) if header <= MAX_BULK_LEN => len,
+            _                                   => return Err(invalid_data(format!("bulk string of {} bytes is too long", header))),
+        };
+
+        // The length is only trusted as far as the input bears it out.
+        let mut arg = Vec::new();
+        r.by_ref().take(len as u64).read_to_end(&mut arg)?;
+        if arg.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bulk string is truncated"));
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Err(invalid_data("bulk string isn't terminated by CRLF".to_string()));
+        }
+        arg.truncate(header);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Keyspace is the scratch copy of the Redis keyspace that the AOF is
+/// replayed into. Keys whose values can't be followed are kept in
+/// `others`, along with the command that last wrote them.
+#[derive(Default)]
+struct Keyspace {
+    strings: HashMap<Vec<u8>, Vec<u8>>,
+    others: BTreeMap<Vec<u8>, String>,
+}
+
+impl Keyspace {
+    fn exists(&self, key: &[u8]) -> bool {
+        self.strings.contains_key(key) || self.others.contains_key(key)
+    }
+
+    /// `set` replays `SET key value [NX|XX] [GET] [EX seconds|...]`,
+    /// returning false for options it doesn't know.
+    fn set(&mut self, args: &[Vec<u8>]) -> bool {
+        let (mut nx, mut xx) = (false, false);
+        let mut opts = args[2..].iter();
+        while let Some(opt) = opts.next() {
+            match String::from_utf8_lossy(opt).to_lowercase().as_str() {
+                "nx"                          => nx = true,
+                "xx"                          => xx = true,
+                "get" | "keepttl"             => (),
+                "ex" | "px" | "exat" | "pxat" => if opts.next().is_none() {
+                    return false;
+                },
+                _                             => return false,
+            }
+        }
+        if nx && xx {
+            return false;
+        }
+
+        let exists = self.exists(&args[0]);
+        if (nx && exists) || (xx && !exists) {
+            return true;
+        }
+        self.strings.insert(args[0].clone(), args[1].clone());
+        true
+    }
+
+    /// `rename` replays `RENAME` or, if `nx` is true, `RENAMENX`,
+    /// returning false if `src` doesn't exist, as Redis refuses that.
+    fn rename(&mut self, src: &[u8], dst: &[u8], nx: bool) -> bool {
+        if !self.exists(src) {
+            return false;
+        }
+        if src == dst || (nx && self.exists(dst)) {
+            return true;
+        }
+        let (v, other) = (self.strings.remove(src), self.others.remove(src));
+        self.strings.remove(dst);
+        self.others.remove(dst);
+        if let Some(v) = v {
+            self.strings.insert(dst.to_vec(), v);
+        }
+        if let Some(cmd) = other {
+            self.others.insert(dst.to_vec(), cmd);
+        }
+        true
+    }
+
+    /// `incr_by` adds `delta` times `sign` to the number at `key`. Like
+    /// Redis, it refuses to overflow, returning false instead.
+    fn incr_by(&mut self, key: &[u8], delta: &[u8], sign: i64) -> bool {
+        let delta = match String::from_utf8_lossy(delta).parse::<i64>().ok().and_then(|d| d.checked_mul(sign)) {
+            Some(d) => d,
+            None    => return false,
+        };
+        let current = match self.strings.get(key) {
+            Some(v) => match String::from_utf8_lossy(v).parse::<i64>() {
+                Ok(n)  => n,
+                Err(_) => return false,
+            },
+            None    => 0,
+        };
+        match current.checked_add(delta) {
+            Some(n) => {
+                self.strings.insert(key.to_vec(), n.to_string().into_bytes());
+                true
+            },
+            None    => false,
+        }
+    }
+
+    /// `apply` replays one command, returning false if it wasn't
+    /// applied.
+    fn apply(&mut self, cmd: &str, args: &[Vec<u8>]) -> bool {
+        match (cmd, args.len()) {
+            ("set", n) if n >= 2 => return self.set(args),
+            ("getset", 2) => {
+                self.strings.insert(args[0].clone(), args[1].clone());
+            },
+            ("setnx", 2) => {
+                if !self.strings.contains_key(&args[0]) {
+                    self.strings.insert(args[0].clone(), args[1].clone());
+                }
+            },
+            ("setex", 3) | ("psetex", 3) => {
+                self.strings.insert(args[0].clone(), args[2].clone());
+            },
+            ("mset", n) if n > 0 && n % 2 == 0 => {
+                for pair in args.chunks(2) {
+                    self.strings.insert(pair[0].clone(), pair[1].clone());
+                }
+            },
+            ("msetnx", n) if n > 0 && n % 2 == 0 => {
+                if args.chunks(2).all(|pair| !self.strings.contains_key(&pair[0])) {
+                    for pair in args.chunks(2) {
+                        self.strings.insert(pair[0].clone(), pair[1].clone());
+                    }
+                }
+            },
+            ("append", 2) => {
+                self.strings.entry(args[0].clone())
+                    .or_default()
+                    .extend_from_slice(&args[1]);
+            },
+            ("incr", 1)   => return self.incr_by(&args[0], b"1", 1),
+            ("decr", 1)   => return self.incr_by(&args[0], b"1", -1),
+            ("incrby", 2) => return self.incr_by(&args[0], &args[1], 1),
+            ("decrby", 2) => return self.incr_by(&args[0], &args[1], -1),
+            ("rename", 2)   => return self.rename(&args[0], &args[1], false),
+            ("renamenx", 2) => return self.rename(&args[0], &args[1], true),
+            ("getdel", 1) => {
+                self.strings.remove(&args[0]);
+            },
+            ("del", _) | ("unlink", _) => {
+                for k in args {
+                    self.strings.remove(k);
+                    self.others.remove(k);
+                }
+            },
+            ("flushall", _) | ("flushdb", _) => {
+                self.strings.clear();
+                self.others.clear();
+            },
+            // The key's value is no longer known, even if it was a
+            // string.
+            (_, n) if n > 0 => {
+                self.strings.remove(&args[0]);
+                self.others.insert(args[0].clone(), cmd.to_string());
+                return false;
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Store {
+    /// `import_redis_aof` replays the Redis append-only file in `r`
+    /// and writes the resulting string keys to the store, overwriting
+    /// existing keys. Only database 0 is imported; commands for other
+    /// databases are reported as skipped.
+    pub fn import_redis_aof<R: Read>(&mut self, r: R) -> Result<AofReport, io::Error> {
+        let mut r = BufReader::new(r);
+        let mut keyspace = Keyspace::default();
+        let mut report = AofReport::default();
+        let mut db = 0;
+
+        while let Some(command) = read_command(&mut r)? {
+            if command.is_empty() {
+                continue;
+            }
+
+            let cmd = String::from_utf8_lossy(&command[0]).to_lowercase();
+            if cmd == "select" && command.len() == 2 {
+                db = parse_len(&command[1])?;
+            }
+
+            let applied = if IGNORED.contains(&cmd.as_str()) {
+                true
+            } else if db != 0 {
+                false
+            } else {
+                keyspace.apply(&cmd, &command[1..])
+            };
+
+            if !applied {
+                *report.skipped_commands.entry(cmd).or_insert(0) += 1;
+            }
+        }
+
+        for (k, cmd) in keyspace.others {
+            if !keyspace.strings.contains_key(&k) {
+                report.skipped_keys.insert(String::from_utf8_lossy(&k).into_owned(), SkipReason::Unsupported(cmd));
+            }
+        }
+
+        for (k, v) in keyspace.strings {
+            let (k, v) = match (String::from_utf8(k), String::from_utf8(v)) {
+                (Ok(k), Ok(v)) => (k, v),
+                (Ok(k), Err(_)) => {
+                    report.skipped_keys.insert(k, SkipReason::InvalidUtf8);
+                    continue;
+                },
+                (Err(err), _) => {
+                    report.skipped_keys.insert(String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                                               SkipReason::InvalidUtf8);
+                    continue;
+                },
+            };
+
+            match self.update(k.clone(), v) {
+                Inserted => report.inserted += 1,
+                Updated  => report.updated += 1,
+                wr       => { report.skipped_keys.insert(k, SkipReason::Rejected(wr)); },
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+
+#[test]
+fn test_import_redis_aof() {
+    let aof = "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n\
+               *3\r\n$3\r\nSET\r\n$6\r\nX-Pro2\r\n$8\r\nFujifilm\r\n\
+               *3\r\n$3\r\nSET\r\n$4\r\nD800\r\n$5\r\nCanon\r\n\
+               *3\r\n$3\r\nset\r\n$4\r\nD800\r\n$5\r\nNikon\r\n\
+               *3\r\n$6\r\nAPPEND\r\n$4\r\nD800\r\n$3\r\n!!!\r\n\
+               *2\r\n$4\r\nINCR\r\n$5\r\ncount\r\n\
+               *3\r\n$6\r\nINCRBY\r\n$5\r\ncount\r\n$2\r\n41\r\n\
+               *4\r\n$4\r\nHSET\r\n$5\r\nlens1\r\n$5\r\nmount\r\n$1\r\nX\r\n\
+               *3\r\n$5\r\nLPUSH\r\n$5\r\nqueue\r\n$3\r\none\r\n\
+               *3\r\n$3\r\nSET\r\n$4\r\ngone\r\n$3\r\nbye\r\n\
+               *2\r\n$3\r\nDEL\r\n$4\r\ngone\r\n\
+               *3\r\n$6\r\nEXPIRE\r\n$6\r\nX-Pro2\r\n$2\r\n60\r\n\
+               *2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n\
+               *3\r\n$3\r\nSET\r\n$5\r\nother\r\n$2\r\ndb\r\n";
+
+    let mut kvs = super::new("".to_string());
+    kvs.insert("D800".to_string(), "Pentax".to_string());
+
+    let report = kvs.import_redis_aof(aof.as_bytes()).unwrap();
+    assert_eq!(report.inserted, 2);
+    assert_eq!(report.updated, 1);
+    assert_eq!(kvs.get("X-Pro2".to_string()).unwrap(), "Fujifilm");
+    assert_eq!(kvs.get("D800".to_string()).unwrap(), "Nikon!!!");
+    assert_eq!(kvs.get("count".to_string()).unwrap(), "42");
+    assert!(kvs.get("gone".to_string()).is_none());
+    assert!(kvs.get("other".to_string()).is_none());
+
+    let skipped: Vec<&str> = report.skipped_keys.keys().map(|k| k.as_str()).collect();
+    assert_eq!(skipped, vec!["lens1", "queue"]);
+    assert_eq!(report.skipped_keys["lens1"], SkipReason::Unsupported("hset".to_string()));
+    assert_eq!(report.skipped_commands.get("hset"), Some(&1));
+    assert_eq!(report.skipped_commands.get("lpush"), Some(&1));
+    assert_eq!(report.skipped_commands.get("set"), Some(&1));
+}
+
+#[test]
+fn test_import_redis_inline() {
+    let mut kvs = super::new("".to_string());
+    let report = kvs.import_redis_aof("SET a 1\nSET b 2\nSADD c x\n".as_bytes()).unwrap();
+    assert_eq!(report.inserted, 2);
+    assert_eq!(kvs.get("b".to_string()).unwrap(), "2");
+    assert!(report.skipped_keys.contains_key("c"));
+}
+
+#[test]
+fn test_import_redis_set_options() {
+    let aof = "SET a 1
+               SET a 2 NX
+               SET b 3 XX
+               SET a 4 XX GET EX 60
+               SET c 5 NX PX 100
+               SET d 6 NX XX
+               SET e 7
+               RENAME e f
+               RENAME missing g
+               SET h 8
+               RENAMENX h a
+               SET i 9
+               SETRANGE i 0 x
+               SADD s x
+               RENAME s t
+";
+
+    let mut kvs = super::new("".to_string());
+    let report = kvs.import_redis_aof(aof.as_bytes()).unwrap();
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "4");
+    assert!(kvs.get("b".to_string()).is_none());
+    assert_eq!(kvs.get("c".to_string()).unwrap(), "5");
+    assert!(kvs.get("d".to_string()).is_none());
+    assert!(kvs.get("e".to_string()).is_none());
+    assert_eq!(kvs.get("f".to_string()).unwrap(), "7");
+    assert_eq!(kvs.get("h".to_string()).unwrap(), "8");
+    assert!(kvs.get("i".to_string()).is_none());
+    assert_eq!(report.inserted, 4);
+
+    assert_eq!(report.skipped_keys.get("i"), Some(&SkipReason::Unsupported("setrange".to_string())));
+    assert_eq!(report.skipped_keys.get("t"), Some(&SkipReason::Unsupported("sadd".to_string())));
+    assert_eq!(report.skipped_keys.len(), 2);
+    assert_eq!(report.skipped_commands.get("set"), Some(&1));
+    assert_eq!(report.skipped_commands.get("rename"), Some(&1));
+}
+
+#[test]
+fn test_import_redis_hostile() {
+    let mut kvs = super::new("".to_string());
+    let huge = "*1\r\n$18446744073709551615\r\nx\r\n";
+    assert_eq!(kvs.import_redis_aof(huge.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let short = "*2\r\n$3\r\nGET\r\n$100000000\r\nx\r\n";
+    assert_eq!(kvs.import_redis_aof(short.as_bytes()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    let many = "*18446744073709551615\r\n";
+    assert_eq!(kvs.import_redis_aof(many.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // Overflowing increments are skipped, as Redis refuses them.
+    let aof = "SET n 9223372036854775807\nINCR n\nDECRBY m -9223372036854775808\n";
+    let report = kvs.import_redis_aof(aof.as_bytes()).unwrap();
+    assert_eq!(kvs.get("n".to_string()).unwrap(), "9223372036854775807");
+    assert!(kvs.get("m".to_string()).is_none());
+    assert_eq!(report.skipped_commands.get("incr"), Some(&1));
+    assert_eq!(report.skipped_commands.get("decrby"), Some(&1));
+}
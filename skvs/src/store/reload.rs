@@ -0,0 +1,206 @@
+//! reload picks up changes made to a store's file by someone else,
+//! such as a configuration management tool pushing a new copy. The
+//! store remembers the modification time and size of its file when
+//! it's loaded or flushed; `reload_if_changed` reloads the file if
+//! either differs, and reports the keys that changed to journal
+//! subscribers.
+//!
+//! Checking is cheap, so it can be done before each read; a
+//! `FileWatcher` instead polls the file on another thread and says
+//! when it's worth checking.
+use super::diff::StoreDiff;
+use super::history::History;
+use super::Store;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// FileStamp identifies a version of a file by its modification time
+/// and size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    /// `of` returns the stamp of the file at `path`.
+    pub fn of(path: &str) -> Result<FileStamp, io::Error> {
+        let md = fs::metadata(path)?;
+        Ok(FileStamp { modified: md.modified()?, len: md.len() })
+    }
+}
+
+impl Store {
+    /// `reload_if_changed` reloads the store from its file if the file
+    /// has changed since the store was loaded or last flushed,
+    /// returning the changes to the store's values (or `None` if it
+    /// didn't reload). Each changed key is sent to journal
+    /// subscribers, and the undo history is cleared. The store's
+    /// configuration, lock, and path are kept.
+    ///
+    /// The check is skipped while a background flush is running,
+    /// since the file is being rewritten by the store itself.
+    pub fn reload_if_changed(&mut self) -> Result<Option<StoreDiff>, io::Error> {
+        self.collect_flush();
+        if self.path.is_empty() || self.flushes.in_progress() {
+            return Ok(None);
+        }
+        if self.stamp == Some(FileStamp::of(&self.path)?) {
+            return Ok(None);
+        }
+
+        let fresh = Store::read_file(&self.path)?;
+        let diff = self.diff(&fresh)?;
+        for (k, ent) in &diff.added {
+            self.journal.record(k, ent.version);
+        }
+        for change in &diff.changed {
+            self.journal.record(&change.key, change.new.version);
+        }
+        for (k, _) in &diff.removed {
+            self.journal.record(k, 0);
+        }
+
+        // The counters count this process's operations, not the
+        // file's.
+        let counters = std::mem::take(&mut self.metrics.counters);
+        self.metrics = fresh.metrics;
+        self.metrics.counters = counters;
+        let hasher = self.hasher();
+        self.values = fresh.values;
+        self.set_hasher(hasher);
+        self.shards = fresh.shards;
+        self.acl = fresh.acl;
+        self.schema_version = fresh.schema_version;
+        self.migrations = fresh.migrations;
+        self.tombstones = fresh.tombstones;
+        self.trash = fresh.trash;
+        self.aliases = fresh.aliases;
+        self.webhooks = fresh.webhooks;
+        self.schedule = fresh.schedule;
+        self.stamp = fresh.stamp;
+        self.history = History::new();
+        self.rebuild_index();
+        self.usages.clear();
+        Ok(Some(diff))
+    }
+
+    /// `watch_file` returns a watcher that polls the store's file
+    /// every `interval`.
+    pub fn watch_file(&self, interval: Duration) -> FileWatcher {
+        FileWatcher::new(&self.path, interval)
+    }
+}
+
+/// FileWatcher polls a file on another thread, noting when it
+/// changes. It notices the store's own flushes too, which
+/// `reload_if_changed` skips. The thread stops when the watcher is
+/// dropped.
+#[derive(Debug)]
+pub struct FileWatcher {
+    changes: Receiver<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    /// `new` starts watching the file at `path`, checking it every
+    /// `interval`.
+    pub fn new(path: &str, interval: Duration) -> FileWatcher {
+        let (tx, changes) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let path = path.to_string();
+        let mut last = FileStamp::of(&path).ok();
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let stamp = FileStamp::of(&path).ok();
+                if stamp != last {
+                    last = stamp;
+                    if tx.send(()).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        FileWatcher { changes, stop }
+    }
+
+    /// `changed` returns true if the file has changed since the last
+    /// call, without waiting.
+    pub fn changed(&self) -> bool {
+        self.changes.try_iter().count() > 0
+    }
+
+    /// `wait` waits up to `timeout` for the file to change, returning
+    /// true if it did.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let changed = self.changes.recv_timeout(timeout).is_ok();
+        // Collapse any further notifications into this one.
+        changed | self.changed()
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+
+#[test]
+fn test_file_watcher() {
+    use testutil::TempStore;
+    let path = TempStore::new("watch");
+    fs::write(&path, "{}").unwrap();
+
+    let watcher = FileWatcher::new(&path, Duration::from_millis(5));
+    assert!(!watcher.changed());
+    fs::write(&path, "{\"values\": {}}").unwrap();
+    assert!(watcher.wait(Duration::from_secs(5)));
+    assert!(!watcher.changed());
+
+    fs::remove_file(&path).unwrap();
+    assert!(watcher.wait(Duration::from_secs(5)));
+}
+
+#[test]
+fn test_reload_if_changed() {
+    use testutil::TempStore;
+    let path = TempStore::new("reload");
+
+    // pusher stands in for a configuration management tool, which
+    // doesn't take the store's lock.
+    let mut pusher = super::new(path.to_string());
+    pusher.insert("a".to_string(), "1".to_string());
+    pusher.insert("b".to_string(), "2".to_string());
+    pusher.flush().unwrap();
+
+    let mut kvs = Store::load(path.to_string()).unwrap();
+    let rx = kvs.subscribe_keys();
+    assert_eq!(kvs.reload_if_changed().unwrap(), None);
+
+    pusher.update("a".to_string(), "one".to_string());
+    pusher.delete("b".to_string());
+    pusher.insert("c".to_string(), "3".to_string());
+    pusher.flush().unwrap();
+
+    let diff = kvs.reload_if_changed().unwrap().unwrap();
+    assert_eq!(diff.len(), 3);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "one");
+    assert!(kvs.get("b".to_string()).is_none());
+    assert_eq!(rx.try_iter().count(), 3);
+
+    // The store's own flushes aren't changes.
+    kvs.insert("d".to_string(), "4".to_string());
+    kvs.flush().unwrap();
+    assert_eq!(kvs.reload_if_changed().unwrap(), None);
+    assert_eq!(kvs.len(), 3);
+
+    drop(kvs);
+}
@@ -0,0 +1,160 @@
+//! rename moves and copies entries between keys. Unlike a `get`
+//! followed by an `insert` and a `delete`, the entry keeps its version
+//! chain: the destination's version carries on from the source's (or
+//! from the entry it replaces, if that's further along), so syncs and
+//! version checks see a newer write rather than a fresh key. Spilled
+//! values are shared rather than copied.
+//!
+//! Both operations are journaled as a write to the destination and,
+//! for `rename`, a delete of the source; each is also a separate step
+//! for `undo`. Secrets can't be moved or copied, since they're sealed
+//! to their key's name, and are rejected with `Denied`.
+//!
+//! ```
+//! use skvs::store::WriteResult;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.insert("draft".to_string(), "v1".to_string());
+//! kvs.update("draft".to_string(), "v2".to_string());
+//! assert_eq!(kvs.rename("draft", "final", false), WriteResult::Inserted);
+//! assert_eq!(kvs.values["final"].version, 3);
+//! assert!(kvs.get("draft".to_string()).is_none());
+//! ```
+use super::{quota, spill};
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::sync::Arc;
+
+impl Store {
+    /// `rename` moves the entry at `old` to `new`, returning `Inserted`
+    /// or, if it replaced an entry, `Updated`. If `new` exists and
+    /// `overwrite` isn't set, `AlreadyExists` is returned and nothing
+    /// changes.
+    pub fn rename(&mut self, old: &str, new: &str, overwrite: bool) -> WriteResult {
+        if let Err(wr) = self.check_writable(old) {
+            return wr;
+        }
+        let wr = self.place(old, new, overwrite);
+        if wr != Inserted && wr != Updated {
+            return wr;
+        }
+
+        if let Some(ent) = Arc::make_mut(&mut self.values).remove(old) {
+            self.bury(old, ent.version + 1);
+            self.reindex(old, Some(&ent));
+            self.count_usage(old, Some(&ent));
+            self.remember(old, Some(&ent));
+        }
+        self.journal.record(old, 0);
+        self.metrics.counters.deleted();
+        self.update_metrics(true, false);
+        wr
+    }
+
+    /// `copy` writes the entry at `src` to `dst` as well, returning
+    /// `Inserted` or, if it replaced an entry, `Updated`. If `dst`
+    /// exists and `overwrite` isn't set, `AlreadyExists` is returned
+    /// and nothing changes.
+    pub fn copy(&mut self, src: &str, dst: &str, overwrite: bool) -> WriteResult {
+        let wr = self.place(src, dst, overwrite);
+        if wr == Inserted || wr == Updated {
+            self.update_metrics(true, false);
+        }
+        wr
+    }
+
+    /// `place` writes the entry at `src` to `dst`, with the next
+    /// version, for `rename` and `copy`.
+    fn place(&mut self, src: &str, dst: &str, overwrite: bool) -> WriteResult {
+        let ent = match self.values.get(src) {
+            Some(ent) => ent.clone(),
+            None      => return DoesNotExist,
+        };
+        if ent.secret {
+            return Denied;
+        }
+        if let Err(err) = self.config.key_policy.check(dst) {
+            return InvalidKey(err);
+        }
+        if let Err(wr) = self.check_writable(dst) {
+            return wr;
+        }
+        let before = self.values.get(dst).cloned();
+        if before.is_some() && (!overwrite || src == dst) {
+            return AlreadyExists;
+        }
+        if let Err(wr) = quota::check(&self.config, &mut self.usages, &self.values, dst, spill::value_len(&ent)) {
+            return wr;
+        }
+
+        let mut placed = ent;
+        placed.version = before.as_ref().map_or(placed.version, |old| old.version.max(placed.version)) + 1;
+        placed.time = self.clock.now();
+        let version = placed.version;
+        Arc::make_mut(&mut self.values).insert(Arc::from(dst), placed);
+
+        let wr = if before.is_some() { Updated } else { Inserted };
+        self.journal.record(dst, version);
+        self.unbury(dst);
+        self.reindex(dst, before.as_ref());
+        self.count_usage(dst, before.as_ref());
+        self.remember(dst, before.as_ref());
+        self.metrics.counters.wrote(wr);
+        wr
+    }
+}
+
+
+#[test]
+fn test_rename() {
+    let mut kvs = super::new("".to_string());
+    kvs.config.tombstones = true;
+    kvs.config.undo_depth = 4;
+    let keys = kvs.subscribe_keys();
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.insert("b".to_string(), "other".to_string());
+
+    assert_eq!(kvs.rename("missing", "c", false), DoesNotExist);
+    assert_eq!(kvs.rename("a", "b", false), AlreadyExists);
+    assert_eq!(kvs.rename("a", "a", true), AlreadyExists);
+    assert_eq!(kvs.rename("a", "", false), InvalidKey(super::KeyError::Empty));
+
+    assert_eq!(kvs.rename("a", "c", false), Inserted);
+    assert!(kvs.get("a".to_string()).is_none());
+    assert_eq!(kvs.get("c".to_string()), Some("2".to_string()));
+    assert_eq!(kvs.values["c"].version, 3);
+    assert_eq!(kvs.tombstones["a"].version, 3);
+    let journaled: Vec<(String, i64)> = keys.try_iter().map(|rec| (rec.key, rec.version)).skip(3).collect();
+    assert_eq!(journaled, vec![("c".to_string(), 3), ("a".to_string(), 0)]);
+
+    assert_eq!(kvs.rename("c", "b", true), Updated);
+    assert_eq!(kvs.get("b".to_string()), Some("2".to_string()));
+    assert_eq!(kvs.values["b"].version, 4);
+    assert_eq!(kvs.len(), 1);
+
+    kvs.undo();
+    kvs.undo();
+    assert_eq!(kvs.get("b".to_string()), Some("other".to_string()));
+    assert_eq!(kvs.get("c".to_string()), Some("2".to_string()));
+}
+
+#[test]
+fn test_copy() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("src".to_string(), "v".to_string());
+    kvs.insert("dst".to_string(), "old".to_string());
+
+    assert_eq!(kvs.copy("src", "dst", false), AlreadyExists);
+    assert_eq!(kvs.copy("src", "new", false), Inserted);
+    assert_eq!(kvs.copy("src", "dst", true), Updated);
+    assert_eq!(kvs.get("src".to_string()), Some("v".to_string()));
+    assert_eq!(kvs.get("dst".to_string()), Some("v".to_string()));
+    assert_eq!(kvs.values["new"].version, 2);
+    assert_eq!(kvs.metrics.size, 3);
+    assert_eq!(kvs.find_keys_by_value("v"), vec!["dst", "new", "src"]);
+
+    let token = kvs.acquire_lease("dst", std::time::Duration::from_secs(10)).unwrap();
+    assert_eq!(kvs.copy("src", "dst", true), Leased);
+    kvs.release_lease("dst", &token).unwrap();
+}
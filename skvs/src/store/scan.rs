@@ -0,0 +1,196 @@
+//! scan pages through the store's entries in key order, so that large
+//! stores can be listed a page at a time. Each page comes with a
+//! cursor for the next one. A cursor records where the page ended
+//! rather than how many entries came before it, so writing or deleting
+//! other keys between calls doesn't make a scan skip or repeat
+//! entries; keys added after the cursor show up when the scan reaches
+//! them.
+//!
+//! ```
+//! use skvs::store::ScanOptions;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! for i in 0..5 {
+//!     kvs.insert(format!("user/{}", i), i.to_string());
+//! }
+//!
+//! let mut opts = ScanOptions::new().prefix("user/").limit(2);
+//! let mut seen = 0;
+//! loop {
+//!     let page = kvs.scan(&opts).unwrap();
+//!     seen += page.entries.len();
+//!     match page.next {
+//!         Some(cursor) => opts = opts.after(cursor),
+//!         None         => break,
+//!     }
+//! }
+//! assert_eq!(seen, 5);
+//! ```
+use super::Store;
+use std::io;
+
+/// Cursor marks where a scan left off. It can be passed to another
+/// process, such as a client of a server, as a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    last: String,
+}
+
+impl Cursor {
+    /// `token` returns the cursor as an opaque string of hex digits.
+    pub fn token(&self) -> String {
+        self.last.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// `from_token` returns the cursor encoded by `token`, or `None`
+    /// if it isn't a cursor token.
+    pub fn from_token(token: &str) -> Option<Cursor> {
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return None;
+        }
+        let bytes: Option<Vec<u8>> = (0..token.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&token[i..i + 2], 16).ok())
+            .collect();
+        String::from_utf8(bytes?).ok().map(|last| Cursor { last })
+    }
+}
+
+/// ScanOptions selects the entries a scan returns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanOptions {
+    /// prefix limits the scan to keys starting with it.
+    pub prefix: String,
+
+    /// limit is the most entries a page holds; `None` returns every
+    /// entry in one page.
+    pub limit: Option<usize>,
+
+    /// cursor starts the scan after the page it came with.
+    pub cursor: Option<Cursor>,
+}
+
+impl ScanOptions {
+    /// `new` returns options that scan the whole store in one page.
+    pub fn new() -> ScanOptions {
+        ScanOptions::default()
+    }
+
+    /// `prefix` limits the scan to keys starting with `prefix`.
+    pub fn prefix(mut self, prefix: &str) -> ScanOptions {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// `limit` returns at most `limit` entries per page. A scan with a
+    /// limit of zero is refused, since its pages couldn't make
+    /// progress.
+    pub fn limit(mut self, limit: usize) -> ScanOptions {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// `after` continues the scan from `cursor`.
+    pub fn after(mut self, cursor: Cursor) -> ScanOptions {
+        self.cursor = Some(cursor);
+        self
+    }
+}
+
+/// ScanPage is a page of a scan: key-value pairs in key order, and the
+/// cursor for the next page, if there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanPage {
+    pub entries: Vec<(String, String)>,
+    pub next: Option<Cursor>,
+}
+
+impl Store {
+    /// `scan` returns the page of entries selected by `opts`. Only the
+    /// keys on the page are sorted, so each page costs a pass over the
+    /// matching keys rather than a sort of them all. If a spilled value
+    /// on the page can't be read, the error is returned, as it is for a
+    /// limit of zero.
+    pub fn scan(&self, opts: &ScanOptions) -> Result<ScanPage, io::Error> {
+        if opts.limit == Some(0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "scan limit must be positive"));
+        }
+
+        let after = opts.cursor.as_ref().map(|cursor| cursor.last.as_str());
+        let mut keys: Vec<&str> = self.values.keys()
+            .map(|k| &**k)
+            .filter(|k| k.starts_with(opts.prefix.as_str()))
+            .filter(|&k| after.is_none_or(|after| k > after))
+            .collect();
+
+        let limit = opts.limit.unwrap_or(keys.len());
+        let more = keys.len() > limit;
+        if more {
+            keys.select_nth_unstable(limit - 1);
+        }
+        keys.truncate(limit);
+        keys.sort_unstable();
+        let next = match keys.last() {
+            Some(last) if more => Some(Cursor { last: last.to_string() }),
+            _                  => None,
+        };
+
+        let entries = keys.into_iter()
+            .map(|k| self.shared_value(&self.values[k]).map(|v| (k.to_string(), v.to_string())))
+            .collect::<Result<_, io::Error>>()?;
+        Ok(ScanPage { entries, next })
+    }
+}
+
+
+#[test]
+fn test_scan() {
+    let mut kvs = super::new("".to_string());
+    for k in &["a/1", "a/2", "a/3", "a/4", "b/1"] {
+        kvs.insert(k.to_string(), k.to_string());
+    }
+
+    let opts = ScanOptions::new().prefix("a/").limit(2);
+    let page = kvs.scan(&opts).unwrap();
+    assert_eq!(page.entries, vec![("a/1".to_string(), "a/1".to_string()),
+                                  ("a/2".to_string(), "a/2".to_string())]);
+    let cursor = page.next.unwrap();
+    assert_eq!(Cursor::from_token(&cursor.token()), Some(cursor.clone()));
+    assert_eq!(Cursor::from_token("not hex"), None);
+
+    // Changing keys on either side of the cursor doesn't disturb it.
+    kvs.delete("a/1".to_string());
+    kvs.insert("a/0".to_string(), "new".to_string());
+    kvs.insert("a/25".to_string(), "new".to_string());
+
+    let page = kvs.scan(&opts.clone().after(cursor)).unwrap();
+    let keys: Vec<&str> = page.entries.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, vec!["a/25", "a/3"]);
+    let page = kvs.scan(&opts.clone().after(page.next.unwrap())).unwrap();
+    assert_eq!(page.entries.len(), 1);
+    assert!(page.next.is_none());
+
+    assert_eq!(kvs.scan(&ScanOptions::new()).unwrap().entries.len(), 6);
+    assert_eq!(kvs.scan(&ScanOptions::new().limit(0)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let mut keys = Vec::new();
+    let mut opts = ScanOptions::new().limit(4);
+    for i in (0..100).rev() {
+        kvs.insert(format!("c/{:02}", i), i.to_string());
+    }
+    loop {
+        let page = kvs.scan(&opts).unwrap();
+        keys.extend(page.entries.into_iter().map(|(k, _)| k));
+        match page.next {
+            Some(cursor) => opts = opts.after(cursor),
+            None         => break,
+        }
+    }
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(keys, sorted);
+    assert_eq!(keys.len(), 106);
+
+    kvs.values_mut().get_mut("b/1").unwrap().blob = Some("missing".to_string());
+    assert!(kvs.scan(&ScanOptions::new().prefix("b/")).is_err());
+    assert!(kvs.scan(&ScanOptions::new().prefix("a/")).is_ok());
+}
@@ -0,0 +1,221 @@
+//! schedule stages writes to be made later, such as configuration
+//! changes that should flip during a maintenance window. A scheduled
+//! write is held, and persisted with the store, until it's due; it's
+//! then made with `update`, so it becomes visible (and is journaled)
+//! like any other write.
+//!
+//! `apply_scheduled` makes the writes that are due; a `Scheduler`
+//! calls it periodically on another thread for a store shared behind
+//! a mutex, the way an `Evictor` expires old entries. Scheduling and
+//! cancelling a write are writes to the store, and are persisted as
+//! its durability policy asks. A due write that's rejected is dropped
+//! and counted in the store's counters as `scheduled_rejections`.
+//!
+//! ```
+//! use skvs::store::Timestamp;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! let id = kvs.insert_at("mode".to_string(), "maintenance".to_string(), Timestamp::from_secs(0));
+//! assert_eq!(kvs.list_pending()[0].id, id);
+//! assert!(kvs.get("mode".to_string()).is_none());
+//! kvs.apply_scheduled();
+//! assert_eq!(kvs.get("mode".to_string()), Some("maintenance".to_string()));
+//! ```
+use super::hlc::Timestamp;
+use super::{Store, WriteResult};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// ScheduledWrite is a write waiting for its time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledWrite {
+    /// id identifies the write for `cancel_scheduled`.
+    pub id: u64,
+    pub key: String,
+    pub value: String,
+
+    /// apply_at is when the write is due.
+    pub apply_at: Timestamp,
+}
+
+/// Schedule holds a store's scheduled writes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    /// next_id is the id the next scheduled write gets; ids aren't
+    /// reused.
+    next_id: u64,
+    writes: Vec<ScheduledWrite>,
+}
+
+impl Store {
+    /// `insert_at` schedules writing `v` to `k` at `apply_at`,
+    /// returning the write's id. Whether the write is allowed is only
+    /// checked when it's made.
+    pub fn insert_at(&mut self, k: String, v: String, apply_at: Timestamp) -> u64 {
+        self.schedule.next_id += 1;
+        let id = self.schedule.next_id;
+        self.schedule.writes.push(ScheduledWrite { id, key: k, value: v, apply_at });
+        self.update_metrics(true, false);
+        id
+    }
+
+    /// `list_pending` returns the scheduled writes that haven't been
+    /// made, in the order they're due.
+    pub fn list_pending(&self) -> Vec<ScheduledWrite> {
+        let mut pending = self.schedule.writes.clone();
+        pending.sort_by_key(|w| (w.apply_at, w.id));
+        pending
+    }
+
+    /// `cancel_scheduled` drops the scheduled write `id`, returning
+    /// true if it was still pending.
+    pub fn cancel_scheduled(&mut self, id: u64) -> bool {
+        let before = self.schedule.writes.len();
+        self.schedule.writes.retain(|w| w.id != id);
+        if self.schedule.writes.len() == before {
+            return false;
+        }
+        self.update_metrics(true, false);
+        true
+    }
+
+    /// `apply_scheduled` makes every scheduled write that's due, in
+    /// the order they're due, and returns each one's result. Rejected
+    /// writes are counted in the store's counters.
+    pub fn apply_scheduled(&mut self) -> Vec<(ScheduledWrite, WriteResult)> {
+        let now = self.clock.now();
+        if self.schedule.writes.iter().all(|w| w.apply_at > now) {
+            return Vec::new();
+        }
+
+        let (mut due, later): (Vec<ScheduledWrite>, Vec<ScheduledWrite>) =
+            self.schedule.writes.drain(..).partition(|w| w.apply_at <= now);
+        self.schedule.writes = later;
+        due.sort_by_key(|w| (w.apply_at, w.id));
+        let applied: Vec<(ScheduledWrite, WriteResult)> = due.into_iter()
+            .map(|w| {
+                let wr = self.update(w.key.clone(), w.value.clone());
+                match wr {
+                    WriteResult::Inserted | WriteResult::Updated => (),
+                    _                                            => self.metrics.counters.scheduled_rejected(),
+                }
+                (w, wr)
+            })
+            .collect();
+
+        // Dropping the due writes changed the schedule, which has to
+        // be persisted even if none of them was made.
+        self.update_metrics(true, false);
+        applied
+    }
+}
+
+/// Scheduler periodically makes the due writes in a shared store. The
+/// thread stops when the scheduler or the store is dropped.
+#[derive(Debug)]
+pub struct Scheduler {
+    stop: Arc<AtomicBool>,
+}
+
+impl Scheduler {
+    /// `start` makes the writes that are due in `store` every
+    /// `interval`. The writes' results are kept in the store's
+    /// counters: `updates` and `inserts` for those that were made,
+    /// and `scheduled_rejections` for those that weren't.
+    pub fn start(store: &Arc<Mutex<Store>>, interval: Duration) -> Scheduler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let store: Weak<Mutex<Store>> = Arc::downgrade(store);
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let store = match store.upgrade() {
+                    Some(store) => store,
+                    None        => return,
+                };
+                let mut kvs = store.lock().unwrap_or_else(|err| err.into_inner());
+                if !stopped.load(Ordering::Relaxed) {
+                    kvs.apply_scheduled();
+                }
+            }
+        });
+        Scheduler { stop }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+
+#[test]
+fn test_schedule() {
+    use super::clock::MockClock;
+    use super::WriteResult::*;
+
+    let start = Timestamp::from_secs(1500000000);
+    let clock = Arc::new(MockClock::new(start));
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(clock.clone());
+    kvs.insert("mode".to_string(), "normal".to_string());
+
+    let later = kvs.insert_at("mode".to_string(), "normal".to_string(), Timestamp::from_secs(1500007200));
+    let window = kvs.insert_at("mode".to_string(), "maintenance".to_string(), Timestamp::from_secs(1500003600));
+    let dropped = kvs.insert_at("banner".to_string(), "down".to_string(), Timestamp::from_secs(1500003600));
+    let bad = kvs.insert_at("".to_string(), "v".to_string(), Timestamp::from_secs(1500003600));
+    assert_eq!(kvs.list_pending().iter().map(|w| w.id).collect::<Vec<_>>(), vec![window, dropped, bad, later]);
+    assert!(kvs.cancel_scheduled(dropped));
+    assert!(!kvs.cancel_scheduled(dropped));
+
+    assert!(kvs.apply_scheduled().is_empty());
+    clock.advance(Duration::from_secs(3600));
+    let applied: Vec<(u64, WriteResult)> = kvs.apply_scheduled().into_iter().map(|(w, wr)| (w.id, wr)).collect();
+    assert_eq!(applied, vec![(window, Updated), (bad, InvalidKey(super::KeyError::Empty))]);
+    assert_eq!(kvs.counters().scheduled_rejections(), 1);
+    assert_eq!(kvs.get("mode".to_string()), Some("maintenance".to_string()));
+    assert!(kvs.get("banner".to_string()).is_none());
+    assert_eq!(kvs.list_pending().len(), 1);
+
+    let id = kvs.insert_at("next".to_string(), "v".to_string(), start);
+    assert!(id > later);
+}
+
+#[test]
+fn test_scheduler() {
+    let kvs = Arc::new(Mutex::new(super::new("".to_string())));
+    kvs.lock().unwrap().insert_at("k".to_string(), "v".to_string(), Timestamp::from_secs(0));
+
+    let scheduler = Scheduler::start(&kvs, Duration::from_millis(1));
+    for _ in 0..1000 {
+        if kvs.lock().unwrap().len() == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(scheduler);
+    assert_eq!(kvs.lock().unwrap().get("k".to_string()), Some("v".to_string()));
+    assert!(kvs.lock().unwrap().list_pending().is_empty());
+}
+
+#[test]
+fn test_schedule_persisted() {
+    use testutil::TempStore;
+    let path = TempStore::new("schedule");
+    let mut kvs = super::new(path.to_string());
+    kvs.config = super::StoreConfig::new().durability(super::DurabilityPolicy::Flush);
+    let kept = kvs.insert_at("k".to_string(), "v".to_string(), Timestamp::from_secs(0));
+    let cancelled = kvs.insert_at("k2".to_string(), "v".to_string(), Timestamp::from_secs(0));
+    assert!(kvs.cancel_scheduled(cancelled));
+    let pending = Store::load(path.to_string()).unwrap().list_pending();
+    assert_eq!(pending.iter().map(|w| w.id).collect::<Vec<_>>(), vec![kept]);
+
+    kvs.insert_at("".to_string(), "v".to_string(), Timestamp::from_secs(0));
+    kvs.cancel_scheduled(kept);
+    assert_eq!(Store::load(path.to_string()).unwrap().list_pending().len(), 1);
+    kvs.apply_scheduled();
+    assert!(Store::load(path.to_string()).unwrap().list_pending().is_empty());
+}
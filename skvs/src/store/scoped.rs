@@ -0,0 +1,147 @@
+//! scoped provides views of the store confined to a key prefix, so a
+//! library can be handed a slice of the keyspace without being able
+//! to touch anything outside of it.
+use super::entry::Entry;
+use super::{Store, WriteResult};
+
+/// A `Scoped` view applies its prefix to every key passed to it, and
+/// strips the prefix from every key it returns.
+///
+/// ```
+/// let mut kvs = skvs::store::new("".to_string());
+/// {
+///     let mut app = kvs.scoped("app1/");
+///     app.insert("name".to_string(), "demo".to_string());
+///     assert_eq!(app.get("name".to_string()).unwrap(), "demo");
+/// }
+/// assert_eq!(kvs.get("app1/name".to_string()).unwrap(), "demo");
+/// ```
+#[derive(Debug)]
+pub struct Scoped<'a> {
+    store: &'a mut Store,
+    prefix: String,
+}
+
+impl Store {
+    /// `scoped` returns a view of the keys starting with `prefix`.
+    pub fn scoped(&mut self, prefix: &str) -> Scoped<'_> {
+        Scoped { store: self, prefix: prefix.to_string() }
+    }
+}
+
+impl<'a> Scoped<'a> {
+    fn key(&self, k: &str) -> String {
+        format!("{}{}", self.prefix, k)
+    }
+
+    /// `prefix` returns the prefix applied to keys in this view.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// `scoped` narrows the view further; the new prefix is appended
+    /// to this view's prefix.
+    pub fn scoped(&mut self, prefix: &str) -> Scoped<'_> {
+        let prefix = self.key(prefix);
+        Scoped { store: &mut *self.store, prefix }
+    }
+
+    /// `get` works like `Store::get` on the prefixed key. An alias
+    /// is only followed to a key inside the view; one that leads out of
+    /// it reads as nothing.
+    pub fn get(&self, k: String) -> Option<String> {
+        let k = self.key(&k);
+        if !self.store.resolve(&k).starts_with(self.prefix.as_str()) {
+            return None;
+        }
+        self.store.get(k)
+    }
+
+    /// `insert` works like `Store::insert` on the prefixed key.
+    pub fn insert(&mut self, k: String, v: String) -> WriteResult {
+        let k = self.key(&k);
+        self.store.insert(k, v)
+    }
+
+    /// `update` works like `Store::update` on the prefixed key.
+    pub fn update(&mut self, k: String, v: String) -> WriteResult {
+        let k = self.key(&k);
+        self.store.update(k, v)
+    }
+
+    /// `delete` works like `Store::delete` on the prefixed key.
+    pub fn delete(&mut self, k: String) -> WriteResult {
+        let k = self.key(&k);
+        self.store.delete(k)
+    }
+
+    /// `iter` visits the entries in this view, with the prefix
+    /// stripped from their keys. Entries are visited in arbitrary
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        let prefix = self.prefix.as_str();
+        self.store.values.iter()
+            .filter_map(move |(k, ent)| k.strip_prefix(prefix).map(|k| (k, ent)))
+    }
+
+    /// `keys` returns the (unprefixed) keys in this view, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.iter().map(|(k, _)| k.to_string()).collect();
+        keys.sort();
+        keys
+    }
+
+    /// `len` returns the number of entries in this view.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// `is_empty` returns true if there are no entries in this view.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+
+#[test]
+fn test_scoped() {
+    use super::WriteResult::*;
+
+    let mut kvs = super::new("".to_string());
+    kvs.insert("app2/name".to_string(), "other".to_string());
+
+    {
+        let mut app = kvs.scoped("app1/");
+        assert!(app.is_empty());
+        assert_eq!(app.insert("name".to_string(), "demo".to_string()), Inserted);
+        assert_eq!(app.update("version".to_string(), "1".to_string()), Inserted);
+        assert!(app.get("app2/name".to_string()).is_none());
+        assert_eq!(app.delete("app2/name".to_string()), DoesNotExist);
+        assert_eq!(app.keys(), vec!["name".to_string(), "version".to_string()]);
+
+        {
+            let mut cfg = app.scoped("cfg/");
+            assert_eq!(cfg.prefix(), "app1/cfg/");
+            cfg.insert("debug".to_string(), "true".to_string());
+        }
+        assert_eq!(app.len(), 3);
+        assert_eq!(app.delete("version".to_string()), Updated);
+    }
+
+    assert_eq!(kvs.len(), 3);
+    assert_eq!(kvs.get("app1/cfg/debug".to_string()).unwrap(), "true");
+    assert_eq!(kvs.get("app2/name".to_string()).unwrap(), "other");
+}
+
+#[test]
+fn test_scoped_alias() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("app1/name".to_string(), "demo".to_string());
+    kvs.insert("app2/secret".to_string(), "hidden".to_string());
+    kvs.alias("app1/current", "app1/name").unwrap();
+    kvs.alias("app1/leak", "app2/secret").unwrap();
+
+    let app = kvs.scoped("app1/");
+    assert_eq!(app.get("current".to_string()), Some("demo".to_string()));
+    assert!(app.get("leak".to_string()).is_none());
+}
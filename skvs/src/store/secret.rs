@@ -0,0 +1,216 @@
+//! secret encrypts individual values, such as API tokens, with a key
+//! held by the store but never written with it. A secret value is
+//! encrypted with ChaCha20-Poly1305 before it's inserted, so neither
+//! snapshots nor the store's file hold the plaintext; `get` returns
+//! the ciphertext, and `get_secret` decrypts it. The key a value is
+//! stored under is authenticated along with it, so a secret can't be
+//! moved to another key.
+//!
+//! Secret values are shown as `***` in the store's `Debug` output and
+//! in exports.
+//!
+//! ```
+//! use skvs::store::SecretKey;
+//!
+//! let key = SecretKey::generate();
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.set_secret_key(SecretKey::from_hex(&key.to_hex()).unwrap());
+//!
+//! kvs.insert_secret("github/token".to_string(), "ghp_example".to_string()).unwrap();
+//! assert_ne!(kvs.get("github/token".to_string()).unwrap(), "ghp_example");
+//! assert_eq!(kvs.get_secret("github/token").unwrap(), "ghp_example");
+//! ```
+extern crate chacha20poly1305;
+
+use self::chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use self::chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use super::{Store, WriteResult};
+use std::error::Error;
+use std::fmt;
+
+/// NONCE_LEN is the length of the nonce stored ahead of each
+/// ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// SecretKey is the 256-bit key a store's secret values are
+/// encrypted with. It's kept out of `Debug` output.
+#[derive(Clone, PartialEq)]
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+    /// `generate` returns a new random key.
+    pub fn generate() -> SecretKey {
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&key);
+        SecretKey(bytes)
+    }
+
+    /// `from_bytes` returns the key made of `bytes`.
+    pub fn from_bytes(bytes: [u8; 32]) -> SecretKey {
+        SecretKey(bytes)
+    }
+
+    /// `from_hex` parses a key written by `to_hex`.
+    pub fn from_hex(s: &str) -> Result<SecretKey, SecretError> {
+        match unhex(s.trim()) {
+            Some(ref bytes) if bytes.len() == 32 => {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(bytes);
+                Ok(SecretKey(key))
+            },
+            _ => Err(SecretError::BadKey),
+        }
+    }
+
+    /// `to_hex` returns the key as 64 hex digits, for keeping
+    /// somewhere other than the store.
+    pub fn to_hex(&self) -> String {
+        hex(&self.0)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretKey(***)")
+    }
+}
+
+/// SecretError explains why a secret couldn't be written or read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecretError {
+    /// NoKey is returned when the store hasn't been given a secret
+    /// key.
+    NoKey,
+
+    /// BadKey is returned by `SecretKey::from_hex` for anything other
+    /// than 64 hex digits.
+    BadKey,
+
+    /// Missing is returned when reading a key that isn't present.
+    Missing,
+
+    /// NotSecret is returned when reading a value that wasn't
+    /// written with `insert_secret`.
+    NotSecret,
+
+    /// Decrypt is returned when a value doesn't decrypt with the
+    /// store's key: the key is wrong, or the value has been altered.
+    Decrypt,
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SecretError::NoKey     => write!(f, "the store has no secret key"),
+            SecretError::BadKey    => write!(f, "a secret key must be 64 hex digits"),
+            SecretError::Missing   => write!(f, "key doesn't exist"),
+            SecretError::NotSecret => write!(f, "value isn't a secret"),
+            SecretError::Decrypt   => write!(f, "value didn't decrypt with the store's key"),
+        }
+    }
+}
+
+impl Error for SecretError {}
+
+impl Store {
+    /// `set_secret_key` sets the key used by `insert_secret` and
+    /// `get_secret`. It isn't persisted, so it must be set again each
+    /// time the store is loaded.
+    pub fn set_secret_key(&mut self, key: SecretKey) {
+        self.secret_key = Some(key);
+    }
+
+    /// `insert_secret` works like `insert`, encrypting `v` with the
+    /// store's secret key first.
+    pub fn insert_secret(&mut self, k: String, v: String) -> Result<WriteResult, SecretError> {
+        let cipher = match self.secret_key {
+            Some(ref key) => key.cipher(),
+            None          => return Err(SecretError::NoKey),
+        };
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // Encryption only fails for values longer than 256 GiB.
+        let sealed = cipher.encrypt(&nonce, Payload { msg: v.as_bytes(), aad: k.as_bytes() })
+            .expect("value is too long to encrypt");
+
+        let mut ciphertext = nonce.to_vec();
+        ciphertext.extend_from_slice(&sealed);
+        Ok(self.insert_entry(k, hex(&ciphertext), true))
+    }
+
+    /// `get_secret` returns the decrypted value of a secret written
+    /// with `insert_secret`.
+    pub fn get_secret(&self, k: &str) -> Result<String, SecretError> {
+        let k = self.resolve(k);
+        let ent = match self.values.get(k) {
+            Some(ent) => ent,
+            None      => return Err(SecretError::Missing),
+        };
+        if !ent.secret {
+            return Err(SecretError::NotSecret);
+        }
+        let cipher = match self.secret_key {
+            Some(ref key) => key.cipher(),
+            None          => return Err(SecretError::NoKey),
+        };
+
+        let ciphertext = match unhex(&ent.value) {
+            Some(bytes) if bytes.len() >= NONCE_LEN => bytes,
+            _                                      => return Err(SecretError::Decrypt),
+        };
+        let (nonce, sealed) = ciphertext.split_at(NONCE_LEN);
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: k.as_bytes() })
+            .map_err(|_| SecretError::Decrypt)?;
+        String::from_utf8(plaintext).map_err(|_| SecretError::Decrypt)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+
+#[test]
+fn test_secrets() {
+    use super::WriteResult::*;
+
+    let mut kvs = super::new("".to_string());
+    let token = "ghp_0123456789".to_string();
+    assert_eq!(kvs.insert_secret("token".to_string(), token.clone()), Err(SecretError::NoKey));
+
+    let key = SecretKey::generate();
+    kvs.set_secret_key(key.clone());
+    assert_eq!(kvs.insert_secret("token".to_string(), token.clone()), Ok(Inserted));
+    assert_eq!(kvs.insert_secret("token".to_string(), token.clone()), Ok(AlreadyExists));
+    kvs.insert("plain".to_string(), "value".to_string());
+
+    assert!(!kvs.get("token".to_string()).unwrap().contains(&token));
+    assert_eq!(kvs.get_secret("token"), Ok(token.clone()));
+    assert_eq!(kvs.get_secret("plain"), Err(SecretError::NotSecret));
+    assert_eq!(kvs.get_secret("missing"), Err(SecretError::Missing));
+    assert!(!format!("{:?}", kvs).contains(&*kvs.values["token"].value));
+    assert!(!format!("{:?}", key).contains(&key.to_hex()));
+
+    // A secret copied to another key doesn't decrypt there.
+    let copied = kvs.values["token"].clone();
+    kvs.values_mut().insert("stolen".into(), copied);
+    assert_eq!(kvs.get_secret("stolen"), Err(SecretError::Decrypt));
+
+    kvs.set_secret_key(SecretKey::from_bytes([7; 32]));
+    assert_eq!(kvs.get_secret("token"), Err(SecretError::Decrypt));
+    assert_eq!(SecretKey::from_hex(&key.to_hex()), Ok(key));
+    assert_eq!(SecretKey::from_hex("abcd"), Err(SecretError::BadKey));
+}
@@ -0,0 +1,183 @@
+//! shard splits the persisted form of a store across several files,
+//! chosen by a hash of each key, so that stores too big to comfortably
+//! write as a single JSON document can be flushed and loaded in
+//! parallel. Sharding only affects persistence: in memory, a sharded
+//! store is the same single map as any other.
+//!
+//! A sharded store at `path` is written as a manifest at `path`
+//! (the store's metrics and shard count, with no values) plus one
+//! file per shard at `path.0`, `path.1`, and so on. Shard files a
+//! flush no longer needs, because the store has fewer shards or none,
+//! are removed once the store has been written.
+extern crate serde_json;
+
+use self::serde_json::{json, Value};
+use super::entry::Entry;
+use super::serde::Serialize;
+use super::canonical;
+use super::disk::{self, Disk};
+use super::{format, DurabilityPolicy, Store, Values, FORMAT_VERSION};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::thread;
+
+/// `shard_of` picks the shard for `k`. It uses FNV-1a rather than the
+/// map's hasher, which is randomly seeded, because the assignment
+/// has to be the same every time the store is opened.
+pub fn shard_of(k: &str, shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in k.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+/// MAX_SHARDS is the largest shard count a store can be loaded with;
+/// a manifest asking for more is treated as corrupt rather than
+/// starting a thread per shard.
+pub const MAX_SHARDS: usize = 4096;
+
+fn shard_path(path: &str, i: usize) -> String {
+    format!("{}.{}", path, i)
+}
+
+/// `write_json` replaces the file at `path` with `v` as JSON, waiting
+/// for it to reach the disk if `sync` is true, and in canonical form if
+/// `canonical` is.
+fn write_json<T: Serialize>(disk: &dyn Disk, path: &str, v: &T, sync: bool, canonical: bool) -> Result<(), io::Error> {
+    disk::write_atomic(disk, path, sync, |w| canonical::write_json(w, v, canonical))
+}
+
+/// `read_shard` reads the shard file at `path`, upgrading it from
+/// `version`, the manifest's format version, if it's older than the
+/// current one.
+fn read_shard(path: String, version: u32) -> Result<Values, io::Error> {
+    let invalid = |err: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err));
+    let file = BufReader::new(File::open(&path)?);
+    if version == FORMAT_VERSION {
+        return serde_json::from_reader(file).map_err(invalid);
+    }
+
+    let values: Value = serde_json::from_reader(file).map_err(invalid)?;
+    let mut doc = json!({ "values": values });
+    format::upgrade(&mut doc, version)?;
+    serde_json::from_value(doc["values"].take()).map_err(invalid)
+}
+
+impl Store {
+    /// `is_sharded` returns true if the store is persisted across
+    /// more than one file.
+    pub fn is_sharded(&self) -> bool {
+        self.shards > 1
+    }
+
+    /// `flush_shards` writes each shard on its own thread, then the
+    /// manifest.
+    pub(super) fn flush_shards(&mut self) -> Result<(), io::Error> {
+        let mut parts: Vec<HashMap<&str, &Entry>> = vec![HashMap::new(); self.shards];
+        for (k, ent) in self.values.iter() {
+            parts[shard_of(k, self.shards)].insert(&**k, ent);
+        }
+
+        let path = &self.path;
+        let disk = &*self.disk;
+        let sync = self.config.durability == DurabilityPolicy::Fsync;
+        let canonical = self.config.canonical;
+        thread::scope(|s| {
+            let handles: Vec<_> = parts.into_iter().enumerate()
+                .map(|(i, part)| s.spawn(move || write_json(disk, &shard_path(path, i), &part, sync, canonical)))
+                .collect();
+            handles.into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err(io::Error::other("shard writer panicked"))))
+                .collect::<Result<Vec<()>, io::Error>>()
+        })?;
+
+        let values = std::mem::take(&mut self.values);
+        let result = write_json(&*self.disk, &self.path, self, sync, canonical);
+        self.values = values;
+        result
+    }
+
+    /// `remove_stale_shards` removes the shard files left over from a
+    /// previous, larger shard count, or from before the store was
+    /// unsharded. It must only be called once the store at `path` no
+    /// longer names them.
+    pub(super) fn remove_stale_shards(&self) {
+        let mut stale = if self.is_sharded() { self.shards } else { 0 };
+        while self.disk.remove(&shard_path(&self.path, stale)).is_ok() {
+            stale += 1;
+        }
+    }
+
+    /// `load_shards` reads the shard files belonging to the manifest
+    /// at `path` in parallel, filling in the store's values.
+    pub(super) fn load_shards(&mut self, path: &str) -> Result<(), io::Error> {
+        if self.shards > MAX_SHARDS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{}: too many shards ({})", path, self.shards)));
+        }
+
+        let version = self.format_version;
+        let parts = thread::scope(|s| {
+            let handles: Vec<_> = (0..self.shards)
+                .map(|i| s.spawn(move || read_shard(shard_path(path, i), version)))
+                .collect();
+            handles.into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err(io::Error::other("shard reader panicked"))))
+                .collect::<Result<Vec<_>, io::Error>>()
+        })?;
+
+        for part in parts {
+            self.values_mut().extend(part);
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_shard_of() {
+    assert_eq!(shard_of("a", 1), 0);
+    for k in &["a", "b", "users/kyle", ""] {
+        assert!(shard_of(k, 7) < 7);
+        assert_eq!(shard_of(k, 7), shard_of(k, 7));
+    }
+}
+
+#[test]
+fn test_sharded_round_trip() {
+    use std::fs;
+    use testutil::TempStore;
+    let path = TempStore::new("shard");
+
+    let mut kvs = super::new(path.to_string());
+    kvs.shards = 4;
+    for i in 0..100 {
+        kvs.insert(format!("key{}", i), format!("value{}", i));
+    }
+    kvs.flush().unwrap();
+    assert!(fs::metadata(shard_path(&path, 3)).is_ok());
+
+    let kvs2 = Store::load(path.to_string()).unwrap();
+    assert!(kvs2.is_sharded());
+    assert_eq!(kvs2.len(), 100);
+    assert_eq!(kvs2.metrics.size, 100);
+    assert_eq!(&*kvs2.values["key42"].value, "value42");
+    drop(kvs2);
+
+    // Resharding to fewer files cleans up the ones no longer used.
+    kvs.shards = 2;
+    kvs.flush().unwrap();
+    assert!(fs::metadata(shard_path(&path, 2)).is_err());
+    assert_eq!(Store::load(path.to_string()).unwrap().len(), 100);
+
+    // So does going back to a single file.
+    kvs.shards = 0;
+    kvs.flush().unwrap();
+    assert!(fs::metadata(shard_path(&path, 0)).is_err());
+    assert!(fs::metadata(shard_path(&path, 1)).is_err());
+    assert_eq!(Store::load(path.to_string()).unwrap().len(), 100);
+}
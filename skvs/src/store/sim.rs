@@ -0,0 +1,311 @@
+//! sim drives a store through a long, random but reproducible
+//! sequence of operations, checking the store's invariants as it goes.
+//! Everything is derived from a seed, so a failing run can be replayed
+//! exactly by rerunning its seed.
+//!
+//! Besides reads and writes, a run injects faults:
+//!
+//! + failed flushes, by pointing the store at a path that can't be
+//!   written;
+//! + crashes, which throw away the in-memory store and reload it from
+//!   disk; and
+//! + torn writes, crashes partway through a flush that leave a prefix
+//!   of the new file on disk.
+//!
+//! After every step the store must agree with a simple model of what
+//! it should contain, versions must never go backwards, and
+//! `metrics.size` must match `len()`. After a crash, the reloaded
+//! store must hold exactly what was last flushed.
+//!
+//! Flushes currently overwrite the store file in place, so a torn
+//! write can destroy the previous copy; the run counts these in
+//! `Report::lost_writes` instead of failing, and restarts from an
+//! empty store.
+extern crate serde_json;
+
+use super::Store;
+use super::WriteResult::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Rng is a small, seedable pseudo-random generator (splitmix64).
+/// It's here so runs are reproducible without depending on a
+/// particular version of an external crate.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// `new` returns a generator seeded with `seed`.
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// `next_u64` returns the next number in the sequence.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// `below` returns a number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// `chance` returns true `percent` percent of the time.
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < percent as usize
+    }
+}
+
+/// Config controls the shape of a simulation run. Percentages are
+/// per step.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// steps is the number of operations to run.
+    pub steps: usize,
+
+    /// keys is the size of the key space; smaller key spaces mean
+    /// more operations hit existing keys.
+    pub keys: usize,
+
+    /// flush_percent is how often a step flushes the store.
+    pub flush_percent: u32,
+
+    /// flush_failure_percent is how often a flush is made to fail.
+    pub flush_failure_percent: u32,
+
+    /// crash_percent is how often a step crashes and reloads the
+    /// store.
+    pub crash_percent: u32,
+
+    /// torn_write_percent is how often a crash happens partway
+    /// through a flush.
+    pub torn_write_percent: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            steps: 1000,
+            keys: 16,
+            flush_percent: 5,
+            flush_failure_percent: 20,
+            crash_percent: 2,
+            torn_write_percent: 25,
+        }
+    }
+}
+
+/// Report summarises a successful run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub writes: usize,
+    pub flushes: usize,
+    pub failed_flushes: usize,
+    pub crashes: usize,
+    pub torn_writes: usize,
+
+    /// lost_writes counts torn writes that left nothing loadable on
+    /// disk.
+    pub lost_writes: usize,
+}
+
+/// Model is what the store should contain: each key's value and
+/// version.
+type Model = HashMap<String, (String, i64)>;
+
+struct Sim<'a> {
+    rng: Rng,
+    config: &'a Config,
+    path: String,
+    kvs: Store,
+    model: Model,
+    durable: Model,
+    report: Report,
+}
+
+/// `run` simulates `config.steps` operations against a store kept in
+/// `dir`, returning a description of the first invariant that was
+/// violated (which includes the seed and step), if any.
+pub fn run(seed: u64, config: &Config, dir: &Path) -> Result<Report, String> {
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    let path = dir.join(format!("sim-{}.json", seed)).to_str().unwrap_or("").to_string();
+    let _ = fs::remove_file(&path);
+
+    let mut sim = Sim {
+        rng: Rng::new(seed),
+        config,
+        kvs: super::new(path.clone()),
+        path,
+        model: Model::new(),
+        durable: Model::new(),
+        report: Report::default(),
+    };
+
+    for step in 0..config.steps {
+        sim.step().map_err(|err| format!("seed {}, step {}: {}", seed, step, err))?;
+    }
+    let _ = fs::remove_file(&sim.path);
+    Ok(sim.report)
+}
+
+impl<'a> Sim<'a> {
+    fn step(&mut self) -> Result<(), String> {
+        if self.rng.chance(self.config.crash_percent) {
+            self.crash()?;
+        } else if self.rng.chance(self.config.flush_percent) {
+            self.flush()?;
+        } else {
+            self.write()?;
+        }
+        self.check()
+    }
+
+    fn write(&mut self) -> Result<(), String> {
+        let k = format!("key{}", self.rng.below(self.config.keys));
+        let v = format!("value{}", self.rng.below(4));
+        let old = self.model.get(&k).cloned();
+
+        let (wr, want) = match self.rng.below(3) {
+            0 => {
+                let wr = self.kvs.insert(k.clone(), v.clone());
+                (wr, if old.is_some() { AlreadyExists } else { Inserted })
+            },
+            1 => {
+                let wr = self.kvs.update(k.clone(), v.clone());
+                (wr, if old.is_some() { Updated } else { Inserted })
+            },
+            _ => {
+                let wr = self.kvs.delete(k.clone());
+                (wr, if old.is_some() { Updated } else { DoesNotExist })
+            },
+        };
+        if wr != want {
+            return Err(format!("writing {}: got {:?}, want {:?}", k, wr, want));
+        }
+        self.report.writes += 1;
+
+        match self.kvs.values.get(k.as_str()) {
+            Some(ent) => {
+                let version = match old {
+                    Some((ref old_value, old_version)) if *old_value == v || wr == AlreadyExists => old_version,
+                    Some((_, old_version)) => old_version + 1,
+                    None                   => 1,
+                };
+                if ent.version != version {
+                    return Err(format!("{} is at version {}, want {}", k, ent.version, version));
+                }
+                self.model.insert(k, (ent.value.to_string(), ent.version));
+            },
+            None      => {
+                self.model.remove(&k);
+            },
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        if self.rng.chance(self.config.flush_failure_percent) {
+            self.kvs.path = Path::new(&self.path).join("not-a-directory").to_str()
+                .unwrap_or("").to_string();
+            let result = self.kvs.flush();
+            self.kvs.path = self.path.clone();
+            if result.is_ok() || self.kvs.write_error.is_none() {
+                return Err("flush to an unwritable path succeeded".to_string());
+            }
+            self.report.failed_flushes += 1;
+            return Ok(());
+        }
+
+        self.kvs.flush().map_err(|err| format!("flush failed: {}", err))?;
+        if self.kvs.write_error.is_some() {
+            return Err("write_error set after a successful flush".to_string());
+        }
+        self.durable = self.model.clone();
+        self.report.flushes += 1;
+        Ok(())
+    }
+
+    fn crash(&mut self) -> Result<(), String> {
+        self.report.crashes += 1;
+        if self.rng.chance(self.config.torn_write_percent) {
+            self.report.torn_writes += 1;
+            let data = serde_json::to_vec(&self.kvs).map_err(|err| err.to_string())?;
+            let torn = self.rng.below(data.len() + 1);
+            fs::write(&self.path, &data[..torn]).map_err(|err| err.to_string())?;
+            if torn == data.len() {
+                // The whole file made it to disk after all.
+                self.durable = self.model.clone();
+            }
+        }
+
+        self.model = self.durable.clone();
+        // Drop the crashed store first, releasing its lock.
+        self.kvs = super::new(String::new());
+        self.kvs = if Path::new(&self.path).exists() {
+            match Store::load(self.path.clone()) {
+                Ok(kvs)  => kvs,
+                Err(_)   => {
+                    // The torn write destroyed the last good copy.
+                    self.report.lost_writes += 1;
+                    self.model.clear();
+                    self.durable.clear();
+                    super::new(self.path.clone())
+                },
+            }
+        } else {
+            super::new(self.path.clone())
+        };
+
+        // The store records the path it was flushed to.
+        self.kvs.path = self.path.clone();
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.kvs.metrics.size != self.kvs.len() {
+            return Err(format!("metrics.size is {}, but there are {} entries",
+                               self.kvs.metrics.size, self.kvs.len()));
+        }
+        if self.kvs.len() != self.model.len() {
+            return Err(format!("store has {} entries, model has {}",
+                               self.kvs.len(), self.model.len()));
+        }
+
+        for (k, &(ref v, version)) in &self.model {
+            match self.kvs.values.get(k.as_str()) {
+                Some(ent) if *ent.value == **v && ent.version == version => (),
+                Some(ent) => return Err(format!("{} is {:?} at version {}, want {:?} at version {}",
+                                                k, ent.value, ent.version, v, version)),
+                None      => return Err(format!("{} is missing", k)),
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_rng_is_deterministic() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+    assert!(Rng::new(1).next_u64() != Rng::new(2).next_u64());
+}
+
+#[test]
+fn test_simulation() {
+    use testutil::TempStore;
+    let dir = TempStore::dir("sim");
+    let config = Config { steps: 500, ..Config::default() };
+    for seed in 0..20 {
+        let report = run(seed, &config, dir.as_path()).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(run(seed, &config, dir.as_path()), Ok(report));
+    }
+}
@@ -0,0 +1,163 @@
+//! slowlog records the operations that took longer than a threshold,
+//! like Redis's SLOWLOG, to help find the keys and flushes behind
+//! latency spikes. It's off unless `StoreConfig::slow_threshold` is
+//! set, and keeps only the latest `StoreConfig::slow_log_len`
+//! operations.
+//!
+//! Reads, inserts, updates, deletes, and flushes are timed; writes
+//! rejected before they touch the store aren't. Background flushes
+//! are logged when the store collects their outcome. Like the journal,
+//! the log belongs to one store and isn't persisted.
+//!
+//! ```
+//! use skvs::store::StoreConfig;
+//! use std::time::Duration;
+//!
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.config = StoreConfig::new().slow_log(Duration::from_secs(0), 16);
+//! kvs.insert("k".to_string(), "value".to_string());
+//! let slow = kvs.slow_log();
+//! assert_eq!((slow[0].op, slow[0].key.as_str(), slow[0].size), ("insert", "k", 5));
+//! ```
+use super::hlc::Timestamp;
+use super::Store;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `SLOW_LOG_LEN` is the number of slow operations kept by default.
+pub const SLOW_LOG_LEN: usize = 128;
+
+/// SlowOp is an operation that took longer than the threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowOp {
+    /// id numbers the slow operations in the order they finished.
+    pub id: u64,
+
+    /// time is when the operation finished.
+    pub time: Timestamp,
+
+    /// op names the operation: "get", "insert", "update", "delete",
+    /// or "flush".
+    pub op: &'static str,
+
+    /// key is the key operated on; it's empty for flushes.
+    pub key: String,
+    pub duration: Duration,
+
+    /// size is the length of the value read or written, in bytes, or
+    /// for a flush, the number of entries written.
+    pub size: usize,
+}
+
+/// SlowLog holds the latest slow operations. It's updated through
+/// shared references, since reads are timed too; cloning it yields an
+/// empty log.
+#[derive(Default)]
+pub struct SlowLog {
+    ops: Mutex<(u64, VecDeque<SlowOp>)>,
+}
+
+impl SlowLog {
+    /// `new` returns an empty log.
+    pub fn new() -> SlowLog {
+        SlowLog::default()
+    }
+}
+
+impl Clone for SlowLog {
+    fn clone(&self) -> SlowLog {
+        SlowLog::new()
+    }
+}
+
+impl fmt::Debug for SlowLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ops = self.ops.lock().unwrap_or_else(|err| err.into_inner());
+        write!(f, "SlowLog {{ ops: {} }}", ops.1.len())
+    }
+}
+
+impl Store {
+    /// `slow_start` returns the time an operation started, if the slow
+    /// log is on.
+    pub(super) fn slow_start(&self) -> Option<Instant> {
+        self.config.slow_threshold.map(|_| Instant::now())
+    }
+
+    /// `log_slow` records the operation that started at `start`, if it
+    /// took longer than the threshold.
+    pub(super) fn log_slow(&self, start: Option<Instant>, op: &'static str, key: &str, size: usize) {
+        if let Some(slow) = self.time_slow(start, op, key, size) {
+            self.push_slow(slow);
+        }
+    }
+
+    /// `time_slow` returns the operation that started at `start`, if it
+    /// took longer than the threshold, without logging it. Its id is
+    /// set when it's logged by `push_slow`.
+    pub(super) fn time_slow(&self, start: Option<Instant>, op: &'static str, key: &str, size: usize)
+                            -> Option<SlowOp> {
+        let (start, threshold) = match (start, self.config.slow_threshold) {
+            (Some(start), Some(threshold)) => (start, threshold),
+            _                              => return None,
+        };
+        let duration = start.elapsed();
+        if duration < threshold {
+            return None;
+        }
+        Some(SlowOp { id: 0, time: self.clock.now(), op, key: key.to_string(), duration, size })
+    }
+
+    /// `push_slow` adds `slow` to the log, numbering it.
+    pub(super) fn push_slow(&self, mut slow: SlowOp) {
+        let mut ops = self.slow_ops.ops.lock().unwrap_or_else(|err| err.into_inner());
+        ops.0 += 1;
+        slow.id = ops.0;
+        ops.1.push_front(slow);
+        ops.1.truncate(self.config.slow_log_len);
+    }
+
+    /// `slow_log` returns the slow operations, newest first.
+    pub fn slow_log(&self) -> Vec<SlowOp> {
+        let ops = self.slow_ops.ops.lock().unwrap_or_else(|err| err.into_inner());
+        ops.1.iter().cloned().collect()
+    }
+
+    /// `reset_slow_log` empties the slow log.
+    pub fn reset_slow_log(&self) {
+        self.slow_ops.ops.lock().unwrap_or_else(|err| err.into_inner()).1.clear();
+    }
+}
+
+
+#[test]
+fn test_slow_log() {
+    use super::StoreConfig;
+
+    let mut kvs = super::new("".to_string());
+    kvs.insert("untimed".to_string(), "v".to_string());
+    assert!(kvs.slow_log().is_empty());
+
+    kvs.config = StoreConfig::new().slow_log(Duration::from_secs(0), 3);
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "22".to_string());
+    kvs.get("a".to_string());
+    kvs.delete("a".to_string());
+    kvs.flush().unwrap();
+    let ops: Vec<(u64, &str, String, usize)> = kvs.slow_log().into_iter()
+        .map(|slow| (slow.id, slow.op, slow.key, slow.size))
+        .collect();
+    assert_eq!(ops, vec![(5, "flush", "".to_string(), 1),
+                         (4, "delete", "a".to_string(), 2),
+                         (3, "get", "a".to_string(), 2)]);
+
+    assert!(kvs.clone().slow_log().is_empty());
+    kvs.reset_slow_log();
+    assert!(kvs.slow_log().is_empty());
+
+    kvs.config = StoreConfig::new().slow_log(Duration::from_secs(3600), 3);
+    kvs.get("untimed".to_string());
+    assert!(kvs.slow_log().is_empty());
+}
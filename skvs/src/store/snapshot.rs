@@ -0,0 +1,233 @@
+//! snapshot provides read-only views of the store frozen at a point in
+//! time. A snapshot shares the store's values map rather than copying
+//! it, so taking one is cheap; the store copies the map on its first
+//! write afterwards, leaving the snapshot untouched. Long-running
+//! iteration or exports can run against a snapshot while the store
+//! keeps changing.
+//!
+//! Snapshots are also how to read several related keys consistently
+//! from a store shared between threads: take a snapshot while holding
+//! the store's lock, then read from it after letting go. Every value
+//! read then comes from the same instant, between whole batches of
+//! writes.
+//!
+//! The side files of values spilled when a snapshot was taken (see the
+//! `spill` module) are kept until the snapshot is dropped, so reading
+//! them through it doesn't fail after the store is flushed.
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! let shared = Arc::new(Mutex::new(skvs::store::new("".to_string())));
+//! {
+//!     let mut kvs = shared.lock().unwrap();
+//!     kvs.insert("balance/a".to_string(), "60".to_string());
+//!     kvs.insert("balance/b".to_string(), "40".to_string());
+//! }
+//!
+//! let snap = shared.lock().unwrap().snapshot();
+//! let balances = snap.get_many(&["balance/a", "balance/b", "balance/c"]);
+//! assert_eq!(balances[0].as_deref(), Some("60"));
+//! assert_eq!(balances[1].as_deref(), Some("40"));
+//! assert!(balances[2].is_none());
+//! ```
+use super::entry::Entry;
+use super::{Format, Metrics, Store, StoreDiff, Values};
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Snapshot is an immutable, consistent view of a store's entries and
+/// metrics.
+///
+/// ```
+/// let mut kvs = skvs::store::new("".to_string());
+/// kvs.insert("k".to_string(), "old".to_string());
+/// let snap = kvs.snapshot();
+/// kvs.update("k".to_string(), "new".to_string());
+/// assert_eq!(snap.get("k").as_deref(), Some("old"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    store: Store,
+}
+
+/// Snapshots follows the values maps held by a store's snapshots, so
+/// that `collect_blobs` keeps the side files they refer to. It's shared
+/// with clones of the store, and forgets each map once every snapshot
+/// holding it is dropped.
+#[derive(Clone, Default)]
+pub(super) struct Snapshots {
+    held: Arc<Mutex<Vec<Weak<Values>>>>,
+}
+
+impl Snapshots {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Weak<Values>>> {
+        self.held.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// `hold` records that a snapshot holds `values`.
+    fn hold(&self, values: &Arc<Values>) {
+        let values = Arc::downgrade(values);
+        let mut held = self.lock();
+        held.retain(|values| values.strong_count() > 0);
+        if !held.iter().any(|other| other.ptr_eq(&values)) {
+            held.push(values);
+        }
+    }
+
+    /// `live` returns the values maps still held by snapshots.
+    pub(super) fn live(&self) -> Vec<Arc<Values>> {
+        let mut held = self.lock();
+        held.retain(|values| values.strong_count() > 0);
+        held.iter().filter_map(Weak::upgrade).collect()
+    }
+}
+
+impl Store {
+    /// `snapshot` returns a view of the store as it is now.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshots.hold(&self.values);
+        Snapshot { store: self.clone() }
+    }
+
+    /// `get_many_consistent` returns the values of `keys`, in order,
+    /// all as of the same instant. No write can come between the reads
+    /// while the store is borrowed; for a shared store, either call
+    /// this with its lock held, or read from a `snapshot` taken with
+    /// it held.
+    pub fn get_many_consistent(&self, keys: &[&str]) -> Vec<Option<String>> {
+        keys.iter().map(|k| self.with_value(k, |v| v.to_string())).collect()
+    }
+}
+
+impl Snapshot {
+    /// `get` returns the value for `k`, if it was present. The value
+    /// is shared with the snapshot rather than copied. A spilled value
+    /// is read from its side file, which the store keeps for as long
+    /// as the snapshot is held.
+    pub fn get(&self, k: &str) -> Option<Arc<str>> {
+        let ent = self.store.values.get(self.store.resolve(k))?;
+        self.store.shared_value(ent).ok()
+    }
+
+    /// `get_many` returns the values of `keys`, in order, as they were
+    /// when the snapshot was taken.
+    pub fn get_many(&self, keys: &[&str]) -> Vec<Option<Arc<str>>> {
+        keys.iter().map(|k| self.get(k)).collect()
+    }
+
+    /// `entry` returns the entry for `k`, following aliases, if it
+    /// was present.
+    pub fn entry(&self, k: &str) -> Option<&Entry> {
+        self.store.values.get(self.store.resolve(k))
+    }
+
+    /// `iter` visits every entry in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.store.values.iter().map(|(k, ent)| (&**k, ent))
+    }
+
+    /// `keys` returns the keys, sorted. Aliases aren't keys, and
+    /// aren't listed.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.store.values.keys().map(|k| &**k).collect();
+        keys.sort();
+        keys
+    }
+
+    /// `len` returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    /// `is_empty` returns true if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.store.values.is_empty()
+    }
+
+    /// `metrics` returns the store's metrics when the snapshot was
+    /// taken.
+    pub fn metrics(&self) -> Metrics {
+        self.store.metrics.clone()
+    }
+
+    /// `diff` returns the changes that turn this snapshot's values
+    /// into `other`'s; see `Store::diff`.
+    pub fn diff(&self, other: &Snapshot) -> Result<StoreDiff, io::Error> {
+        self.store.diff(&other.store)
+    }
+
+    /// `export` works like `Store::export`.
+    pub fn export<W: Write>(&self, format: Format, w: &mut W) -> Result<(), io::Error> {
+        self.store.export(format, w)
+    }
+
+    /// `export_consul` works like `Store::export_consul`.
+    pub fn export_consul<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        self.store.export_consul(w)
+    }
+
+    /// `export_etcd` works like `Store::export_etcd`.
+    pub fn export_etcd<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        self.store.export_etcd(w)
+    }
+}
+
+
+#[test]
+fn test_snapshot() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.insert("b".to_string(), "2".to_string());
+
+    let snap = kvs.snapshot();
+    assert!(Arc::ptr_eq(&kvs.values, &snap.store.values));
+
+    kvs.update("a".to_string(), "3".to_string());
+    kvs.delete("b".to_string());
+    kvs.insert("c".to_string(), "4".to_string());
+    assert!(!Arc::ptr_eq(&kvs.values, &snap.store.values));
+
+    assert_eq!(snap.keys(), vec!["a", "b"]);
+    assert_eq!(snap.get("a").as_deref(), Some("1"));
+    assert_eq!(snap.entry("a").unwrap().version, 1);
+    assert_eq!(snap.metrics().size, 2);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "3");
+
+    let mut out = Vec::new();
+    snap.export(Format::Csv, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "key,value\na,1\nb,2\n");
+}
+
+#[test]
+fn test_get_many_consistent() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    let shared = Arc::new(Mutex::new(super::new("".to_string())));
+    {
+        let mut kvs = shared.lock().unwrap();
+        kvs.insert("a".to_string(), "0".to_string());
+        kvs.insert("b".to_string(), "0".to_string());
+        assert_eq!(kvs.get_many_consistent(&["a", "missing"]), vec![Some("0".to_string()), None]);
+    }
+
+    // The writer keeps a and b equal, updating both under one lock.
+    let writer = {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for i in 1..500 {
+                let mut kvs = shared.lock().unwrap();
+                kvs.update("a".to_string(), i.to_string());
+                kvs.update("b".to_string(), i.to_string());
+            }
+        })
+    };
+    for _ in 0..500 {
+        let snap = shared.lock().unwrap().snapshot();
+        let values = snap.get_many(&["a", "b"]);
+        assert_eq!(values[0], values[1]);
+    }
+    writer.join().unwrap();
+}
@@ -0,0 +1,295 @@
+//! spill keeps very large values out of memory and out of the store
+//! file. When `StoreConfig::spill_bytes` is set, flushing moves each
+//! value larger than it into a side file under `<path>.d/`, named for
+//! a hash of its contents, and keeps only the file's name in the
+//! entry. Unchanged values are never written again, so flushes stay
+//! quick however large the values are.
+//!
+//! Reads, scans, exports, snapshots, diffs, patches, merges, undo, the
+//! reverse index, and the trash read spilled values back as needed;
+//! quotas, stats, and the slow log take a spilled value's length from
+//! its side file's name instead. Secrets and in-memory stores are
+//! never spilled, nor are values under the prefixes given to
+//! `preload`.
+//!
+//! Side files no longer referenced by the store, its trash, or its
+//! snapshots are removed after each flush; `collect_blobs` does so on
+//! demand.
+use super::entry::Entry;
+use super::hasher::FxHasher;
+use super::{disk, DurabilityPolicy, Store};
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::sync::Arc;
+
+/// `blob_dir` returns the directory holding the side files of the
+/// store at `path`.
+pub fn blob_dir(path: &str) -> String {
+    format!("{}.d", path)
+}
+
+fn blob_path(path: &str, name: &str) -> String {
+    format!("{}/{}", blob_dir(path), name)
+}
+
+/// `blob_name` names the side file for `v` after its contents: a hash
+/// of them, then their length.
+fn blob_name(v: &str) -> String {
+    let mut h = FxHasher::default();
+    h.write(v.as_bytes());
+    format!("{:016x}{:08x}", h.finish(), v.len() as u32)
+}
+
+/// `value_len` returns the length of the value of `ent` without
+/// reading it back if it was spilled, since its side file's name
+/// records it. Only the low 32 bits of the length are recorded, so
+/// spilled values of 4 GiB or more are undercounted.
+pub(super) fn value_len(ent: &Entry) -> usize {
+    match ent.blob {
+        Some(ref name) => name.get(16..24).and_then(|len| usize::from_str_radix(len, 16).ok()).unwrap_or(0),
+        None           => ent.value.len(),
+    }
+}
+
+impl Store {
+    /// `shared_value` returns the value of `ent`, reading it from its
+    /// side file if it was spilled. The value is shared with `ent` when
+    /// it's in memory, or with the cache of spilled values, rather than
+    /// copied. Failed reads are counted in the store's counters as
+    /// `blob_errors`.
+    pub(super) fn shared_value(&self, ent: &Entry) -> Result<Arc<str>, io::Error> {
+        match ent.blob {
+            Some(ref name) => self.read_blob(name),
+            None           => Ok(ent.value.clone()),
+        }
+    }
+
+    /// `unspilled` returns a copy of `ent` with its value in memory.
+    pub(super) fn unspilled(&self, ent: &Entry) -> Result<Entry, io::Error> {
+        if ent.blob.is_none() {
+            return Ok(ent.clone());
+        }
+        let value = self.shared_value(ent)?;
+        Ok(Entry { value, blob: None, ..ent.clone() })
+    }
+
+    /// `read_blob` reads the side file `name`, through the cache of
+    /// spilled values if there is one; see the `lazy` module.
+    fn read_blob(&self, name: &str) -> Result<Arc<str>, io::Error> {
+        let max = self.config.blob_cache;
+        if max > 0 {
+            if let Some(v) = self.blobs.get(name) {
+                return Ok(v);
+            }
+        }
+        let v: Arc<str> = Arc::from(self.read_side_file(name)?);
+        if max > 0 {
+            self.blobs.put(name, v.clone(), max);
+        }
+        Ok(v)
+    }
+
+    /// `read_side_file` reads the side file `name` from disk, counting
+    /// the read if it fails.
+    fn read_side_file(&self, name: &str) -> Result<String, io::Error> {
+        fs::read_to_string(blob_path(&self.path, name)).map_err(|err| {
+            self.metrics.counters.blob_failed();
+            io::Error::new(err.kind(), format!("{}: {}", name, err))
+        })
+    }
+
+    /// `spill` moves the values larger than the configured threshold
+    /// into side files.
+    pub(super) fn spill(&mut self) -> Result<(), io::Error> {
+        let max = match self.config.spill_bytes {
+            Some(max) if !self.path.is_empty() => max,
+            _                                  => return Ok(()),
+        };
+        let large: Vec<Arc<str>> = self.values.iter()
+            .filter(|&(k, ent)| ent.blob.is_none() && !ent.secret && ent.value.len() > max && !self.is_hot(k))
+            .map(|(k, _)| k.clone())
+            .collect();
+        if large.is_empty() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(blob_dir(&self.path))?;
+        for k in large {
+            let v = self.values[&k].value.clone();
+            let name = self.write_blob(&v)?;
+            if let Some(ent) = self.values_mut().get_mut(&k) {
+                ent.value = Arc::from("");
+                ent.blob = Some(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// `write_blob` writes `v` to its side file, unless it's already
+    /// there, and returns the file's name. The file is written like
+    /// the store's, and synced under `DurabilityPolicy::Fsync`.
+    fn write_blob(&self, v: &str) -> Result<String, io::Error> {
+        let base = blob_name(v);
+        let mut name = base.clone();
+        for i in 1.. {
+            match fs::read_to_string(blob_path(&self.path, &name)) {
+                Ok(ref existing) if existing == v                    => return Ok(name),
+                Ok(_)                                                => name = format!("{}-{}", base, i),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => break,
+                Err(err)                                             => return Err(err),
+            }
+        }
+        let sync = self.config.durability == DurabilityPolicy::Fsync;
+        disk::write_atomic(&*self.disk, &blob_path(&self.path, &name), sync, |w| w.write_all(v.as_bytes()))?;
+        Ok(name)
+    }
+
+    /// `collect_blobs` removes the side files that neither the store,
+    /// its trash, nor any of its snapshots refers to, returning how
+    /// many were removed.
+    pub fn collect_blobs(&self) -> Result<usize, io::Error> {
+        let dir = match fs::read_dir(blob_dir(&self.path)) {
+            Ok(dir)                                           => dir,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err)                                          => return Err(err),
+        };
+        let held = self.snapshots.live();
+        let live: HashSet<&str> = self.values.values()
+            .chain(held.iter().flat_map(|values| values.values()))
+            .chain(self.trash.values().map(|deleted| &deleted.entry))
+            .filter_map(|ent| ent.blob.as_deref())
+            .collect();
+
+        let mut removed = 0;
+        for file in dir {
+            let file = file?;
+            if !live.contains(&*file.file_name().to_string_lossy()) {
+                fs::remove_file(file.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+
+#[test]
+fn test_spill() {
+    use testutil::TempStore;
+    use super::StoreConfig;
+
+    let path = TempStore::new("spill");
+    let large = "x".repeat(1000);
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(100).undo_depth(4);
+    kvs.insert("small".to_string(), "v".to_string());
+    kvs.insert("large".to_string(), large.clone());
+    kvs.insert("copy".to_string(), large.clone());
+    kvs.insert("gone".to_string(), "y".repeat(1000));
+    kvs.flush().unwrap();
+
+    assert_eq!(&*kvs.values["large"].value, "");
+    assert_eq!(kvs.values["large"].blob, kvs.values["copy"].blob);
+    assert!(kvs.values["small"].blob.is_none());
+    assert_eq!(kvs.get("large".to_string()), Some(large.clone()));
+    assert_eq!(kvs.with_value("large", |v| v.len()), Some(1000));
+    assert_eq!(fs::read_dir(blob_dir(&path)).unwrap().count(), 2);
+
+    kvs.delete("gone".to_string());
+    kvs.update("copy".to_string(), "".to_string());
+    assert_eq!(kvs.get("copy".to_string()), Some("".to_string()));
+    kvs.undo();
+    assert_eq!(kvs.get("copy".to_string()), Some(large.clone()));
+    kvs.flush().unwrap();
+    assert_eq!(fs::read_dir(blob_dir(&path)).unwrap().count(), 1);
+
+    kvs.soft_delete("large".to_string());
+    kvs.delete("copy".to_string());
+    kvs.flush().unwrap();
+    assert_eq!(kvs.collect_blobs().unwrap(), 0);
+    kvs.restore("large".to_string()).unwrap();
+    assert_eq!(kvs.get("large".to_string()), Some(large));
+}
+
+#[test]
+fn test_spilled_snapshot() {
+    use testutil::TempStore;
+    use super::StoreConfig;
+
+    let path = TempStore::new("spilled-snapshot");
+    let large = "x".repeat(1000);
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(100);
+    kvs.insert("large".to_string(), large.clone());
+    kvs.flush().unwrap();
+    assert!(kvs.values["large"].blob.is_some());
+
+    // The snapshot keeps the side file the store no longer needs.
+    let snap = kvs.snapshot();
+    kvs.update("large".to_string(), "small".to_string());
+    kvs.flush().unwrap();
+    assert_eq!(snap.get("large").as_deref(), Some(large.as_str()));
+    assert_eq!(fs::read_dir(blob_dir(&path)).unwrap().count(), 1);
+
+    drop(snap);
+    assert_eq!(kvs.collect_blobs().unwrap(), 1);
+}
+
+#[test]
+fn test_unreadable_blob() {
+    use testutil::TempStore;
+    use super::{StoreConfig, TypedError};
+
+    let path = TempStore::new("unreadable");
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(10);
+    kvs.insert("k".to_string(), "x".repeat(100));
+    kvs.spill().unwrap();
+    fs::remove_dir_all(blob_dir(&path)).unwrap();
+
+    assert!(kvs.get("k".to_string()).is_none());
+    match kvs.get_as::<String>("k") {
+        Err(TypedError::Unreadable(_)) => (),
+        other                          => panic!("{:?}", other),
+    }
+    assert!(kvs.preload(&["k"]).is_err());
+    assert_eq!(kvs.counters().blob_errors(), 3);
+    assert_eq!((kvs.counters().hits(), kvs.counters().misses()), (0, 2));
+}
+
+#[test]
+fn test_spilled_reads() {
+    use testutil::TempStore;
+    use super::{Quota, StoreConfig};
+
+    let path = TempStore::new("spilled-reads");
+    let large = "x".repeat(1000);
+
+    let mut kvs = super::new(path.to_string());
+    kvs.config = StoreConfig::new().spill_values_over(100).quota("q/", Quota::new().max_bytes(1500));
+    kvs.insert("q/large".to_string(), large.clone());
+    kvs.insert("small".to_string(), "v".to_string());
+    let before = kvs.snapshot();
+    kvs.spill().unwrap();
+    kvs.set_reverse_index(true);
+    assert!(kvs.values["q/large"].blob.is_some());
+
+    assert_eq!(kvs.snapshot().get("q/large").as_deref(), Some(large.as_str()));
+    assert!(before.diff(&kvs.snapshot()).unwrap().is_empty());
+    assert_eq!(kvs.find_keys_by_value(&large), vec!["q/large"]);
+    assert_eq!(kvs.usage("q/").bytes, 1007);
+    assert_eq!(kvs.entry_size("q/large").unwrap().raw, 1007);
+    assert_eq!(kvs.insert("q/more".to_string(), "y".repeat(600)), super::WriteResult::QuotaExceeded);
+
+    let mut copy = super::new("".to_string());
+    copy.apply_diff(&copy.diff(&kvs).unwrap()).unwrap();
+    assert_eq!(copy.get("q/large".to_string()), Some(large.clone()));
+
+    kvs.delete("q/large".to_string());
+    assert!(kvs.find_keys_by_value(&large).is_empty());
+}
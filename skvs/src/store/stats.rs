@@ -0,0 +1,374 @@
+//! stats reports how much space entries take up, both as raw key and
+//! value bytes and as stored in the persisted store, so that users can
+//! see which parts of the keyspace dominate the store file.
+//!
+//! skvs doesn't compress values yet, so the stored size is currently
+//! the size of an entry's JSON encoding (including its metadata and
+//! any escaping); the ratios show where that overhead is significant.
+//!
+//! `analyze` goes further, describing the shape of the store: how its
+//! keys are spread across prefixes, how large its values are, and which
+//! entries are oldest, newest, and most often written.
+extern crate serde_json;
+
+use super::entry::Entry;
+use super::hlc::Timestamp;
+use super::{spill, Store};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+/// EntrySize compares the raw and stored sizes of an entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EntrySize {
+    /// raw is the number of bytes in the key and value.
+    pub raw: usize,
+
+    /// stored is the number of bytes the entry takes up in the
+    /// persisted store.
+    pub stored: usize,
+}
+
+impl EntrySize {
+    /// `ratio` returns stored bytes per raw byte; values below 1
+    /// mean the stored form is smaller than the raw data.
+    pub fn ratio(&self) -> f64 {
+        if self.raw == 0 {
+            return 1.0;
+        }
+        self.stored as f64 / self.raw as f64
+    }
+}
+
+/// PrefixSize aggregates the sizes of all entries under a prefix.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrefixSize {
+    /// entries is the number of entries under the prefix.
+    pub entries: usize,
+
+    /// size is the total size of those entries.
+    pub size: EntrySize,
+}
+
+/// `prefix_of` returns the first path segment of `k`, including the
+/// trailing '/'; keys without a '/' have an empty prefix.
+pub fn prefix_of(k: &str) -> &str {
+    match k.find('/') {
+        Some(i) => &k[..i + 1],
+        None    => "",
+    }
+}
+
+fn size_of(k: &str, ent: &Entry) -> EntrySize {
+    // This mirrors the `"key":{...}` member written for the entry in
+    // the store's values map, plus the separating comma.
+    let stored = match (serde_json::to_vec(k), serde_json::to_vec(ent)) {
+        (Ok(k), Ok(ent)) => k.len() + 1 + ent.len() + 1,
+        _                => 0,
+    };
+
+    EntrySize { raw: k.len() + spill::value_len(ent), stored }
+}
+
+impl Store {
+    /// `entry_size` returns the raw and stored sizes of the entry
+    /// for `k`, if there is one.
+    pub fn entry_size(&self, k: &str) -> Option<EntrySize> {
+        self.values.get(k).map(|ent| size_of(k, ent))
+    }
+
+    /// `sizes_by_prefix` aggregates entry sizes by the first path
+    /// segment of their keys (see `prefix_of`).
+    pub fn sizes_by_prefix(&self) -> BTreeMap<String, PrefixSize> {
+        let mut sizes: BTreeMap<String, PrefixSize> = BTreeMap::new();
+        for (k, ent) in self.values.iter() {
+            let size = size_of(k, ent);
+            let agg = sizes.entry(prefix_of(k).to_string()).or_default();
+            agg.entries += 1;
+            agg.size.raw += size.raw;
+            agg.size.stored += size.stored;
+        }
+        sizes
+    }
+
+    /// `report` summarises the store for people: its path, size, and
+    /// times, and the `top` largest entries by stored size.
+    pub fn report(&self, top: usize) -> Report {
+        let mut largest: Vec<(String, EntrySize)> = self.values.iter()
+            .map(|(k, ent)| (k.to_string(), size_of(k, ent)))
+            .collect();
+        largest.sort_by(|a, b| b.1.stored.cmp(&a.1.stored).then_with(|| a.0.cmp(&b.0)));
+        largest.truncate(top);
+
+        Report {
+            path: self.path.clone(),
+            entries: self.len(),
+            disk_size: self.disk_size(),
+            last_update: self.metrics.last_update,
+            last_write: self.metrics.last_write,
+            largest,
+        }
+    }
+
+    /// `analyze` summarises the shape of the store; see `StoreStats`.
+    pub fn analyze(&self) -> StoreStats {
+        let mut value_sizes: Vec<SizeBucket> = SIZE_BUCKETS.iter()
+            .map(|&up_to| SizeBucket { up_to: Some(up_to), entries: 0 })
+            .collect();
+        value_sizes.push(SizeBucket { up_to: None, entries: 0 });
+        for ent in self.values.values() {
+            let len = spill::value_len(ent);
+            let i = SIZE_BUCKETS.iter().position(|&up_to| len <= up_to).unwrap_or(SIZE_BUCKETS.len());
+            value_sizes[i].entries += 1;
+        }
+
+        let mut by_time: Vec<(String, Timestamp)> = self.values.iter()
+            .map(|(k, ent)| (k.to_string(), ent.time))
+            .collect();
+        by_time.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        let oldest: Vec<(String, Timestamp)> = by_time.iter().take(ANALYZE_TOP).cloned().collect();
+        let newest: Vec<(String, Timestamp)> = by_time.into_iter().rev().take(ANALYZE_TOP).collect();
+
+        let mut versions: Vec<(String, i64)> = self.values.iter()
+            .map(|(k, ent)| (k.to_string(), ent.version))
+            .collect();
+        versions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        versions.truncate(ANALYZE_TOP);
+
+        StoreStats {
+            entries: self.len(),
+            prefixes: self.sizes_by_prefix(),
+            value_sizes,
+            oldest,
+            newest,
+            highest_versions: versions,
+        }
+    }
+
+    /// `disk_size` returns the number of bytes the store's files take
+    /// up, including its shards, or `None` if it hasn't been written.
+    fn disk_size(&self) -> Option<u64> {
+        if self.path.is_empty() {
+            return None;
+        }
+        let mut size = fs::metadata(&self.path).ok()?.len();
+        if self.is_sharded() {
+            for i in 0..self.shards {
+                size += fs::metadata(format!("{}.{}", self.path, i)).map(|md| md.len()).unwrap_or(0);
+            }
+        }
+        Some(size)
+    }
+}
+
+/// Report is a summary of a store, for printing; see `Store::report`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub path: String,
+    pub entries: usize,
+
+    /// disk_size is the size of the store's files, if it's been
+    /// written.
+    pub disk_size: Option<u64>,
+
+    pub last_update: Timestamp,
+    pub last_write: Timestamp,
+
+    /// largest lists the largest entries, largest first.
+    pub largest: Vec<(String, EntrySize)>,
+}
+
+/// `or_never` formats `ts`, or "never" for the zero timestamp.
+pub(super) fn or_never(ts: Timestamp) -> String {
+    if ts == Timestamp::default() { "never".to_string() } else { ts.to_string() }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() { "(in memory)" } else { &self.path };
+        writeln!(f, "path:        {}", path)?;
+        writeln!(f, "entries:     {}", self.entries)?;
+        match self.disk_size {
+            Some(size) => writeln!(f, "disk size:   {} bytes", size)?,
+            None       => writeln!(f, "disk size:   not written")?,
+        }
+        writeln!(f, "last update: {}", or_never(self.last_update))?;
+        writeln!(f, "last write:  {}", or_never(self.last_write))?;
+
+        if !self.largest.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:<32} {:>12} {:>12}", "largest entries", "raw", "stored")?;
+            for (k, size) in &self.largest {
+                writeln!(f, "{:<32} {:>12} {:>12}", k, size.raw, size.stored)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `ANALYZE_TOP` is how many entries `analyze` lists in each of its
+/// rankings.
+const ANALYZE_TOP: usize = 5;
+
+/// `SIZE_BUCKETS` are the upper bounds, in bytes, of the value size
+/// distribution reported by `analyze`; larger values are counted in a
+/// final, open-ended bucket.
+const SIZE_BUCKETS: [usize; 7] = [16, 64, 256, 1024, 4096, 16384, 65536];
+
+/// SizeBucket counts the values in a range of sizes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SizeBucket {
+    /// up_to is the largest value size, in bytes, in the bucket; the
+    /// last bucket has no upper bound.
+    pub up_to: Option<usize>,
+
+    pub entries: usize,
+}
+
+/// StoreStats describes the shape of a store; see `Store::analyze`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoreStats {
+    pub entries: usize,
+
+    /// prefixes aggregates the entries by the first path segment of
+    /// their keys (see `prefix_of`).
+    pub prefixes: BTreeMap<String, PrefixSize>,
+
+    /// value_sizes is the distribution of value sizes, smallest
+    /// bucket first.
+    pub value_sizes: Vec<SizeBucket>,
+
+    /// oldest and newest list the entries written longest ago and
+    /// most recently, with the time of their last write.
+    pub oldest: Vec<(String, Timestamp)>,
+    pub newest: Vec<(String, Timestamp)>,
+
+    /// highest_versions lists the most often written entries, highest
+    /// version first.
+    pub highest_versions: Vec<(String, i64)>,
+}
+
+impl fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<24} {:>8} {:>12} {:>12} {:>6}", "prefix", "entries", "raw", "stored", "ratio")?;
+        for (prefix, agg) in &self.prefixes {
+            let prefix = if prefix.is_empty() { "(none)" } else { prefix };
+            writeln!(f, "{:<24} {:>8} {:>12} {:>12} {:>6.2}", prefix, agg.entries,
+                     agg.size.raw, agg.size.stored, agg.size.ratio())?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<24} {:>8}", "value size", "entries")?;
+        for bucket in &self.value_sizes {
+            let range = match bucket.up_to {
+                Some(up_to) => format!("<= {} bytes", up_to),
+                None        => "larger".to_string(),
+            };
+            writeln!(f, "{:<24} {:>8}", range, bucket.entries)?;
+        }
+
+        for (title, entries) in &[("oldest entries", &self.oldest), ("newest entries", &self.newest)] {
+            if !entries.is_empty() {
+                writeln!(f)?;
+                writeln!(f, "{:<32} last write", title)?;
+                for (k, ts) in entries.iter() {
+                    writeln!(f, "{:<32} {}", k, ts)?;
+                }
+            }
+        }
+
+        if !self.highest_versions.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:<32} {:>8}", "highest versions", "version")?;
+            for (k, version) in &self.highest_versions {
+                writeln!(f, "{:<32} {:>8}", k, version)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn test_prefix_of() {
+    assert_eq!(prefix_of("users/kyle/email"), "users/");
+    assert_eq!(prefix_of("motd"), "");
+    assert_eq!(prefix_of("/abs"), "/");
+}
+
+#[test]
+fn test_sizes_by_prefix() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("users/a".to_string(), "1".to_string());
+    kvs.insert("users/b".to_string(), "22".to_string());
+    kvs.insert("motd".to_string(), "hello".to_string());
+
+    let a = kvs.entry_size("users/a").unwrap();
+    assert_eq!(a.raw, 8);
+    assert!(a.stored > a.raw);
+    assert!(a.ratio() > 1.0);
+    assert!(kvs.entry_size("users/c").is_none());
+
+    let sizes = kvs.sizes_by_prefix();
+    assert_eq!(sizes.len(), 2);
+    assert_eq!(sizes["users/"].entries, 2);
+    assert_eq!(sizes["users/"].size.raw, 8 + 9);
+    assert_eq!(sizes[""].size.raw, 9);
+}
+
+#[test]
+fn test_report() {
+    let mut kvs = super::new("".to_string());
+    let empty = kvs.report(5).to_string();
+    assert!(empty.contains("(in memory)"));
+    assert!(empty.contains("last update: never"));
+    assert!(!empty.contains("largest"));
+
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.insert("b".to_string(), "a much longer value".to_string());
+    kvs.insert("c".to_string(), "22".to_string());
+    let report = kvs.report(2);
+    assert_eq!(report.entries, 3);
+    assert_eq!(report.disk_size, None);
+    let largest: Vec<&str> = report.largest.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(largest, vec!["b", "c"]);
+    assert!(report.to_string().contains("last write:  never"));
+    assert!(kvs.to_string().starts_with("(in memory): 3 entries, last updated 20"));
+}
+
+#[test]
+fn test_analyze() {
+    use super::clock::MockClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let clock = Arc::new(MockClock::new(Timestamp::from_secs(1500000000)));
+    let mut kvs = super::new("".to_string());
+    kvs.set_clock(clock.clone());
+    assert!(!kvs.analyze().to_string().contains("oldest"));
+
+    kvs.insert("users/a".to_string(), "1".to_string());
+    clock.advance(Duration::from_secs(1));
+    kvs.insert("users/b".to_string(), "x".repeat(100));
+    clock.advance(Duration::from_secs(1));
+    kvs.insert("motd".to_string(), "x".repeat(100000));
+    kvs.update("users/a".to_string(), "2".to_string());
+    kvs.update("users/a".to_string(), "3".to_string());
+
+    let stats = kvs.analyze();
+    assert_eq!(stats.entries, 3);
+    assert_eq!(stats.prefixes["users/"].entries, 2);
+    assert_eq!(stats.prefixes[""].entries, 1);
+    assert_eq!(stats.value_sizes.len(), SIZE_BUCKETS.len() + 1);
+    assert_eq!(stats.value_sizes[0], SizeBucket { up_to: Some(16), entries: 1 });
+    assert_eq!(stats.value_sizes[2].entries, 1);
+    assert_eq!(stats.value_sizes.last().unwrap().entries, 1);
+    assert_eq!(stats.oldest[0].0, "users/b");
+    assert_eq!(stats.newest[0].0, "users/a");
+    assert_eq!(stats.highest_versions[0], ("users/a".to_string(), 3));
+
+    let table = stats.to_string();
+    assert!(table.contains("(none)"));
+    assert!(table.contains("<= 256 bytes"));
+    assert!(table.contains("highest versions"));
+}
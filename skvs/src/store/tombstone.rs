@@ -0,0 +1,85 @@
+//! tombstone keeps a record of deleted keys so that deletions can be
+//! synced between stores. Without one, `Store::merge` can't tell a key
+//! that was deleted here from one that was never here, and a merge
+//! brings deleted keys back.
+//!
+//! Tombstones are only left when the store is configured to keep them
+//! (`StoreConfig::tombstones`). They are persisted with the store, and
+//! take part in last-writer-wins merges like entries do: a tombstone
+//! removes any entry older than it, and is itself removed when the key
+//! is written again. Deletions win ties with writes.
+//!
+//! Tombstones accumulate, so they should be collected with
+//! `gc_tombstones` once every copy of the store has seen them.
+use super::entry::Entry;
+use super::hlc::Timestamp;
+use super::Store;
+
+/// Tombstone records when a key was deleted, and the version it was
+/// deleted at (one past the version of the deleted entry).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub time: Timestamp,
+    pub version: i64,
+}
+
+impl Tombstone {
+    /// `buries` returns true if this tombstone is newer than `ent`,
+    /// so that `ent` should be deleted.
+    pub fn buries(&self, ent: &Entry) -> bool {
+        (self.time, self.version) >= (ent.time, ent.version)
+    }
+}
+
+impl Store {
+    /// `bury` leaves a tombstone for `k`, deleted at `version`, if the
+    /// store keeps tombstones.
+    pub(super) fn bury(&mut self, k: &str, version: i64) {
+        if self.config.tombstones {
+            let tomb = Tombstone { time: self.clock.now(), version };
+            self.tombstones.insert(k.to_string(), tomb);
+        }
+    }
+
+    /// `unbury` removes the tombstone for `k`, which is being written
+    /// again.
+    pub(super) fn unbury(&mut self, k: &str) {
+        if !self.tombstones.is_empty() {
+            self.tombstones.remove(k);
+        }
+    }
+
+    /// `gc_tombstones` removes the tombstones for deletions made
+    /// before `older_than`, returning how many were removed.
+    pub fn gc_tombstones(&mut self, older_than: Timestamp) -> usize {
+        let before = self.tombstones.len();
+        self.tombstones.retain(|_, tomb| tomb.time >= older_than);
+        before - self.tombstones.len()
+    }
+}
+
+
+#[test]
+fn test_tombstones() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.delete("k".to_string());
+    assert!(kvs.tombstones.is_empty());
+
+    kvs.config.tombstones = true;
+    kvs.insert("k".to_string(), "v".to_string());
+    kvs.update("k".to_string(), "v2".to_string());
+    kvs.delete("k".to_string());
+    assert_eq!(kvs.tombstones["k"].version, 3);
+    assert!(kvs.get("k".to_string()).is_none());
+
+    // Writing the key again removes its tombstone.
+    kvs.insert("k".to_string(), "v3".to_string());
+    assert!(kvs.tombstones.is_empty());
+
+    kvs.delete("k".to_string());
+    let later = Timestamp::now();
+    assert_eq!(kvs.gc_tombstones(Timestamp::from_secs(0)), 0);
+    assert_eq!(kvs.gc_tombstones(later), 1);
+    assert!(kvs.tombstones.is_empty());
+}
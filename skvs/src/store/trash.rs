@@ -0,0 +1,140 @@
+//! trash implements soft deletes: a soft-deleted key disappears from
+//! the store like any deleted key, but its entry is kept in the trash
+//! (which is persisted with the store) until it's restored or purged.
+use super::entry::Entry;
+use super::hlc::Timestamp;
+use super::{Store, WriteResult};
+use super::WriteResult::*;
+use std::io;
+
+/// Deleted is a soft-deleted entry, along with when it was deleted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Deleted {
+    pub entry: Entry,
+    pub time: Timestamp,
+}
+
+impl Store {
+    /// `soft_delete` removes `k` from the store like `delete`, but
+    /// keeps its entry in the trash so it can be restored. Soft
+    /// deleting a key that's already in the trash replaces the trashed
+    /// entry.
+    pub fn soft_delete(&mut self, k: String) -> WriteResult {
+        let entry = match self.values.get(k.as_str()) {
+            Some(ent) => ent.clone(),
+            None      => return DoesNotExist,
+        };
+
+        let wr = self.delete(k.clone());
+        if wr == Updated {
+            self.trash.insert(k, Deleted { entry, time: self.clock.now() });
+        }
+        wr
+    }
+
+    /// `restore` puts the trashed entry for `k` back in the store, as
+    /// a new write with the next version, and returns `Inserted`. It's
+    /// checked like any insert: it returns `AlreadyExists` if `k` has
+    /// been written since it was deleted, and the store's
+    /// configuration, quotas, and leases can reject it; either way the
+    /// entry stays in the trash. It returns `DoesNotExist` if `k` isn't
+    /// in the trash. If the entry's value was spilled and its side
+    /// file can't be read, the error is returned and the entry stays in
+    /// the trash.
+    pub fn restore(&mut self, k: String) -> Result<WriteResult, io::Error> {
+        let (v, ent) = match self.trash.get(&k) {
+            Some(deleted) => (self.shared_value(&deleted.entry)?, &deleted.entry),
+            None          => return Ok(DoesNotExist),
+        };
+        let (version, secret) = (ent.version + 1, ent.secret);
+
+        let wr = self.insert_version(k.clone(), v.to_string(), secret, version);
+        if wr == Inserted {
+            self.trash.remove(&k);
+        }
+        Ok(wr)
+    }
+
+    /// `purge` permanently removes `k` from the trash, returning
+    /// `Updated`, or `DoesNotExist` if it wasn't there.
+    pub fn purge(&mut self, k: String) -> WriteResult {
+        match self.trash.remove(&k) {
+            Some(_) => Updated,
+            None    => DoesNotExist,
+        }
+    }
+
+    /// `list_deleted` returns the trashed keys and their entries,
+    /// sorted by key.
+    pub fn list_deleted(&self) -> Vec<(&str, &Deleted)> {
+        let mut deleted: Vec<(&str, &Deleted)> = self.trash.iter()
+            .map(|(k, deleted)| (k.as_str(), deleted))
+            .collect();
+        deleted.sort_by_key(|&(k, _)| k);
+        deleted
+    }
+}
+
+
+#[test]
+fn test_soft_delete() {
+    let mut kvs = super::new("".to_string());
+    kvs.insert("a".to_string(), "1".to_string());
+    kvs.update("a".to_string(), "2".to_string());
+    kvs.insert("b".to_string(), "3".to_string());
+
+    assert_eq!(kvs.soft_delete("a".to_string()), Updated);
+    assert_eq!(kvs.soft_delete("a".to_string()), DoesNotExist);
+    assert_eq!(kvs.soft_delete("b".to_string()), Updated);
+    assert!(kvs.get("a".to_string()).is_none());
+    assert_eq!(kvs.metrics.size, 0);
+
+    let deleted: Vec<&str> = kvs.list_deleted().iter().map(|&(k, _)| k).collect();
+    assert_eq!(deleted, vec!["a", "b"]);
+    assert_eq!(&*kvs.list_deleted()[0].1.entry.value, "2");
+
+    assert_eq!(kvs.restore("a".to_string()).unwrap(), Inserted);
+    assert_eq!(kvs.get("a".to_string()).unwrap(), "2");
+    assert_eq!(kvs.values["a"].version, 3);
+    assert_eq!(kvs.restore("a".to_string()).unwrap(), DoesNotExist);
+
+    kvs.insert("b".to_string(), "4".to_string());
+    assert_eq!(kvs.restore("b".to_string()).unwrap(), AlreadyExists);
+    assert_eq!(kvs.purge("b".to_string()), Updated);
+    assert_eq!(kvs.purge("b".to_string()), DoesNotExist);
+    assert!(kvs.list_deleted().is_empty());
+    assert_eq!(kvs.metrics.size, 2);
+
+    kvs.soft_delete("a".to_string());
+    kvs.trash.get_mut("a").unwrap().entry.blob = Some("missing".to_string());
+    assert!(kvs.restore("a".to_string()).is_err());
+    assert!(kvs.trash.contains_key("a"));
+    assert!(kvs.get("a".to_string()).is_none());
+}
+
+#[test]
+fn test_restore_checked() {
+    use super::{Quota, StoreConfig};
+    use std::time::Duration;
+
+    let mut kvs = super::new("".to_string());
+    kvs.config = StoreConfig::new().undo_depth(8).quota("q/", Quota::new().max_keys(1));
+    kvs.insert("q/a".to_string(), "1".to_string());
+    kvs.soft_delete("q/a".to_string());
+    kvs.insert("q/b".to_string(), "2".to_string());
+    assert_eq!(kvs.restore("q/a".to_string()).unwrap(), QuotaExceeded);
+    assert!(kvs.trash.contains_key("q/a"));
+
+    kvs.insert("l".to_string(), "3".to_string());
+    kvs.soft_delete("l".to_string());
+    let token = kvs.acquire_lease("l", Duration::from_secs(10)).unwrap();
+    assert_eq!(kvs.restore("l".to_string()).unwrap(), Leased);
+    assert!(kvs.trash.contains_key("l"));
+    kvs.release_lease("l", &token).unwrap();
+
+    // A restore is recorded in the undo history like any insert.
+    assert_eq!(kvs.restore("l".to_string()).unwrap(), Inserted);
+    assert_eq!(kvs.values["l"].version, 2);
+    assert_eq!(kvs.undo(), Updated);
+    assert!(kvs.get("l".to_string()).is_none());
+}
@@ -0,0 +1,99 @@
+//! typed stores structured values as JSON, so callers can read and
+//! write their own types without converting to and from strings at
+//! every call site.
+extern crate serde_json;
+
+use super::serde::de::DeserializeOwned;
+use super::serde::Serialize;
+use super::{Store, WriteResult};
+use std::error::Error;
+use std::fmt;
+
+/// TypedError explains why a typed read or write failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedError {
+    /// Missing is returned when reading a key that isn't present.
+    Missing,
+
+    /// Parse is returned when the stored value isn't valid JSON for
+    /// the requested type; it holds the parser's message.
+    Parse(String),
+
+    /// Encode is returned when the value couldn't be serialized.
+    Encode(String),
+
+    /// Unreadable is returned when the value was spilled and its side
+    /// file couldn't be read; it holds the error.
+    Unreadable(String),
+}
+
+impl fmt::Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TypedError::Missing             => write!(f, "key doesn't exist"),
+            TypedError::Parse(ref err)      => write!(f, "value didn't parse: {}", err),
+            TypedError::Encode(ref err)     => write!(f, "value couldn't be encoded: {}", err),
+            TypedError::Unreadable(ref err) => write!(f, "value couldn't be read: {}", err),
+        }
+    }
+}
+
+impl Error for TypedError {}
+
+impl Store {
+    /// `get_as` returns the value for `k`, following aliases, parsed
+    /// from JSON as a `T`.
+    ///
+    /// ```
+    /// let mut kvs = skvs::store::new("".to_string());
+    /// kvs.insert_as("retries".to_string(), &3u32).unwrap();
+    /// let retries: u32 = kvs.get_as("retries").unwrap();
+    /// assert_eq!(retries, 3);
+    /// ```
+    pub fn get_as<T: DeserializeOwned>(&self, k: &str) -> Result<T, TypedError> {
+        let v = self.values.get(self.resolve(k)).map(|ent| self.shared_value(ent));
+        self.metrics.counters.read(matches!(v, Some(Ok(_))));
+        match v {
+            Some(Ok(v))    => serde_json::from_str(&v).map_err(|err| TypedError::Parse(err.to_string())),
+            Some(Err(err)) => Err(TypedError::Unreadable(err.to_string())),
+            None           => Err(TypedError::Missing),
+        }
+    }
+
+    /// `insert_as` works like `insert`, storing `v` as JSON.
+    pub fn insert_as<T: Serialize>(&mut self, k: String, v: &T) -> Result<WriteResult, TypedError> {
+        let v = serde_json::to_string(v).map_err(|err| TypedError::Encode(err.to_string()))?;
+        Ok(self.insert(k, v))
+    }
+
+    /// `update_as` works like `update`, storing `v` as JSON.
+    pub fn update_as<T: Serialize>(&mut self, k: String, v: &T) -> Result<WriteResult, TypedError> {
+        let v = serde_json::to_string(v).map_err(|err| TypedError::Encode(err.to_string()))?;
+        Ok(self.update(k, v))
+    }
+}
+
+
+#[test]
+fn test_typed() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Camera {
+        make: String,
+        megapixels: u32,
+    }
+
+    let mut kvs = super::new("".to_string());
+    let d800 = Camera { make: "Nikon".to_string(), megapixels: 36 };
+    assert_eq!(kvs.insert_as("D800".to_string(), &d800), Ok(WriteResult::Inserted));
+    assert_eq!(kvs.get_as::<Camera>("D800"), Ok(d800));
+    assert_eq!(kvs.get_as::<Camera>("K-1"), Err(TypedError::Missing));
+
+    kvs.insert("K-1".to_string(), "Pentax".to_string());
+    match kvs.get_as::<Camera>("K-1") {
+        Err(TypedError::Parse(_)) => (),
+        result                    => panic!("unexpected result {:?}", result),
+    }
+
+    assert_eq!(kvs.update_as("K-1".to_string(), &vec![1, 2]), Ok(WriteResult::Updated));
+    assert_eq!(kvs.get("K-1".to_string()).unwrap(), "[1,2]");
+}
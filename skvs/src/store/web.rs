@@ -0,0 +1,68 @@
+//! web keeps a store in the browser's `localStorage`, so the store can
+//! back a web frontend built for `wasm32-unknown-unknown` with the
+//! `web` feature. The in-memory store doesn't touch the filesystem
+//! unless it's flushed, so in the browser it's used as the cache of a
+//! `CachedStore` with a `LocalStorage` backend:
+//!
+//! ```ignore
+//! let backend = LocalStorage::new("settings/")?;
+//! let mut kvs = CachedStore::new(backend, WritePolicy::WriteThrough);
+//! kvs.update("theme".to_string(), "dark".to_string())?;
+//! ```
+//!
+//! IndexedDB isn't supported: its API is asynchronous, and
+//! `PersistenceBackend` isn't. Background flushes and `FileWatcher`
+//! need threads and files, and don't work in the browser.
+extern crate wasm_bindgen;
+extern crate web_sys;
+
+use self::wasm_bindgen::JsValue;
+use super::cache::PersistenceBackend;
+use std::io;
+
+/// LocalStorage is a `PersistenceBackend` keeping each key in the
+/// browser's `localStorage`, under a prefix so several stores can
+/// share it.
+pub struct LocalStorage {
+    storage: web_sys::Storage,
+    prefix: String,
+}
+
+fn js_error(err: JsValue) -> io::Error {
+    io::Error::other(format!("localStorage: {:?}", err))
+}
+
+impl LocalStorage {
+    /// `new` returns a backend keeping keys in the window's
+    /// `localStorage` under `prefix`. It fails outside of a window
+    /// (e.g. in a worker), or if the browser has storage turned off.
+    pub fn new(prefix: &str) -> Result<LocalStorage, io::Error> {
+        let window = match web_sys::window() {
+            Some(window) => window,
+            None         => return Err(io::Error::other("localStorage: there's no window")),
+        };
+        match window.local_storage() {
+            Ok(Some(storage)) => Ok(LocalStorage { storage, prefix: prefix.to_string() }),
+            Ok(None)          => Err(io::Error::other("localStorage isn't available")),
+            Err(err)          => Err(js_error(err)),
+        }
+    }
+
+    fn item(&self, k: &str) -> String {
+        format!("{}{}", self.prefix, k)
+    }
+}
+
+impl PersistenceBackend for LocalStorage {
+    fn get(&mut self, k: &str) -> Result<Option<String>, io::Error> {
+        self.storage.get_item(&self.item(k)).map_err(js_error)
+    }
+
+    fn put(&mut self, k: &str, v: &str) -> Result<(), io::Error> {
+        self.storage.set_item(&self.item(k), v).map_err(js_error)
+    }
+
+    fn delete(&mut self, k: &str) -> Result<(), io::Error> {
+        self.storage.remove_item(&self.item(k)).map_err(js_error)
+    }
+}
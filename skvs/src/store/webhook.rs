@@ -0,0 +1,222 @@
+//! webhook notifies URLs of writes to the keys they care about. Each
+//! `WebhookRule` pairs a glob pattern (see the `glob` module) with an
+//! `http://` URL; the rules are persisted with the store, and
+//! `Store::start_webhooks` starts forwarding events by them.
+//!
+//! Records are held per endpoint until it's due: an endpoint is sent
+//! at most one batch per interval, and a key written several times in
+//! that interval is only reported once, at its latest version. At most
+//! `MAX_PENDING` keys are held for an endpoint; writes to further keys
+//! are dropped and counted as failures. When the store is dropped,
+//! whatever is still held is sent straight away.
+//!
+//! ```
+//! let mut kvs = skvs::store::new("".to_string());
+//! kvs.add_webhook("feature-flags/*", "http://localhost:8080/hooks/flags").unwrap();
+//! assert!(kvs.add_webhook("feature-flags/*", "https://example.com/").is_err());
+//! assert_eq!(kvs.webhooks.len(), 1);
+//! ```
+use super::events::{EventSink, Forwarder, WebhookSink, MAX_BATCH};
+use super::glob::glob_match;
+use super::journal::JournalRecord;
+use super::Store;
+use std::collections::BTreeMap;
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// `MAX_PENDING` is the most keys held for an endpoint between
+/// batches.
+pub const MAX_PENDING: usize = 10_000;
+
+/// WebhookRule sends writes to keys matching `pattern` to `url`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookRule {
+    pub pattern: String,
+    pub url: String,
+}
+
+/// Endpoint is a URL, the patterns it's notified for, and the records
+/// held for it: the latest version written to each key, and how many
+/// records were dropped because too many keys were held.
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    sink: WebhookSink,
+    patterns: Vec<String>,
+    pending: BTreeMap<String, i64>,
+    dropped: usize,
+    last: Option<Instant>,
+}
+
+/// Webhooks is an `EventSink` that routes records to endpoints by
+/// their rules, coalescing and rate limiting them per endpoint.
+#[derive(Debug)]
+pub struct Webhooks {
+    endpoints: Vec<Endpoint>,
+    interval: Duration,
+}
+
+impl Webhooks {
+    /// `new` returns a sink for `rules` that sends each endpoint at
+    /// most one batch per `interval`.
+    pub fn new(rules: &[WebhookRule], interval: Duration) -> Result<Webhooks, io::Error> {
+        let mut endpoints: Vec<Endpoint> = Vec::new();
+        for rule in rules {
+            match endpoints.iter_mut().find(|ep| ep.url == rule.url) {
+                Some(ep) => ep.patterns.push(rule.pattern.clone()),
+                None     => endpoints.push(Endpoint {
+                    url: rule.url.clone(),
+                    sink: WebhookSink::new(&rule.url)?,
+                    patterns: vec![rule.pattern.clone()],
+                    pending: BTreeMap::new(),
+                    dropped: 0,
+                    last: None,
+                }),
+            }
+        }
+        Ok(Webhooks { endpoints, interval })
+    }
+}
+
+impl EventSink for Webhooks {
+    /// Records are only held here; `poll` sends them.
+    fn send(&mut self, batch: &[JournalRecord]) -> Result<(), io::Error> {
+        for rec in batch {
+            for ep in &mut self.endpoints {
+                if !ep.patterns.iter().any(|pattern| glob_match(pattern, &rec.key)) {
+                    continue;
+                }
+                let full = ep.pending.len() >= MAX_PENDING;
+                match ep.pending.get_mut(&rec.key) {
+                    Some(version) => *version = rec.version,
+                    None if full  => ep.dropped += 1,
+                    None          => {
+                        ep.pending.insert(rec.key.clone(), rec.version);
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, last: bool) -> usize {
+        let mut failed = 0;
+        let interval = self.interval;
+        for ep in &mut self.endpoints {
+            failed += mem::take(&mut ep.dropped);
+            let due = last || ep.last.is_none_or(|sent| sent.elapsed() >= interval);
+            if ep.pending.is_empty() || !due {
+                continue;
+            }
+            ep.last = Some(Instant::now());
+            let pending: Vec<JournalRecord> = mem::take(&mut ep.pending).into_iter()
+                .map(|(key, version)| JournalRecord { key, version })
+                .collect();
+            for batch in pending.chunks(MAX_BATCH) {
+                if ep.sink.send(batch).is_err() {
+                    failed += batch.len();
+                }
+            }
+        }
+        failed
+    }
+}
+
+impl Store {
+    /// `add_webhook` notifies `url` of writes to keys matching
+    /// `pattern`. Only `http://` URLs are accepted.
+    pub fn add_webhook(&mut self, pattern: &str, url: &str) -> Result<(), io::Error> {
+        WebhookSink::new(url)?;
+        let rule = WebhookRule { pattern: pattern.to_string(), url: url.to_string() };
+        if !self.webhooks.contains(&rule) {
+            self.webhooks.push(rule);
+        }
+        Ok(())
+    }
+
+    /// `remove_webhook` removes the rule sending writes to keys
+    /// matching `pattern` to `url`, returning true if there was one.
+    pub fn remove_webhook(&mut self, pattern: &str, url: &str) -> bool {
+        let before = self.webhooks.len();
+        self.webhooks.retain(|rule| rule.pattern != pattern || rule.url != url);
+        self.webhooks.len() != before
+    }
+
+    /// `start_webhooks` starts notifying endpoints by the store's
+    /// current rules, sending each at most one batch per `interval`.
+    /// Rules added or removed afterwards take effect the next time
+    /// it's called.
+    pub fn start_webhooks(&mut self, interval: Duration) -> Result<Forwarder, io::Error> {
+        let hooks = Webhooks::new(&self.webhooks, interval)?;
+        Ok(self.forward_events(hooks))
+    }
+}
+
+
+#[test]
+fn test_webhook_rules() {
+    let mut kvs = super::new("".to_string());
+    kvs.add_webhook("feature-flags/*", "http://localhost/flags").unwrap();
+    kvs.add_webhook("feature-flags/*", "http://localhost/flags").unwrap();
+    kvs.add_webhook("billing/**", "http://localhost/flags").unwrap();
+    assert!(kvs.add_webhook("billing/**", "ftp://localhost/").is_err());
+    assert_eq!(kvs.webhooks.len(), 2);
+
+    let hooks = Webhooks::new(&kvs.webhooks, Duration::from_secs(1)).unwrap();
+    assert_eq!(hooks.endpoints.len(), 1);
+    assert_eq!(hooks.endpoints[0].patterns, vec!["feature-flags/*", "billing/**"]);
+
+    assert!(kvs.remove_webhook("billing/**", "http://localhost/flags"));
+    assert!(!kvs.remove_webhook("billing/**", "http://localhost/flags"));
+    assert_eq!(kvs.webhooks, vec![WebhookRule { pattern: "feature-flags/*".to_string(), url: "http://localhost/flags".to_string() }]);
+}
+
+#[test]
+fn test_webhooks() {
+    use super::events::serve_http;
+
+    let (addr, server) = serve_http(&["204 No Content", "204 No Content"]);
+    let rules = [
+        WebhookRule { pattern: "feature-flags/*".to_string(), url: format!("http://{}/flags", addr) },
+        WebhookRule { pattern: "billing/**".to_string(), url: format!("http://{}/billing", addr) },
+    ];
+    let mut hooks = Webhooks::new(&rules, Duration::from_secs(3600)).unwrap();
+    let rec = |key: &str, version| JournalRecord { key: key.to_string(), version };
+
+    // The first batch goes straight out...
+    hooks.send(&[rec("feature-flags/a", 1)]).unwrap();
+    assert_eq!(hooks.poll(false), 0);
+
+    // ...but the next waits out the interval, keeping only the latest
+    // write to each key.
+    hooks.send(&[rec("feature-flags/a", 2), rec("feature-flags/b", 1), rec("other", 1), rec("feature-flags/a", 3)]).unwrap();
+    assert_eq!(hooks.poll(false), 0);
+    let pending: Vec<(&str, i64)> = hooks.endpoints[0].pending.iter().map(|(k, &v)| (k.as_str(), v)).collect();
+    assert_eq!(pending, vec![("feature-flags/a", 3), ("feature-flags/b", 1)]);
+    assert!(hooks.endpoints[1].pending.is_empty());
+
+    assert_eq!(hooks.poll(true), 0);
+    assert!(hooks.endpoints[0].pending.is_empty());
+    let paths: Vec<String> = server.join().unwrap().iter()
+        .map(|request| request.split_whitespace().nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(paths, vec!["/flags", "/flags"]);
+}
+
+#[test]
+fn test_webhooks_cap() {
+    let rules = [WebhookRule { pattern: "**".to_string(), url: "http://127.0.0.1:9/".to_string() }];
+    let mut hooks = Webhooks::new(&rules, Duration::from_secs(3600)).unwrap();
+    hooks.endpoints[0].last = Some(Instant::now());
+
+    let batch: Vec<JournalRecord> = (0..MAX_PENDING + 5)
+        .map(|i| JournalRecord { key: format!("k{}", i), version: 1 })
+        .collect();
+    hooks.send(&batch).unwrap();
+    hooks.send(&[JournalRecord { key: "k0".to_string(), version: 2 }]).unwrap();
+    assert_eq!(hooks.endpoints[0].pending.len(), MAX_PENDING);
+    assert_eq!(hooks.endpoints[0].pending["k0"], 2);
+    assert_eq!(hooks.poll(false), 5);
+    assert_eq!(hooks.poll(false), 0);
+}
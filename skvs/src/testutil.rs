@@ -0,0 +1,108 @@
+//! testutil holds fixtures shared by the crate's tests.
+use std::fmt;
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::process;
+
+/// TempStore is a path in the temporary directory for a test's store.
+/// Everything named after it (the store file, its lock file, its side
+/// file directory, shards, and temporary files) is removed when it's
+/// made, in case an earlier run left any behind, and again when it's
+/// dropped.
+#[derive(Debug)]
+pub struct TempStore {
+    path: String,
+}
+
+impl TempStore {
+    /// `new` returns the path `skvs-NAME-PID.json`.
+    pub fn new(name: &str) -> TempStore {
+        TempStore::at(format!("skvs-{}-{}.json", name, process::id()))
+    }
+
+    /// `dir` returns the path `skvs-NAME-PID`, for tests that keep
+    /// their stores in a directory of their own.
+    pub fn dir(name: &str) -> TempStore {
+        TempStore::at(format!("skvs-{}-{}", name, process::id()))
+    }
+
+    /// `as_path` returns the path as a `Path`.
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    fn at(name: String) -> TempStore {
+        let path = std::env::temp_dir().join(name).to_str().unwrap().to_string();
+        let store = TempStore { path };
+        store.remove();
+        store
+    }
+
+    fn remove(&self) {
+        let path = Path::new(&self.path);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let prefix = format!("{}.", name);
+        let entries = match fs::read_dir(path.parent().unwrap()) {
+            Ok(entries) => entries,
+            Err(_)      => return,
+        };
+        for entry in entries.flatten() {
+            let entry_name = entry.file_name();
+            let entry_name = entry_name.to_string_lossy();
+            if entry_name != name && !entry_name.starts_with(&prefix) {
+                continue;
+            }
+            let _ = match entry.file_type() {
+                Ok(kind) if kind.is_dir() => fs::remove_dir_all(entry.path()),
+                _                         => fs::remove_file(entry.path()),
+            };
+        }
+    }
+}
+
+impl Deref for TempStore {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for TempStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.path.fmt(f)
+    }
+}
+
+impl AsRef<Path> for TempStore {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.path)
+    }
+}
+
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+
+#[test]
+fn test_temp_store() {
+    let path = TempStore::new("temp-store");
+    fs::write(&path, "{}").unwrap();
+    fs::write(format!("{}.lock", path), "").unwrap();
+    fs::create_dir(format!("{}.d", path)).unwrap();
+    fs::write(format!("{}.d/blob", path), "v").unwrap();
+    let other = format!("{}-other", path);
+    fs::write(&other, "").unwrap();
+
+    let kept = path.to_string();
+    drop(path);
+    assert!(!Path::new(&kept).exists());
+    assert!(!Path::new(&format!("{}.lock", kept)).exists());
+    assert!(!Path::new(&format!("{}.d", kept)).exists());
+    assert!(Path::new(&other).exists());
+    fs::remove_file(other).unwrap();
+}